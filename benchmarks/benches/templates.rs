@@ -51,6 +51,282 @@ fn create_real_env() -> Environment<'static> {
     env
 }
 
+fn expensive_filter(value: String) -> String {
+    // stands in for something like markdown rendering
+    let mut rv = value;
+    for _ in 0..50 {
+        rv = rv.chars().rev().collect();
+    }
+    rv
+}
+
+fn create_filter_env(memoized: bool) -> Environment<'static> {
+    let mut env = Environment::new();
+    if memoized {
+        env.add_filter_memoized("expensive", expensive_filter);
+    } else {
+        env.add_filter("expensive", expensive_filter);
+    }
+    env.add_template(
+        "filter.html",
+        "{% for item in items %}{{ text|expensive }}{% endfor %}",
+    )
+    .unwrap();
+    env
+}
+
+fn do_render_filter(env: &Environment) {
+    let tmpl = env.get_template("filter.html").unwrap();
+    tmpl.render(context! {
+        text => "Hello *World*! ".repeat(20),
+        items => (0..100).collect::<Vec<_>>(),
+    })
+    .unwrap();
+}
+
+fn create_many_filters_env() -> Environment<'static> {
+    let mut env = Environment::new();
+    // every include starts out with an empty filter cache so all of the
+    // filters and tests have to be resolved again for every item.
+    env.add_template(
+        "item.html",
+        "{{ item|lower|upper|title|capitalize|trim|replace('I', 'i')\
+         |reverse|first|default('')|e }}{{ item|length|abs|int|float|round }}\
+         {{ item is string }}{{ item is defined }}{{ item is lower }}{{ item is number }}",
+    )
+    .unwrap();
+    env.add_template(
+        "many_filters.html",
+        "{% for item in items %}{% include 'item.html' %}{% endfor %}",
+    )
+    .unwrap();
+    env
+}
+
+fn do_render_many_filters(env: &Environment) {
+    let tmpl = env.get_template("many_filters.html").unwrap();
+    tmpl.render(context! {
+        items => (0..100).map(|x| format!("Item {x}")).collect::<Vec<_>>(),
+    })
+    .unwrap();
+}
+
+fn create_many_includes_env() -> Environment<'static> {
+    let mut env = Environment::new();
+    env.add_template("layout.html", "<main>{% block body %}{% endblock %}</main>")
+        .unwrap();
+    for (name, source) in [
+        ("header.html", "<h1>{{ item }}</h1>"),
+        (
+            "meta.html",
+            "<p>#{{ loop.index if loop is defined else item }}</p>",
+        ),
+        ("body.html", "<p>{{ item * 2 }}</p>"),
+        ("footer.html", "<small>{{ item is even }}</small>"),
+    ] {
+        env.add_template(name, source).unwrap();
+    }
+    env.add_template(
+        "many_includes.html",
+        "{% extends 'layout.html' %}{% block body %}\
+         {% for item in items %}{% include 'header.html' %}{% include 'meta.html' %}\
+         {% include 'body.html' %}{% include 'footer.html' %}{% endfor %}\
+         {% endblock %}",
+    )
+    .unwrap();
+    env
+}
+
+fn do_render_many_includes(env: &Environment) {
+    let tmpl = env.get_template("many_includes.html").unwrap();
+    black_box(
+        tmpl.render(context! {
+            items => (0..200).collect::<Vec<_>>(),
+        })
+        .unwrap(),
+    );
+}
+
+fn create_many_macros_env() -> Environment<'static> {
+    let mut env = Environment::new();
+    let mut source = String::from("{% macro wrap() %}<{{ caller() }}>{% endmacro %}");
+    for idx in 0..50 {
+        source.push_str(&format!(
+            "{{% macro m{idx}(x) %}}{{% call wrap() %}}{{{{ x }}}}{{% endcall %}}{{% endmacro %}}"
+        ));
+    }
+    source.push_str("{% for item in items %}");
+    for idx in 0..50 {
+        source.push_str(&format!("{{{{ m{idx}(item) }}}}"));
+    }
+    source.push_str("{% endfor %}");
+    env.add_template("many_macros.html", Box::leak(source.into_boxed_str()))
+        .unwrap();
+    env
+}
+
+fn do_render_many_macros(env: &Environment) {
+    let tmpl = env.get_template("many_macros.html").unwrap();
+    tmpl.render(context! {
+        items => (0..20).collect::<Vec<_>>(),
+    })
+    .unwrap();
+}
+
+fn create_large_loop_env() -> Environment<'static> {
+    let mut env = Environment::new();
+    env.add_template(
+        "large_loop.html",
+        "{% for item in items %}{% if loop.previtem is defined %}.{% endif %}{% endfor %}",
+    )
+    .unwrap();
+    env
+}
+
+fn do_render_large_loop(env: &Environment) {
+    let tmpl = env.get_template("large_loop.html").unwrap();
+    tmpl.render(context! {
+        items => (0..10000).collect::<Vec<_>>(),
+    })
+    .unwrap();
+}
+
+fn do_render_large_loop_with_capacity(env: &Environment) {
+    let tmpl = env.get_template("large_loop.html").unwrap();
+    tmpl.render_with_capacity(
+        context! {
+            items => (0..10000).collect::<Vec<_>>(),
+        },
+        10000,
+    )
+    .unwrap();
+}
+
+fn create_concat_env() -> Environment<'static> {
+    let mut env = Environment::new();
+    env.add_template(
+        "concat.html",
+        "{% for item in items %}{{ a ~ b ~ c ~ d ~ e ~ a ~ b ~ c ~ d ~ item }}{% endfor %}",
+    )
+    .unwrap();
+    env
+}
+
+fn do_render_concat(env: &Environment) {
+    let tmpl = env.get_template("concat.html").unwrap();
+    tmpl.render(context! {
+        a => "alpha",
+        b => "beta",
+        c => 42,
+        d => "delta",
+        e => "epsilon",
+        items => (0..1000).collect::<Vec<_>>(),
+    })
+    .unwrap();
+}
+
+fn create_json_env() -> Environment<'static> {
+    let mut env = Environment::new();
+    env.add_template(
+        "data.json",
+        "[{% for item in items %}{{ item }},{% endfor %}null]",
+    )
+    .unwrap();
+    env
+}
+
+fn create_json_items() -> minijinja::Value {
+    minijinja::Value::from(
+        (0..1000)
+            .map(|idx| {
+                context! {
+                    id => idx,
+                    name => format!("item {idx}"),
+                    tags => vec!["a", "b", "c"],
+                    nested => context! { active => idx % 2 == 0, score => idx as f64 / 3.0 },
+                }
+            })
+            .collect::<Vec<_>>(),
+    )
+}
+
+fn do_render_json(env: &Environment, items: &minijinja::Value) {
+    let tmpl = env.get_template("data.json").unwrap();
+    tmpl.render(context! { items => items.clone() }).unwrap();
+}
+
+fn create_rows_env() -> Environment<'static> {
+    let mut env = Environment::new();
+    env.add_template(
+        "row.html",
+        "<tr>{% for cell in row %}<td>{{ cell }}</td>{% endfor %}</tr>",
+    )
+    .unwrap();
+    env
+}
+
+fn create_rows() -> Vec<minijinja::Value> {
+    (0..1000)
+        .map(|idx| context! { row => (idx..idx + 20).collect::<Vec<_>>() })
+        .collect()
+}
+
+fn do_render_rows_loop(env: &Environment, rows: &[minijinja::Value]) {
+    let tmpl = env.get_template("row.html").unwrap();
+    for row in rows {
+        black_box(tmpl.render(row).unwrap());
+    }
+}
+
+fn do_render_rows_many(env: &Environment, rows: &[minijinja::Value]) {
+    let tmpl = env.get_template("row.html").unwrap();
+    for rv in tmpl.render_many(rows) {
+        black_box(rv.unwrap());
+    }
+}
+
+fn create_sum_env() -> Environment<'static> {
+    let mut env = Environment::new();
+    env.add_template(
+        "sum.txt",
+        "{{ items|sum }}|{{ items|max }}|{{ items|select('odd')|length }}",
+    )
+    .unwrap();
+    env
+}
+
+fn create_sum_items() -> minijinja::Value {
+    minijinja::Value::from((0..10000).map(minijinja::Value::from).collect::<Vec<_>>())
+}
+
+fn do_render_sum(env: &Environment, items: &minijinja::Value) {
+    let tmpl = env.get_template("sum.txt").unwrap();
+    black_box(tmpl.render(context! { items => items.clone() }).unwrap());
+}
+
+fn create_trim_env() -> Environment<'static> {
+    let mut env = Environment::new();
+    env.add_template(
+        "trim.txt",
+        "{% for item in items %}{{ item|trim|lower }}{% endfor %}",
+    )
+    .unwrap();
+    env
+}
+
+fn create_trim_items() -> minijinja::Value {
+    minijinja::Value::from(
+        (0..1000)
+            .map(|idx| minijinja::Value::from(format!("item number {idx}")))
+            .collect::<Vec<_>>(),
+    )
+}
+
+fn do_render_trim(env: &Environment, items: &minijinja::Value) {
+    let tmpl = env.get_template("trim.txt").unwrap();
+    black_box(tmpl.render(context! { items => items.clone() }).unwrap());
+}
+
 pub fn criterion_benchmark(c: &mut Criterion) {
     c.bench_function("parse", |b| b.iter(do_parse));
     c.bench_function("compile", |b| b.iter(do_parse_and_compile));
@@ -58,6 +334,63 @@ pub fn criterion_benchmark(c: &mut Criterion) {
         let env = create_real_env();
         b.iter(|| do_render(&env));
     });
+    c.bench_function("render_repeated_filter", |b| {
+        let env = create_filter_env(false);
+        b.iter(|| do_render_filter(&env));
+    });
+    c.bench_function("render_repeated_filter_memoized", |b| {
+        let env = create_filter_env(true);
+        b.iter(|| do_render_filter(&env));
+    });
+    c.bench_function("render_many_filters", |b| {
+        let env = create_many_filters_env();
+        b.iter(|| do_render_many_filters(&env));
+    });
+    c.bench_function("render_many_includes", |b| {
+        let env = create_many_includes_env();
+        b.iter(|| do_render_many_includes(&env));
+    });
+    c.bench_function("render_many_macros", |b| {
+        let env = create_many_macros_env();
+        b.iter(|| do_render_many_macros(&env));
+    });
+    c.bench_function("render_large_loop", |b| {
+        let env = create_large_loop_env();
+        b.iter(|| do_render_large_loop(&env));
+    });
+    c.bench_function("render_large_loop_with_capacity", |b| {
+        let env = create_large_loop_env();
+        b.iter(|| do_render_large_loop_with_capacity(&env));
+    });
+    c.bench_function("render_concat", |b| {
+        let env = create_concat_env();
+        b.iter(|| do_render_concat(&env));
+    });
+    c.bench_function("render_rows_loop", |b| {
+        let env = create_rows_env();
+        let rows = create_rows();
+        b.iter(|| do_render_rows_loop(&env, &rows));
+    });
+    c.bench_function("render_rows_many", |b| {
+        let env = create_rows_env();
+        let rows = create_rows();
+        b.iter(|| do_render_rows_many(&env, &rows));
+    });
+    c.bench_function("render_json", |b| {
+        let env = create_json_env();
+        let items = create_json_items();
+        b.iter(|| do_render_json(&env, &items));
+    });
+    c.bench_function("render_large_list_sum", |b| {
+        let env = create_sum_env();
+        let items = create_sum_items();
+        b.iter(|| do_render_sum(&env, &items));
+    });
+    c.bench_function("render_trim_unchanged", |b| {
+        let env = create_trim_env();
+        let items = create_trim_items();
+        b.iter(|| do_render_trim(&env, &items));
+    });
 }
 
 criterion_group!(benches, criterion_benchmark);
@@ -0,0 +1,98 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::sync::Arc;
+
+use minijinja::value::{DynObject, Object, ObjectRepr, Value};
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+
+/// A datetime value that can be passed to templates.
+///
+/// This wraps an [`OffsetDateTime`] from the `time` crate.  When printed it is
+/// rendered in RFC 3339 format which is also how it serializes.  Datetimes can
+/// be compared with each other and the individual components can be accessed
+/// as attributes (`year`, `month`, `day`, `hour`, `minute`, `second`,
+/// `microsecond`, `weekday` and `timestamp`).  All the datetime filters of
+/// this crate accept it as input.
+///
+/// ```
+/// use minijinja::{context, Environment, Value};
+/// use minijinja_contrib::datetime::DateTime;
+/// use time::OffsetDateTime;
+///
+/// let mut env = Environment::new();
+/// minijinja_contrib::add_to_environment(&mut env);
+/// let dt = DateTime::from(OffsetDateTime::from_unix_timestamp(1706702400).unwrap());
+/// let rv = env.render_str(
+///     "{{ dt.year }} {{ dt|dateformat(format='short') }}",
+///     context! { dt => Value::from(dt) },
+/// ).unwrap();
+/// assert_eq!(rv, "2024 2024-01-31");
+/// ```
+///
+/// This type requires the `datetime` feature.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct DateTime(OffsetDateTime);
+
+impl DateTime {
+    /// Returns the current time in UTC.
+    pub fn now() -> DateTime {
+        DateTime(OffsetDateTime::now_utc())
+    }
+
+    /// Returns the wrapped [`OffsetDateTime`].
+    pub fn as_offset_datetime(&self) -> OffsetDateTime {
+        self.0
+    }
+}
+
+impl From<OffsetDateTime> for DateTime {
+    fn from(value: OffsetDateTime) -> DateTime {
+        DateTime(value)
+    }
+}
+
+impl From<DateTime> for OffsetDateTime {
+    fn from(value: DateTime) -> OffsetDateTime {
+        value.0
+    }
+}
+
+impl From<DateTime> for Value {
+    fn from(value: DateTime) -> Value {
+        Value::from_object(value)
+    }
+}
+
+impl Object for DateTime {
+    fn repr(self: &Arc<Self>) -> ObjectRepr {
+        ObjectRepr::Plain
+    }
+
+    fn get_value(self: &Arc<Self>, key: &Value) -> Option<Value> {
+        let dt = self.0;
+        Some(match key.as_str()? {
+            "year" => Value::from(dt.year()),
+            "month" => Value::from(dt.month() as u8),
+            "day" => Value::from(dt.day()),
+            "hour" => Value::from(dt.hour()),
+            "minute" => Value::from(dt.minute()),
+            "second" => Value::from(dt.second()),
+            "microsecond" => Value::from(dt.microsecond()),
+            "weekday" => Value::from(dt.weekday().number_days_from_monday()),
+            "timestamp" => Value::from(dt.unix_timestamp_nanos() as f64 / 1e9),
+            _ => return None,
+        })
+    }
+
+    fn custom_cmp(self: &Arc<Self>, other: &DynObject) -> Option<Ordering> {
+        Some(self.0.cmp(&other.downcast_ref::<DateTime>()?.0))
+    }
+
+    fn render(self: &Arc<Self>, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0.format(&Rfc3339) {
+            Ok(rv) => f.write_str(&rv),
+            Err(_) => write!(f, "{}", self.0),
+        }
+    }
+}
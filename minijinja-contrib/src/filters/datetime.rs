@@ -7,6 +7,8 @@ use serde::Deserialize;
 use time::format_description::well_known::iso8601::Iso8601;
 use time::{format_description, Date, OffsetDateTime, PrimitiveDateTime};
 
+use crate::datetime::DateTime;
+
 fn handle_serde_error(err: serde::de::value::Error) -> Error {
     Error::new(ErrorKind::InvalidOperation, "not a valid date or timestamp").with_source(err)
 }
@@ -22,7 +24,9 @@ fn value_to_datetime(
     let mut timezone_already_handled = false;
 
     #[allow(unused_mut)]
-    let (mut datetime, had_time) = if let Some(s) = value.as_str() {
+    let (mut datetime, had_time) = if let Some(dt) = value.downcast_object_ref::<DateTime>() {
+        (dt.as_offset_datetime(), true)
+    } else if let Some(s) = value.as_str() {
         match OffsetDateTime::parse(s, &Iso8601::PARSING) {
             Ok(dt) => (dt, true),
             Err(original_err) => match PrimitiveDateTime::parse(s, &Iso8601::PARSING) {
@@ -155,8 +159,8 @@ fn get_timezone(
 
 /// Formats a timestamp as date and time.
 ///
-/// The value needs to be a unix timestamp, or a parsable string (ISO 8601) or a
-/// format supported by `chrono` or `time`.
+/// The value needs to be a unix timestamp, a [`DateTime`], or a parsable string
+/// (ISO 8601) or a format supported by `chrono` or `time`.
 ///
 /// The filter accepts two keyword arguments (`format` and `tz`) to influence the format
 /// and the timezone.  The default format is `"medium"`.  The defaults for these keyword
@@ -228,8 +232,8 @@ pub fn datetimeformat(state: &State, value: Value, kwargs: Kwargs) -> Result<Str
 
 /// Formats a timestamp as time.
 ///
-/// The value needs to be a unix timestamp, or a parsable string (ISO 8601) or a
-/// format supported by `chrono` or `time`.
+/// The value needs to be a unix timestamp, a [`DateTime`], or a parsable string
+/// (ISO 8601) or a format supported by `chrono` or `time`.
 ///
 /// The filter accepts two keyword arguments (`format` and `tz`) to influence the format
 /// and the timezone.  The default format is `"medium"`.  The defaults for these keyword
@@ -299,8 +303,8 @@ pub fn timeformat(state: &State, value: Value, kwargs: Kwargs) -> Result<String,
 
 /// Formats a timestamp as date.
 ///
-/// The value needs to be a unix timestamp, or a parsable string (ISO 8601) or a
-/// format supported by `chrono` or `time`.  If the string does not include time
+/// The value needs to be a unix timestamp, a [`DateTime`], or a parsable string
+/// (ISO 8601) or a format supported by `chrono` or `time`.  If the string does not include time
 /// information, then timezone adjustments are not performed.
 ///
 /// The filter accepts two keyword arguments (`format` and `tz`) to influence the format
@@ -1,6 +1,6 @@
 use std::convert::TryFrom;
 
-use minijinja::value::Value;
+use minijinja::value::{Kwargs, Value, ValueKind};
 use minijinja::{Error, ErrorKind};
 
 #[cfg(feature = "datetime")]
@@ -70,13 +70,13 @@ pub fn pluralize(v: Value, singular: Option<Value>, plural: Option<Value>) -> Re
 #[cfg(feature = "rand")]
 #[cfg_attr(docsrs, doc(cfg(feature = "rand")))]
 pub fn random(state: &minijinja::State, seq: Value) -> Result<Value, Error> {
-    use crate::globals::get_rng;
+    use crate::globals::with_rng;
     use minijinja::value::ValueKind;
     use rand::Rng;
 
     if matches!(seq.kind(), ValueKind::Seq | ValueKind::String) {
         let len = seq.len().unwrap_or(0);
-        let idx = get_rng(state).gen_range(0..len);
+        let idx = with_rng(state, |rng| rng.gen_range(0..len));
         seq.get_item_by_index(idx)
     } else {
         Err(Error::new(
@@ -86,6 +86,36 @@ pub fn random(state: &minijinja::State, seq: Value) -> Result<Value, Error> {
     }
 }
 
+/// Returns the items of a sequence, iterable or string in random order.
+///
+/// The random number generated can be seeded with the `RAND_SEED`
+/// global context variable.
+///
+/// ```jinja
+/// {% for item in [1, 2, 3, 4]|shuffle %}{{ item }}{% endfor %}
+/// ```
+#[cfg(feature = "rand")]
+#[cfg_attr(docsrs, doc(cfg(feature = "rand")))]
+pub fn shuffle(state: &minijinja::State, seq: Value) -> Result<Value, Error> {
+    use crate::globals::with_rng;
+    use minijinja::value::ValueKind;
+    use rand::seq::SliceRandom;
+
+    if matches!(
+        seq.kind(),
+        ValueKind::Seq | ValueKind::Iterable | ValueKind::String
+    ) {
+        let mut items = seq.try_iter()?.collect::<Vec<_>>();
+        with_rng(state, |rng| items.shuffle(rng));
+        Ok(Value::from(items))
+    } else {
+        Err(Error::new(
+            ErrorKind::InvalidOperation,
+            "can only shuffle sequences",
+        ))
+    }
+}
+
 /// Formats the value like a "human-readable" file size.
 ///
 /// For example. 13 kB, 4.1 MB, 102 Bytes, etc.  Per default decimal prefixes are
@@ -121,3 +151,101 @@ pub fn filesizeformat(value: f64, binary: Option<bool>) -> String {
         unreachable!();
     }
 }
+
+/// Formats a number with grouped thousands and a fixed number of decimals.
+///
+/// The number of decimals can be passed as first argument or as `decimals`
+/// keyword argument.  If it's not provided integers are formatted without
+/// decimals and floats with as many decimals as needed to represent them.
+/// Rounding happens half away from zero.  The separators can be changed with
+/// the `thousands_sep` (defaults to `,`) and `decimal_point` (defaults to `.`)
+/// keyword arguments.  Scientific notation is never used.
+///
+/// ```jinja
+/// {{ 1234567.89|numberformat }} -> 1,234,567.89
+/// {{ 1234.5|numberformat(2) }} -> 1,234.50
+/// {{ 1234.5|numberformat(decimals=0, thousands_sep=".", decimal_point=",") }} -> 1.235
+/// ```
+pub fn numberformat(
+    value: Value,
+    decimals: Option<usize>,
+    kwargs: Kwargs,
+) -> Result<String, Error> {
+    let decimals = match decimals {
+        Some(decimals) => Some(decimals),
+        None => kwargs.get::<Option<usize>>("decimals")?,
+    };
+    let thousands_sep = kwargs.get::<Option<&str>>("thousands_sep")?.unwrap_or(",");
+    let decimal_point = kwargs.get::<Option<&str>>("decimal_point")?.unwrap_or(".");
+    kwargs.assert_all_used()?;
+
+    if value.kind() != ValueKind::Number {
+        return Err(Error::new(
+            ErrorKind::InvalidOperation,
+            format!("numberformat requires a number, got {}", value.kind()),
+        ));
+    }
+
+    // integers are formatted from their exact representation, floats from
+    // the shortest representation that round-trips.
+    let repr = if value.is_integer() {
+        value.to_string()
+    } else {
+        let f = f64::try_from(value.clone())?;
+        if !f.is_finite() {
+            return Ok(value.to_string());
+        }
+        format!("{}", f)
+    };
+    let (negative, repr) = match repr.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, &repr[..]),
+    };
+    let (int_part, frac_part) = repr.split_once('.').unwrap_or((repr, ""));
+    let mut digits = int_part.as_bytes().to_vec();
+    let int_len = digits.len();
+
+    match decimals {
+        Some(decimals) if frac_part.len() > decimals => {
+            digits.extend_from_slice(&frac_part.as_bytes()[..decimals]);
+            if frac_part.as_bytes()[decimals] >= b'5' {
+                let mut carry = true;
+                for digit in digits.iter_mut().rev() {
+                    if *digit == b'9' {
+                        *digit = b'0';
+                    } else {
+                        *digit += 1;
+                        carry = false;
+                        break;
+                    }
+                }
+                if carry {
+                    digits.insert(0, b'1');
+                }
+            }
+        }
+        Some(decimals) => {
+            digits.extend_from_slice(frac_part.as_bytes());
+            digits.resize(int_len + decimals, b'0');
+        }
+        None => digits.extend_from_slice(frac_part.as_bytes()),
+    }
+
+    let int_len = digits.len() - decimals.unwrap_or(frac_part.len());
+    let (int_digits, frac_digits) = digits.split_at(int_len);
+    let mut rv = String::new();
+    if negative && digits.iter().any(|&x| x != b'0') {
+        rv.push('-');
+    }
+    for (idx, &digit) in int_digits.iter().enumerate() {
+        if idx > 0 && (int_digits.len() - idx) % 3 == 0 {
+            rv.push_str(thousands_sep);
+        }
+        rv.push(digit as char);
+    }
+    if !frac_digits.is_empty() {
+        rv.push_str(decimal_point);
+        rv.extend(frac_digits.iter().map(|&x| x as char));
+    }
+    Ok(rv)
+}
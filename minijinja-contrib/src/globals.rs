@@ -6,14 +6,13 @@ use minijinja::value::Value;
 use minijinja::value::{from_args, Object, ObjectRepr};
 use minijinja::{Error, ErrorKind, State};
 
-/// Returns the current time in UTC as unix timestamp.
+/// Returns the current time in UTC as [`DateTime`](crate::datetime::DateTime).
 ///
-/// To format this timestamp, use the [`datetimeformat`](crate::filters::datetimeformat) filter.
+/// To format this value, use the [`datetimeformat`](crate::filters::datetimeformat) filter.
 #[cfg(feature = "datetime")]
 #[cfg_attr(docsrs, doc(cfg(feature = "datetime")))]
 pub fn now() -> Value {
-    let now = time::OffsetDateTime::now_utc();
-    Value::from(((now.unix_timestamp_nanos() / 1000) as f64) / 1_000_000.0)
+    Value::from(crate::datetime::DateTime::now())
 }
 
 /// Returns a cycler.
@@ -123,20 +122,55 @@ pub fn joiner(sep: Option<Value>) -> Value {
     })
 }
 
-/// Returns the rng for the state
+/// The random number generator shared by all calls within a render.
 #[cfg(feature = "rand")]
-pub(crate) fn get_rng(state: &State) -> rand::rngs::SmallRng {
+struct RenderRng(std::sync::Mutex<(Option<u64>, rand::rngs::SmallRng)>);
+
+/// Invokes a callback with the rng of the render.
+///
+/// The rng is stored in the temporary storage of the state and seeded with
+/// the `RAND_SEED` context variable or global (see
+/// [`set_random_seed`](crate::set_random_seed)).  Without a seed, a random
+/// seed is picked for every render.  Changing the
+/// `RAND_SEED` variable reseeds the rng.
+#[cfg(feature = "rand")]
+pub(crate) fn with_rng<R, F>(state: &State, f: F) -> R
+where
+    F: FnOnce(&mut rand::rngs::SmallRng) -> R,
+{
     use rand::rngs::SmallRng;
     use rand::SeedableRng;
 
-    if let Some(seed) = state
+    let seed = state
         .lookup("RAND_SEED")
-        .and_then(|x| u64::try_from(x).ok())
-    {
-        SmallRng::seed_from_u64(seed)
-    } else {
-        SmallRng::from_entropy()
+        .and_then(|x| u64::try_from(x).ok());
+    let make_rng = |seed: Option<u64>| match seed {
+        Some(seed) => SmallRng::seed_from_u64(seed),
+        None => SmallRng::from_entropy(),
+    };
+
+    let rng = state.get_or_insert_temp(|| RenderRng((seed, make_rng(seed)).into()));
+    let mut rng = rng.0.lock().unwrap();
+    if rng.0 != seed {
+        *rng = (seed, make_rng(seed));
     }
+    f(&mut rng.1)
+}
+
+/// Returns a random float between `0.0` (inclusive) and `1.0` (exclusive).
+///
+/// The random number generated can be seeded with the `RAND_SEED`
+/// global context variable.
+///
+/// ```jinja
+/// {% if random() < 0.5 %}variant A{% else %}variant B{% endif %}
+/// ```
+#[cfg(feature = "rand")]
+#[cfg_attr(docsrs, doc(cfg(feature = "rand")))]
+pub fn random(state: &State) -> f64 {
+    use rand::Rng;
+
+    with_rng(state, |rng| rng.gen())
 }
 
 /// Returns a random number in a given range.
@@ -157,7 +191,7 @@ pub fn randrange(state: &State, n: i64, m: Option<i64>) -> i64 {
         Some(m) => (n, m),
     };
 
-    get_rng(state).gen_range(lower..upper)
+    with_rng(state, |rng| rng.gen_range(lower..upper))
 }
 
 /// Generates a random lorem ipsum.
@@ -223,56 +257,56 @@ pub fn lipsum(
     let n = n.or(n_kwargs).unwrap_or(5);
     let mut rv = String::new();
 
-    let mut rng = get_rng(state);
-
-    for _ in 0..n {
-        let mut next_capitalized = true;
-        let mut last_fullstop = 0;
-        let mut last = "";
+    with_rng(state, |rng| {
+        for _ in 0..n {
+            let mut next_capitalized = true;
+            let mut last_fullstop = 0;
+            let mut last = "";
 
-        for idx in 0..rng.gen_range(min..max) {
-            if idx > 0 {
-                rv.push(' ');
-            } else if html {
-                rv.push_str("<p>");
-            }
-            let word = loop {
-                let word = LIPSUM_WORDS.choose(&mut rng).copied().unwrap_or("");
-                if word != last {
-                    last = word;
-                    break word;
+            for idx in 0..rng.gen_range(min..max) {
+                if idx > 0 {
+                    rv.push(' ');
+                } else if html {
+                    rv.push_str("<p>");
                 }
-            };
+                let word = loop {
+                    let word = LIPSUM_WORDS.choose(rng).copied().unwrap_or("");
+                    if word != last {
+                        last = word;
+                        break word;
+                    }
+                };
 
-            if next_capitalized {
-                for (idx, c) in word.char_indices() {
-                    if idx == 0 {
-                        use std::fmt::Write;
-                        write!(rv, "{}", c.to_uppercase()).ok();
-                    } else {
-                        rv.push(c);
+                if next_capitalized {
+                    for (idx, c) in word.char_indices() {
+                        if idx == 0 {
+                            use std::fmt::Write;
+                            write!(rv, "{}", c.to_uppercase()).ok();
+                        } else {
+                            rv.push(c);
+                        }
                     }
+                    next_capitalized = false;
+                } else {
+                    rv.push_str(word);
+                }
+
+                if idx - last_fullstop > rng.gen_range(10..20) {
+                    rv.push('.');
+                    last_fullstop = idx;
+                    next_capitalized = true;
                 }
-                next_capitalized = false;
-            } else {
-                rv.push_str(word);
             }
 
-            if idx - last_fullstop > rng.gen_range(10..20) {
+            if !rv.ends_with('.') {
                 rv.push('.');
-                last_fullstop = idx;
-                next_capitalized = true;
             }
+            if html {
+                rv.push_str("</p>");
+            }
+            rv.push_str("\n\n");
         }
-
-        if !rv.ends_with('.') {
-            rv.push('.');
-        }
-        if html {
-            rv.push_str("</p>");
-        }
-        rv.push_str("\n\n");
-    }
+    });
 
     if html {
         Ok(Value::from_safe_string(rv))
@@ -21,6 +21,11 @@ pub mod pycompat;
 /// Utility filters.
 pub mod filters;
 
+/// Datetime values.
+#[cfg(feature = "datetime")]
+#[cfg_attr(docsrs, doc(cfg(feature = "datetime")))]
+pub mod datetime;
+
 /// Globals
 pub mod globals;
 
@@ -34,6 +39,7 @@ pub mod globals;
 pub fn add_to_environment(env: &mut Environment) {
     env.add_filter("pluralize", filters::pluralize);
     env.add_filter("filesizeformat", filters::filesizeformat);
+    env.add_filter("numberformat", filters::numberformat);
     #[cfg(feature = "datetime")]
     {
         env.add_filter("datetimeformat", filters::datetimeformat);
@@ -44,9 +50,39 @@ pub fn add_to_environment(env: &mut Environment) {
     #[cfg(feature = "rand")]
     {
         env.add_filter("random", filters::random);
+        env.add_filter("shuffle", filters::shuffle);
         env.add_function("lipsum", globals::lipsum);
+        env.add_function("random", globals::random);
         env.add_function("randrange", globals::randrange);
     }
     env.add_function("cycler", globals::cycler);
     env.add_function("joiner", globals::joiner);
 }
+
+/// Sets or unsets the seed for the random functionality of this crate.
+///
+/// The `random` and `shuffle` filters as well as the `lipsum`, `random` and
+/// `randrange` functions share one random number generator per render.  It
+/// is seeded from the `RAND_SEED` variable which this function registers as
+/// a global.  A `RAND_SEED` passed in the context takes precedence.
+///
+/// With a fixed seed every render produces the same output, which is useful
+/// for tests and caching.  By default no seed is configured and a different
+/// random seed is used for every render.  Passing `None` removes the global
+/// again.
+///
+/// ```
+/// use minijinja::Environment;
+///
+/// let mut env = Environment::new();
+/// minijinja_contrib::add_to_environment(&mut env);
+/// minijinja_contrib::set_random_seed(&mut env, Some(42));
+/// ```
+#[cfg(feature = "rand")]
+#[cfg_attr(docsrs, doc(cfg(feature = "rand")))]
+pub fn set_random_seed(env: &mut Environment, seed: Option<u64>) {
+    match seed {
+        Some(seed) => env.add_global("RAND_SEED", seed),
+        None => env.remove_global("RAND_SEED"),
+    }
+}
@@ -249,3 +249,68 @@ fn test_timeformat() {
         .unwrap();
     assert_eq!(expr.eval(()).unwrap().to_string(), "19:37");
 }
+
+#[test]
+fn test_datetime_value() {
+    use minijinja_contrib::datetime::DateTime;
+    use time::OffsetDateTime;
+
+    let mut env = minijinja::Environment::new();
+    minijinja_contrib::add_to_environment(&mut env);
+
+    let earlier = DateTime::from(OffsetDateTime::from_unix_timestamp(1687624642).unwrap());
+    let later = DateTime::from(OffsetDateTime::from_unix_timestamp(1687711042).unwrap());
+    let ctx = context! {
+        earlier => minijinja::Value::from(earlier),
+        later => minijinja::Value::from(later),
+    };
+
+    assert_eq!(
+        env.render_str("{{ earlier }}", &ctx).unwrap(),
+        "2023-06-24T16:37:22Z"
+    );
+    assert_eq!(
+        env.render_str(
+            "{{ earlier.year }}-{{ earlier.month }}-{{ earlier.day }} {{ earlier.weekday }}",
+            &ctx
+        )
+        .unwrap(),
+        "2023-6-24 5"
+    );
+    assert_eq!(
+        env.render_str(
+            "{{ later|datetimeformat(format='iso', tz='Europe/Vienna') }}",
+            &ctx
+        )
+        .unwrap(),
+        "2023-06-25T18:37:22+02:00"
+    );
+    assert_eq!(
+        env.render_str(
+            "{{ earlier < later }} {{ earlier > later }} {{ earlier == earlier }} {{ earlier == later }}",
+            &ctx
+        )
+        .unwrap(),
+        "true false true false"
+    );
+    assert_eq!(
+        env.render_str(
+            "{{ [later, earlier]|sort|map(attribute='day')|join(',') }}",
+            &ctx
+        )
+        .unwrap(),
+        "24,25"
+    );
+    assert_eq!(
+        env.render_str(
+            "{{ now() > later }} {{ earlier < now() }} {{ now().year >= 2024 }}",
+            &ctx
+        )
+        .unwrap(),
+        "true true true"
+    );
+    assert_eq!(
+        serde_json::to_string(&minijinja::Value::from(earlier)).unwrap(),
+        "\"2023-06-24T16:37:22Z\""
+    );
+}
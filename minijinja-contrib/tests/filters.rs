@@ -1,5 +1,5 @@
 use minijinja::{context, Environment};
-use minijinja_contrib::filters::pluralize;
+use minijinja_contrib::filters::{numberformat, pluralize};
 use similar_asserts::assert_eq;
 
 #[test]
@@ -105,6 +105,48 @@ fn test_random() {
     insta::assert_snapshot!(render!(in env, r"{% set RAND_SEED = 42 %}{{ 'HelloWorld'|random }}"), @"e");
 }
 
+#[test]
+#[cfg(feature = "rand")]
+fn test_shuffle() {
+    use minijinja_contrib::filters::shuffle;
+
+    let mut env = Environment::new();
+    env.add_filter("shuffle", shuffle);
+    minijinja_contrib::set_random_seed(&mut env, Some(42));
+    env.add_template(
+        "shuffle.txt",
+        "{{ range(20)|shuffle }}|{{ range(20)|shuffle }}",
+    )
+    .unwrap();
+
+    let tmpl = env.get_template("shuffle.txt").unwrap();
+    let rv = tmpl.render(()).unwrap();
+    assert_eq!(tmpl.render(()).unwrap(), rv);
+
+    // subsequent shuffles within a render continue the sequence
+    let (first, second) = rv.split_once('|').unwrap();
+    assert_ne!(first, second);
+    let mut items = first
+        .trim_matches(&['[', ']'][..])
+        .split(", ")
+        .map(|x| x.parse::<i64>().unwrap())
+        .collect::<Vec<_>>();
+    items.sort();
+    assert_eq!(items, (0..20).collect::<Vec<_>>());
+
+    // the context seed takes precedence over the environment seed
+    let rv2 = env
+        .render_str("{% set RAND_SEED = 23 %}{{ range(20)|shuffle }}", ())
+        .unwrap();
+    assert_ne!(rv2, first);
+
+    let err = env.render_str("{{ 42|shuffle }}", ()).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "invalid operation: can only shuffle sequences (in <string>:1)"
+    );
+}
+
 #[test]
 fn test_filesizeformat() {
     use minijinja::render;
@@ -126,3 +168,40 @@ fn test_filesizeformat() {
     insta::assert_snapshot!(render!(in env, r"{{ (1024 * 1024 * 1024 * 1024 * 1024 * 1024 * 1024 * 1024)|filesizeformat }}"), @"1.2 YB");
     insta::assert_snapshot!(render!(in env, r"{{ (1024 * 1024 * 1024 * 1024 * 1024 * 1024 * 1024 * 1024 * 1024 * 1024)|filesizeformat }}"), @"1267650.6 YB");
 }
+
+#[test]
+fn test_numberformat() {
+    let mut env = Environment::new();
+    env.add_filter("numberformat", numberformat);
+
+    for (tmpl, expected) in [
+        ("{{ 1234567.89|numberformat }}", "1,234,567.89"),
+        ("{{ 1234567|numberformat }}", "1,234,567"),
+        ("{{ 123|numberformat }}", "123"),
+        ("{{ -1234567|numberformat }}", "-1,234,567"),
+        ("{{ 1234.5|numberformat(2) }}", "1,234.50"),
+        ("{{ 1234|numberformat(decimals=2) }}", "1,234.00"),
+        ("{{ 2.675|numberformat(2) }}", "2.68"),
+        ("{{ 0.5|numberformat(0) }}", "1"),
+        ("{{ -0.5|numberformat(0) }}", "-1"),
+        ("{{ 999.996|numberformat(2) }}", "1,000.00"),
+        ("{{ -0.001|numberformat(2) }}", "0.00"),
+        ("{{ 1e20|numberformat }}", "100,000,000,000,000,000,000"),
+        ("{{ 1e-7|numberformat }}", "0.0000001"),
+        ("{{ 1e-7|numberformat(3) }}", "0.000"),
+        (
+            "{{ 1234567.891|numberformat(2, thousands_sep='.', decimal_point=',') }}",
+            "1.234.567,89",
+        ),
+        ("{{ 1234567|numberformat(thousands_sep='') }}", "1234567"),
+    ] {
+        assert_eq!(env.render_str(tmpl, ()).unwrap(), expected, "{}", tmpl);
+    }
+
+    let err = env.render_str("{{ 'foo'|numberformat }}", ()).unwrap_err();
+    assert_eq!(err.kind(), minijinja::ErrorKind::InvalidOperation);
+    assert_eq!(
+        err.detail(),
+        Some("numberformat requires a number, got string")
+    );
+}
@@ -83,3 +83,31 @@ fn test_randrange() {
     assert_snapshot!(render!(in env, r"{% set RAND_SEED = 42 %}{{ randrange(10) }}"), @"1");
     assert_snapshot!(render!(in env, r"{% set RAND_SEED = 42 %}{{ randrange(-50, 50) }}"), @"-20");
 }
+
+#[test]
+#[cfg(feature = "rand")]
+fn test_random() {
+    use minijinja_contrib::globals::random;
+
+    let mut env = Environment::new();
+    env.add_function("random", random);
+
+    let rv = render!(in env, r"{{ random() }}|{{ random() }}");
+    let (a, b) = rv.split_once('|').unwrap();
+    assert!((0.0..1.0).contains(&a.parse::<f64>().unwrap()));
+    assert_ne!(a, b);
+
+    minijinja_contrib::set_random_seed(&mut env, Some(42));
+    let rv = render!(in env, r"{{ random() }}|{{ random() }}");
+    assert_eq!(render!(in env, r"{{ random() }}|{{ random() }}"), rv);
+    assert_ne!(
+        render!(in env, r"{% set RAND_SEED = 1 %}{{ random() }}|{{ random() }}"),
+        rv
+    );
+
+    minijinja_contrib::set_random_seed(&mut env, None);
+    assert_ne!(
+        render!(in env, r"{{ random() }}"),
+        render!(in env, r"{{ random() }}")
+    );
+}
@@ -64,7 +64,9 @@ pub enum Stmt<'a> {
     WithBlock(Spanned<WithBlock<'a>>),
     Set(Spanned<Set<'a>>),
     SetBlock(Spanned<SetBlock<'a>>),
+    ConstDecl(Spanned<ConstDecl<'a>>),
     AutoEscape(Spanned<AutoEscape<'a>>),
+    UndefinedBlock(Spanned<UndefinedBlock<'a>>),
     FilterBlock(Spanned<FilterBlock<'a>>),
     #[cfg(feature = "multi_template")]
     Block(Spanned<Block<'a>>),
@@ -85,6 +87,8 @@ pub enum Stmt<'a> {
     #[cfg(feature = "loop_controls")]
     Break(Spanned<Break>),
     Do(Spanned<Do<'a>>),
+    #[cfg(feature = "i18n")]
+    Trans(Spanned<Trans<'a>>),
 }
 
 #[cfg(feature = "internal_debug")]
@@ -99,7 +103,9 @@ impl<'a> fmt::Debug for Stmt<'a> {
             Stmt::WithBlock(s) => fmt::Debug::fmt(s, f),
             Stmt::Set(s) => fmt::Debug::fmt(s, f),
             Stmt::SetBlock(s) => fmt::Debug::fmt(s, f),
+            Stmt::ConstDecl(s) => fmt::Debug::fmt(s, f),
             Stmt::AutoEscape(s) => fmt::Debug::fmt(s, f),
+            Stmt::UndefinedBlock(s) => fmt::Debug::fmt(s, f),
             Stmt::FilterBlock(s) => fmt::Debug::fmt(s, f),
             #[cfg(feature = "multi_template")]
             Stmt::Block(s) => fmt::Debug::fmt(s, f),
@@ -120,6 +126,8 @@ impl<'a> fmt::Debug for Stmt<'a> {
             #[cfg(feature = "loop_controls")]
             Stmt::Break(s) => fmt::Debug::fmt(s, f),
             Stmt::Do(s) => fmt::Debug::fmt(s, f),
+            #[cfg(feature = "i18n")]
+            Stmt::Trans(s) => fmt::Debug::fmt(s, f),
         }
     }
 }
@@ -206,6 +214,7 @@ pub struct ForLoop<'a> {
     pub iter: Expr<'a>,
     pub filter_expr: Option<Expr<'a>>,
     pub recursive: bool,
+    pub precount: bool,
     pub body: Vec<Stmt<'a>>,
     pub else_body: Vec<Stmt<'a>>,
 }
@@ -235,6 +244,14 @@ pub struct Set<'a> {
     pub expr: Expr<'a>,
 }
 
+/// A const declaration.
+#[cfg_attr(feature = "internal_debug", derive(Debug))]
+#[cfg_attr(feature = "unstable_machinery_serde", derive(serde::Serialize))]
+pub struct ConstDecl<'a> {
+    pub name: &'a str,
+    pub expr: Expr<'a>,
+}
+
 /// A set capture statement.
 #[cfg_attr(feature = "internal_debug", derive(Debug))]
 #[cfg_attr(feature = "unstable_machinery_serde", derive(serde::Serialize))]
@@ -267,7 +284,9 @@ pub struct Extends<'a> {
 #[cfg_attr(feature = "unstable_machinery_serde", derive(serde::Serialize))]
 pub struct Include<'a> {
     pub name: Expr<'a>,
+    pub context: Option<Expr<'a>>,
     pub ignore_missing: bool,
+    pub ignore_errors: bool,
 }
 
 /// An auto escape control block.
@@ -278,6 +297,14 @@ pub struct AutoEscape<'a> {
     pub body: Vec<Stmt<'a>>,
 }
 
+/// An undefined behavior control block.
+#[cfg_attr(feature = "internal_debug", derive(Debug))]
+#[cfg_attr(feature = "unstable_machinery_serde", derive(serde::Serialize))]
+pub struct UndefinedBlock<'a> {
+    pub behavior: Expr<'a>,
+    pub body: Vec<Stmt<'a>>,
+}
+
 /// Applies filters to a block.
 #[cfg_attr(feature = "internal_debug", derive(Debug))]
 #[cfg_attr(feature = "unstable_machinery_serde", derive(serde::Serialize))]
@@ -325,6 +352,19 @@ pub struct Do<'a> {
     pub call: Spanned<Call<'a>>,
 }
 
+/// A translation block.
+///
+/// The message ids use `%(name)s` placeholders for the variables which are
+/// passed as keyword arguments to `gettext` or `ngettext`.
+#[cfg_attr(feature = "internal_debug", derive(Debug))]
+#[cfg(feature = "i18n")]
+#[cfg_attr(feature = "unstable_machinery_serde", derive(serde::Serialize))]
+pub struct Trans<'a> {
+    pub singular: String,
+    pub plural: Option<(String, &'a str)>,
+    pub vars: Vec<(&'a str, Expr<'a>)>,
+}
+
 /// A "from" import
 #[cfg_attr(feature = "internal_debug", derive(Debug))]
 #[cfg(feature = "multi_template")]
@@ -418,6 +458,9 @@ pub enum BinOpKind {
     Pow,
     Concat,
     In,
+    /// A custom operator registered with the environment.
+    #[cfg(feature = "custom_operators")]
+    Custom(u32),
 }
 
 /// A binary operator expression.
@@ -1,9 +1,12 @@
 use std::collections::BTreeMap;
+use std::fmt::Write;
 
 use crate::compiler::ast;
 use crate::compiler::instructions::{
-    Instruction, Instructions, LocalId, LOOP_FLAG_RECURSIVE, LOOP_FLAG_WITH_LOOP_VAR, MAX_LOCALS,
+    Instruction, Instructions, LocalId, LOOP_FLAG_PRECOUNT, LOOP_FLAG_RECURSIVE,
+    LOOP_FLAG_WITH_LOOP_VAR, MAX_LOCALS,
 };
+use crate::compiler::meta::find_expr_undeclared;
 use crate::compiler::tokens::Span;
 use crate::output::CaptureMode;
 use crate::value::ops::neg;
@@ -18,6 +21,43 @@ type Caller<'source> = ast::Spanned<ast::Macro<'source>>;
 #[cfg(not(feature = "macros"))]
 type Caller<'source> = std::marker::PhantomData<&'source ()>;
 
+/// Flattens a chain of `~` operators into its operands.
+fn collect_concat_operands<'a, 'source>(
+    expr: &'a ast::Expr<'source>,
+    out: &mut Vec<&'a ast::Expr<'source>>,
+) {
+    match expr {
+        ast::Expr::BinOp(op) if matches!(op.op, ast::BinOpKind::Concat) => {
+            collect_concat_operands(&op.left, out);
+            collect_concat_operands(&op.right, out);
+        }
+        _ => out.push(expr),
+    }
+}
+
+/// Returns the path of a plain variable lookup followed by attribute or
+/// constant item lookups (eg: `user.emails[0]`).
+fn expr_path(expr: &ast::Expr<'_>) -> Option<String> {
+    match expr {
+        ast::Expr::Var(var) => Some(var.id.to_string()),
+        ast::Expr::GetAttr(attr) => {
+            let mut rv = some!(expr_path(&attr.expr));
+            rv.push('.');
+            rv.push_str(attr.name);
+            Some(rv)
+        }
+        ast::Expr::GetItem(item) => match item.subscript_expr {
+            ast::Expr::Const(ref key) => {
+                let mut rv = some!(expr_path(&item.expr));
+                write!(rv, "[{:?}]", key.value).ok();
+                Some(rv)
+            }
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
 /// For the first `MAX_LOCALS` filters/tests, an ID is returned for faster lookups from the stack.
 fn get_local_id<'source>(ids: &mut BTreeMap<&'source str, LocalId>, name: &'source str) -> LocalId {
     if let Some(id) = ids.get(name) {
@@ -115,7 +155,6 @@ impl<'source> CodeGenerator<'source> {
     }
 
     /// Creates a sub generator.
-    #[cfg(feature = "multi_template")]
     fn new_subgenerator(&self) -> CodeGenerator<'source> {
         let mut sub = CodeGenerator::new(self.instructions.name(), self.instructions.source());
         sub.current_line = self.current_line;
@@ -124,7 +163,6 @@ impl<'source> CodeGenerator<'source> {
     }
 
     /// Finishes a sub generator and syncs it back.
-    #[cfg(feature = "multi_template")]
     fn finish_subgenerator(&mut self, sub: CodeGenerator<'source>) -> Instructions<'source> {
         self.current_line = sub.current_line;
         let (instructions, blocks) = sub.finish();
@@ -133,7 +171,7 @@ impl<'source> CodeGenerator<'source> {
     }
 
     /// Starts a for loop
-    pub fn start_for_loop(&mut self, with_loop_var: bool, recursive: bool) {
+    pub fn start_for_loop(&mut self, with_loop_var: bool, recursive: bool, precount: bool) {
         let mut flags = 0;
         if with_loop_var {
             flags |= LOOP_FLAG_WITH_LOOP_VAR;
@@ -141,6 +179,9 @@ impl<'source> CodeGenerator<'source> {
         if recursive {
             flags |= LOOP_FLAG_RECURSIVE;
         }
+        if precount {
+            flags |= LOOP_FLAG_PRECOUNT;
+        }
         self.add(Instruction::PushLoop(flags));
         let instr = self.add(Instruction::Iterate(!0));
         self.pending_block.push(PendingBlock::Loop {
@@ -288,6 +329,19 @@ impl<'source> CodeGenerator<'source> {
                 self.compile_expr(&set.expr);
                 self.compile_assignment(&set.target);
             }
+            ast::Stmt::ConstDecl(c) => {
+                self.set_line_from_span(c.span());
+                let mut sub = self.new_subgenerator();
+                sub.compile_expr(&c.expr);
+                let instructions = self.finish_subgenerator(sub);
+                let mut refs = find_expr_undeclared(&c.expr)
+                    .into_iter()
+                    .collect::<Vec<_>>();
+                refs.sort();
+                let idx = self.instructions.add_const(c.name, refs, instructions);
+                self.add_with_span(Instruction::LoadTemplateConst(idx), c.span());
+                self.add(Instruction::StoreLocal(c.name));
+            }
             ast::Stmt::SetBlock(set_block) => {
                 self.set_line_from_span(set_block.span());
                 self.add(Instruction::BeginCapture(CaptureMode::Capture));
@@ -309,6 +363,15 @@ impl<'source> CodeGenerator<'source> {
                 }
                 self.add(Instruction::PopAutoEscape);
             }
+            ast::Stmt::UndefinedBlock(undefined_block) => {
+                self.set_line_from_span(undefined_block.span());
+                self.compile_expr(&undefined_block.behavior);
+                self.add(Instruction::PushUndefinedBehavior);
+                for node in &undefined_block.body {
+                    self.compile_stmt(node);
+                }
+                self.add(Instruction::PopUndefinedBehavior);
+            }
             ast::Stmt::FilterBlock(filter_block) => {
                 self.set_line_from_span(filter_block.span());
                 self.add(Instruction::BeginCapture(CaptureMode::Capture));
@@ -328,7 +391,7 @@ impl<'source> CodeGenerator<'source> {
                 self.add(Instruction::BeginCapture(CaptureMode::Discard));
                 self.add(Instruction::PushWith);
                 self.compile_expr(&import.expr);
-                self.add_with_span(Instruction::Include(false), import.span());
+                self.add_with_span(Instruction::Include(0), import.span());
                 self.add(Instruction::ExportLocals);
                 self.add(Instruction::PopFrame);
                 self.compile_assignment(&import.name);
@@ -339,7 +402,7 @@ impl<'source> CodeGenerator<'source> {
                 self.add(Instruction::BeginCapture(CaptureMode::Discard));
                 self.add(Instruction::PushWith);
                 self.compile_expr(&from_import.expr);
-                self.add_with_span(Instruction::Include(false), from_import.span());
+                self.add_with_span(Instruction::Include(0), from_import.span());
                 for (name, _) in &from_import.names {
                     self.compile_expr(name);
                 }
@@ -357,9 +420,24 @@ impl<'source> CodeGenerator<'source> {
             }
             #[cfg(feature = "multi_template")]
             ast::Stmt::Include(include) => {
+                use crate::compiler::instructions::{
+                    INCLUDE_IGNORE_ERRORS, INCLUDE_IGNORE_MISSING,
+                };
                 self.set_line_from_span(include.span());
                 self.compile_expr(&include.name);
-                self.add_with_span(Instruction::Include(include.ignore_missing), include.span());
+                let mut flags = 0;
+                if include.ignore_missing {
+                    flags |= INCLUDE_IGNORE_MISSING;
+                }
+                if include.ignore_errors {
+                    flags |= INCLUDE_IGNORE_ERRORS;
+                }
+                if let Some(ref context) = include.context {
+                    self.compile_expr(context);
+                    self.add_with_span(Instruction::IncludeWithContext(flags), include.span());
+                } else {
+                    self.add_with_span(Instruction::Include(flags), include.span());
+                }
             }
             #[cfg(feature = "macros")]
             ast::Stmt::Macro(macro_decl) => {
@@ -397,9 +475,39 @@ impl<'source> CodeGenerator<'source> {
             ast::Stmt::Do(do_tag) => {
                 self.compile_do(do_tag);
             }
+            #[cfg(feature = "i18n")]
+            ast::Stmt::Trans(trans) => {
+                self.compile_trans(trans);
+            }
         }
     }
 
+    #[cfg(feature = "i18n")]
+    fn compile_trans(&mut self, trans: &ast::Spanned<ast::Trans<'source>>) {
+        self.set_line_from_span(trans.span());
+        self.push_span(trans.span());
+        self.add(Instruction::LoadConst(Value::from(trans.singular.as_str())));
+        let mut arg_count = 1;
+        if let Some((ref plural, count)) = trans.plural {
+            self.add(Instruction::LoadConst(Value::from(plural.as_str())));
+            if let Some((_, expr)) = trans.vars.iter().find(|(name, _)| *name == count) {
+                self.compile_expr(expr);
+            }
+            arg_count += 2;
+        }
+        if !trans.vars.is_empty() {
+            for (name, expr) in &trans.vars {
+                self.add(Instruction::LoadConst(Value::from(*name)));
+                self.compile_expr(expr);
+            }
+            self.add(Instruction::BuildKwargs(trans.vars.len()));
+            arg_count += 1;
+        }
+        self.add(Instruction::Translate(trans.plural.is_some(), arg_count));
+        self.add(Instruction::Emit);
+        self.pop_span();
+    }
+
     #[cfg(feature = "multi_template")]
     fn compile_block(&mut self, block: &ast::Spanned<ast::Block<'source>>) {
         self.set_line_from_span(block.span());
@@ -517,7 +625,10 @@ impl<'source> CodeGenerator<'source> {
             }
         }
         self.compile_expr(&expr.expr);
-        self.add(Instruction::Emit);
+        let idx = self.add(Instruction::Emit);
+        if let Some(path) = expr_path(&expr.expr) {
+            self.instructions.set_expr_path(idx, path);
+        }
     }
 
     fn compile_for_loop(&mut self, for_loop: &ast::Spanned<ast::ForLoop<'source>>) {
@@ -530,7 +641,7 @@ impl<'source> CodeGenerator<'source> {
         if let Some(ref filter_expr) = for_loop.filter_expr {
             self.add(Instruction::LoadConst(Value::from(0usize)));
             self.compile_expr(&for_loop.iter);
-            self.start_for_loop(false, false);
+            self.start_for_loop(false, false, false);
             self.add(Instruction::DupTop);
             self.compile_assignment(&for_loop.target);
             self.compile_expr(filter_expr);
@@ -547,7 +658,7 @@ impl<'source> CodeGenerator<'source> {
             self.compile_expr(&for_loop.iter);
         }
 
-        self.start_for_loop(true, for_loop.recursive);
+        self.start_for_loop(true, for_loop.recursive, for_loop.precount);
         self.compile_assignment(&for_loop.target);
         for node in &for_loop.body {
             self.compile_stmt(node);
@@ -578,13 +689,26 @@ impl<'source> CodeGenerator<'source> {
             }
             ast::Expr::GetAttr(attr) => {
                 self.push_span(attr.span());
-                self.compile_expr(&attr.expr);
+                self.compile_namespace_path(&attr.expr);
                 self.add(Instruction::SetAttr(attr.name));
             }
             _ => unreachable!(),
         }
     }
 
+    /// Compiles the target of a dotted assignment.
+    ///
+    /// Intermediate namespaces along the path are created as needed.
+    fn compile_namespace_path(&mut self, expr: &ast::Expr<'source>) {
+        match expr {
+            ast::Expr::GetAttr(attr) => {
+                self.compile_namespace_path(&attr.expr);
+                self.add(Instruction::GetOrCreateNamespace(attr.name));
+            }
+            _ => self.compile_expr(expr),
+        }
+    }
+
     /// Compiles an expression.
     pub fn compile_expr(&mut self, expr: &ast::Expr<'source>) {
         match expr {
@@ -844,8 +968,23 @@ impl<'source> CodeGenerator<'source> {
             ast::BinOpKind::FloorDiv => Instruction::IntDiv,
             ast::BinOpKind::Rem => Instruction::Rem,
             ast::BinOpKind::Pow => Instruction::Pow,
-            ast::BinOpKind::Concat => Instruction::StringConcat,
+            ast::BinOpKind::Concat => {
+                let mut operands = Vec::new();
+                collect_concat_operands(&c.left, &mut operands);
+                collect_concat_operands(&c.right, &mut operands);
+                if operands.len() > 2 {
+                    for operand in &operands {
+                        self.compile_expr(operand);
+                    }
+                    self.add(Instruction::StringConcatMany(operands.len()));
+                    self.pop_span();
+                    return;
+                }
+                Instruction::StringConcat
+            }
             ast::BinOpKind::In => Instruction::In,
+            #[cfg(feature = "custom_operators")]
+            ast::BinOpKind::Custom(id) => Instruction::CustomBinop(id),
         };
         self.compile_expr(&c.left);
         self.compile_expr(&c.right);
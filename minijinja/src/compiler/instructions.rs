@@ -1,8 +1,10 @@
 #[cfg(feature = "internal_debug")]
 use std::fmt;
+use std::sync::Mutex;
 
 use crate::compiler::tokens::Span;
 use crate::output::CaptureMode;
+use crate::utils::CallableIds;
 use crate::value::Value;
 
 /// This loop has the loop var.
@@ -11,6 +13,17 @@ pub const LOOP_FLAG_WITH_LOOP_VAR: u8 = 1;
 /// This loop is recursive.
 pub const LOOP_FLAG_RECURSIVE: u8 = 2;
 
+/// This loop buffers the iterable upfront to establish its length.
+pub const LOOP_FLAG_PRECOUNT: u8 = 4;
+
+/// A missing template is skipped by the include.
+#[cfg(feature = "multi_template")]
+pub const INCLUDE_IGNORE_MISSING: u8 = 1;
+
+/// Errors while rendering the included template are skipped by the include.
+#[cfg(feature = "multi_template")]
+pub const INCLUDE_IGNORE_ERRORS: u8 = 2;
+
 /// This macro uses the caller var.
 #[cfg(feature = "macros")]
 pub const MACRO_CALLER: u8 = 2;
@@ -45,6 +58,9 @@ pub enum Instruction<'source> {
     /// Sets an attribute.
     SetAttr(&'source str),
 
+    /// Looks up an attribute of a namespace, creating a namespace if missing.
+    GetOrCreateNamespace(&'source str),
+
     /// Looks up an item.
     GetItem,
 
@@ -54,6 +70,9 @@ pub enum Instruction<'source> {
     /// Loads a constant value.
     LoadConst(Value),
 
+    /// Loads the value of a template constant, evaluating it on first use.
+    LoadTemplateConst(u32),
+
     /// Builds a map of the last n pairs on the stack.
     BuildMap(usize),
 
@@ -118,9 +137,16 @@ pub enum Instruction<'source> {
     /// String concatenation operator
     StringConcat,
 
+    /// Concatenates the last n values on the stack into a string.
+    StringConcatMany(usize),
+
     /// Performs a containment check
     In,
 
+    /// Applies a custom binary operator.
+    #[cfg(feature = "custom_operators")]
+    CustomBinop(u32),
+
     /// Apply a filter.
     ApplyFilter(&'source str, usize, LocalId),
 
@@ -168,6 +194,12 @@ pub enum Instruction<'source> {
     /// Resets the auto escape flag to the previous value.
     PopAutoEscape,
 
+    /// Sets the undefined behavior to the current value.
+    PushUndefinedBehavior,
+
+    /// Resets the undefined behavior to the previous value.
+    PopUndefinedBehavior,
+
     /// Begins capturing of output (false) or discard (true).
     BeginCapture(CaptureMode),
 
@@ -177,6 +209,11 @@ pub enum Instruction<'source> {
     /// Calls a global function
     CallFunction(&'source str, usize),
 
+    /// Translates a message with the environment's translator.  The flag
+    /// selects the plural form.
+    #[cfg(feature = "i18n")]
+    Translate(bool, usize),
+
     /// Calls a method
     CallMethod(&'source str, usize),
 
@@ -207,8 +244,16 @@ pub enum Instruction<'source> {
     LoadBlocks,
 
     /// Includes another template.
+    ///
+    /// The argument are include flags.
     #[cfg(feature = "multi_template")]
-    Include(bool),
+    Include(u8),
+
+    /// Includes another template with the map on stack as its context.
+    ///
+    /// The argument are include flags.
+    #[cfg(feature = "multi_template")]
+    IncludeWithContext(u8),
 
     /// Builds a module
     #[cfg(feature = "multi_template")]
@@ -235,6 +280,20 @@ pub enum Instruction<'source> {
     GetClosure,
 }
 
+impl<'source> Instruction<'source> {
+    /// Returns `true` if the instruction can write into the output.
+    pub(crate) fn may_emit(&self) -> bool {
+        match self {
+            Instruction::EmitRaw(_) | Instruction::Emit | Instruction::FastSuper => true,
+            #[cfg(feature = "multi_template")]
+            Instruction::CallBlock(_)
+            | Instruction::Include(_)
+            | Instruction::IncludeWithContext(_) => true,
+            _ => false,
+        }
+    }
+}
+
 #[derive(Copy, Clone)]
 struct LineInfo {
     first_instruction: u32,
@@ -248,12 +307,27 @@ struct SpanInfo {
     span: Option<Span>,
 }
 
+/// A constant declared with `{% const %}` in a template.
+///
+/// The value is computed lazily on first use and then cached for as long
+/// as the compiled template is alive.
+pub struct TemplateConst<'source> {
+    pub(crate) name: &'source str,
+    pub(crate) refs: Vec<&'source str>,
+    pub(crate) instructions: Instructions<'source>,
+    pub(crate) value: Mutex<Option<Value>>,
+}
+
 /// Wrapper around instructions to help with location management.
 pub struct Instructions<'source> {
     pub(crate) instructions: Vec<Instruction<'source>>,
     line_infos: Vec<LineInfo>,
     #[cfg(feature = "debug")]
     span_infos: Vec<SpanInfo>,
+    expr_paths: Vec<(u32, String)>,
+    filter_ids: Vec<Option<u32>>,
+    test_ids: Vec<Option<u32>>,
+    consts: Vec<TemplateConst<'source>>,
     name: &'source str,
     source: &'source str,
 }
@@ -263,6 +337,10 @@ pub(crate) static EMPTY_INSTRUCTIONS: Instructions<'static> = Instructions {
     line_infos: Vec::new(),
     #[cfg(feature = "debug")]
     span_infos: Vec::new(),
+    expr_paths: Vec::new(),
+    filter_ids: Vec::new(),
+    test_ids: Vec::new(),
+    consts: Vec::new(),
     name: "<unknown>",
     source: "",
 };
@@ -275,11 +353,81 @@ impl<'source> Instructions<'source> {
             line_infos: Vec::with_capacity(128),
             #[cfg(feature = "debug")]
             span_infos: Vec::with_capacity(128),
+            expr_paths: Vec::new(),
+            filter_ids: Vec::new(),
+            test_ids: Vec::new(),
+            consts: Vec::new(),
             name,
             source,
         }
     }
 
+    /// Resolves the interned ids of all filters and tests with a local id.
+    ///
+    /// Filters and tests not yet known to the interner are left unresolved
+    /// and are looked up by name at runtime.
+    pub(crate) fn resolve_callable_ids(&mut self, ids: &CallableIds) {
+        fn record(table: &mut Vec<Option<u32>>, local_id: LocalId, id: Option<u32>) {
+            if local_id == !0 {
+                return;
+            }
+            let idx = local_id as usize;
+            if table.len() <= idx {
+                table.resize(idx + 1, None);
+            }
+            table[idx] = id;
+        }
+
+        for instr in &self.instructions {
+            match *instr {
+                Instruction::ApplyFilter(name, _, local_id) => {
+                    record(&mut self.filter_ids, local_id, ids.get(name));
+                }
+                Instruction::PerformTest(name, _, local_id) => {
+                    record(&mut self.test_ids, local_id, ids.get(name));
+                }
+                _ => {}
+            }
+        }
+
+        for c in &mut self.consts {
+            c.instructions.resolve_callable_ids(ids);
+        }
+    }
+
+    /// Registers a template constant and returns its index.
+    pub fn add_const(
+        &mut self,
+        name: &'source str,
+        refs: Vec<&'source str>,
+        instructions: Instructions<'source>,
+    ) -> u32 {
+        self.consts.push(TemplateConst {
+            name,
+            refs,
+            instructions,
+            value: Mutex::new(None),
+        });
+        (self.consts.len() - 1) as u32
+    }
+
+    /// Returns all template constants.
+    pub(crate) fn consts(&self) -> &[TemplateConst<'source>] {
+        &self.consts
+    }
+
+    /// Returns the interned id of the filter with the given local id.
+    #[inline(always)]
+    pub(crate) fn filter_id(&self, local_id: LocalId) -> Option<u32> {
+        self.filter_ids.get(local_id as usize).copied().flatten()
+    }
+
+    /// Returns the interned id of the test with the given local id.
+    #[inline(always)]
+    pub(crate) fn test_id(&self, local_id: LocalId) -> Option<u32> {
+        self.test_ids.get(local_id as usize).copied().flatten()
+    }
+
     /// Returns the name of the template.
     pub fn name(&self) -> &'source str {
         self.name
@@ -393,6 +541,24 @@ impl<'source> Instructions<'source> {
         }
     }
 
+    /// Records the path of the expression emitted by an instruction.
+    ///
+    /// Paths must be recorded in instruction order.
+    pub(crate) fn set_expr_path(&mut self, idx: usize, path: String) {
+        self.expr_paths.push((idx as u32, path));
+    }
+
+    /// Looks up the path of the expression emitted by an instruction.
+    ///
+    /// This is only recorded for plain variable lookups followed by attribute
+    /// or constant item lookups (eg: `user.emails[0]`).
+    pub(crate) fn get_expr_path(&self, idx: usize) -> Option<&str> {
+        self.expr_paths
+            .binary_search_by_key(&idx, |x| x.0 as usize)
+            .ok()
+            .map(|idx| self.expr_paths[idx].1.as_str())
+    }
+
     /// Returns a list of all names referenced in the current block backwards
     /// from the given pc.
     #[cfg(feature = "debug")]
@@ -419,6 +585,49 @@ impl<'source> Instructions<'source> {
         rv
     }
 
+    /// Returns the name of the template extended with a constant name.
+    ///
+    /// This looks for the first `extends` in the instructions and only
+    /// returns a name if the template name is a string literal.
+    #[cfg(feature = "multi_template")]
+    pub fn static_parent_name(&self) -> Option<&str> {
+        let idx = self
+            .instructions
+            .iter()
+            .position(|x| matches!(x, Instruction::LoadBlocks))?;
+        match self.instructions[..idx].last() {
+            Some(Instruction::LoadConst(name)) => name.as_str(),
+            _ => None,
+        }
+    }
+
+    /// Iterates over all instructions with their index, line and span.
+    ///
+    /// The index is the program counter that the VM reports to the
+    /// [trace callback](crate::Environment::set_trace_callback) when the
+    /// instruction is executed.  Indices are stable for the lifetime of a
+    /// compiled template: they are assigned once at compile time and are
+    /// never renumbered.  A template that is compiled again (for instance
+    /// after its source changed, or with another version of MiniJinja) can
+    /// produce entirely different instructions, so coverage maps must be
+    /// rebuilt whenever the template is recompiled.
+    ///
+    /// Note that the root template and every block have their own set of
+    /// instructions, each indexed from zero.  Every instruction emitted from
+    /// template source has a line, but only instructions that can fail carry
+    /// a precise span.  The span is always `None` when the `debug` feature is
+    /// disabled.
+    #[cfg(feature = "unstable_machinery")]
+    pub fn iter_with_spans(
+        &self,
+    ) -> impl Iterator<Item = (usize, &Instruction<'source>, Option<usize>, Option<Span>)> + '_
+    {
+        self.instructions
+            .iter()
+            .enumerate()
+            .map(|(idx, instr)| (idx, instr, self.get_line(idx), self.get_span(idx)))
+    }
+
     /// Returns the number of instructions
     pub fn len(&self) -> usize {
         self.instructions.len()
@@ -1,11 +1,16 @@
 use std::borrow::Cow;
-use std::ops::ControlFlow;
+use std::ops::{ControlFlow, Range};
 
 use crate::compiler::tokens::{Span, Token};
 use crate::error::{Error, ErrorKind};
 use crate::syntax::SyntaxConfig;
 use crate::utils::{memchr, memstr, unescape};
 
+#[cfg(feature = "custom_operators")]
+use crate::operators::CustomOperators;
+#[cfg(feature = "custom_operators")]
+use std::sync::Arc;
+
 /// Internal config struct to control whitespace in the engine.
 #[derive(Copy, Clone, Debug, Default)]
 pub struct WhitespaceConfig {
@@ -14,6 +19,49 @@ pub struct WhitespaceConfig {
     pub trim_blocks: bool,
 }
 
+/// Why whitespace was removed from a template.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum TrimReason {
+    /// A `-` on the delimiter of a tag, variable or comment removed it.
+    TrimMarker,
+    /// The newline after a tag was removed because of `trim_blocks`.
+    TrimBlocks,
+    /// The whitespace before a tag on its own line was removed because of
+    /// `lstrip_blocks`.  This also happens for line statements.
+    LstripBlocks,
+    /// The trailing newline of the template was removed because
+    /// `keep_trailing_newline` is not enabled.
+    TrailingNewline,
+}
+
+/// Describes whitespace that was removed from a template when compiling it.
+///
+/// See [`Template::whitespace_report`](crate::Template::whitespace_report).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WhitespaceTrim {
+    reason: TrimReason,
+    range: Range<usize>,
+    line: usize,
+}
+
+impl WhitespaceTrim {
+    /// Returns the reason why the whitespace was removed.
+    pub fn reason(&self) -> TrimReason {
+        self.reason
+    }
+
+    /// Returns the byte range of the removed whitespace in the source.
+    pub fn range(&self) -> Range<usize> {
+        self.range.clone()
+    }
+
+    /// Returns the line (starting at 1) where the removed whitespace starts.
+    pub fn line(&self) -> usize {
+        self.line
+    }
+}
+
 /// Tokenizes jinja templates.
 pub struct Tokenizer<'s> {
     stack: Vec<LexerState>,
@@ -27,6 +75,9 @@ pub struct Tokenizer<'s> {
     paren_balance: isize,
     syntax_config: SyntaxConfig,
     ws_config: WhitespaceConfig,
+    trims: Option<Vec<WhitespaceTrim>>,
+    #[cfg(feature = "custom_operators")]
+    custom_operators: Option<Arc<CustomOperators>>,
 }
 
 enum LexerState {
@@ -316,9 +367,28 @@ impl<'s> Tokenizer<'s> {
             pending_start_marker: None,
             syntax_config,
             ws_config: whitespace_config,
+            trims: None,
+            #[cfg(feature = "custom_operators")]
+            custom_operators: None,
         }
     }
 
+    /// Makes the tokenizer recognize the given custom operators.
+    #[cfg(feature = "custom_operators")]
+    pub(crate) fn set_custom_operators(&mut self, operators: Arc<CustomOperators>) {
+        self.custom_operators = Some(operators);
+    }
+
+    /// Records all whitespace removed from here on.
+    pub fn record_trims(&mut self) {
+        self.trims = Some(Vec::new());
+    }
+
+    /// Returns the recorded trims.
+    pub fn take_trims(&mut self) -> Vec<WhitespaceTrim> {
+        self.trims.take().unwrap_or_default()
+    }
+
     /// Produces the next token from the tokenizer.
     pub fn next_token(&mut self) -> Result<Option<(Token<'s>, Span)>, Error> {
         loop {
@@ -396,6 +466,19 @@ impl<'s> Tokenizer<'s> {
         }
     }
 
+    fn record_trim(&mut self, removed: &str, reason: TrimReason) {
+        if let Some(ref mut trims) = self.trims {
+            if !removed.is_empty() {
+                let start = removed.as_ptr() as usize - self.source.as_ptr() as usize;
+                trims.push(WhitespaceTrim {
+                    reason,
+                    range: start..start + removed.len(),
+                    line: self.source[..start].matches('\n').count() + 1,
+                });
+            }
+        }
+    }
+
     #[inline]
     fn syntax_error(&mut self, msg: &'static str) -> Error {
         Error::new(ErrorKind::SyntaxError, msg)
@@ -525,25 +608,26 @@ impl<'s> Tokenizer<'s> {
         })
     }
 
-    fn skip_whitespace(&mut self) {
+    fn skip_whitespace(&mut self) -> &'s str {
         let skipped = self
             .rest()
             .chars()
             .map_while(|c| c.is_whitespace().then(|| c.len_utf8()))
             .sum();
-        if skipped > 0 {
-            self.advance(skipped);
-        }
+        self.advance(skipped)
     }
 
     fn skip_newline_if_trim_blocks(&mut self) {
         if self.ws_config.trim_blocks {
+            let mut skip = 0;
             if self.rest_bytes().get(0) == Some(&b'\r') {
-                self.advance(1);
+                skip += 1;
             }
-            if self.rest_bytes().get(0) == Some(&b'\n') {
-                self.advance(1);
+            if self.rest_bytes().get(skip) == Some(&b'\n') {
+                skip += 1;
             }
+            let skipped = self.advance(skip);
+            self.record_trim(skipped, TrimReason::TrimBlocks);
         }
     }
 
@@ -581,7 +665,8 @@ impl<'s> Tokenizer<'s> {
         }
         if self.trim_leading_whitespace {
             self.trim_leading_whitespace = false;
-            self.skip_whitespace();
+            let skipped = self.skip_whitespace();
+            self.record_trim(skipped, TrimReason::TrimMarker);
         }
         let old_loc = self.loc();
         let (lead, span) =
@@ -596,7 +681,8 @@ impl<'s> Tokenizer<'s> {
                             let trimmed = lstrip_block(peeked);
                             let lead = self.advance(trimmed.len());
                             let span = self.span(old_loc);
-                            self.advance(peeked.len() - trimmed.len());
+                            let skipped = self.advance(peeked.len() - trimmed.len());
+                            self.record_trim(skipped, TrimReason::LstripBlocks);
                             (lead, span)
                         }
                         Whitespace::Default | Whitespace::Preserve => {
@@ -607,7 +693,8 @@ impl<'s> Tokenizer<'s> {
                             let trimmed = peeked.trim_end();
                             let lead = self.advance(trimmed.len());
                             let span = self.span(old_loc);
-                            self.advance(peeked.len() - trimmed.len());
+                            let skipped = self.advance(peeked.len() - trimmed.len());
+                            self.record_trim(skipped, TrimReason::TrimMarker);
                             (lead, span)
                         }
                     }
@@ -703,7 +790,8 @@ impl<'s> Tokenizer<'s> {
                 self.advance(end);
                 let span = self.span(old_loc);
                 self.advance(self.block_start().len() + endraw);
-                match ws_start {
+                let untrimmed = result;
+                let start_reason = match ws_start {
                     Whitespace::Default if self.ws_config.trim_blocks => {
                         if result.starts_with('\r') {
                             result = &result[1..];
@@ -711,17 +799,28 @@ impl<'s> Tokenizer<'s> {
                         if result.starts_with('\n') {
                             result = &result[1..];
                         }
+                        TrimReason::TrimBlocks
                     }
                     Whitespace::Remove => {
                         result = result.trim_start();
+                        TrimReason::TrimMarker
                     }
-                    _ => {}
-                }
-                result = match ws {
-                    Whitespace::Default if self.ws_config.lstrip_blocks => lstrip_block(result),
-                    Whitespace::Remove => result.trim_end(),
-                    _ => result,
+                    _ => TrimReason::TrimMarker,
+                };
+                self.record_trim(&untrimmed[..untrimmed.len() - result.len()], start_reason);
+                let untrimmed = result;
+                let end_reason = match ws {
+                    Whitespace::Default if self.ws_config.lstrip_blocks => {
+                        result = lstrip_block(result);
+                        TrimReason::LstripBlocks
+                    }
+                    Whitespace::Remove => {
+                        result = result.trim_end();
+                        TrimReason::TrimMarker
+                    }
+                    _ => TrimReason::TrimMarker,
                 };
+                self.record_trim(&untrimmed[result.len()..], end_reason);
                 self.handle_tail_ws(ws_next);
                 return Ok(ControlFlow::Break((Token::TemplateData(result), span)));
             }
@@ -822,6 +921,17 @@ impl<'s> Tokenizer<'s> {
             BlockSentinel::LineStatement => {}
         }
 
+        // custom operators, unless a built-in operator is at least as long
+        #[cfg(feature = "custom_operators")]
+        if let Some(ref operators) = self.custom_operators {
+            if let Some((id, op)) = operators.find_prefix(rest, builtin_operator_len(rest)) {
+                let len = op.symbol.len();
+                let tok = Token::CustomOperator(&rest[..len], id, op.precedence);
+                self.advance(len);
+                return Ok(ControlFlow::Break((tok, self.span(old_loc))));
+            }
+        }
+
         // two character operators
         let op = match rest.as_bytes().get(..2) {
             Some(b"//") => Some(Token::FloorDiv),
@@ -887,6 +997,43 @@ impl<'s> Tokenizer<'s> {
     }
 }
 
+/// Returns the length of the built-in operator at the start of `rest`.
+#[cfg(feature = "custom_operators")]
+fn builtin_operator_len(rest: &str) -> usize {
+    match rest.as_bytes() {
+        [b'/', b'/', ..]
+        | [b'*', b'*', ..]
+        | [b'=', b'=', ..]
+        | [b'!', b'=', ..]
+        | [b'>', b'=', ..]
+        | [b'<', b'=', ..] => 2,
+        [b'+' | b'-' | b'*' | b'/' | b'%' | b'!' | b'.' | b',' | b':' | b'~' | b'|' | b'='
+        | b'>' | b'<', ..] => 1,
+        _ => 0,
+    }
+}
+
+/// Returns all whitespace that is removed when compiling a template.
+pub fn whitespace_trims(
+    input: &str,
+    syntax_config: SyntaxConfig,
+    whitespace_config: WhitespaceConfig,
+) -> Result<Vec<WhitespaceTrim>, Error> {
+    let mut tokenizer = Tokenizer::new(input, false, syntax_config, whitespace_config);
+    tokenizer.record_trims();
+    while ok!(tokenizer.next_token()).is_some() {}
+    let mut rv = tokenizer.take_trims();
+    let kept = tokenizer.source.len();
+    if kept < input.len() {
+        rv.push(WhitespaceTrim {
+            reason: TrimReason::TrailingNewline,
+            range: kept..input.len(),
+            line: input[..kept].matches('\n').count() + 1,
+        });
+    }
+    Ok(rv)
+}
+
 /// Utility function to quickly tokenize into an iterator.
 #[cfg(any(test, feature = "unstable_machinery"))]
 pub fn tokenize(
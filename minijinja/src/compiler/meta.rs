@@ -1,7 +1,8 @@
-use std::collections::HashSet;
+use std::collections::{BTreeSet, HashSet};
 use std::fmt::Write;
 
 use crate::compiler::ast;
+use crate::error::{Error, ErrorKind};
 
 struct AssignmentTracker<'a> {
     out: HashSet<&'a str>,
@@ -66,6 +67,89 @@ pub fn find_undeclared(t: &ast::Stmt<'_>, track_nested: bool) -> HashSet<String>
     }
 }
 
+/// Finds all variables that are referenced by an expression.
+pub fn find_expr_undeclared<'a>(expr: &ast::Expr<'a>) -> HashSet<&'a str> {
+    let mut state = AssignmentTracker {
+        out: HashSet::new(),
+        nested_out: None,
+        assigned: vec![Default::default()],
+    };
+    tracker_visit_expr(expr, &mut state);
+    state.out
+}
+
+/// Ensures that all `const` declarations of a template are pure.
+///
+/// A const expression is evaluated once without a render context, so it may
+/// only refer to consts declared earlier and to globals of the environment.
+/// Names the template assigns itself on the top level are rejected as well as
+/// they would shadow the global for everything but the const.
+pub fn check_const_decls(
+    t: &ast::Stmt<'_>,
+    filename: &str,
+    global_names: &BTreeSet<String>,
+) -> Result<(), Error> {
+    let children = match t {
+        ast::Stmt::Template(t) => &t.children,
+        _ => return Ok(()),
+    };
+    if !children
+        .iter()
+        .any(|x| matches!(x, ast::Stmt::ConstDecl(_)))
+    {
+        return Ok(());
+    }
+
+    // with nested tracking enabled lookups are not recorded as assignments
+    let mut state = AssignmentTracker {
+        out: HashSet::new(),
+        nested_out: Some(HashSet::new()),
+        assigned: vec![Default::default()],
+    };
+    for child in children {
+        match child {
+            ast::Stmt::ConstDecl(_) => {}
+            // macro arguments are local to the macro
+            #[cfg(feature = "macros")]
+            ast::Stmt::Macro(m) => state.assign(m.name),
+            _ => track_walk(child, &mut state),
+        }
+    }
+    let template_vars = state.assigned.pop().unwrap_or_default();
+
+    let mut consts = HashSet::new();
+    for child in children {
+        let c = match child {
+            ast::Stmt::ConstDecl(c) => c,
+            _ => continue,
+        };
+        let mut refs = find_expr_undeclared(&c.expr)
+            .into_iter()
+            .collect::<Vec<_>>();
+        refs.sort();
+        for name in refs {
+            let reason = if template_vars.contains(name) {
+                "a template variable"
+            } else if consts.contains(name) || global_names.contains(name) {
+                continue;
+            } else {
+                "neither a constant nor a global"
+            };
+            let mut err = Error::new(
+                ErrorKind::SyntaxError,
+                format!(
+                    "const {:?} references {:?} which is {}",
+                    c.name, name, reason
+                ),
+            );
+            err.set_filename_and_span(filename, c.span());
+            return Err(err);
+        }
+        consts.insert(c.name);
+    }
+    Ok(())
+}
+
 fn tracker_visit_expr_opt<'a>(expr: &Option<ast::Expr<'a>>, state: &mut AssignmentTracker<'a>) {
     if let Some(expr) = expr {
         tracker_visit_expr(expr, state);
@@ -226,11 +310,21 @@ fn track_walk<'a>(node: &ast::Stmt<'a>, state: &mut AssignmentTracker<'a>) {
             stmt.body.iter().for_each(|x| track_walk(x, state));
             state.pop();
         }
+        ast::Stmt::UndefinedBlock(stmt) => {
+            tracker_visit_expr(&stmt.behavior, state);
+            state.push();
+            stmt.body.iter().for_each(|x| track_walk(x, state));
+            state.pop();
+        }
         ast::Stmt::FilterBlock(stmt) => {
             state.push();
             stmt.body.iter().for_each(|x| track_walk(x, state));
             state.pop();
         }
+        ast::Stmt::ConstDecl(stmt) => {
+            tracker_visit_expr(&stmt.expr, state);
+            state.assign(stmt.name);
+        }
         ast::Stmt::SetBlock(stmt) => {
             track_assign(&stmt.target, state);
             state.push();
@@ -245,7 +339,13 @@ fn track_walk<'a>(node: &ast::Stmt<'a>, state: &mut AssignmentTracker<'a>) {
             state.pop();
         }
         #[cfg(feature = "multi_template")]
-        ast::Stmt::Extends(_) | ast::Stmt::Include(_) => {}
+        ast::Stmt::Extends(_) => {}
+        #[cfg(feature = "multi_template")]
+        ast::Stmt::Include(stmt) => {
+            if let Some(ref context) = stmt.context {
+                tracker_visit_expr(context, state);
+            }
+        }
         #[cfg(feature = "multi_template")]
         ast::Stmt::Import(stmt) => {
             track_assign(&stmt.name, state);
@@ -277,5 +377,11 @@ fn track_walk<'a>(node: &ast::Stmt<'a>, state: &mut AssignmentTracker<'a>) {
                 .iter()
                 .for_each(|x| tracker_visit_expr(x, state));
         }
+        #[cfg(feature = "i18n")]
+        ast::Stmt::Trans(stmt) => {
+            stmt.vars
+                .iter()
+                .for_each(|(_, expr)| tracker_visit_expr(expr, state));
+        }
     }
 }
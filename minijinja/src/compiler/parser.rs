@@ -9,6 +9,11 @@ use crate::error::{Error, ErrorKind};
 use crate::syntax::SyntaxConfig;
 use crate::value::Value;
 
+#[cfg(feature = "custom_operators")]
+use crate::operators::{CustomOperators, OperatorPrecedence};
+#[cfg(feature = "custom_operators")]
+use std::sync::Arc;
+
 const MAX_RECURSION: usize = 150;
 const RESERVED_NAMES: [&str; 8] = [
     "true", "True", "false", "False", "none", "None", "loop", "self",
@@ -169,6 +174,7 @@ struct Parser<'a> {
     in_loop: bool,
     #[allow(unused)]
     blocks: BTreeSet<&'a str>,
+    consts: BTreeSet<&'a str>,
     depth: usize,
 }
 
@@ -240,6 +246,7 @@ impl<'a> Parser<'a> {
             in_macro: false,
             in_loop: false,
             blocks: BTreeSet::new(),
+            consts: BTreeSet::new(),
             depth: 0,
         }
     }
@@ -294,6 +301,10 @@ impl<'a> Parser<'a> {
                 Some((Token::Gt, _)) => ast::BinOpKind::Gt,
                 Some((Token::Gte, _)) => ast::BinOpKind::Gte,
                 Some((Token::Ident("in"), _)) => ast::BinOpKind::In,
+                #[cfg(feature = "custom_operators")]
+                Some((Token::CustomOperator(_, id, OperatorPrecedence::Comparison), _)) => {
+                    ast::BinOpKind::Custom(*id)
+                }
                 Some((Token::Ident("not"), _)) => {
                     ok!(self.stream.next());
                     expect_token!(self, Token::Ident("in"), "in");
@@ -330,6 +341,10 @@ impl<'a> Parser<'a> {
     binop!(parse_math1, parse_concat, {
         Some((Token::Plus, _)) => ast::BinOpKind::Add,
         Some((Token::Minus, _)) => ast::BinOpKind::Sub,
+        #[cfg(feature = "custom_operators")]
+        Some((Token::CustomOperator(_, id, OperatorPrecedence::Additive), _)) => {
+            ast::BinOpKind::Custom(*id)
+        }
     });
     binop!(parse_concat, parse_math2, {
         Some((Token::Tilde, _)) => ast::BinOpKind::Concat,
@@ -339,9 +354,17 @@ impl<'a> Parser<'a> {
         Some((Token::Div, _)) => ast::BinOpKind::Div,
         Some((Token::FloorDiv, _)) => ast::BinOpKind::FloorDiv,
         Some((Token::Mod, _)) => ast::BinOpKind::Rem,
+        #[cfg(feature = "custom_operators")]
+        Some((Token::CustomOperator(_, id, OperatorPrecedence::Multiplicative), _)) => {
+            ast::BinOpKind::Custom(*id)
+        }
     });
     binop!(parse_pow, parse_unary, {
         Some((Token::Pow, _)) => ast::BinOpKind::Pow,
+        #[cfg(feature = "custom_operators")]
+        Some((Token::CustomOperator(_, id, OperatorPrecedence::Power), _)) => {
+            ast::BinOpKind::Custom(*id)
+        }
     });
     unaryop!(parse_unary_only, parse_primary, {
         Some((Token::Minus, _)) => ast::UnaryOpKind::Neg,
@@ -680,6 +703,7 @@ impl<'a> Parser<'a> {
                 SetParseResult::SetBlock(rv) => ast::Stmt::SetBlock(respan!(rv)),
             },
             "autoescape" => ast::Stmt::AutoEscape(respan!(ok!(self.parse_auto_escape()))),
+            "undefined" => ast::Stmt::UndefinedBlock(respan!(ok!(self.parse_undefined_block()))),
             "filter" => ast::Stmt::FilterBlock(respan!(ok!(self.parse_filter_block()))),
             #[cfg(feature = "multi_template")]
             "block" => ast::Stmt::Block(respan!(ok!(self.parse_block()))),
@@ -710,6 +734,9 @@ impl<'a> Parser<'a> {
                 ast::Stmt::Break(respan!(ast::Break))
             }
             "do" => ast::Stmt::Do(respan!(ok!(self.parse_do()))),
+            "const" => ast::Stmt::ConstDecl(respan!(ok!(self.parse_const()))),
+            #[cfg(feature = "i18n")]
+            "trans" => ast::Stmt::Trans(respan!(ok!(self.parse_trans()))),
             name => syntax_error!("unknown statement {}", name),
         })
     }
@@ -784,7 +811,17 @@ impl<'a> Parser<'a> {
         } else {
             None
         };
-        let recursive = skip_token!(self, Token::Ident("recursive"));
+        let mut recursive = false;
+        let mut precount = false;
+        loop {
+            if !recursive && skip_token!(self, Token::Ident("recursive")) {
+                recursive = true;
+            } else if !precount && skip_token!(self, Token::Ident("precount")) {
+                precount = true;
+            } else {
+                break;
+            }
+        }
         expect_token!(self, Token::BlockEnd, "end of block");
         let body = ok!(self.subparse(&|tok| matches!(tok, Token::Ident("endfor" | "else"))));
         let else_body = if skip_token!(self, Token::Ident("else")) {
@@ -800,6 +837,7 @@ impl<'a> Parser<'a> {
             iter,
             filter_expr,
             recursive,
+            precount,
             body,
             else_body,
         })
@@ -923,6 +961,14 @@ impl<'a> Parser<'a> {
         Ok(ast::AutoEscape { enabled, body })
     }
 
+    fn parse_undefined_block(&mut self) -> Result<ast::UndefinedBlock<'a>, Error> {
+        let behavior = ok!(self.parse_expr());
+        expect_token!(self, Token::BlockEnd, "end of block");
+        let body = ok!(self.subparse(&|tok| matches!(tok, Token::Ident("endundefined"))));
+        ok!(self.stream.next());
+        Ok(ast::UndefinedBlock { behavior, body })
+    }
+
     fn parse_filter_chain(&mut self) -> Result<ast::Expr<'a>, Error> {
         let mut filter = None;
 
@@ -966,28 +1012,43 @@ impl<'a> Parser<'a> {
     #[cfg(feature = "multi_template")]
     fn parse_include(&mut self) -> Result<ast::Include<'a>, Error> {
         let name = ok!(self.parse_expr());
-
-        // with/without context is without meaning in MiniJinja, but for syntax
-        // compatibility it's supported.
-        if skip_token!(self, Token::Ident("without" | "with")) {
-            expect_token!(self, Token::Ident("context"), "missing keyword");
-        }
-
-        let ignore_missing = if skip_token!(self, Token::Ident("ignore")) {
-            expect_token!(self, Token::Ident("missing"), "missing keyword");
-            if skip_token!(self, Token::Ident("without" | "with")) {
-                expect_token!(self, Token::Ident("context"), "missing keyword");
+        let mut context = ok!(self.parse_include_context());
+        let mut ignore_missing = false;
+        let mut ignore_errors = false;
+        while skip_token!(self, Token::Ident("ignore")) {
+            if skip_token!(self, Token::Ident("errors")) {
+                ignore_errors = true;
+            } else {
+                expect_token!(self, Token::Ident("missing"), "missing or errors keyword");
+                ignore_missing = true;
             }
-            true
-        } else {
-            false
-        };
+        }
+        if context.is_none() && (ignore_missing || ignore_errors) {
+            context = ok!(self.parse_include_context());
+        }
         Ok(ast::Include {
             name,
+            context,
             ignore_missing,
+            ignore_errors,
         })
     }
 
+    #[cfg(feature = "multi_template")]
+    fn parse_include_context(&mut self) -> Result<Option<ast::Expr<'a>>, Error> {
+        // with/without context is without meaning in MiniJinja, but for syntax
+        // compatibility it's supported.  `with` followed by any other
+        // expression provides the variables of the included template.
+        if skip_token!(self, Token::Ident("without")) {
+            expect_token!(self, Token::Ident("context"), "missing keyword");
+        } else if skip_token!(self, Token::Ident("with"))
+            && !skip_token!(self, Token::Ident("context"))
+        {
+            return Ok(Some(ok!(self.parse_expr())));
+        }
+        Ok(None)
+    }
+
     #[cfg(feature = "multi_template")]
     fn parse_import(&mut self) -> Result<ast::Import<'a>, Error> {
         let expr = ok!(self.parse_expr());
@@ -1106,6 +1167,24 @@ impl<'a> Parser<'a> {
         })
     }
 
+    fn parse_const(&mut self) -> Result<ast::ConstDecl<'a>, Error> {
+        // statements are parsed with the recursion guard held, so a depth
+        // of one means we are at the top level of the template.
+        if self.depth != 1 {
+            syntax_error!("const statements are only allowed at the top level of a template");
+        }
+        let (name, _) = expect_token!(self, Token::Ident(name) => name, "identifier");
+        if RESERVED_NAMES.contains(&name) {
+            syntax_error!("cannot assign to reserved variable name {}", name);
+        }
+        if !self.consts.insert(name) {
+            syntax_error!("const '{}' defined twice", name);
+        }
+        expect_token!(self, Token::Assign, "assignment operator");
+        let expr = ok!(self.parse_expr());
+        Ok(ast::ConstDecl { name, expr })
+    }
+
     fn parse_do(&mut self) -> Result<ast::Do<'a>, Error> {
         let call = match ok!(self.parse_expr()) {
             ast::Expr::Call(call) => call,
@@ -1117,6 +1196,102 @@ impl<'a> Parser<'a> {
         Ok(ast::Do { call })
     }
 
+    #[cfg(feature = "i18n")]
+    fn parse_trans(&mut self) -> Result<ast::Trans<'a>, Error> {
+        let mut vars = Vec::new();
+        let mut trimmed = false;
+
+        while !matches_token!(self, Token::BlockEnd) {
+            if !vars.is_empty() {
+                expect_token!(self, Token::Comma, "`,`");
+            }
+            let (name, span) = expect_token!(self, Token::Ident(name) => name, "identifier");
+            if skip_token!(self, Token::Assign) {
+                vars.push((name, ok!(self.parse_expr())));
+            } else if name == "trimmed" || name == "notrimmed" {
+                trimmed = name == "trimmed";
+            } else {
+                vars.push((
+                    name,
+                    ast::Expr::Var(Spanned::new(ast::Var { id: name }, span)),
+                ));
+            }
+        }
+        expect_token!(self, Token::BlockEnd, "end of block");
+
+        let mut singular = String::new();
+        let mut plural = None::<(String, Option<&'a str>)>;
+        loop {
+            let buf = match plural {
+                Some((ref mut buf, _)) => buf,
+                None => &mut singular,
+            };
+            match ok!(self.stream.next()) {
+                Some((Token::TemplateData(raw), _)) => buf.push_str(&raw.replace('%', "%%")),
+                Some((Token::VariableStart, _)) => {
+                    let (name, span) =
+                        expect_token!(self, Token::Ident(name) => name, "identifier");
+                    expect_token!(self, Token::VariableEnd, "end of variable block");
+                    buf.push_str("%(");
+                    buf.push_str(name);
+                    buf.push_str(")s");
+                    if !vars.iter().any(|(x, _)| *x == name) {
+                        vars.push((
+                            name,
+                            ast::Expr::Var(Spanned::new(ast::Var { id: name }, span)),
+                        ));
+                    }
+                }
+                Some((Token::BlockStart, _)) => match ok!(self.stream.next()) {
+                    Some((Token::Ident("endtrans"), _)) => break,
+                    Some((Token::Ident("pluralize"), _)) if plural.is_none() => {
+                        let count = if matches_token!(self, Token::Ident(_)) {
+                            let (name, span) =
+                                expect_token!(self, Token::Ident(name) => name, "identifier");
+                            if !vars.iter().any(|(x, _)| *x == name) {
+                                vars.push((
+                                    name,
+                                    ast::Expr::Var(Spanned::new(ast::Var { id: name }, span)),
+                                ));
+                            }
+                            Some(name)
+                        } else {
+                            None
+                        };
+                        expect_token!(self, Token::BlockEnd, "end of block");
+                        plural = Some((String::new(), count));
+                    }
+                    _ => syntax_error!("control structures in translation blocks are not allowed"),
+                },
+                Some(_) => unreachable!("lexer produced garbage"),
+                None => syntax_error!("unexpected end of input, expected endtrans"),
+            }
+        }
+
+        if trimmed {
+            singular = trim_translation_whitespace(&singular);
+        }
+        let plural = match plural {
+            Some((mut plural, count)) => {
+                if trimmed {
+                    plural = trim_translation_whitespace(&plural);
+                }
+                let count = match count.or_else(|| vars.first().map(|(name, _)| *name)) {
+                    Some(count) => count,
+                    None => syntax_error!("pluralize requires a count variable"),
+                };
+                Some((plural, count))
+            }
+            None => None,
+        };
+
+        Ok(ast::Trans {
+            singular,
+            plural,
+            vars,
+        })
+    }
+
     fn subparse(
         &mut self,
         end_check: &dyn Fn(&Token) -> bool,
@@ -1163,14 +1338,48 @@ impl<'a> Parser<'a> {
     }
 }
 
+/// Collapses the whitespace of a translation string for `{% trans trimmed %}`.
+#[cfg(feature = "i18n")]
+fn trim_translation_whitespace(s: &str) -> String {
+    s.trim()
+        .lines()
+        .map(|line| line.trim())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
 /// Parses a template.
+#[cfg_attr(feature = "custom_operators", allow(unused))]
 pub fn parse<'source>(
     source: &'source str,
     filename: &str,
     syntax_config: SyntaxConfig,
     whitespace_config: WhitespaceConfig,
+) -> Result<ast::Stmt<'source>, Error> {
+    parse_template(
+        Parser::new(source, false, syntax_config, whitespace_config),
+        filename,
+    )
+}
+
+/// Parses a template that may use the given custom operators.
+#[cfg(feature = "custom_operators")]
+pub(crate) fn parse_with_operators<'source>(
+    source: &'source str,
+    filename: &str,
+    syntax_config: SyntaxConfig,
+    whitespace_config: WhitespaceConfig,
+    operators: Arc<CustomOperators>,
 ) -> Result<ast::Stmt<'source>, Error> {
     let mut parser = Parser::new(source, false, syntax_config, whitespace_config);
+    parser.stream.tokenizer.set_custom_operators(operators);
+    parse_template(parser, filename)
+}
+
+fn parse_template<'source>(
+    mut parser: Parser<'source>,
+    filename: &str,
+) -> Result<ast::Stmt<'source>, Error> {
     parser.parse().map_err(|mut err| {
         if err.line().is_none() {
             err.set_filename_and_span(filename, parser.stream.last_span())
@@ -1180,8 +1389,28 @@ pub fn parse<'source>(
 }
 
 /// Parses an expression
+#[cfg_attr(feature = "custom_operators", allow(unused))]
 pub fn parse_expr(source: &str) -> Result<ast::Expr<'_>, Error> {
+    parse_expression(Parser::new(
+        source,
+        true,
+        Default::default(),
+        Default::default(),
+    ))
+}
+
+/// Parses an expression that may use the given custom operators.
+#[cfg(feature = "custom_operators")]
+pub(crate) fn parse_expr_with_operators(
+    source: &str,
+    operators: Arc<CustomOperators>,
+) -> Result<ast::Expr<'_>, Error> {
     let mut parser = Parser::new(source, true, Default::default(), Default::default());
+    parser.stream.tokenizer.set_custom_operators(operators);
+    parse_expression(parser)
+}
+
+fn parse_expression(mut parser: Parser<'_>) -> Result<ast::Expr<'_>, Error> {
     parser
         .parse_expr()
         .and_then(|result| {
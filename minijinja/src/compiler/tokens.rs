@@ -82,6 +82,9 @@ pub enum Token<'a> {
     BraceOpen,
     /// Close Brace
     BraceClose,
+    /// A custom binary operator registered with the environment.
+    #[cfg(feature = "custom_operators")]
+    CustomOperator(&'a str, u32, crate::operators::OperatorPrecedence),
 }
 
 impl<'a> fmt::Display for Token<'a> {
@@ -122,6 +125,8 @@ impl<'a> fmt::Display for Token<'a> {
             Token::ParenClose => f.write_str("`)`"),
             Token::BraceOpen => f.write_str("`{{`"),
             Token::BraceClose => f.write_str("`}}`"),
+            #[cfg(feature = "custom_operators")]
+            Token::CustomOperator(symbol, _, _) => write!(f, "`{symbol}`"),
         }
     }
 }
@@ -3,7 +3,13 @@ use std::fmt;
 
 use crate::compiler::tokens::Span;
 use crate::error::ErrorKind;
-use crate::value::Value;
+use crate::value::{Value, ValueKind};
+
+/// Collections with more items than this are summarized in debug info.
+const MAX_COLLECTION_ITEMS: usize = 20;
+
+/// Strings longer than this (in chars) are truncated in debug info.
+const MAX_STRING_CHARS: usize = 200;
 
 /// This is a snapshot of the debug information.
 #[cfg_attr(docsrs, doc(cfg(feature = "debug")))]
@@ -11,6 +17,7 @@ use crate::value::Value;
 pub(crate) struct DebugInfo {
     pub(crate) template_source: Option<String>,
     pub(crate) referenced_locals: BTreeMap<String, Value>,
+    pub(crate) locals: BTreeMap<String, Value>,
 }
 
 struct VarPrinter<'x>(&'x BTreeMap<String, Value>);
@@ -36,6 +43,30 @@ impl DebugInfo {
     }
 }
 
+/// Replaces values that are too large to retain in debug info with a summary.
+///
+/// Large sequences and maps are replaced with a string describing their size,
+/// long strings are truncated.
+pub(crate) fn summarize_value(value: Value) -> Value {
+    match value.kind() {
+        ValueKind::Seq | ValueKind::Map | ValueKind::Iterable => match value.len() {
+            Some(len) if len > MAX_COLLECTION_ITEMS => {
+                Value::from(format!("<{} of {} items>", value.kind(), len))
+            }
+            _ => value,
+        },
+        ValueKind::String => match value.as_str() {
+            Some(s) if s.chars().count() > MAX_STRING_CHARS => {
+                let mut rv = s.chars().take(MAX_STRING_CHARS).collect::<String>();
+                rv.push_str("...");
+                Value::from(rv)
+            }
+            _ => value,
+        },
+        _ => value,
+    }
+}
+
 pub(super) fn render_debug_info(
     f: &mut fmt::Formatter,
     name: Option<&str>,
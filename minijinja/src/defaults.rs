@@ -38,7 +38,10 @@ pub fn default_auto_escape_callback(name: &str) -> AutoEscape {
 /// This formatter takes a value and directly writes it into the output format
 /// while honoring the requested auto escape format of the state.  If the
 /// value is already marked as safe, it's handled as if no auto escaping
-/// was requested.
+/// was requested.  Safe strings carry no information about which format
+/// they were escaped for, so filters producing format specific output
+/// should use [`State::safe_string_for`] to only mark their return value
+/// as safe when it matches the current auto escape mode.
 ///
 /// * [`Html`](AutoEscape::Html): performs HTML escaping
 #[cfg_attr(
@@ -71,21 +74,39 @@ pub(crate) fn get_builtin_filters() -> BTreeMap<Cow<'static, str>, filters::Boxe
         rv.insert("items".into(), BoxedFilter::new(filters::items));
         rv.insert("reverse".into(), BoxedFilter::new(filters::reverse));
         rv.insert("trim".into(), BoxedFilter::new(filters::trim));
+        rv.insert("striptags".into(), BoxedFilter::new(filters::striptags));
+        rv.insert("nl2br".into(), BoxedFilter::new(filters::nl2br));
         rv.insert("join".into(), BoxedFilter::new(filters::join));
         rv.insert("split".into(), BoxedFilter::new(filters::split));
         rv.insert("lines".into(), BoxedFilter::new(filters::lines));
-        rv.insert("default".into(), BoxedFilter::new(filters::default));
+        rv.insert("wordwrap".into(), BoxedFilter::new(filters::wordwrap));
+        rv.insert("truncate".into(), BoxedFilter::new(filters::truncate));
+        rv.insert("format".into(), BoxedFilter::new(filters::format));
+        rv.insert("b64encode".into(), BoxedFilter::new(filters::b64encode));
+        rv.insert("b64decode".into(), BoxedFilter::new(filters::b64decode));
+        rv.insert("hex".into(), BoxedFilter::new(filters::hex));
+        let default = BoxedFilter::new(filters::default).handling_undefined();
+        rv.insert("default".into(), default.clone());
+        rv.insert("d".into(), default);
         rv.insert("round".into(), BoxedFilter::new(filters::round));
         rv.insert("abs".into(), BoxedFilter::new(filters::abs));
         rv.insert("int".into(), BoxedFilter::new(filters::int));
         rv.insert("float".into(), BoxedFilter::new(filters::float));
+        rv.insert(
+            "filesizeformat".into(),
+            BoxedFilter::new(filters::filesizeformat),
+        );
+        rv.insert(
+            "humanize_duration".into(),
+            BoxedFilter::new(filters::humanize_duration),
+        );
         rv.insert("attr".into(), BoxedFilter::new(filters::attr));
         rv.insert("first".into(), BoxedFilter::new(filters::first));
         rv.insert("last".into(), BoxedFilter::new(filters::last));
         rv.insert("min".into(), BoxedFilter::new(filters::min));
         rv.insert("max".into(), BoxedFilter::new(filters::max));
+        rv.insert("sum".into(), BoxedFilter::new(filters::sum));
         rv.insert("sort".into(), BoxedFilter::new(filters::sort));
-        rv.insert("d".into(), BoxedFilter::new(filters::default));
         rv.insert("list".into(), BoxedFilter::new(filters::list));
         rv.insert("bool".into(), BoxedFilter::new(filters::bool));
         rv.insert("batch".into(), BoxedFilter::new(filters::batch));
@@ -98,6 +119,7 @@ pub(crate) fn get_builtin_filters() -> BTreeMap<Cow<'static, str>, filters::Boxe
         rv.insert("map".into(), BoxedFilter::new(filters::map));
         rv.insert("groupby".into(), BoxedFilter::new(filters::groupby));
         rv.insert("unique".into(), BoxedFilter::new(filters::unique));
+        rv.insert("set".into(), BoxedFilter::new(filters::set));
         rv.insert("pprint".into(), BoxedFilter::new(filters::pprint));
 
         #[cfg(feature = "json")]
@@ -115,8 +137,14 @@ pub(crate) fn get_builtin_filters() -> BTreeMap<Cow<'static, str>, filters::Boxe
 
 pub(crate) fn get_builtin_tests() -> BTreeMap<Cow<'static, str>, BoxedTest> {
     let mut rv = BTreeMap::new();
-    rv.insert("undefined".into(), BoxedTest::new(tests::is_undefined));
-    rv.insert("defined".into(), BoxedTest::new(tests::is_defined));
+    rv.insert(
+        "undefined".into(),
+        BoxedTest::new(tests::is_undefined).handling_undefined(),
+    );
+    rv.insert(
+        "defined".into(),
+        BoxedTest::new(tests::is_defined).handling_undefined(),
+    );
     rv.insert("none".into(), BoxedTest::new(tests::is_none));
     let is_safe = BoxedTest::new(tests::is_safe);
     rv.insert("safe".into(), is_safe.clone());
@@ -166,6 +194,7 @@ pub(crate) fn get_builtin_tests() -> BTreeMap<Cow<'static, str>, BoxedTest> {
         rv.insert("ge".into(), is_ge.clone());
         rv.insert(">=".into(), is_ge);
         rv.insert("in".into(), BoxedTest::new(tests::is_in));
+        rv.insert("sameas".into(), BoxedTest::new(tests::is_sameas));
         rv.insert("true".into(), BoxedTest::new(tests::is_true));
         rv.insert("false".into(), BoxedTest::new(tests::is_false));
         rv.insert("filter".into(), BoxedTest::new(tests::is_filter));
@@ -196,6 +225,10 @@ pub(crate) fn get_globals() -> BTreeMap<Cow<'static, str>, Value> {
             "namespace".into(),
             BoxedFunction::new(functions::namespace).to_value(),
         );
+        rv.insert(
+            "counter".into(),
+            BoxedFunction::new(functions::counter).to_value(),
+        );
     }
 
     rv
@@ -7,20 +7,38 @@ use serde::Serialize;
 
 use crate::compiler::codegen::CodeGenerator;
 use crate::compiler::instructions::Instructions;
+#[cfg(not(feature = "custom_operators"))]
 use crate::compiler::parser::parse_expr;
+#[cfg(feature = "custom_operators")]
+use crate::compiler::parser::parse_expr_with_operators;
 use crate::error::{attach_basic_debug_info, Error, ErrorKind};
 use crate::expression::Expression;
 use crate::output::Output;
 use crate::template::{CompiledTemplate, CompiledTemplateRef, Template, TemplateConfig};
-use crate::utils::{AutoEscape, BTreeMapKeysDebug, UndefinedBehavior};
+use crate::utils::{
+    AutoEscape, CallKind, Callables, LoopCycleBehavior, UndefinedBehavior, UndefinedContext,
+};
 use crate::value::{FunctionArgs, FunctionResult, Value};
+use crate::vm::undefined_object::PendingUndefined;
 use crate::vm::State;
+use crate::warning::Warning;
 use crate::{defaults, filters, functions, tests};
 
 type FormatterFunc = dyn Fn(&mut Output, &State, &Value) -> Result<(), Error> + Sync + Send;
 type PathJoinFunc = dyn for<'s> Fn(&'s str, &'s str) -> Cow<'s, str> + Sync + Send;
 type UnknownMethodFunc =
     dyn Fn(&State, &Value, &str, &[Value]) -> Result<Value, Error> + Sync + Send;
+type CallPolicyFunc = dyn Fn(CallKind, &str) -> bool + Sync + Send;
+type UndefinedHandlerFunc =
+    dyn Fn(&State, UndefinedContext<'_>) -> Result<Value, Error> + Sync + Send;
+type AttrAccessFunc = dyn Fn(&Value, &str) -> bool + Sync + Send;
+type WarningSinkFunc = dyn Fn(Warning) + Sync + Send;
+#[cfg(feature = "internal_debug")]
+type TraceFunc = dyn Fn(&State, usize) + Sync + Send;
+#[cfg(feature = "multi_template")]
+type TemplateLoadFunc = dyn Fn(&str) + Sync + Send;
+#[cfg(feature = "i18n")]
+type TranslatorFunc = dyn Fn(&str) -> String + Sync + Send;
 
 /// The maximum recursion in the VM.  Normally each stack frame
 /// adds one to this counter (eg: every time a frame is added).
@@ -29,6 +47,18 @@ type UnknownMethodFunc =
 /// requires enabling the `stacker` feature.
 const MAX_RECURSION: usize = 500;
 
+/// Restricts a recursion limit to what the engine supports.
+pub(crate) fn clamp_recursion_limit(level: usize) -> usize {
+    #[cfg(not(feature = "stacker"))]
+    {
+        level.min(MAX_RECURSION)
+    }
+    #[cfg(feature = "stacker")]
+    {
+        level
+    }
+}
+
 /// An abstraction that holds the engine configuration.
 ///
 /// This object holds the central configuration state for templates.  It is also
@@ -47,18 +77,42 @@ const MAX_RECURSION: usize = 500;
 #[derive(Clone)]
 pub struct Environment<'source> {
     templates: TemplateStore<'source>,
-    filters: BTreeMap<Cow<'source, str>, filters::BoxedFilter>,
-    tests: BTreeMap<Cow<'source, str>, tests::BoxedTest>,
+    filters: Callables<'source, filters::BoxedFilter>,
+    tests: Callables<'source, tests::BoxedTest>,
     globals: BTreeMap<Cow<'source, str>, Value>,
+    global_aliases: BTreeMap<Cow<'source, str>, Cow<'source, str>>,
     path_join_callback: Option<Arc<PathJoinFunc>>,
     pub(crate) unknown_method_callback: Option<Arc<UnknownMethodFunc>>,
+    call_policy: Option<Arc<CallPolicyFunc>>,
+    undefined_handler: Option<Arc<UndefinedHandlerFunc>>,
+    attr_access_filter: Option<Arc<AttrAccessFunc>>,
+    warning_sink: Option<Arc<WarningSinkFunc>>,
+    #[cfg(feature = "internal_debug")]
+    pub(crate) trace_callback: Option<Arc<TraceFunc>>,
+    #[cfg(feature = "multi_template")]
+    template_load_callback: Option<Arc<TemplateLoadFunc>>,
+    #[cfg(feature = "i18n")]
+    pub(crate) translator: Option<Arc<TranslatorFunc>>,
     undefined_behavior: UndefinedBehavior,
+    strict_comparisons: bool,
+    defer_undefined_errors: bool,
+    mark_undefined_in_output: bool,
+    lenient_super: bool,
+    strict_kwargs: bool,
+    sort_map_keys: bool,
     formatter: Arc<FormatterFunc>,
     #[cfg(feature = "debug")]
     debug: bool,
     #[cfg(feature = "fuel")]
     fuel: Option<u64>,
+    #[cfg(feature = "fuel")]
+    charge_fuel_for_output: bool,
     recursion_limit: usize,
+    #[cfg(feature = "multi_template")]
+    max_include_depth: usize,
+    max_loop_recursion_depth: usize,
+    loop_cycle_behavior: LoopCycleBehavior,
+    keep_partial_output_on_error: bool,
 }
 
 impl<'source> Default for Environment<'source> {
@@ -71,8 +125,8 @@ impl<'source> fmt::Debug for Environment<'source> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("Environment")
             .field("globals", &self.globals)
-            .field("tests", &BTreeMapKeysDebug(&self.tests))
-            .field("filters", &BTreeMapKeysDebug(&self.filters))
+            .field("tests", &self.tests)
+            .field("filters", &self.filters)
             .field("templates", &self.templates)
             .finish()
     }
@@ -86,22 +140,47 @@ impl<'source> Environment<'source> {
     /// default configuration you can use the alternative
     /// [`empty`](Environment::empty) method.
     pub fn new() -> Environment<'source> {
+        let mut config = TemplateConfig::new(Arc::new(defaults::default_auto_escape_callback));
+        let globals = defaults::get_globals();
+        config.global_names = Arc::new(globals.keys().map(|x| x.to_string()).collect());
         Environment {
-            templates: TemplateStore::new(TemplateConfig::new(Arc::new(
-                defaults::default_auto_escape_callback,
-            ))),
-            filters: defaults::get_builtin_filters(),
-            tests: defaults::get_builtin_tests(),
-            globals: defaults::get_globals(),
+            filters: Callables::new(&config.callable_ids, defaults::get_builtin_filters()),
+            tests: Callables::new(&config.callable_ids, defaults::get_builtin_tests()),
+            templates: TemplateStore::new(config),
+            globals,
+            global_aliases: BTreeMap::new(),
             path_join_callback: None,
             unknown_method_callback: None,
+            call_policy: None,
+            undefined_handler: None,
+            attr_access_filter: None,
+            warning_sink: None,
+            #[cfg(feature = "internal_debug")]
+            trace_callback: None,
+            #[cfg(feature = "multi_template")]
+            template_load_callback: None,
+            #[cfg(feature = "i18n")]
+            translator: None,
             undefined_behavior: UndefinedBehavior::default(),
+            strict_comparisons: false,
+            defer_undefined_errors: false,
+            mark_undefined_in_output: false,
+            lenient_super: false,
+            strict_kwargs: false,
+            sort_map_keys: false,
             formatter: Arc::new(defaults::escape_formatter),
             #[cfg(feature = "debug")]
             debug: cfg!(debug_assertions),
             #[cfg(feature = "fuel")]
             fuel: None,
+            #[cfg(feature = "fuel")]
+            charge_fuel_for_output: false,
             recursion_limit: MAX_RECURSION,
+            #[cfg(feature = "multi_template")]
+            max_include_depth: usize::MAX,
+            max_loop_recursion_depth: usize::MAX,
+            loop_cycle_behavior: LoopCycleBehavior::default(),
+            keep_partial_output_on_error: false,
         }
     }
 
@@ -115,15 +194,39 @@ impl<'source> Environment<'source> {
             filters: Default::default(),
             tests: Default::default(),
             globals: Default::default(),
+            global_aliases: BTreeMap::new(),
             path_join_callback: None,
             unknown_method_callback: None,
+            call_policy: None,
+            undefined_handler: None,
+            attr_access_filter: None,
+            warning_sink: None,
+            #[cfg(feature = "internal_debug")]
+            trace_callback: None,
+            #[cfg(feature = "multi_template")]
+            template_load_callback: None,
+            #[cfg(feature = "i18n")]
+            translator: None,
             undefined_behavior: UndefinedBehavior::default(),
+            strict_comparisons: false,
+            defer_undefined_errors: false,
+            mark_undefined_in_output: false,
+            lenient_super: false,
+            strict_kwargs: false,
+            sort_map_keys: false,
             formatter: Arc::new(defaults::escape_formatter),
             #[cfg(feature = "debug")]
             debug: cfg!(debug_assertions),
             #[cfg(feature = "fuel")]
             fuel: None,
+            #[cfg(feature = "fuel")]
+            charge_fuel_for_output: false,
             recursion_limit: MAX_RECURSION,
+            #[cfg(feature = "multi_template")]
+            max_include_depth: usize::MAX,
+            max_loop_recursion_depth: usize::MAX,
+            loop_cycle_behavior: LoopCycleBehavior::default(),
+            keep_partial_output_on_error: false,
         }
     }
 
@@ -182,6 +285,11 @@ impl<'source> Environment<'source> {
     /// it's stored on the environment.  This means the loader is only invoked
     /// once per template name.
     ///
+    /// The loader is used for all template lookups, including those caused by
+    /// `include`, `extends` and `import` tags.  Returning `Ok(None)` is treated
+    /// like any other missing template so `{% include ... ignore missing %}`
+    /// works as expected.
+    ///
     /// For loading templates from the file system, you can use the
     /// [`path_loader`](crate::path_loader) function.
     ///
@@ -210,6 +318,49 @@ impl<'source> Environment<'source> {
         self.templates.set_loader(f);
     }
 
+    /// Registers a callback that reports the current version of a template.
+    ///
+    /// This is useful for long running processes that want to pick up changes
+    /// to templates produced by the [loader](Self::set_loader) without having
+    /// to clear all templates.  The callback is invoked with the name of the
+    /// template every time it's fetched and can return any string that
+    /// identifies the current version of the template (such as a modification
+    /// time or a hash).  If the version differs from the one the cached template
+    /// was loaded with, the loader is invoked again and the template is
+    /// recompiled.  If the callback returns `None`, no version information is
+    /// available and the cached template is used as-is.
+    ///
+    /// The callback is only consulted for templates produced by the loader.
+    /// As outdated versions of templates might still be in use, they are only
+    /// dropped the next time the templates of the environment are modified
+    /// (eg: with [`add_template`](Self::add_template),
+    /// [`remove_template`](Self::remove_template) or
+    /// [`clear_templates`](Self::clear_templates)).
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # use minijinja::Environment;
+    /// # use std::path::Path;
+    /// fn create_env() -> Environment<'static> {
+    ///     let mut env = Environment::new();
+    ///     env.set_loader(minijinja::path_loader("templates"));
+    ///     env.set_template_version_fn(|name| {
+    ///         let meta = std::fs::metadata(Path::new("templates").join(name)).ok()?;
+    ///         Some(format!("{:?}", meta.modified().ok()?))
+    ///     });
+    ///     env
+    /// }
+    /// ```
+    #[cfg(feature = "loader")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "loader")))]
+    pub fn set_template_version_fn<F>(&mut self, f: F)
+    where
+        F: Fn(&str) -> Option<String> + Send + Sync + 'static,
+    {
+        self.templates.set_version_fn(f);
+    }
+
     /// Preserve the trailing newline when rendering templates.
     ///
     /// The default is `false`, which causes a single newline, if present, to be
@@ -328,6 +479,181 @@ impl<'source> Environment<'source> {
         self.unknown_method_callback = Some(Arc::new(f));
     }
 
+    /// Sets a policy that decides which filters, tests and functions may be called.
+    ///
+    /// The policy is invoked with the [`CallKind`] and the name of the filter,
+    /// test or function before it is called.  If it returns `false` the call
+    /// fails with an [`InvalidOperation`](crate::ErrorKind::InvalidOperation)
+    /// error naming the blocked item.  This is useful to restrict untrusted
+    /// templates to a safe subset of the environment.  Items that do not exist
+    /// continue to fail with the usual unknown filter, test or function errors.
+    ///
+    /// The policy also applies to filters and tests invoked indirectly, eg:
+    /// through the `map` or `select` filters or [`State::apply_filter`].
+    /// Global functions are checked under the name they are registered with
+    /// on the environment no matter how the template refers to them, so
+    /// storing them in a variable does not get around the policy.  Macros are
+    /// checked under their declared name.  Other callables, such as those
+    /// passed in the context, are checked under the name they are called by
+    /// and calls to them through an expression (eg: `{{ items[0]() }}`) are
+    /// not checked.
+    ///
+    /// ```
+    /// use minijinja::{CallKind, ErrorKind};
+    /// # let mut env = minijinja::Environment::new();
+    /// env.add_filter("shout", |value: String| value.to_uppercase());
+    /// env.set_call_policy(|kind, name| match kind {
+    ///     CallKind::Filter => name != "shout",
+    ///     _ => true,
+    /// });
+    /// let err = env.render_str("{{ 'hi'|shout }}", ()).unwrap_err();
+    /// assert_eq!(err.kind(), ErrorKind::InvalidOperation);
+    /// ```
+    pub fn set_call_policy<F>(&mut self, f: F)
+    where
+        F: Fn(CallKind, &str) -> bool + Sync + Send + 'static,
+    {
+        self.call_policy = Some(Arc::new(f));
+    }
+
+    /// Sets a filter that decides which attributes templates may access.
+    ///
+    /// The filter is invoked with the value and the name of the attribute
+    /// whenever a template accesses an attribute (`value.name`), an item
+    /// with a string key (`value["name"]`) or calls a method
+    /// (`value.name()`).  If it returns `false` the attribute is treated as
+    /// if it did not exist which means that it evaluates to undefined and
+    /// calling it as a method fails.  Combined with
+    /// [`UndefinedBehavior::Strict`] this turns access to denied attributes
+    /// into an error.  This is the equivalent of Jinja2's `is_safe_attribute`
+    /// and is useful to hide private fields of objects from untrusted
+    /// templates.
+    ///
+    /// Note that this only applies to attribute and item access in the
+    /// template syntax.  Filters that look up attributes by name such as
+    /// `attr` or `map(attribute=...)` as well as iterating or serializing the
+    /// value are not affected.  Use [`set_call_policy`](Self::set_call_policy)
+    /// to disallow these filters if needed.
+    ///
+    /// ```
+    /// # use minijinja::context;
+    /// # let mut env = minijinja::Environment::new();
+    /// env.set_attr_access_filter(|_value, attr| !attr.starts_with('_'));
+    /// let rv = env.render_str(
+    ///     "{{ user.name }}|{{ user._password }}|{{ user['_password'] }}",
+    ///     context! { user => context! { name => "Peter", _password => "secret" } },
+    /// ).unwrap();
+    /// assert_eq!(rv, "Peter||");
+    /// ```
+    pub fn set_attr_access_filter<F>(&mut self, f: F)
+    where
+        F: Fn(&Value, &str) -> bool + Sync + Send + 'static,
+    {
+        self.attr_access_filter = Some(Arc::new(f));
+    }
+
+    /// Sets a callback that is invoked whenever a template loads another template.
+    ///
+    /// The callback is invoked with the resolved name of the template every
+    /// time `{% include %}`, `{% extends %}` or `{% import %}` successfully
+    /// loads a template during rendering.  This reflects the names that were
+    /// actually picked at runtime which includes dynamic template names and
+    /// the choice made when a list of templates is passed to `include`.
+    /// Templates that could not be found are not reported.  The template that
+    /// is rendered directly is not reported either.
+    ///
+    /// The callback is invoked after the template was loaded but before it
+    /// starts rendering.  As a result a template that includes another
+    /// template is reported before any template included by the latter.  If a
+    /// template is loaded more than once during a render, the callback is
+    /// invoked every time.
+    ///
+    /// ```
+    /// # use std::sync::{Arc, Mutex};
+    /// # let mut env = minijinja::Environment::new();
+    /// env.add_template("a.html", "{% include ['missing.html', 'b.html'] %}").unwrap();
+    /// env.add_template("b.html", "Hello!").unwrap();
+    /// let loaded = Arc::new(Mutex::new(Vec::new()));
+    /// let loaded_ref = loaded.clone();
+    /// env.set_template_load_callback(move |name| {
+    ///     loaded_ref.lock().unwrap().push(name.to_string());
+    /// });
+    /// env.render_str("{% include 'a.html' %}", ()).unwrap();
+    /// assert_eq!(*loaded.lock().unwrap(), ["a.html", "b.html"]);
+    /// ```
+    ///
+    /// This requires the `multi_template` feature.
+    #[cfg(feature = "multi_template")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "multi_template")))]
+    pub fn set_template_load_callback<F>(&mut self, f: F)
+    where
+        F: Fn(&str) + Sync + Send + 'static,
+    {
+        self.template_load_callback = Some(Arc::new(f));
+    }
+
+    /// Sets the translator used by `gettext`, `ngettext` and `{% trans %}`.
+    ///
+    /// The `{% trans %}` tag always uses the configured translator.  To also
+    /// use it from expressions, register the `gettext` and `ngettext`
+    /// functions with [`add_gettext_functions`](Self::add_gettext_functions).
+    ///
+    /// The translator is invoked with the message id (eg: `"Hello %(name)s!"`)
+    /// and returns the translated message.  Placeholders in the translated
+    /// message are substituted afterwards.  For plurals the translator is
+    /// invoked with the singular or plural message id depending on the count.
+    /// If no translator is set, messages are not translated.
+    ///
+    /// ```
+    /// # let mut env = minijinja::Environment::new();
+    /// env.set_translator(|msgid| match msgid {
+    ///     "Hello %(name)s!" => "Hallo %(name)s!".into(),
+    ///     other => other.into(),
+    /// });
+    /// let rv = env.render_str("{% trans %}Hello {{ name }}!{% endtrans %}", minijinja::context! {
+    ///     name => "Peter",
+    /// }).unwrap();
+    /// assert_eq!(rv, "Hallo Peter!");
+    /// ```
+    ///
+    /// This requires the `i18n` feature.
+    #[cfg(feature = "i18n")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "i18n")))]
+    pub fn set_translator<F>(&mut self, f: F)
+    where
+        F: Fn(&str) -> String + Sync + Send + 'static,
+    {
+        self.translator = Some(Arc::new(f));
+    }
+
+    /// Registers the `gettext` (aliased as `_`) and `ngettext` functions.
+    ///
+    /// The functions translate messages with the translator configured with
+    /// [`set_translator`](Self::set_translator).  This replaces existing
+    /// globals with the same names.  The `{% trans %}` tag does not need
+    /// these functions and keeps working even if they are shadowed.
+    ///
+    /// ```
+    /// # let mut env = minijinja::Environment::new();
+    /// env.set_translator(|msgid| match msgid {
+    ///     "Hello %(name)s!" => "Hallo %(name)s!".into(),
+    ///     other => other.into(),
+    /// });
+    /// env.add_gettext_functions();
+    /// let rv = env.render_str("{{ _('Hello %(name)s!', name='Peter') }}", ()).unwrap();
+    /// assert_eq!(rv, "Hallo Peter!");
+    /// ```
+    ///
+    /// This requires the `i18n` feature.
+    #[cfg(feature = "i18n")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "i18n")))]
+    pub fn add_gettext_functions(&mut self) {
+        let gettext = functions::BoxedFunction::new(functions::gettext).to_value();
+        self.add_global("_", gettext.clone());
+        self.add_global("gettext", gettext);
+        self.add_function("ngettext", functions::ngettext);
+    }
+
     /// Removes all stored templates.
     ///
     /// This method is mainly useful when combined with a loader as it causes
@@ -503,6 +829,270 @@ impl<'source> Environment<'source> {
         self.undefined_behavior
     }
 
+    /// Sets a handler that is invoked whenever a template accesses something
+    /// that does not exist.
+    ///
+    /// The handler is called for missing variables (`{{ name }}`), missing
+    /// attributes (`{{ value.name }}`) and missing items (`{{ value[key] }}`)
+    /// with an [`UndefinedContext`] describing the access.  Whatever it
+    /// returns is used in place of the undefined value: it can return a
+    /// placeholder value, [`Value::UNDEFINED`] (for instance after logging the
+    /// access) or an error which fails rendering.
+    ///
+    /// When a handler is set it replaces the built-in
+    /// [undefined behavior](Self::set_undefined_behavior) for these accesses.
+    /// Values that are already undefined (for instance when printed or
+    /// iterated over) are still handled by the undefined behavior.  Note that
+    /// tests such as `is defined` see the value returned by the handler.
+    ///
+    /// ```
+    /// use minijinja::{UndefinedContext, Value};
+    /// # let mut env = minijinja::Environment::new();
+    /// env.set_undefined_handler(|_state, ctx| {
+    ///     Ok(Value::from(match ctx {
+    ///         UndefinedContext::Lookup { name } => format!("[missing: {name}]"),
+    ///         UndefinedContext::GetAttr { name, .. } => format!("[missing: .{name}]"),
+    ///         UndefinedContext::GetItem { key, .. } => format!("[missing: [{key}]]"),
+    ///         _ => "[missing]".into(),
+    ///     }))
+    /// });
+    /// let ctx = minijinja::context!(obj => minijinja::context!());
+    /// let rv = env.render_str("{{ user }} {{ obj.name }}", ctx).unwrap();
+    /// assert_eq!(rv, "[missing: user] [missing: .name]");
+    /// ```
+    pub fn set_undefined_handler<F>(&mut self, f: F)
+    where
+        F: Fn(&State, UndefinedContext<'_>) -> Result<Value, Error> + Sync + Send + 'static,
+    {
+        self.undefined_handler = Some(Arc::new(f));
+    }
+
+    /// Sets a sink that receives non-fatal warnings.
+    ///
+    /// While rendering, the engine reports [`Warning`]s for conditions that
+    /// do not fail rendering but might point to a problem.  These include
+    /// includes that fell back to a later template in a list of choices
+    /// ([`IncludeFallback`](crate::WarningKind::IncludeFallback)) and
+    /// lookups that produced undefined values
+    /// ([`Undefined`](crate::WarningKind::Undefined)).  Filters, tests and
+    /// functions can report their own warnings with
+    /// [`State::warn`](crate::State::warn).  When no sink is set, no
+    /// warnings are created.
+    ///
+    /// ```
+    /// # use std::sync::{Arc, Mutex};
+    /// # use minijinja::{Environment, WarningKind};
+    /// let warnings = Arc::new(Mutex::new(Vec::new()));
+    /// let mut env = Environment::new();
+    /// let sink = warnings.clone();
+    /// env.set_warning_sink(move |warning| sink.lock().unwrap().push(warning));
+    /// env.render_str("{{ missing }}", ()).unwrap();
+    /// let warnings = warnings.lock().unwrap();
+    /// assert_eq!(warnings[0].kind(), WarningKind::Undefined);
+    /// assert_eq!(warnings[0].line(), Some(1));
+    /// ```
+    pub fn set_warning_sink<F>(&mut self, f: F)
+    where
+        F: Fn(Warning) + Sync + Send + 'static,
+    {
+        self.warning_sink = Some(Arc::new(f));
+    }
+
+    /// Sets a callback that is invoked before every instruction is executed.
+    ///
+    /// The callback is passed the state and the index of the instruction
+    /// about to be executed.  While it runs the operand stack can be inspected
+    /// with [`State::operand_stack_depth`] and [`State::peek_operand_stack`].
+    /// This is useful to debug the engine or to verify that custom filters and
+    /// functions leave the stack balanced.
+    ///
+    /// This is an unstable debugging API that is only available with the
+    /// `internal_debug` feature.  It might change or go away at any point.
+    #[cfg(feature = "internal_debug")]
+    pub fn set_trace_callback<F>(&mut self, f: F)
+    where
+        F: Fn(&State, usize) + Sync + Send + 'static,
+    {
+        self.trace_callback = Some(Arc::new(f));
+    }
+
+    /// Defers undefined errors from the access to where the value is used.
+    ///
+    /// Normally accessing an attribute or item of an undefined value fails
+    /// right away unless the [undefined behavior](Self::set_undefined_behavior)
+    /// is [`Chainable`](UndefinedBehavior::Chainable).  With this enabled
+    /// such accesses instead produce a pending undefined value which carries
+    /// the error.  The same happens for filters that fail because they were
+    /// applied to an undefined value.  Filters pass a pending undefined value
+    /// on, which lets a `default` filter further down the chain handle the
+    /// missing value.  The `defined` and `undefined` tests also treat it as
+    /// undefined.  Any other use of the value, such as printing it, using it
+    /// in an operator, iterating over it, placing it in a list or passing it
+    /// to a function, fails with the deferred error.
+    ///
+    #[cfg_attr(
+        feature = "builtins",
+        doc = r#"
+```
+# use minijinja::{Environment, ErrorKind, UndefinedBehavior};
+let mut env = Environment::new();
+env.set_undefined_behavior(UndefinedBehavior::Strict);
+env.set_defer_undefined_errors(true);
+let tmpl = "{{ user.name|upper|default('?') }}";
+assert_eq!(env.render_str(tmpl, ()).unwrap(), "?");
+let err = env.render_str("{{ user.name|upper }}", ()).unwrap_err();
+assert_eq!(err.kind(), ErrorKind::UndefinedError);
+```
+"#
+    )]
+    pub fn set_defer_undefined_errors(&mut self, yes: bool) {
+        self.defer_undefined_errors = yes;
+    }
+
+    /// Returns `true` if undefined errors are deferred.
+    pub fn defer_undefined_errors(&self) -> bool {
+        self.defer_undefined_errors
+    }
+
+    /// Renders undefined values as visible markers.
+    ///
+    /// Normally printing an undefined value renders nothing which makes it
+    /// easy to miss mistakes in templates.
+    /// With this enabled undefined values instead render as
+    /// `<undefined:name>` where `name` is the path of the expression (eg:
+    /// `user.name`) if it was a plain variable, attribute or item lookup, or
+    /// as `<undefined>` otherwise.  The marker is escaped like any other
+    /// string.  Other values including `none` render as usual and strict
+    /// undefined behavior still fails.  This is meant for development only and is off by
+    /// default.
+    ///
+    /// ```
+    /// # use minijinja::{context, Environment};
+    /// let mut env = Environment::new();
+    /// env.set_mark_undefined_in_output(true);
+    /// let ctx = context!(user => context!());
+    /// let rv = env.render_str("[{{ user.name }}][{{ none }}]", ctx).unwrap();
+    /// assert_eq!(rv, "[<undefined:user.name>][none]");
+    /// ```
+    pub fn set_mark_undefined_in_output(&mut self, yes: bool) {
+        self.mark_undefined_in_output = yes;
+    }
+
+    /// Returns `true` if undefined values are rendered as markers.
+    pub fn mark_undefined_in_output(&self) -> bool {
+        self.mark_undefined_in_output
+    }
+
+    /// Makes `super()` render nothing if there is no parent block.
+    ///
+    /// By default calling `super()` in a block that does not override a
+    /// block of a parent template fails with an error.  With this enabled
+    /// it renders an empty string instead.  This lets blocks and macros
+    /// that optionally call `super()` be used both in base templates and in
+    /// templates extending them.  Calling `super()` outside of a block is
+    /// still an error.
+    ///
+    #[cfg_attr(
+        feature = "multi_template",
+        doc = r#"
+```
+# use minijinja::Environment;
+let mut env = Environment::new();
+env.set_lenient_super(true);
+let rv = env.render_str("{% block title %}[{{ super() }}]{% endblock %}", ());
+assert_eq!(rv.unwrap(), "[]");
+```
+"#
+    )]
+    pub fn set_lenient_super(&mut self, yes: bool) {
+        self.lenient_super = yes;
+    }
+
+    /// Returns `true` if `super()` without a parent block renders nothing.
+    pub fn lenient_super(&self) -> bool {
+        self.lenient_super
+    }
+
+    /// Rejects keyword arguments that a filter, test or function ignores.
+    ///
+    /// Most built-in filters that accept keyword arguments already fail on
+    /// keyword arguments they do not know.  With this enabled keyword
+    /// arguments passed to functions that do not take keyword arguments at
+    /// all fail with an error naming the keyword argument instead of a
+    /// generic "too many arguments" error, and the `map` filter fails if it
+    /// is given keyword arguments it would otherwise not forward to the
+    /// mapped filter.  This helps catching typos in argument names.
+    ///
+    /// ```
+    /// # use minijinja::Environment;
+    /// let mut env = Environment::new();
+    /// env.add_filter("shout", |value: String| value.to_uppercase());
+    /// env.set_strict_kwargs(true);
+    /// let err = env.render_str("{{ 'foo'|shout(fist=true) }}", ()).unwrap_err();
+    /// assert_eq!(err.detail(), Some("unknown keyword argument 'fist'"));
+    /// ```
+    pub fn set_strict_kwargs(&mut self, yes: bool) {
+        self.strict_kwargs = yes;
+    }
+
+    /// Returns `true` if unknown keyword arguments are rejected.
+    pub fn strict_kwargs(&self) -> bool {
+        self.strict_kwargs
+    }
+
+    /// Enables or disables strict comparisons.
+    ///
+    /// By default the ordering operators (`<`, `<=`, `>` and `>=`) accept
+    /// values of any kind.  Values of different kinds are then ordered by
+    /// their kind (see [comparisons](crate::value#comparisons)) which means
+    /// that for instance `"10" < 9` is `false`.  With strict comparisons
+    /// enabled, ordering values of different kinds instead fails with
+    /// [`InvalidOperation`](crate::ErrorKind::InvalidOperation).  Equality
+    /// checks are not affected by this setting.
+    ///
+    /// ```
+    /// # use minijinja::{Environment, ErrorKind};
+    /// let mut env = Environment::new();
+    /// env.set_strict_comparisons(true);
+    /// let expr = env.compile_expression("'10' < 9").unwrap();
+    /// assert_eq!(expr.eval(()).unwrap_err().kind(), ErrorKind::InvalidOperation);
+    /// ```
+    pub fn set_strict_comparisons(&mut self, yes: bool) {
+        self.strict_comparisons = yes;
+    }
+
+    /// Returns `true` if strict comparisons are enabled.
+    pub fn strict_comparisons(&self) -> bool {
+        self.strict_comparisons
+    }
+
+    /// Makes maps iterate in sorted key order.
+    ///
+    /// Maps created by MiniJinja itself iterate in a stable order, but maps
+    /// passed in from the outside (for instance a `HashMap`) might not.  When
+    /// this is enabled, looping over a map, the `items` filter and the
+    /// `tojson` filter emit the keys of maps in sorted order instead.  This
+    /// is useful to get reproducible output, for instance for snapshot tests
+    /// or caching.  The default is to keep the order of the map.
+    ///
+    /// ```
+    /// # use minijinja::{Environment, context};
+    /// # use std::collections::HashMap;
+    /// let mut env = Environment::new();
+    /// env.set_sort_map_keys(true);
+    /// let map: HashMap<_, _> = [("b", 2), ("a", 1), ("c", 3)].into_iter().collect();
+    /// let rv = env.render_str("{% for k in map %}{{ k }}{% endfor %}", context!(map)).unwrap();
+    /// assert_eq!(rv, "abc");
+    /// ```
+    pub fn set_sort_map_keys(&mut self, yes: bool) {
+        self.sort_map_keys = yes;
+    }
+
+    /// Returns `true` if maps iterate in sorted key order.
+    pub fn sort_map_keys(&self) -> bool {
+        self.sort_map_keys
+    }
+
     /// Sets a different formatter function.
     ///
     /// The formatter is invoked to format the given value into the provided
@@ -568,6 +1158,32 @@ impl<'source> Environment<'source> {
         self.debug
     }
 
+    /// Enables or disables keeping the partial output on errors.
+    ///
+    /// When enabled, errors returned from rendering a template to a string
+    /// carry the output that was rendered up to the point where the error
+    /// happened.  It can be retrieved with
+    /// [`Error::partial_output`](crate::Error::partial_output).  Output that was
+    /// captured (eg: by a `{% set %}` block or a macro call) but not yet written
+    /// is not included.  This is a debugging aid and disabled by default.
+    ///
+    /// ```
+    /// # let mut env = minijinja::Environment::new();
+    /// env.set_keep_partial_output_on_error(true);
+    /// let err = env.render_str("Hello {{ name }}!\n{{ 1 + 'a' }}", minijinja::context! {
+    ///     name => "World",
+    /// }).unwrap_err();
+    /// assert_eq!(err.partial_output(), Some("Hello World!\n"));
+    /// ```
+    pub fn set_keep_partial_output_on_error(&mut self, enabled: bool) {
+        self.keep_partial_output_on_error = enabled;
+    }
+
+    /// Returns `true` if the partial output is kept on errors.
+    pub fn keep_partial_output_on_error(&self) -> bool {
+        self.keep_partial_output_on_error
+    }
+
     /// Sets the optional fuel of the engine.
     ///
     /// When MiniJinja is compiled with the `fuel` feature then every
@@ -593,6 +1209,41 @@ impl<'source> Environment<'source> {
         self.fuel
     }
 
+    /// Enables charging fuel for the output.
+    ///
+    /// By default fuel is only consumed by the executed instructions.  A single
+    /// instruction can however write a lot of output, for instance if a filter
+    /// produces a large string.  When this is enabled, every byte written to
+    /// the output additionally consumes one unit of [fuel](Self::set_fuel)
+    /// so that the fuel bounds both the work done and the amount of output
+    /// produced.
+    ///
+    /// Only output that ends up in the rendered result is charged.  Output
+    /// that is captured (eg: with `{% set %}` blocks, `{% filter %}` blocks or
+    /// macro calls) is charged once it is emitted and output that is
+    /// captured and then discarded does not consume fuel.
+    ///
+    /// ```
+    /// # use minijinja::{Environment, ErrorKind};
+    /// # let mut env = Environment::new();
+    /// env.set_fuel(Some(100));
+    /// env.set_charge_fuel_for_output(true);
+    /// let err = env.render_str("{{ 'x' * 200 }}", ()).unwrap_err();
+    /// assert_eq!(err.kind(), ErrorKind::OutOfFuel);
+    /// ```
+    #[cfg(feature = "fuel")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "fuel")))]
+    pub fn set_charge_fuel_for_output(&mut self, yes: bool) {
+        self.charge_fuel_for_output = yes;
+    }
+
+    /// Returns `true` if fuel is charged for the output.
+    #[cfg(feature = "fuel")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "fuel")))]
+    pub fn charge_fuel_for_output(&self) -> bool {
+        self.charge_fuel_for_output
+    }
+
     /// Sets the syntax for the environment.
     ///
     /// This setting is used whenever a template is loaded into the environment.
@@ -612,6 +1263,53 @@ impl<'source> Environment<'source> {
         &self.templates.template_config.syntax_config
     }
 
+    /// Registers a custom binary operator.
+    ///
+    /// The operator is written with the given `symbol` between its two
+    /// operands and binds as tightly as the built-in operators of the given
+    /// [`OperatorPrecedence`](crate::OperatorPrecedence).  When the operator is
+    /// used in a template the function is invoked with the left and right
+    /// operand.
+    ///
+    /// Symbols can be made of the characters ``!#$%&*+-./:;<=>?@\^`|~``.
+    /// Where a built-in operator matches the same input, the built-in one
+    /// wins: registering `+` has no effect, but `+++` is read as a custom
+    /// operator.  Registering a symbol again replaces the earlier function.
+    ///
+    /// Like [`set_syntax`](Self::set_syntax) this setting is used whenever a
+    /// template is loaded, so operators should be registered before templates
+    /// are added to the environment.
+    ///
+    /// ```
+    /// # use minijinja::{Environment, OperatorPrecedence, Value};
+    /// let mut env = Environment::new();
+    /// env.add_binary_operator("<=>", OperatorPrecedence::Comparison, |a, b| {
+    ///     Ok(Value::from(a.cmp(b) as i32))
+    /// });
+    /// let rv = env.render_str("{{ 1 <=> 2 }}", ()).unwrap();
+    /// assert_eq!(rv, "-1");
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// This panics if the symbol is empty or contains other characters.
+    #[cfg(feature = "custom_operators")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "custom_operators")))]
+    pub fn add_binary_operator<F>(
+        &mut self,
+        symbol: &str,
+        precedence: crate::OperatorPrecedence,
+        f: F,
+    ) where
+        F: Fn(&Value, &Value) -> Result<Value, Error> + Sync + Send + 'static,
+    {
+        Arc::make_mut(&mut self.templates.template_config.custom_operators).insert(
+            symbol,
+            precedence,
+            Arc::new(f),
+        );
+    }
+
     /// Reconfigures the runtime recursion limit.
     ///
     /// This defaults to `500`.  Raising it above that level requires the `stacker`
@@ -630,14 +1328,7 @@ impl<'source> Environment<'source> {
     /// mean that in all cases stack can grow to the limits desired.  For instance in
     /// WASM the maximum limits are additionally enforced by the runtime.
     pub fn set_recursion_limit(&mut self, level: usize) {
-        #[cfg(not(feature = "stacker"))]
-        {
-            self.recursion_limit = level.min(MAX_RECURSION);
-        }
-        #[cfg(feature = "stacker")]
-        {
-            self.recursion_limit = level;
-        }
+        self.recursion_limit = clamp_recursion_limit(level);
     }
 
     /// Returns the current max recursion limit.
@@ -645,6 +1336,114 @@ impl<'source> Environment<'source> {
         self.recursion_limit
     }
 
+    /// Sets the maximum depth to which `{% include %}` tags can nest.
+    ///
+    /// Unlike the [recursion limit](Self::set_recursion_limit) this only
+    /// counts included (and imported) templates.  When a template is included
+    /// while this many includes are already active, rendering fails with an
+    /// [`InvalidOperation`](crate::ErrorKind::InvalidOperation) error naming
+    /// the template that could not be included.  This is useful to catch
+    /// templates that accidentally include each other early and with a more
+    /// understandable error.  By default the depth is unlimited.
+    ///
+    /// ```
+    /// # use minijinja::{Environment, ErrorKind};
+    /// # let mut env = Environment::new();
+    /// env.add_template("a.html", "{% include 'b.html' %}").unwrap();
+    /// env.add_template("b.html", "Hello!").unwrap();
+    /// env.set_max_include_depth(1);
+    /// assert_eq!(env.get_template("a.html").unwrap().render(()).unwrap(), "Hello!");
+    /// env.set_max_include_depth(0);
+    /// let err = env.get_template("a.html").unwrap().render(()).unwrap_err();
+    /// assert_eq!(err.kind(), ErrorKind::InvalidOperation);
+    /// ```
+    ///
+    /// This requires the `multi_template` feature.
+    #[cfg(feature = "multi_template")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "multi_template")))]
+    pub fn set_max_include_depth(&mut self, depth: usize) {
+        self.max_include_depth = depth;
+    }
+
+    /// Returns the maximum include depth.
+    #[cfg(feature = "multi_template")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "multi_template")))]
+    pub fn max_include_depth(&self) -> usize {
+        self.max_include_depth
+    }
+
+    /// Sets the maximum depth to which recursive loops can recurse.
+    ///
+    /// Unlike the [recursion limit](Self::set_recursion_limit) this only
+    /// counts calls to `loop()` in loops marked `recursive`.  The outermost
+    /// loop has a depth of `0` (see `loop.depth0`).  When a recursive loop
+    /// would go deeper than this, rendering fails with an
+    /// [`InvalidOperation`](crate::ErrorKind::InvalidOperation) error.  This
+    /// makes it possible to bound the rendering of deeply nested data
+    /// structures without also restricting macros or includes.  By default
+    /// the depth is unlimited.
+    ///
+    #[cfg_attr(
+        feature = "builtins",
+        doc = r#"
+```
+# use minijinja::{Environment, ErrorKind, context};
+# let mut env = Environment::new();
+env.set_max_loop_recursion_depth(1);
+let tmpl = "{% for item in [[[1]]] recursive %}{{ loop(item) if item is sequence else item }}{% endfor %}";
+let err = env.render_str(tmpl, context!()).unwrap_err();
+assert_eq!(err.kind(), ErrorKind::InvalidOperation);
+```
+"#
+    )]
+    pub fn set_max_loop_recursion_depth(&mut self, depth: usize) {
+        self.max_loop_recursion_depth = depth;
+    }
+
+    /// Returns the maximum recursive loop depth.
+    pub fn max_loop_recursion_depth(&self) -> usize {
+        self.max_loop_recursion_depth
+    }
+
+    /// Sets what happens when a recursive loop recurses into a cycle.
+    ///
+    /// If template data contains cycles (for instance a tree node that
+    /// refers back to one of its ancestors) a recursive loop over it would
+    /// recurse until it hits the recursion limit.  With cycle detection
+    /// enabled, recursing with `loop()` into an object that is already being
+    /// iterated by the same recursive loop is detected.  Objects are compared
+    /// by identity, not by value.  Depending on the [`LoopCycleBehavior`]
+    /// rendering either fails or the recursive call renders nothing.
+    ///
+    /// Detection only affects loops marked `recursive`.  By default cycles are
+    /// not detected.
+    ///
+    #[cfg_attr(
+        feature = "builtins",
+        doc = r#"
+```
+# use minijinja::{Environment, ErrorKind, LoopCycleBehavior, Value};
+# let mut env = Environment::new();
+env.set_loop_cycle_behavior(LoopCycleBehavior::Error);
+// an object is not a cycle if it's merely seen twice
+let item = Value::from(vec![1, 2]);
+let rv = env.render_str(
+    "{% for x in items recursive %}{{ loop(x) if x is sequence else x }}{% endfor %}",
+    minijinja::context!(items => vec![item.clone(), item]),
+).unwrap();
+assert_eq!(rv, "1212");
+```
+"#
+    )]
+    pub fn set_loop_cycle_behavior(&mut self, behavior: LoopCycleBehavior) {
+        self.loop_cycle_behavior = behavior;
+    }
+
+    /// Returns the current loop cycle behavior.
+    pub fn loop_cycle_behavior(&self) -> LoopCycleBehavior {
+        self.loop_cycle_behavior
+    }
+
     /// Compiles an expression.
     ///
     /// This lets one compile an expression in the template language and
@@ -672,9 +1471,16 @@ impl<'source> Environment<'source> {
         .map(|instr| Expression::new_owned(self, instr))
     }
 
-    fn _compile_expression<'expr>(&self, expr: &'expr str) -> Result<Instructions<'expr>, Error> {
+    pub(crate) fn _compile_expression<'expr>(
+        &self,
+        expr: &'expr str,
+    ) -> Result<Instructions<'expr>, Error> {
+        #[cfg(feature = "custom_operators")]
+        let ast = parse_expr_with_operators(expr, self.custom_operators().clone());
+        #[cfg(not(feature = "custom_operators"))]
+        let ast = parse_expr(expr);
         attach_basic_debug_info(
-            parse_expr(expr).map(|ast| {
+            ast.map(|ast| {
                 let mut gen = CodeGenerator::new("<expression>", expr);
                 gen.compile_expr(&ast);
                 gen.finish().0
@@ -697,13 +1503,75 @@ impl<'source> Environment<'source> {
         Rv: FunctionResult,
         Args: for<'a> FunctionArgs<'a>,
     {
-        self.filters
-            .insert(name.into(), filters::BoxedFilter::new(f));
+        self.insert_filter(name.into(), filters::BoxedFilter::new(f));
+    }
+
+    /// Adds a new filter function whose results are memoized.
+    ///
+    /// This works like [`add_filter`](Self::add_filter) but within a single
+    /// render the filter is only invoked once for every distinct set of
+    /// arguments.  Subsequent applications with equal arguments return the
+    /// cached result.  This is useful for pure but expensive filters such as
+    /// markdown rendering.  The filter must therefore not depend on anything
+    /// but its arguments.
+    ///
+    /// Arguments that cannot be reliably compared (iterators, callables or
+    /// other custom objects) bypass the cache.  Errors are not cached.
+    /// The cache is discarded at the end of the render.
+    ///
+    /// ```
+    /// # use minijinja::Environment;
+    /// # let mut env = Environment::new();
+    /// env.add_filter_memoized("markdown", |value: String| {
+    ///     // imagine expensive markdown rendering here
+    ///     format!("<p>{value}</p>")
+    /// });
+    /// ```
+    pub fn add_filter_memoized<N, F, Rv, Args>(&mut self, name: N, f: F)
+    where
+        N: Into<Cow<'source, str>>,
+        // the crazy bounds here exist to enable borrowing in closures
+        F: filters::Filter<Rv, Args>
+            + for<'a> filters::Filter<Rv, <Args as FunctionArgs<'a>>::Output>,
+        Rv: FunctionResult,
+        Args: for<'a> FunctionArgs<'a>,
+    {
+        self.insert_filter(name.into(), filters::BoxedFilter::new_memoized(f));
     }
 
     /// Removes a filter by name.
     pub fn remove_filter(&mut self, name: &str) {
-        self.filters.remove(name);
+        self.filters
+            .remove(&self.templates.template_config.callable_ids, name);
+    }
+
+    /// Registers `old` as a deprecated alias of the filter `new`.
+    ///
+    /// Applying the filter under its old name dispatches to whatever filter is
+    /// registered as `new` at the time of the call.  If a
+    /// [warning sink](Self::set_warning_sink) is set, a
+    /// [`Deprecated`](crate::WarningKind::Deprecated) warning is reported once
+    /// for every place in a template that uses the old name.  This is useful
+    /// to rename filters without breaking existing templates.  Registering a
+    /// filter under the old name again removes the alias.
+    ///
+    /// ```
+    /// # use minijinja::Environment;
+    /// # let mut env = Environment::new();
+    /// env.add_filter("slugify", |value: &str| value.to_lowercase().replace(' ', "-"));
+    /// env.add_filter_alias("slug", "slugify");
+    /// assert_eq!(env.render_str("{{ 'Hello World'|slug }}", ()).unwrap(), "hello-world");
+    /// ```
+    pub fn add_filter_alias<O, N>(&mut self, old: O, new: N)
+    where
+        O: Into<Cow<'source, str>>,
+        N: Into<Cow<'source, str>>,
+    {
+        self.filters.insert_alias(
+            &self.templates.template_config.callable_ids,
+            old.into(),
+            new.into(),
+        );
     }
 
     /// Adds a new test function.
@@ -719,12 +1587,33 @@ impl<'source> Environment<'source> {
         Rv: tests::TestResult,
         Args: for<'a> FunctionArgs<'a>,
     {
-        self.tests.insert(name.into(), tests::BoxedTest::new(f));
+        self.tests.insert(
+            &self.templates.template_config.callable_ids,
+            name.into(),
+            tests::BoxedTest::new(f),
+        );
     }
 
     /// Removes a test by name.
     pub fn remove_test(&mut self, name: &str) {
-        self.tests.remove(name);
+        self.tests
+            .remove(&self.templates.template_config.callable_ids, name);
+    }
+
+    /// Registers `old` as a deprecated alias of the test `new`.
+    ///
+    /// This works like [`add_filter_alias`](Self::add_filter_alias) but for
+    /// tests.
+    pub fn add_test_alias<O, N>(&mut self, old: O, new: N)
+    where
+        O: Into<Cow<'source, str>>,
+        N: Into<Cow<'source, str>>,
+    {
+        self.tests.insert_alias(
+            &self.templates.template_config.callable_ids,
+            old.into(),
+            new.into(),
+        );
     }
 
     /// Adds a new global function.
@@ -745,20 +1634,93 @@ impl<'source> Environment<'source> {
         self.add_global(name.into(), Value::from_function(f))
     }
 
+    /// Adds a new async global function.
+    ///
+    /// The function returns a future which is awaited by the engine when
+    /// the template is rendered with [`Template::render_async`].  Calling an
+    /// async function in any other situation (for instance with
+    /// [`Template::render`], within macros, includes or blocks) fails with
+    /// an error.  For more details have a look at
+    /// [`AsyncFunction`](crate::functions::AsyncFunction).
+    ///
+    /// ```
+    /// # use minijinja::Environment;
+    /// # let mut env = Environment::new();
+    /// env.add_async_function("load_title", |id: i64| async move {
+    ///     format!("Post #{id}")
+    /// });
+    /// ```
+    #[cfg(feature = "async")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "async")))]
+    pub fn add_async_function<N, F, Fut, Args>(&mut self, name: N, f: F)
+    where
+        N: Into<Cow<'source, str>>,
+        F: functions::AsyncFunction<Fut, Args>
+            + for<'a> functions::AsyncFunction<Fut, <Args as FunctionArgs<'a>>::Output>,
+        Fut: std::future::Future + Send + 'static,
+        Fut::Output: FunctionResult,
+        Args: for<'a> FunctionArgs<'a>,
+    {
+        self.add_global(
+            name.into(),
+            Value::from_object(functions::BoxedFunction::new_async(f)),
+        )
+    }
+
+    /// Adds a new async filter.
+    ///
+    /// This works like [`add_async_function`](Self::add_async_function) but
+    /// registers the async callable as filter.  The value the filter is
+    /// applied to is passed as first argument.
+    #[cfg(feature = "async")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "async")))]
+    pub fn add_async_filter<N, F, Fut, Args>(&mut self, name: N, f: F)
+    where
+        N: Into<Cow<'source, str>>,
+        F: functions::AsyncFunction<Fut, Args>
+            + for<'a> functions::AsyncFunction<Fut, <Args as FunctionArgs<'a>>::Output>,
+        Fut: std::future::Future + Send + 'static,
+        Fut::Output: FunctionResult,
+        Args: for<'a> FunctionArgs<'a>,
+    {
+        self.insert_filter(name.into(), filters::BoxedFilter::new_async(f));
+    }
+
     /// Adds a global variable.
     pub fn add_global<N, V>(&mut self, name: N, value: V)
     where
         N: Into<Cow<'source, str>>,
         V: Into<Value>,
     {
-        self.globals.insert(name.into(), value.into());
+        let name = name.into();
+        self.global_aliases.remove(&name);
+        Arc::make_mut(&mut self.templates.template_config.global_names).insert(name.to_string());
+        self.globals.insert(name, value.into());
     }
 
     /// Removes a global function or variable by name.
     pub fn remove_global(&mut self, name: &str) {
+        self.global_aliases.remove(name);
+        Arc::make_mut(&mut self.templates.template_config.global_names).remove(name);
         self.globals.remove(name);
     }
 
+    /// Registers `old` as a deprecated alias of the global function `new`.
+    ///
+    /// This works like [`add_filter_alias`](Self::add_filter_alias) but for
+    /// functions and other globals.  The deprecation warning is reported when
+    /// the function is called under its old name.
+    pub fn add_function_alias<O, N>(&mut self, old: O, new: N)
+    where
+        O: Into<Cow<'source, str>>,
+        N: Into<Cow<'source, str>>,
+    {
+        let old = old.into();
+        self.globals.remove(&old);
+        Arc::make_mut(&mut self.templates.template_config.global_names).insert(old.to_string());
+        self.global_aliases.insert(old, new.into());
+    }
+
     /// Returns an empty [`State`] for testing purposes and similar.
     pub fn empty_state(&self) -> State<'_, '_> {
         State::new_for_env(self)
@@ -766,7 +1728,13 @@ impl<'source> Environment<'source> {
 
     /// Looks up a function.
     pub(crate) fn get_global(&self, name: &str) -> Option<Value> {
-        self.globals.get(name).cloned()
+        match self.globals.get(name) {
+            Some(value) => Some(value.clone()),
+            None => self
+                .globals
+                .get(some!(self.global_aliases.get(name)))
+                .cloned(),
+        }
     }
 
     /// Looks up a filter.
@@ -779,6 +1747,134 @@ impl<'source> Environment<'source> {
         self.tests.get(name)
     }
 
+    /// Looks up a filter by its interned id.
+    pub(crate) fn get_filter_by_id(&self, id: u32) -> Option<&filters::BoxedFilter> {
+        self.filters.get_by_id(id)
+    }
+
+    /// Looks up a test function by its interned id.
+    pub(crate) fn get_test_by_id(&self, id: u32) -> Option<&tests::BoxedTest> {
+        self.tests.get_by_id(id)
+    }
+
+    fn insert_filter(&mut self, name: Cow<'source, str>, filter: filters::BoxedFilter) {
+        self.filters
+            .insert(&self.templates.template_config.callable_ids, name, filter);
+    }
+
+    /// Handles the access of something that does not exist.
+    pub(crate) fn handle_undefined(
+        &self,
+        state: &State,
+        ctx: UndefinedContext<'_>,
+    ) -> Result<Value, Error> {
+        match (&self.undefined_handler, ctx) {
+            (Some(handler), ctx) => handler(state, ctx),
+            (None, UndefinedContext::Lookup { .. }) => Ok(Value::UNDEFINED),
+            (None, UndefinedContext::GetAttr { parent, .. })
+            | (None, UndefinedContext::GetItem { parent, .. }) => {
+                let rv = state
+                    .undefined_behavior()
+                    .handle_undefined(parent.is_undefined());
+                if self.defer_undefined_errors {
+                    // the error is carried by the value and raised once used
+                    Ok(rv.unwrap_or_else(PendingUndefined::wrap))
+                } else {
+                    rv
+                }
+            }
+        }
+    }
+
+    /// Returns `true` if warnings are collected.
+    #[inline(always)]
+    pub(crate) fn has_warning_sink(&self) -> bool {
+        self.warning_sink.is_some()
+    }
+
+    /// Reports a warning to the warning sink.
+    pub(crate) fn emit_warning(&self, warning: Warning) {
+        if let Some(ref sink) = self.warning_sink {
+            sink(warning);
+        }
+    }
+
+    /// Returns `true` if a call policy is set.
+    #[inline(always)]
+    pub(crate) fn has_call_policy(&self) -> bool {
+        self.call_policy.is_some()
+    }
+
+    /// Checks the call policy for calling a callable value.
+    ///
+    /// Globals are checked under every name they are registered with so that
+    /// aliasing them in a template does not bypass the policy.  All other
+    /// callables are checked under `name` if one is given.
+    pub(crate) fn check_callable_policy(
+        &self,
+        callable: &Value,
+        name: Option<&str>,
+    ) -> Result<(), Error> {
+        if self.call_policy.is_none() {
+            return Ok(());
+        }
+        let mut is_global = false;
+        for (global_name, value) in self.globals.iter() {
+            if value.ptr_eq(callable) {
+                is_global = true;
+                ok!(self.check_call_policy(CallKind::Function, global_name));
+            }
+        }
+        match name {
+            Some(name) if !is_global => self.check_call_policy(CallKind::Function, name),
+            _ => Ok(()),
+        }
+    }
+
+    pub(crate) fn check_call_policy(&self, kind: CallKind, name: &str) -> Result<(), Error> {
+        match self.call_policy {
+            Some(ref policy) if !policy(kind, name) => Err(Error::new(
+                ErrorKind::InvalidOperation,
+                format!(
+                    "{} {} is not allowed",
+                    match kind {
+                        CallKind::Filter => "filter",
+                        CallKind::Test => "test",
+                        CallKind::Function => "function",
+                    },
+                    name
+                ),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Returns the sorted names of all registered filters, tests or globals.
+    pub(crate) fn registered_names(&self, kind: CallKind) -> impl Iterator<Item = &str> {
+        match kind {
+            CallKind::Filter => Box::new(self.filters.names()) as Box<dyn Iterator<Item = &str>>,
+            CallKind::Test => Box::new(self.tests.names()),
+            CallKind::Function => Box::new(self.globals.keys().map(|x| &**x)),
+        }
+    }
+
+    /// Returns the target name if `name` is an alias of a filter, test or global.
+    pub(crate) fn alias_target(&self, kind: CallKind, name: &str) -> Option<&str> {
+        match kind {
+            CallKind::Filter => self.filters.alias_target(name),
+            CallKind::Test => self.tests.alias_target(name),
+            CallKind::Function => self.global_aliases.get(name).map(|x| &**x),
+        }
+    }
+
+    #[inline]
+    pub(crate) fn is_attr_accessible(&self, value: &Value, attr: &str) -> bool {
+        match self.attr_access_filter {
+            Some(ref filter) => filter(value, attr),
+            None => true,
+        }
+    }
+
     pub(crate) fn initial_auto_escape(&self, name: &str) -> AutoEscape {
         (self.templates.template_config.default_auto_escape)(name)
     }
@@ -794,13 +1890,36 @@ impl<'source> Environment<'source> {
         state: &State,
         out: &mut Output,
     ) -> Result<(), Error> {
-        if value.is_undefined() && matches!(self.undefined_behavior, UndefinedBehavior::Strict) {
+        if value.is_undefined() && matches!(state.undefined_behavior(), UndefinedBehavior::Strict) {
             Err(Error::from(ErrorKind::UndefinedError))
         } else {
             (self.formatter)(out, state, value)
         }
     }
 
+    /// Returns the registered custom operators.
+    #[cfg(feature = "custom_operators")]
+    pub(crate) fn custom_operators(&self) -> &Arc<crate::operators::CustomOperators> {
+        &self.templates.template_config.custom_operators
+    }
+
+    /// Applies a custom binary operator by id.
+    #[cfg(feature = "custom_operators")]
+    pub(crate) fn apply_custom_operator(
+        &self,
+        id: u32,
+        left: &Value,
+        right: &Value,
+    ) -> Result<Value, Error> {
+        match self.custom_operators().get(id) {
+            Some(op) => (op.func)(left, right),
+            None => Err(Error::new(
+                ErrorKind::InvalidOperation,
+                "unknown custom operator",
+            )),
+        }
+    }
+
     /// Performs a template path join.
     pub(crate) fn join_template_path<'s>(&self, name: &'s str, parent: &'s str) -> Cow<'s, str> {
         match self.path_join_callback {
@@ -808,11 +1927,20 @@ impl<'source> Environment<'source> {
             None => Cow::Borrowed(name),
         }
     }
+
+    /// Notifies the template load callback about a loaded template.
+    #[cfg(feature = "multi_template")]
+    pub(crate) fn notify_template_load(&self, name: &str) {
+        if let Some(ref cb) = self.template_load_callback {
+            cb(name);
+        }
+    }
 }
 
 #[cfg(not(feature = "loader"))]
 mod basic_store {
     use super::*;
+    use crate::utils::BTreeMapKeysDebug;
 
     #[derive(Clone)]
     pub(crate) struct BasicStore<'source> {
@@ -50,10 +50,40 @@ struct ErrorRepr {
     lineno: usize,
     span: Option<Span>,
     source: Option<Arc<dyn std::error::Error + Send + Sync>>,
+    template_stack: Vec<TemplateFrame>,
+    partial_output: Option<String>,
+    available_names: Option<Vec<String>>,
     #[cfg(feature = "debug")]
     debug_info: Option<Arc<crate::debug::DebugInfo>>,
 }
 
+/// A frame in the template stack of an error.
+///
+/// Each frame represents a template that was being evaluated when the
+/// error happened, much like a frame in a Python traceback.  See
+/// [`Error::template_stack`] for more information.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TemplateFrame {
+    name: String,
+    lineno: usize,
+}
+
+impl TemplateFrame {
+    /// Returns the name of the template.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the line number within the template.
+    pub fn line(&self) -> Option<usize> {
+        if self.lineno > 0 {
+            Some(self.lineno)
+        } else {
+            None
+        }
+    }
+}
+
 impl fmt::Debug for Error {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let mut err = f.debug_struct("Error");
@@ -211,6 +241,9 @@ impl Error {
                 lineno: 0,
                 span: None,
                 source: None,
+                template_stack: Vec::new(),
+                partial_output: None,
+                available_names: None,
                 #[cfg(feature = "debug")]
                 debug_info: None,
             }),
@@ -234,6 +267,37 @@ impl Error {
         self.repr.lineno = span.start_line as usize;
     }
 
+    pub(crate) fn push_template_frame(&mut self, name: &str, lineno: usize) {
+        self.repr.template_stack.push(TemplateFrame {
+            name: name.into(),
+            lineno,
+        });
+    }
+
+    /// Carries over the template stack from an error this error wraps.
+    pub(crate) fn with_template_stack_of(mut self, other: &Error) -> Self {
+        self.repr.template_stack = other.repr.template_stack.clone();
+        self
+    }
+
+    pub(crate) fn with_available_names(mut self, names: Vec<String>) -> Self {
+        self.repr.available_names = Some(names);
+        self
+    }
+
+    /// Carries over the location and debug info from another error.
+    pub(crate) fn with_location_of(mut self, other: &Error) -> Self {
+        self.repr.name.clone_from(&other.repr.name);
+        self.repr.lineno = other.repr.lineno;
+        self.repr.span = other.repr.span;
+        self.repr.template_stack = other.repr.template_stack.clone();
+        #[cfg(feature = "debug")]
+        {
+            self.repr.debug_info.clone_from(&other.repr.debug_info);
+        }
+        self
+    }
+
     pub(crate) fn new_not_found(name: &str) -> Error {
         Error::new(
             ErrorKind::TemplateNotFound,
@@ -279,6 +343,71 @@ impl Error {
         }
     }
 
+    /// Returns the stack of templates that were evaluated when the error happened.
+    ///
+    /// The first frame is the innermost template (where the error happened)
+    /// followed by a frame for every include, macro call, block or `super()`
+    /// call that led there.  Errors raised in includes are additionally wrapped
+    /// in a [`BadInclude`](ErrorKind::BadInclude) error, the outer error however
+    /// carries the full stack.
+    ///
+    #[cfg_attr(
+        all(feature = "macros", feature = "multi_template"),
+        doc = r#"
+```
+# use minijinja::Environment;
+let mut env = Environment::new();
+env.add_template("macros.txt", "{% macro fail() %}\n{{ 'a' + 1 }}{% endmacro %}").unwrap();
+env.add_template("index.txt", "{% from 'macros.txt' import fail %}\n{{ fail() }}").unwrap();
+let err = env.get_template("index.txt").unwrap().render(()).unwrap_err();
+let frames = err.template_stack().iter()
+    .map(|x| (x.name(), x.line().unwrap()))
+    .collect::<Vec<_>>();
+assert_eq!(frames, [("macros.txt", 2), ("index.txt", 2)]);
+```
+"#
+    )]
+    pub fn template_stack(&self) -> &[TemplateFrame] {
+        &self.repr.template_stack
+    }
+
+    /// Returns the names that were available when an unknown name was used.
+    ///
+    /// For [`UnknownFilter`](ErrorKind::UnknownFilter),
+    /// [`UnknownTest`](ErrorKind::UnknownTest),
+    /// [`UnknownFunction`](ErrorKind::UnknownFunction) and
+    /// [`UnknownBlock`](ErrorKind::UnknownBlock) errors raised while
+    /// rendering this is the sorted list of registered filters, tests, globals
+    /// or blocks respectively.  The kind of the error tells which category the
+    /// names belong to.  This is intended for tools and is not part of the
+    /// error message.
+    ///
+    /// ```
+    /// # use minijinja::{Environment, ErrorKind};
+    /// let mut env = Environment::empty();
+    /// env.add_filter("upper", |x: String| x.to_uppercase());
+    /// env.add_filter("lower", |x: String| x.to_lowercase());
+    /// let err = env.render_str("{{ 'a'|title }}", ()).unwrap_err();
+    /// assert_eq!(err.kind(), ErrorKind::UnknownFilter);
+    /// assert_eq!(err.available_names().unwrap(), ["lower", "upper"]);
+    /// ```
+    pub fn available_names(&self) -> Option<&[String]> {
+        self.repr.available_names.as_deref()
+    }
+
+    /// Returns the output rendered before the error happened.
+    ///
+    /// This is only available if keeping the partial output was enabled with
+    /// [`Environment::set_keep_partial_output_on_error`](crate::Environment::set_keep_partial_output_on_error)
+    /// and the template was rendered to a string.
+    pub fn partial_output(&self) -> Option<&str> {
+        self.repr.partial_output.as_deref()
+    }
+
+    pub(crate) fn set_partial_output(&mut self, output: String) {
+        self.repr.partial_output = Some(output);
+    }
+
     /// Returns the byte range of where the error occurred if available.
     ///
     /// In combination with [`template_source`](Self::template_source) this can be
@@ -345,6 +474,31 @@ impl Error {
         self.debug_info().and_then(|x| x.source())
     }
 
+    /// Returns the local variables that were visible where the error occurred.
+    ///
+    /// This includes variables set in the template, macro arguments and the
+    /// `loop` variable of enclosing loops but not the context passed to the
+    /// template or globals.  Large collections and long strings are replaced
+    /// with a summary.  Like [`template_source`](Self::template_source) this
+    /// requires the debug mode to be enabled.
+    ///
+    /// ```
+    /// # use minijinja::Environment;
+    /// # let mut env = Environment::new();
+    /// # env.set_debug(true);
+    /// let err = env.render_str("{% for item in [1, 2] %}{{ item + 'x' }}{% endfor %}", ())
+    ///     .unwrap_err();
+    /// let locals = err.local_variables().map(|(k, _)| k).collect::<Vec<_>>();
+    /// assert_eq!(locals, ["item", "loop"]);
+    /// ```
+    #[cfg(feature = "debug")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "debug")))]
+    pub fn local_variables(&self) -> impl Iterator<Item = (&str, &crate::value::Value)> + '_ {
+        self.debug_info()
+            .into_iter()
+            .flat_map(|x| x.locals.iter().map(|(k, v)| (k.as_str(), v)))
+    }
+
     /// Returns the line number where the error occurred.
     #[cfg(feature = "debug")]
     pub(crate) fn span(&self) -> Option<Span> {
@@ -384,6 +538,9 @@ impl From<ErrorKind> for Error {
                 lineno: 0,
                 span: None,
                 source: None,
+                template_stack: Vec::new(),
+                partial_output: None,
+                available_names: None,
                 #[cfg(feature = "debug")]
                 debug_info: None,
             }),
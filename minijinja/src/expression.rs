@@ -6,7 +6,10 @@ use serde::Serialize;
 use crate::compiler::ast;
 use crate::compiler::instructions::Instructions;
 use crate::compiler::meta::find_undeclared;
+#[cfg(not(feature = "custom_operators"))]
 use crate::compiler::parser::parse_expr;
+#[cfg(feature = "custom_operators")]
+use crate::compiler::parser::parse_expr_with_operators;
 use crate::environment::Environment;
 use crate::error::Error;
 use crate::output::Output;
@@ -33,6 +36,30 @@ use crate::vm::Vm;
 /// let rv = expr.eval(context!(number => 15)).unwrap();
 /// assert!(rv.is_true());
 /// ```
+///
+/// # Reuse
+///
+/// Compiling parses the expression once into instructions that are kept by
+/// the handle.  Evaluating it does not parse or compile anything again, so
+/// a compiled expression can be evaluated cheaply any number of times with
+/// different contexts, for instance to apply a user provided rule to many
+/// records:
+///
+/// ```rust
+/// # use minijinja::{Environment, context};
+/// let env = Environment::new();
+/// let rule = env.compile_expression("score > 10 and tags").unwrap();
+/// let records = [
+///     context!(score => 42, tags => vec!["a"]),
+///     context!(score => 42, tags => Vec::<String>::new()),
+///     context!(score => 5, tags => vec!["b"]),
+/// ];
+/// let matches = records
+///     .iter()
+///     .filter(|record| rule.eval(record).map_or(false, |rv| rv.is_true()))
+///     .count();
+/// assert_eq!(matches, 1);
+/// ```
 pub struct Expression<'env, 'source> {
     env: &'env Environment<'source>,
     instr: ExpressionBacking<'source>,
@@ -91,12 +118,31 @@ impl<'env, 'source> Expression<'env, 'source> {
         self._eval(Value::from_serialize(&ctx))
     }
 
+    /// Evaluates the expression with some context and additional globals.
+    ///
+    /// This works the same as
+    /// [`Template::render_with_globals`](crate::Template::render_with_globals).
+    pub fn eval_with_globals<S: Serialize, G: Serialize>(
+        &self,
+        ctx: S,
+        globals: G,
+    ) -> Result<Value, Error> {
+        self._eval_with_globals(Value::from_serialize(&ctx), Value::from_serialize(&globals))
+    }
+
     /// Returns a set of all undeclared variables in the expression.
     ///
     /// This works the same as
     /// [`Template::undeclared_variables`](crate::Template::undeclared_variables).
     pub fn undeclared_variables(&self, nested: bool) -> HashSet<String> {
-        match parse_expr(self.instructions().source()) {
+        #[cfg(feature = "custom_operators")]
+        let ast = parse_expr_with_operators(
+            self.instructions().source(),
+            self.env.custom_operators().clone(),
+        );
+        #[cfg(not(feature = "custom_operators"))]
+        let ast = parse_expr(self.instructions().source());
+        match ast {
             Ok(expr) => find_undeclared(
                 &ast::Stmt::EmitExpr(ast::Spanned::new(
                     ast::EmitExpr { expr },
@@ -109,14 +155,17 @@ impl<'env, 'source> Expression<'env, 'source> {
     }
 
     fn _eval(&self, root: Value) -> Result<Value, Error> {
-        Ok(ok!(Vm::new(self.env).eval(
-            self.instructions(),
-            root,
-            &BTreeMap::new(),
-            &mut Output::null(),
-            crate::AutoEscape::None,
-        ))
-        .0
-        .expect("expression evaluation did not leave value on stack"))
+        self._eval_with_globals(root, Value::UNDEFINED)
+    }
+
+    fn _eval_with_globals(&self, root: Value, globals: Value) -> Result<Value, Error> {
+        let _guard = crate::value::value_optimization();
+        let vm = Vm::new(self.env);
+        let blocks = BTreeMap::new();
+        let mut state =
+            ok!(vm.new_state(self.instructions(), root, &blocks, crate::AutoEscape::None,));
+        state.ctx.set_globals(globals);
+        Ok(ok!(vm.eval_state(&mut state, &mut Output::null()))
+            .expect("expression evaluation did not leave value on stack"))
     }
 }
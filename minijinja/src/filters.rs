@@ -112,18 +112,23 @@
 //!
 //! Some additional filters are available in the
 //! [`minijinja-contrib`](https://crates.io/crates/minijinja-contrib) crate.
-use std::sync::Arc;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
 
 use crate::error::Error;
 use crate::utils::{write_escaped, SealedMarker};
-use crate::value::{ArgType, FunctionArgs, FunctionResult, Value};
+use crate::value::{ArgType, FunctionArgs, FunctionResult, Value, ValueKind, ValueRepr};
 use crate::vm::State;
 use crate::{AutoEscape, Output};
 
 type FilterFunc = dyn Fn(&State, &[Value]) -> Result<Value, Error> + Sync + Send + 'static;
 
 #[derive(Clone)]
-pub(crate) struct BoxedFilter(Arc<FilterFunc>);
+pub(crate) struct BoxedFilter {
+    func: Arc<FilterFunc>,
+    handles_undefined: bool,
+}
 
 /// A utility trait that represents filters.
 ///
@@ -230,6 +235,7 @@ tuple_impls! { A B }
 tuple_impls! { A B C }
 tuple_impls! { A B C D }
 tuple_impls! { A B C D E }
+tuple_impls! { A B C D E F }
 
 impl BoxedFilter {
     /// Creates a new boxed filter.
@@ -239,15 +245,138 @@ impl BoxedFilter {
         Rv: FunctionResult,
         Args: for<'a> FunctionArgs<'a>,
     {
-        BoxedFilter(Arc::new(move |state, args| -> Result<Value, Error> {
+        BoxedFilter::from_func(Arc::new(move |state, args| -> Result<Value, Error> {
             f.apply_to(ok!(Args::from_values(Some(state), args)), SealedMarker)
                 .into_result()
         }))
     }
 
+    fn from_func(func: Arc<FilterFunc>) -> BoxedFilter {
+        BoxedFilter {
+            func,
+            handles_undefined: false,
+        }
+    }
+
+    /// Marks the filter as one that handles undefined values.
+    ///
+    /// Such filters are also applied to undefined values with deferred
+    /// errors which all other filters pass on unchanged.
+    #[cfg(feature = "builtins")]
+    pub(crate) fn handling_undefined(mut self) -> BoxedFilter {
+        self.handles_undefined = true;
+        self
+    }
+
+    /// Returns `true` if the filter handles undefined values.
+    pub(crate) fn handles_undefined(&self) -> bool {
+        self.handles_undefined
+    }
+
+    /// Creates a new boxed filter that memoizes its results.
+    ///
+    /// Results are cached for the duration of a single render keyed by the
+    /// argument values.  Calls with arguments that cannot be reliably
+    /// compared (such as iterators or callables) are not cached.
+    pub(crate) fn new_memoized<F, Rv, Args>(f: F) -> BoxedFilter
+    where
+        F: Filter<Rv, Args> + for<'a> Filter<Rv, <Args as FunctionArgs<'a>>::Output>,
+        Rv: FunctionResult,
+        Args: for<'a> FunctionArgs<'a>,
+    {
+        static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        let inner = BoxedFilter::new(f);
+        BoxedFilter::from_func(Arc::new(move |state, args| -> Result<Value, Error> {
+            let key = match args.iter().map(MemoKey::new).collect() {
+                Some(args) => (id, args),
+                None => return inner.apply_to(state, args),
+            };
+            let cache = state.get_or_insert_temp(MemoizedFilterResults::default);
+            if let Some(rv) = cache.0.lock().unwrap().get(&key) {
+                return Ok(rv.clone());
+            }
+            // the lock is not held while the filter runs so that it can
+            // apply other memoized filters.
+            let rv = ok!(inner.apply_to(state, args));
+            cache.0.lock().unwrap().insert(key, rv.clone());
+            Ok(rv)
+        }))
+    }
+
+    /// Creates a new boxed filter from an async function.
+    #[cfg(feature = "async")]
+    pub(crate) fn new_async<F, Fut, Args>(f: F) -> BoxedFilter
+    where
+        F: crate::functions::AsyncFunction<Fut, Args>
+            + for<'a> crate::functions::AsyncFunction<Fut, <Args as FunctionArgs<'a>>::Output>,
+        Fut: std::future::Future + Send + 'static,
+        Fut::Output: FunctionResult,
+        Args: for<'a> FunctionArgs<'a>,
+    {
+        BoxedFilter::from_func(Arc::new(crate::functions::make_async_callable(f)))
+    }
+
     /// Applies the filter to a value and argument.
     pub fn apply_to(&self, state: &State, args: &[Value]) -> Result<Value, Error> {
-        (self.0)(state, args)
+        (self.func)(state, args)
+    }
+}
+
+/// Identifies a memoized filter call: the filter's id and the arguments.
+type MemoizationKey = (usize, Vec<MemoKey>);
+
+/// The per-render cache of memoized filters.
+#[derive(Default)]
+struct MemoizedFilterResults(Mutex<HashMap<MemoizationKey, Value>>);
+
+/// A value as part of a memoization key.
+///
+/// Unlike values, keys of different types never compare equal so that
+/// `1`, `1.0` and `true` are cached separately.  Strings also carry their
+/// safe flag.
+#[derive(PartialEq, Eq, Hash)]
+enum MemoKey {
+    Undefined,
+    None,
+    Bool(bool),
+    Int(Value),
+    Float(u64),
+    String(Value, bool),
+    Bytes(Value),
+    Seq(Vec<MemoKey>),
+    Map(Vec<(MemoKey, MemoKey)>),
+}
+
+impl MemoKey {
+    /// Creates the key for a value unless it can't be reliably compared.
+    fn new(value: &Value) -> Option<MemoKey> {
+        Some(match value.0 {
+            ValueRepr::Undefined => MemoKey::Undefined,
+            ValueRepr::None => MemoKey::None,
+            ValueRepr::Bool(b) => MemoKey::Bool(b),
+            ValueRepr::U64(_) | ValueRepr::I64(_) | ValueRepr::U128(_) | ValueRepr::I128(_) => {
+                MemoKey::Int(value.clone())
+            }
+            ValueRepr::F64(f) => MemoKey::Float(f.to_bits()),
+            ValueRepr::String(..) | ValueRepr::SmallStr(_) => {
+                MemoKey::String(value.clone(), value.is_safe())
+            }
+            ValueRepr::Bytes(_) => MemoKey::Bytes(value.clone()),
+            ValueRepr::Object(_) => match value.kind() {
+                ValueKind::Seq => MemoKey::Seq(some!(some!(value.try_iter().ok())
+                    .map(|item| MemoKey::new(&item))
+                    .collect())),
+                ValueKind::Map => MemoKey::Map(some!(some!(value.try_iter().ok())
+                    .map(|key| {
+                        let item = some!(value.get_item(&key).ok());
+                        Some((some!(MemoKey::new(&key)), some!(MemoKey::new(&item))))
+                    })
+                    .collect())),
+                _ => return None,
+            },
+            ValueRepr::Invalid(_) => return None,
+        })
     }
 }
 
@@ -293,22 +422,51 @@ mod builtins {
     use super::*;
 
     use crate::error::ErrorKind;
-    use crate::utils::splitn_whitespace;
-    use crate::value::ops::as_f64;
+    use crate::utils::{splitn_whitespace, CallKind, HtmlEscape};
+    use crate::value::one_shot_object::OneShotIterator;
+    use crate::value::ops::{self, as_f64};
     use crate::value::{Enumerator, Kwargs, Object, ObjectRepr, ValueKind, ValueRepr};
     use std::borrow::Cow;
     use std::cmp::Ordering;
     use std::fmt::Write;
     use std::mem;
 
+    /// Applies a string transformation to a value.
+    ///
+    /// If the value is a plain string and the transformation leaves it
+    /// unchanged, the original value is returned without allocating.
+    fn map_str<F>(value: &Value, f: F) -> Value
+    where
+        F: for<'x> FnOnce(&'x str) -> Cow<'x, str>,
+    {
+        match value.as_str() {
+            Some(s) if !value.is_safe() => match f(s) {
+                Cow::Borrowed(rv) if rv.len() == s.len() => value.clone(),
+                rv => Value::from(rv.into_owned()),
+            },
+            _ => Value::from(f(&value.as_cow_str()).into_owned()),
+        }
+    }
+
     /// Converts a value to uppercase.
     ///
     /// ```jinja
     /// <h1>{{ chapter.title|upper }}</h1>
     /// ```
     #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
-    pub fn upper(v: Cow<'_, str>) -> String {
-        v.to_uppercase()
+    pub fn upper(v: &Value) -> Value {
+        map_str(v, |s| {
+            let unchanged = if s.is_ascii() {
+                !s.bytes().any(|b| b.is_ascii_lowercase())
+            } else {
+                s.chars().all(|c| c.to_uppercase().eq(Some(c)))
+            };
+            if unchanged {
+                Cow::Borrowed(s)
+            } else {
+                Cow::Owned(s.to_uppercase())
+            }
+        })
     }
 
     /// Converts a value to lowercase.
@@ -317,8 +475,19 @@ mod builtins {
     /// <h1>{{ chapter.title|lower }}</h1>
     /// ```
     #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
-    pub fn lower(v: Cow<'_, str>) -> String {
-        v.to_lowercase()
+    pub fn lower(v: &Value) -> Value {
+        map_str(v, |s| {
+            let unchanged = if s.is_ascii() {
+                !s.bytes().any(|b| b.is_ascii_uppercase())
+            } else {
+                s.chars().all(|c| c.to_lowercase().eq(Some(c)))
+            };
+            if unchanged {
+                Cow::Borrowed(s)
+            } else {
+                Cow::Owned(s.to_lowercase())
+            }
+        })
     }
 
     /// Converts a value to title case.
@@ -419,6 +588,16 @@ mod builtins {
     /// * `case_sensitive`: set to `true` to make the sorting of strings case sensitive.
     /// * `by`: set to `"value"` to sort by value. Defaults to `"key"`.
     /// * `reverse`: set to `true` to sort in reverse.
+    ///
+    /// ```jinja
+    /// {% for key, value in mymap|dictsort(by="value") %}
+    ///   <li>{{ key }}: {{ value }}
+    /// {% endfor %}
+    /// ```
+    ///
+    /// Keys and values are compared like with the `sort` filter.  Values of
+    /// different kinds (for instance numeric and string keys) are ordered by
+    /// their kind first.
     #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
     pub fn dictsort(v: Value, kwargs: Kwargs) -> Result<Value, Error> {
         if v.kind() == ValueKind::Map {
@@ -467,7 +646,9 @@ mod builtins {
     /// which is typically arbitrary unless the `preserve_order` feature
     /// is used in which case the original order of the map is retained.
     /// It's generally better to use `|dictsort` which sorts the map by
-    /// key before iterating.
+    /// key before iterating.  If
+    /// [`Environment::set_sort_map_keys`](crate::Environment::set_sort_map_keys)
+    /// is enabled the items are emitted in sorted key order.
     ///
     /// ```jinja
     /// <dl>
@@ -478,11 +659,14 @@ mod builtins {
     /// </dl>
     /// ```
     #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
-    pub fn items(v: Value) -> Result<Value, Error> {
+    pub fn items(state: &State, v: Value) -> Result<Value, Error> {
         if v.kind() == ValueKind::Map {
-            let mut rv = Vec::with_capacity(v.len().unwrap_or(0));
-            let iter = ok!(v.try_iter());
-            for key in iter {
+            let mut keys = ok!(v.try_iter()).collect::<Vec<_>>();
+            if state.env().sort_map_keys() {
+                keys.sort();
+            }
+            let mut rv = Vec::with_capacity(keys.len());
+            for key in keys {
                 let value = v.get_item(&key).unwrap_or(Value::UNDEFINED);
                 rv.push(Value::from(vec![key, value]));
             }
@@ -509,24 +693,180 @@ mod builtins {
 
     /// Trims a value
     #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
-    pub fn trim(s: Cow<'_, str>, chars: Option<Cow<'_, str>>) -> String {
+    pub fn trim(v: &Value, chars: Option<Cow<'_, str>>) -> Value {
         match chars {
             Some(chars) => {
                 let chars = chars.chars().collect::<Vec<_>>();
-                s.trim_matches(&chars[..]).to_string()
+                map_str(v, |s| Cow::Borrowed(s.trim_matches(&chars[..])))
+            }
+            None => map_str(v, |s| Cow::Borrowed(s.trim())),
+        }
+    }
+
+    /// Strips SGML/XML tags and collapses adjacent whitespace.
+    ///
+    /// Comments and tags are removed, runs of whitespace are replaced by a
+    /// single space and basic HTML entities are decoded afterwards.  A `<`
+    /// that is never closed is left in the output as is.  This matches the
+    /// behavior of the Jinja2 filter.
+    ///
+    /// ```jinja
+    /// {{ "<p>Hello <b>World</b></p>"|striptags }} -> Hello World
+    /// ```
+    #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
+    pub fn striptags(s: &str) -> String {
+        let s = strip_delimited(s, "<!--", "-->");
+        let s = strip_delimited(&s, "<", ">");
+        let mut rv = String::with_capacity(s.len());
+        for word in s.split_whitespace() {
+            if !rv.is_empty() {
+                rv.push(' ');
             }
-            None => s.trim().to_string(),
+            rv.push_str(word);
         }
+        unescape_html(&rv)
+    }
+
+    /// Removes everything from `open` up to and including the next `close`
+    /// in a single forward scan.
+    fn strip_delimited<'a>(s: &'a str, open: &str, close: &str) -> Cow<'a, str> {
+        let mut rv = String::new();
+        let mut rest = s;
+        while let Some(start) = rest.find(open) {
+            match rest[start..].find(close) {
+                Some(end) => {
+                    rv.push_str(&rest[..start]);
+                    rest = &rest[start + end + close.len()..];
+                }
+                // nothing after this can be closed either
+                None => break,
+            }
+        }
+        if rest.len() == s.len() {
+            Cow::Borrowed(s)
+        } else {
+            rv.push_str(rest);
+            Cow::Owned(rv)
+        }
+    }
+
+    fn unescape_html(s: &str) -> String {
+        let mut rv = String::with_capacity(s.len());
+        let mut rest = s;
+        while let Some(start) = rest.find('&') {
+            rv.push_str(&rest[..start]);
+            rest = &rest[start..];
+            let decoded = rest.find(';').and_then(|end| {
+                let c = match &rest[1..end] {
+                    "amp" => '&',
+                    "lt" => '<',
+                    "gt" => '>',
+                    "quot" => '"',
+                    "apos" => '\'',
+                    "nbsp" => '\u{a0}',
+                    entity => {
+                        let num = entity.strip_prefix('#')?;
+                        let code = match num.strip_prefix(['x', 'X']) {
+                            Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+                            None => num.parse().ok()?,
+                        };
+                        char::from_u32(code)?
+                    }
+                };
+                Some((c, end + 1))
+            });
+            match decoded {
+                Some((c, len)) => {
+                    rv.push(c);
+                    rest = &rest[len..];
+                }
+                None => {
+                    rv.push('&');
+                    rest = &rest[1..];
+                }
+            }
+        }
+        rv.push_str(rest);
+        rv
+    }
+
+    /// Converts newlines into HTML line breaks.
+    ///
+    /// Every newline is prefixed with a `<br>` tag and the result is marked as
+    /// safe so that the tags are not escaped again.  This filter is meant to be
+    /// applied to content that was already escaped.  To prevent unsafe input
+    /// from being marked as safe, values that are not [safe](Value::from_safe_string)
+    /// are HTML escaped first.
+    ///
+    /// ```jinja
+    /// {{ comment|e|nl2br }}
+    /// ```
+    #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
+    pub fn nl2br(value: &Value) -> Result<Value, Error> {
+        let escaped;
+        let s = match value.as_str() {
+            Some(s) if value.is_safe() => s,
+            Some(s) => {
+                escaped = HtmlEscape(s).to_string();
+                &escaped
+            }
+            None => {
+                return Err(Error::new(
+                    ErrorKind::InvalidOperation,
+                    format!("cannot convert {} to line breaks", value.kind()),
+                ))
+            }
+        };
+        let mut rv = String::with_capacity(s.len());
+        for line in s.split_inclusive('\n') {
+            match line.strip_suffix('\n') {
+                Some(line) => {
+                    let (line, nl) = match line.strip_suffix('\r') {
+                        Some(line) => (line, "\r\n"),
+                        None => (line, "\n"),
+                    };
+                    rv.push_str(line);
+                    rv.push_str("<br>");
+                    rv.push_str(nl);
+                }
+                None => rv.push_str(line),
+            }
+        }
+        Ok(Value::from_safe_string(rv))
     }
 
     /// Joins a sequence by a character
+    ///
+    /// ```jinja
+    /// {{ ["foo", "bar"]|join(", ") }} -> foo, bar
+    /// ```
+    ///
+    /// The optional `attribute` keyword argument joins an attribute of the
+    /// items instead of the items themselves:
+    ///
+    /// ```jinja
+    /// {{ users|join(", ", attribute="name") }}
+    /// ```
+    ///
+    /// The filter is aware of auto escaping.  If auto escaping is enabled and
+    /// the separator or any of the items is a [safe](Value::from_safe_string)
+    /// string, every part that is not safe is escaped on its own and the
+    /// result is marked as safe.  This way safe strings are not escaped a
+    /// second time.
     #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
-    pub fn join(val: Value, joiner: Option<Cow<'_, str>>) -> Result<String, Error> {
+    pub fn join(
+        state: &State,
+        val: Value,
+        joiner: Option<Value>,
+        kwargs: Kwargs,
+    ) -> Result<Value, Error> {
+        let attr = ok!(kwargs.get::<Option<Cow<'_, str>>>("attribute"));
+        ok!(kwargs.assert_all_used());
+
         if val.is_undefined() || val.is_none() {
-            return Ok(String::new());
+            return Ok(Value::from(""));
         }
 
-        let joiner = joiner.as_ref().unwrap_or(&Cow::Borrowed(""));
         let iter = ok!(val.try_iter().map_err(|err| {
             Error::new(
                 ErrorKind::InvalidOperation,
@@ -534,19 +874,38 @@ mod builtins {
             )
             .with_source(err)
         }));
-
-        let mut rv = String::new();
-        for item in iter {
-            if !rv.is_empty() {
-                rv.push_str(joiner);
-            }
-            if let Some(s) = item.as_str() {
-                rv.push_str(s);
+        let items: Vec<Value> = match attr {
+            Some(ref attr) => iter
+                .map(|item| item.get_path_or_default(attr, &Value::UNDEFINED))
+                .collect(),
+            None => iter.collect(),
+        };
+        let joiner = joiner.unwrap_or_else(|| Value::from(""));
+
+        let escape = !matches!(state.auto_escape(), AutoEscape::None)
+            && (joiner.is_safe() || items.iter().any(|x| x.is_safe()));
+        let write_part = |out: &mut Output, part: &Value| {
+            if escape {
+                write_escaped(out, state.auto_escape(), part)
+            } else if let Some(s) = part.as_str() {
+                out.write_str(s).map_err(Error::from)
             } else {
-                write!(rv, "{item}").ok();
+                write!(out, "{part}").map_err(Error::from)
             }
+        };
+        let mut rv = String::new();
+        let mut out = Output::with_string(&mut rv);
+        for (idx, item) in items.iter().enumerate() {
+            if idx > 0 {
+                ok!(write_part(&mut out, &joiner));
+            }
+            ok!(write_part(&mut out, item));
         }
-        Ok(rv)
+        Ok(if escape {
+            Value::from_safe_string(rv)
+        } else {
+            Value::from(rv)
+        })
     }
 
     /// Split a string into its substrings, using `split` as the separator string.
@@ -592,19 +951,458 @@ mod builtins {
         Value::from_iter(s.lines().map(|x| x.to_string()))
     }
 
+    /// Wraps a string to the given width.
+    ///
+    /// The filter accepts a few arguments which can be passed positionally or
+    /// as keyword arguments:
+    ///
+    /// * `width`: the maximum number of characters per line.  Defaults to 79.
+    /// * `break_long_words`: set to `false` to not break words that are longer
+    ///   than `width`.  Defaults to `true`.
+    /// * `wrapstring`: the string used to join the lines.  Defaults to a newline.
+    ///
+    /// Existing newlines are retained so paragraphs stay separate.  Within a
+    /// line, runs of whitespace are collapsed into a single space.  The width
+    /// is measured in unicode scalar values.
+    ///
+    /// ```jinja
+    /// {{ "The quick brown fox jumps over the lazy dog."|wordwrap(15) }}
+    ///   -> The quick brown
+    ///      fox jumps over
+    ///      the lazy dog.
+    /// ```
+    #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
+    pub fn wordwrap(
+        s: &str,
+        width: Option<usize>,
+        break_long_words: Option<bool>,
+        wrapstring: Option<&str>,
+        kwargs: Kwargs,
+    ) -> Result<String, Error> {
+        let width = ok!(kwargs.get::<Option<usize>>("width"))
+            .or(width)
+            .unwrap_or(79);
+        let break_long_words = ok!(kwargs.get::<Option<bool>>("break_long_words"))
+            .or(break_long_words)
+            .unwrap_or(true);
+        let kwarg_wrapstring = ok!(kwargs.get::<Option<&str>>("wrapstring"));
+        let wrapstring = kwarg_wrapstring.or(wrapstring).unwrap_or("\n");
+        ok!(kwargs.assert_all_used());
+        if width == 0 {
+            return Err(Error::new(
+                ErrorKind::InvalidOperation,
+                "width must be greater than zero",
+            ));
+        }
+
+        let mut rv = Vec::new();
+        for line in s.lines() {
+            let mut current = String::new();
+            let mut current_len = 0;
+            for word in line.split_whitespace() {
+                let mut word = word;
+                loop {
+                    let word_len = word.chars().count();
+                    let sep = if current_len == 0 { 0 } else { 1 };
+                    if current_len + sep + word_len <= width {
+                        if sep == 1 {
+                            current.push(' ');
+                        }
+                        current.push_str(word);
+                        current_len += sep + word_len;
+                        break;
+                    } else if word_len > width && break_long_words {
+                        // fill up the current line with the start of the word
+                        let space = width.saturating_sub(current_len + sep);
+                        if space > 0 {
+                            let split = word.char_indices().nth(space).map_or(word.len(), |x| x.0);
+                            if sep == 1 {
+                                current.push(' ');
+                            }
+                            current.push_str(&word[..split]);
+                            word = &word[split..];
+                        }
+                    } else if current_len == 0 {
+                        current.push_str(word);
+                        current_len = word_len;
+                        break;
+                    }
+                    rv.push(std::mem::take(&mut current));
+                    current_len = 0;
+                }
+            }
+            rv.push(current);
+        }
+        Ok(rv.join(wrapstring))
+    }
+
+    /// Truncates a string to the given length.
+    ///
+    /// The filter accepts a few arguments which can be passed positionally or
+    /// as keyword arguments:
+    ///
+    /// * `length`: the maximum length of the result including `end`.
+    ///   Defaults to 255.
+    /// * `killwords`: set to `true` to cut the text at exactly `length`
+    ///   characters.  By default the last word that does not fit is discarded.
+    /// * `end`: the string appended to truncated text.  Defaults to `...`.
+    /// * `leeway`: strings that exceed `length` by at most this many characters
+    ///   are not truncated.  Defaults to 5.
+    ///
+    /// All lengths are measured in unicode scalar values.
+    ///
+    /// ```jinja
+    /// {{ "foo bar baz qux"|truncate(9, leeway=0) }}
+    ///   -> foo...
+    /// {{ "foo bar baz qux"|truncate(9, true, leeway=0) }}
+    ///   -> foo ba...
+    /// {{ "foo bar baz qux"|truncate(9, true, "", 0) }}
+    ///   -> foo bar b
+    /// ```
+    #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
+    pub fn truncate(
+        s: &str,
+        length: Option<usize>,
+        killwords: Option<bool>,
+        end: Option<&str>,
+        leeway: Option<usize>,
+        kwargs: Kwargs,
+    ) -> Result<String, Error> {
+        let length = ok!(kwargs.get::<Option<usize>>("length"))
+            .or(length)
+            .unwrap_or(255);
+        let killwords = ok!(kwargs.get::<Option<bool>>("killwords"))
+            .or(killwords)
+            .unwrap_or(false);
+        let kwarg_end = ok!(kwargs.get::<Option<&str>>("end"));
+        let end = kwarg_end.or(end).unwrap_or("...");
+        let leeway = ok!(kwargs.get::<Option<usize>>("leeway"))
+            .or(leeway)
+            .unwrap_or(5);
+        ok!(kwargs.assert_all_used());
+
+        let end_len = end.chars().count();
+        if length < end_len {
+            return Err(Error::new(
+                ErrorKind::InvalidOperation,
+                format!("expected length >= {}, got {}", end_len, length),
+            ));
+        }
+        if s.chars().count() <= length.saturating_add(leeway) {
+            return Ok(s.to_string());
+        }
+
+        let cut = s
+            .char_indices()
+            .nth(length - end_len)
+            .map_or(s.len(), |x| x.0);
+        let mut rv = &s[..cut];
+        if !killwords {
+            if let Some(pos) = rv.rfind(' ') {
+                rv = &rv[..pos];
+            }
+        }
+        Ok(format!("{}{}", rv, end))
+    }
+
+    /// Applies printf-style formatting to a string.
+    ///
+    /// The string is used as format string and the arguments to the filter
+    /// are substituted in order.  The following conversions are supported:
+    ///
+    /// * `%s`: the value converted to a string
+    /// * `%d` and `%i`: an integer
+    /// * `%f` and `%F`: a float (with a default precision of 6)
+    /// * `%x` and `%X`: an integer in lower or upper case hexadecimal
+    /// * `%%`: a literal percent sign
+    ///
+    /// Each conversion can be preceded by the flags `-` (left align), `0`
+    /// (pad numbers with zeroes) and `+` (always show the sign), a minimum
+    /// width and a precision (`.2`).  For `%s` the precision limits the number
+    /// of characters.
+    ///
+    /// ```jinja
+    /// {{ "%s is %d years old"|format(name, age) }}
+    /// {{ "%.2f"|format(3.14159) }} -> 3.14
+    /// {{ "%05d"|format(42) }} -> 00042
+    /// ```
+    ///
+    /// It's an error if there are too few or too many arguments or if an
+    /// argument cannot be converted to what the format string expects.
+    #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
+    pub fn format(format_str: &str, args: crate::value::Rest<Value>) -> Result<String, Error> {
+        let mut rv = String::with_capacity(format_str.len());
+        let mut args = args.iter();
+        let mut chars = format_str.chars().peekable();
+
+        fn parse_number(chars: &mut std::iter::Peekable<std::str::Chars<'_>>) -> Option<usize> {
+            let mut rv = None;
+            while let Some(digit) = chars.peek().and_then(|c| c.to_digit(10)) {
+                rv = Some(rv.unwrap_or(0usize).saturating_mul(10) + digit as usize);
+                chars.next();
+            }
+            rv
+        }
+
+        fn int_arg(value: &Value, spec: char) -> Result<i128, Error> {
+            match value.kind() {
+                ValueKind::Bool | ValueKind::Number => {
+                    if let Ok(rv) = i128::try_from(value.clone()) {
+                        Ok(rv)
+                    } else if let Some(f) = as_f64(value, true) {
+                        Ok(f.trunc() as i128)
+                    } else {
+                        Err(format_type_error(value, spec))
+                    }
+                }
+                _ => Err(format_type_error(value, spec)),
+            }
+        }
+
+        fn format_type_error(value: &Value, spec: char) -> Error {
+            Error::new(
+                ErrorKind::InvalidOperation,
+                format!("%{} format requires a number, got {}", spec, value.kind()),
+            )
+        }
+
+        while let Some(c) = chars.next() {
+            if c != '%' {
+                rv.push(c);
+                continue;
+            }
+            if chars.peek() == Some(&'%') {
+                chars.next();
+                rv.push('%');
+                continue;
+            }
+
+            let mut left_align = false;
+            let mut zero_pad = false;
+            let mut plus_sign = false;
+            loop {
+                match chars.peek() {
+                    Some('-') => left_align = true,
+                    Some('0') => zero_pad = true,
+                    Some('+') => plus_sign = true,
+                    _ => break,
+                }
+                chars.next();
+            }
+            let width = parse_number(&mut chars).unwrap_or(0);
+            let precision = if chars.peek() == Some(&'.') {
+                chars.next();
+                Some(parse_number(&mut chars).unwrap_or(0))
+            } else {
+                None
+            };
+
+            let spec = ok!(chars.next().ok_or_else(|| {
+                Error::new(ErrorKind::InvalidOperation, "incomplete format specifier")
+            }));
+            if !matches!(spec, 's' | 'd' | 'i' | 'f' | 'F' | 'x' | 'X') {
+                return Err(Error::new(
+                    ErrorKind::InvalidOperation,
+                    format!("unsupported format specifier %{}", spec),
+                ));
+            }
+            let arg = ok!(args.next().ok_or_else(|| {
+                Error::new(
+                    ErrorKind::InvalidOperation,
+                    "not enough arguments for format string",
+                )
+            }));
+
+            let (negative, body) = match spec {
+                's' => {
+                    let s = arg.to_string();
+                    let s = match precision {
+                        Some(precision) => s.chars().take(precision).collect(),
+                        None => s,
+                    };
+                    (false, s)
+                }
+                'd' | 'i' => {
+                    let n = ok!(int_arg(arg, spec));
+                    (n < 0, n.unsigned_abs().to_string())
+                }
+                'x' => {
+                    let n = ok!(int_arg(arg, spec));
+                    (n < 0, format!("{:x}", n.unsigned_abs()))
+                }
+                'X' => {
+                    let n = ok!(int_arg(arg, spec));
+                    (n < 0, format!("{:X}", n.unsigned_abs()))
+                }
+                _ => {
+                    let f = ok!(as_f64(arg, true)
+                        .filter(|_| !matches!(arg.kind(), ValueKind::String))
+                        .ok_or_else(|| format_type_error(arg, spec)));
+                    (
+                        f.is_sign_negative() && f != 0.0,
+                        format!("{:.*}", precision.unwrap_or(6), f.abs()),
+                    )
+                }
+            };
+
+            let sign = if negative {
+                "-"
+            } else if plus_sign && spec != 's' {
+                "+"
+            } else {
+                ""
+            };
+            let len = sign.len() + body.chars().count();
+            let padding = width.saturating_sub(len);
+            if left_align {
+                rv.push_str(sign);
+                rv.push_str(&body);
+                rv.extend(std::iter::repeat(' ').take(padding));
+            } else if zero_pad && spec != 's' {
+                rv.push_str(sign);
+                rv.extend(std::iter::repeat('0').take(padding));
+                rv.push_str(&body);
+            } else {
+                rv.extend(std::iter::repeat(' ').take(padding));
+                rv.push_str(sign);
+                rv.push_str(&body);
+            }
+        }
+
+        if args.next().is_some() {
+            return Err(Error::new(
+                ErrorKind::InvalidOperation,
+                "not all arguments converted during string formatting",
+            ));
+        }
+
+        Ok(rv)
+    }
+
+    const BASE64_ALPHABET: &[u8; 64] =
+        b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    fn bytes_arg<'a>(value: &'a Value, filter: &str) -> Result<&'a [u8], Error> {
+        value.as_bytes().ok_or_else(|| {
+            Error::new(
+                ErrorKind::InvalidOperation,
+                format!(
+                    "{} filter requires bytes or a string, got {}",
+                    filter,
+                    value.kind()
+                ),
+            )
+        })
+    }
+
+    /// Encodes bytes or a string with base64.
+    ///
+    /// Strings are encoded as UTF-8.  The standard alphabet with padding is used.
+    ///
+    /// ```jinja
+    /// <img src="data:image/png;base64,{{ image_data|b64encode }}">
+    /// ```
+    #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
+    pub fn b64encode(value: &Value) -> Result<String, Error> {
+        let bytes = ok!(bytes_arg(value, "b64encode"));
+        let mut rv = String::with_capacity((bytes.len() + 2) / 3 * 4);
+        for chunk in bytes.chunks(3) {
+            let n = chunk
+                .iter()
+                .enumerate()
+                .fold(0u32, |n, (idx, &b)| n | (b as u32) << (16 - idx * 8));
+            for idx in 0..4 {
+                if idx <= chunk.len() {
+                    rv.push(BASE64_ALPHABET[(n >> (18 - idx * 6) & 0x3f) as usize] as char);
+                } else {
+                    rv.push('=');
+                }
+            }
+        }
+        Ok(rv)
+    }
+
+    /// Decodes a base64 encoded string into bytes.
+    ///
+    /// Both padded and unpadded input with the standard alphabet is accepted,
+    /// but padded input must be padded to a multiple of four characters.
+    /// The result is a bytes value.
+    ///
+    /// ```jinja
+    /// {{ "aGVsbG8="|b64decode|hex }} -> 68656c6c6f
+    /// ```
+    #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
+    pub fn b64decode(value: &str) -> Result<Value, Error> {
+        let invalid = || Error::new(ErrorKind::InvalidOperation, "invalid base64 data");
+        let data = value.trim_end_matches('=').as_bytes();
+        let padding = value.len() - data.len();
+        if data.len() % 4 == 1 || (padding > 0 && value.len() % 4 != 0) || padding > 2 {
+            return Err(invalid());
+        }
+        let mut rv = Vec::with_capacity(data.len() / 4 * 3 + 2);
+        for chunk in data.chunks(4) {
+            let mut n = 0u32;
+            for (idx, &c) in chunk.iter().enumerate() {
+                let digit = ok!(BASE64_ALPHABET
+                    .iter()
+                    .position(|&x| x == c)
+                    .ok_or_else(invalid));
+                n |= (digit as u32) << (18 - idx * 6);
+            }
+            for idx in 0..chunk.len() - 1 {
+                rv.push((n >> (16 - idx * 8)) as u8);
+            }
+        }
+        Ok(Value::from_bytes(rv))
+    }
+
+    /// Encodes bytes or a string as lowercase hexadecimal string.
+    ///
+    /// Strings are encoded as UTF-8.
+    ///
+    /// ```jinja
+    /// {{ checksum|hex }}
+    /// ```
+    #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
+    pub fn hex(value: &Value) -> Result<String, Error> {
+        let bytes = ok!(bytes_arg(value, "hex"));
+        let mut rv = String::with_capacity(bytes.len() * 2);
+        for b in bytes {
+            write!(rv, "{:02x}", b).ok();
+        }
+        Ok(rv)
+    }
+
     /// If the value is undefined it will return the passed default value,
     /// otherwise the value of the variable:
     ///
     /// ```jinja
     /// <p>{{ my_variable|default("my_variable was not defined") }}</p>
     /// ```
+    ///
+    /// If the second argument (or the `boolean` keyword argument) is set to
+    /// `true`, the default value is also used if the value is falsy (for
+    /// instance `none`, an empty string, zero or an empty list):
+    ///
+    /// ```jinja
+    /// <p>{{ title|default("Untitled", boolean=true) }}</p>
+    /// ```
     #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
-    pub fn default(value: Value, other: Option<Value>) -> Value {
-        if value.is_undefined() {
+    pub fn default(
+        value: Value,
+        other: Option<Value>,
+        boolean: Option<bool>,
+        kwargs: Kwargs,
+    ) -> Result<Value, Error> {
+        let boolean = ok!(kwargs.get::<Option<bool>>("boolean"))
+            .or(boolean)
+            .unwrap_or(false);
+        ok!(kwargs.assert_all_used());
+        Ok(if value.is_undefined() || (boolean && !value.is_true()) {
             other.unwrap_or_else(|| Value::from(""))
         } else {
             value
-        }
+        })
     }
 
     /// Returns the absolute value of a number.
@@ -636,11 +1434,36 @@ mod builtins {
 
     /// Converts a value into an integer.
     ///
+    /// The filter accepts a few keyword arguments:
+    ///
+    /// * `base`: the base (between 2 and 36) in which strings are parsed.  A
+    ///   `0x`, `0o` or `0b` prefix matching the base is permitted.  Defaults to 10.
+    /// * `default`: the value to return if the conversion fails.  If not
+    ///   provided, failing to convert the value is an error.
+    ///
     /// ```jinja
     /// {{ "42"|int == 42 }} -> true
+    /// {{ "0xff"|int(base=16) }} -> 255
+    /// {{ "nope"|int(default=0) }} -> 0
     /// ```
     #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
-    pub fn int(value: Value) -> Result<Value, Error> {
+    pub fn int(value: Value, kwargs: Kwargs) -> Result<Value, Error> {
+        let base = ok!(kwargs.get::<Option<u32>>("base")).unwrap_or(10);
+        let default = ok!(kwargs.get::<Option<Value>>("default"));
+        ok!(kwargs.assert_all_used());
+        if !(2..=36).contains(&base) {
+            return Err(Error::new(
+                ErrorKind::InvalidOperation,
+                format!("invalid base {} (must be between 2 and 36)", base),
+            ));
+        }
+        match (int_helper(value, base), default) {
+            (Err(_), Some(default)) => Ok(default),
+            (rv, _) => rv,
+        }
+    }
+
+    fn int_helper(value: Value, base: u32) -> Result<Value, Error> {
         match &value.0 {
             ValueRepr::Undefined | ValueRepr::None => Ok(Value::from(0)),
             ValueRepr::Bool(x) => Ok(Value::from(*x as u64)),
@@ -648,6 +1471,36 @@ mod builtins {
                 Ok(value)
             }
             ValueRepr::F64(v) => Ok(Value::from(*v as i128)),
+            ValueRepr::String(..) | ValueRepr::SmallStr(_) if base != 10 => {
+                let s = value.as_str().unwrap().trim();
+                let (sign, digits) = match s.strip_prefix('-') {
+                    Some(rest) => ("-", rest),
+                    None => ("", s.strip_prefix('+').unwrap_or(s)),
+                };
+                let prefix = match base {
+                    16 => Some("0x"),
+                    8 => Some("0o"),
+                    2 => Some("0b"),
+                    _ => None,
+                };
+                let digits = match prefix {
+                    Some(prefix)
+                        if digits.len() > 2 && digits[..2].eq_ignore_ascii_case(prefix) =>
+                    {
+                        &digits[2..]
+                    }
+                    _ => digits,
+                };
+                i128::from_str_radix(&format!("{}{}", sign, digits), base)
+                    .map(Value::from)
+                    .map_err(|err| {
+                        Error::new(
+                            ErrorKind::InvalidOperation,
+                            format!("cannot parse {:?} as integer in base {}", s, base),
+                        )
+                        .with_source(err)
+                    })
+            }
             ValueRepr::String(..) | ValueRepr::SmallStr(_) => {
                 let s = value.as_str().unwrap();
                 if let Ok(i) = s.parse::<i128>() {
@@ -669,11 +1522,25 @@ mod builtins {
 
     /// Converts a value into a float.
     ///
+    /// The filter accepts a `default` keyword argument with the value to
+    /// return if the conversion fails.  If not provided, failing to convert
+    /// the value is an error.
+    ///
     /// ```jinja
     /// {{ "42.5"|float == 42.5 }} -> true
+    /// {{ "nope"|float(default=0) }} -> 0
     /// ```
     #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
-    pub fn float(value: Value) -> Result<Value, Error> {
+    pub fn float(value: Value, kwargs: Kwargs) -> Result<Value, Error> {
+        let default = ok!(kwargs.get::<Option<Value>>("default"));
+        ok!(kwargs.assert_all_used());
+        match (float_helper(value), default) {
+            (Err(_), Some(default)) => Ok(default),
+            (rv, _) => rv,
+        }
+    }
+
+    fn float_helper(value: Value) -> Result<Value, Error> {
         match &value.0 {
             ValueRepr::Undefined | ValueRepr::None => Ok(Value::from(0.0)),
             ValueRepr::Bool(x) => Ok(Value::from(*x as u64 as f64)),
@@ -693,6 +1560,97 @@ mod builtins {
         }
     }
 
+    /// Formats a number of bytes as a human readable file size.
+    ///
+    /// By default decimal prefixes are used (`kB`, `MB`, `GB`, …).  If the
+    /// `binary` argument is set to `true` the binary prefixes (`KiB`, `MiB`,
+    /// `GiB`, …) are used instead.  Sizes below one kilobyte are rendered as
+    /// a number of bytes, which like in Jinja2 also applies to negative values.
+    ///
+    /// ```jinja
+    /// {{ 1500000|filesizeformat }} -> 1.5 MB
+    /// {{ 1500000|filesizeformat(binary=true) }} -> 1.4 MiB
+    /// ```
+    #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
+    pub fn filesizeformat(
+        value: &Value,
+        binary: Option<bool>,
+        kwargs: Kwargs,
+    ) -> Result<String, Error> {
+        const DECIMAL: [&str; 8] = ["kB", "MB", "GB", "TB", "PB", "EB", "ZB", "YB"];
+        const BINARY: [&str; 8] = ["KiB", "MiB", "GiB", "TiB", "PiB", "EiB", "ZiB", "YiB"];
+
+        let bytes = ok!(as_f64(value, true).ok_or_else(|| {
+            Error::new(
+                ErrorKind::InvalidOperation,
+                format!("cannot format {} as file size", value.kind()),
+            )
+        }));
+        let binary = ok!(kwargs.get::<Option<bool>>("binary")).or(binary);
+        ok!(kwargs.assert_all_used());
+        let (base, prefixes) = if binary.unwrap_or(false) {
+            (1024f64, BINARY)
+        } else {
+            (1000f64, DECIMAL)
+        };
+
+        if bytes == 1.0 {
+            return Ok("1 Byte".into());
+        } else if bytes < base {
+            return Ok(format!("{} Bytes", bytes as i128));
+        }
+        let mut unit = base;
+        for (idx, prefix) in prefixes.iter().enumerate() {
+            unit *= base;
+            if bytes < unit || idx == prefixes.len() - 1 {
+                return Ok(format!("{:.1} {}", base * bytes / unit, prefix));
+            }
+        }
+        unreachable!()
+    }
+
+    /// Formats a number of seconds as a human readable duration.
+    ///
+    /// The duration is broken up into days, hours, minutes and seconds and
+    /// only the non zero components are rendered.  Fractional seconds are
+    /// truncated and negative durations are prefixed with a minus sign.
+    ///
+    /// ```jinja
+    /// {{ 7380|humanize_duration }} -> 2h 3m
+    /// {{ 90061|humanize_duration }} -> 1d 1h 1m 1s
+    /// ```
+    #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
+    pub fn humanize_duration(value: &Value) -> Result<String, Error> {
+        let seconds = ok!(as_f64(value, true)
+            .filter(|x| x.is_finite())
+            .ok_or_else(|| {
+                Error::new(
+                    ErrorKind::InvalidOperation,
+                    format!("cannot format {} as duration", value.kind()),
+                )
+            }));
+        let mut remaining = seconds.abs() as u128;
+        if remaining == 0 {
+            return Ok("0s".into());
+        }
+
+        let mut rv = String::new();
+        if seconds < 0.0 {
+            rv.push('-');
+        }
+        for &(size, suffix) in &[(86400, 'd'), (3600, 'h'), (60, 'm'), (1, 's')] {
+            let count = remaining / size;
+            remaining %= size;
+            if count > 0 {
+                if !rv.is_empty() && rv != "-" {
+                    rv.push(' ');
+                }
+                write!(rv, "{}{}", count, suffix).ok();
+            }
+        }
+        Ok(rv)
+    }
+
     /// Looks up an attribute.
     ///
     /// In MiniJinja this is the same as the `[]` operator.  In Jinja2 there is a
@@ -710,21 +1668,48 @@ mod builtins {
     /// Round the number to a given precision.
     ///
     /// Round the number to a given precision. The first parameter specifies the
-    /// precision (default is 0).
+    /// precision (default is 0), the second the rounding method:
+    ///
+    /// * `"common"`: rounds half away from zero (default)
+    /// * `"ceil"`: always rounds up
+    /// * `"floor"`: always rounds down
+    ///
+    /// Floats stay floats even if rounded to a precision of 0.  Integers are
+    /// returned unchanged.
     ///
     /// ```jinja
     /// {{ 42.55|round }}
     ///   -> 43.0
+    /// {{ 42.55|round(1, "floor") }}
+    ///   -> 42.5
     /// ```
     #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
-    pub fn round(value: Value, precision: Option<i32>) -> Result<Value, Error> {
+    pub fn round(
+        value: Value,
+        precision: Option<i32>,
+        method: Option<&str>,
+    ) -> Result<Value, Error> {
+        let method: fn(f64) -> f64 = match method.unwrap_or("common") {
+            "common" => f64::round,
+            "ceil" => f64::ceil,
+            "floor" => f64::floor,
+            other => {
+                return Err(Error::new(
+                    ErrorKind::InvalidOperation,
+                    format!(
+                        "invalid rounding method {:?} (must be common, ceil or floor)",
+                        other
+                    ),
+                ))
+            }
+        };
         match value.0 {
             ValueRepr::I64(_) | ValueRepr::I128(_) | ValueRepr::U64(_) | ValueRepr::U128(_) => {
                 Ok(value)
             }
             ValueRepr::F64(val) => {
                 let x = 10f64.powi(precision.unwrap_or(0));
-                Ok(Value::from((x * val).round() / x))
+                Ok(Value::from(method(x * val) / x))
             }
             _ => Err(Error::new(
                 ErrorKind::InvalidOperation,
@@ -735,7 +1720,11 @@ mod builtins {
 
     /// Returns the first item from an iterable.
     ///
-    /// If the list is empty `undefined` is returned.
+    /// If the list is empty `undefined` is returned.  Only the first item is
+    /// pulled from lazy iterables, the rest is never produced.  Re-iterable
+    /// values start over, but a one-shot iterator that was already consumed
+    /// (for instance by a loop) cannot produce its first item again which is
+    /// an error.
     ///
     /// ```jinja
     /// <dl>
@@ -747,8 +1736,23 @@ mod builtins {
     pub fn first(value: Value) -> Result<Value, Error> {
         if let Some(s) = value.as_str() {
             Ok(s.chars().next().map_or(Value::UNDEFINED, Value::from))
-        } else if let Some(mut iter) = value.as_object().and_then(|x| x.try_iter()) {
-            Ok(iter.next().unwrap_or(Value::UNDEFINED))
+        } else if let Some(obj) = value.as_object() {
+            if obj
+                .downcast_ref::<OneShotIterator>()
+                .map_or(false, |x| x.is_exhausted())
+            {
+                return Err(Error::new(
+                    ErrorKind::InvalidOperation,
+                    "cannot get first item from an exhausted one-shot iterator",
+                ));
+            }
+            match obj.try_iter() {
+                Some(mut iter) => Ok(iter.next().unwrap_or(Value::UNDEFINED)),
+                None => Err(Error::new(
+                    ErrorKind::InvalidOperation,
+                    "cannot get first item from value",
+                )),
+            }
         } else {
             Err(Error::new(
                 ErrorKind::InvalidOperation,
@@ -759,7 +1763,9 @@ mod builtins {
 
     /// Returns the last item from an iterable.
     ///
-    /// If the list is empty `undefined` is returned.
+    /// If the list is empty `undefined` is returned.  Iterables that cannot
+    /// be walked backwards are exhausted to find the last item, which means
+    /// a one-shot iterator is empty afterwards.
     ///
     /// ```jinja
     /// <h2>Most Recent Update</h2>
@@ -777,6 +1783,11 @@ mod builtins {
         if let Some(s) = value.as_str() {
             Ok(s.chars().next_back().map_or(Value::UNDEFINED, Value::from))
         } else if matches!(value.kind(), ValueKind::Seq | ValueKind::Iterable) {
+            // plain iterators are consumed directly rather than reversed as
+            // reversing would first collect all of their items.
+            if let Some(Enumerator::Iter(iter)) = value.as_object().map(|x| x.enumerate()) {
+                return Ok(iter.last().unwrap_or_default());
+            }
             let rev = ok!(value.reverse());
             let mut iter = ok!(rev.try_iter());
             Ok(iter.next().unwrap_or_default())
@@ -788,30 +1799,138 @@ mod builtins {
         }
     }
 
+    fn min_or_max(
+        state: &State,
+        value: Value,
+        kwargs: Kwargs,
+        wanted: Ordering,
+    ) -> Result<Value, Error> {
+        let iter = ok!(state
+            .undefined_behavior()
+            .try_iter_ref(&value)
+            .map_err(|err| {
+                Error::new(ErrorKind::InvalidOperation, "cannot convert value to list")
+                    .with_source(err)
+            }));
+        let attr = ok!(kwargs.get::<Option<&str>>("attribute"));
+        let default = if kwargs.has("default") {
+            Some(ok!(kwargs.get::<Value>("default")))
+        } else {
+            None
+        };
+        ok!(kwargs.assert_all_used());
+
+        let mut rv = None::<(Cow<'_, Value>, Value)>;
+        for item in iter {
+            let key = match attr {
+                Some(attr) => item.get_path(attr),
+                None => item.as_ref().clone(),
+            };
+            match rv {
+                Some((_, ref best)) if key.cmp(best) != wanted => {}
+                _ => rv = Some((item, key)),
+            }
+        }
+
+        match (rv, default) {
+            (Some((item, _)), _) => Ok(item.into_owned()),
+            (None, Some(default)) => Ok(default),
+            (None, None) => Err(Error::new(
+                ErrorKind::InvalidOperation,
+                "cannot find item in empty sequence without default",
+            )),
+        }
+    }
+
     /// Returns the smallest item from an iterable.
     ///
+    /// Items are compared the same way the comparison operators compare
+    /// values.  The filter accepts a few keyword arguments:
+    ///
+    /// * `attribute`: can be set to an attribute or dotted path to compare
+    ///   the items by that attribute.
+    /// * `default`: the value to return if the iterable is empty.  If no
+    ///   default is provided, an empty iterable results in an error.
+    ///
     /// ```jinja
     /// {{ [1, 2, 3, 4]|min }} -> 1
+    /// {{ users|min(attribute="age") }}
+    /// {{ []|min(default=none) }} -> none
     /// ```
     #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
-    pub fn min(state: &State, value: Value) -> Result<Value, Error> {
-        let iter = ok!(state.undefined_behavior().try_iter(value).map_err(|err| {
-            Error::new(ErrorKind::InvalidOperation, "cannot convert value to list").with_source(err)
-        }));
-        Ok(iter.min().unwrap_or(Value::UNDEFINED))
+    pub fn min(state: &State, value: Value, kwargs: Kwargs) -> Result<Value, Error> {
+        min_or_max(state, value, kwargs, Ordering::Less)
     }
 
     /// Returns the largest item from an iterable.
     ///
+    /// This accepts the same keyword arguments as [`min`].
+    ///
     /// ```jinja
     /// {{ [1, 2, 3, 4]|max }} -> 4
+    /// {{ items|max(attribute="score", default=none) }}
     /// ```
     #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
-    pub fn max(state: &State, value: Value) -> Result<Value, Error> {
-        let iter = ok!(state.undefined_behavior().try_iter(value).map_err(|err| {
-            Error::new(ErrorKind::InvalidOperation, "cannot convert value to list").with_source(err)
-        }));
-        Ok(iter.max().unwrap_or(Value::UNDEFINED))
+    pub fn max(state: &State, value: Value, kwargs: Kwargs) -> Result<Value, Error> {
+        min_or_max(state, value, kwargs, Ordering::Greater)
+    }
+
+    /// Sums up all the values in a sequence.
+    ///
+    /// The filter accepts a few keyword arguments:
+    ///
+    /// * `attribute`: can be set to an attribute or dotted path to sum up
+    ///   that attribute of the items instead of the items themselves.
+    /// * `start`: the value to start from.  Defaults to `0`.  This is also
+    ///   the value returned for empty sequences.
+    ///
+    /// Integers and floats can be mixed, in which case the result is a float.
+    /// Summing up values that are not numbers is an error.
+    ///
+    /// ```jinja
+    /// {{ range(10)|sum }} -> 45
+    /// Total: {{ items|sum(attribute="price") }}
+    /// ```
+    #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
+    pub fn sum(state: &State, values: Value, kwargs: Kwargs) -> Result<Value, Error> {
+        let iter = ok!(state
+            .undefined_behavior()
+            .try_iter_ref(&values)
+            .map_err(|err| {
+                Error::new(ErrorKind::InvalidOperation, "cannot convert value to list")
+                    .with_source(err)
+            }));
+        let attr = ok!(kwargs.get::<Option<&str>>("attribute"));
+        let mut rv = ok!(kwargs.get::<Option<Value>>("start")).unwrap_or(Value::from(0));
+        ok!(kwargs.assert_all_used());
+
+        for item in iter {
+            let value = match attr {
+                Some(attr) => Cow::Owned(item.get_path(attr)),
+                None => item,
+            };
+            if value.is_undefined() {
+                ok!(state.undefined_behavior().handle_undefined(false));
+                continue;
+            } else if !value.is_number() {
+                return Err(Error::new(
+                    ErrorKind::InvalidOperation,
+                    format!("can only sum numbers, got {}", value.kind()),
+                ));
+            }
+            rv = ok!(ops::add(&rv, &value));
+        }
+        Ok(rv)
+    }
+
+    /// Looks up the sort key of an item by attribute or dotted path.
+    ///
+    /// This goes through the same attribute access checks as the engine.
+    /// Attributes that cannot be accessed or looked up are undefined.
+    fn sort_key(state: &State, item: &Value, path: &str) -> Value {
+        item.get_path_checked(path, |value, attr| {
+            state.env().is_attr_accessible(value, attr)
+        })
     }
 
     /// Returns the sorted version of the given list.
@@ -822,34 +1941,46 @@ mod builtins {
     /// * `attribute`: can be set to an attribute or dotted path to sort by that attribute
     /// * `reverse`: set to `true` to sort in reverse.
     ///
+    /// The sort is stable: items that compare equal keep their relative order,
+    /// also when sorting in reverse.  Undefined values (for instance items that
+    /// lack the attribute) always sort last.
+    ///
     /// ```jinja
-    /// {{ [1, 3, 2, 4]|sort }} -> [4, 3, 2, 1]
-    /// {{ [1, 3, 2, 4]|sort(reverse=true) }} -> [1, 2, 3, 4]
-    /// # Sort users by age attribute in descending order.
-    /// {{ users|sort(attribute="age") }}
+    /// {{ [1, 3, 2, 4]|sort }} -> [1, 2, 3, 4]
+    /// {{ [1, 3, 2, 4]|sort(reverse=true) }} -> [4, 3, 2, 1]
     /// # Sort users by age attribute in ascending order.
+    /// {{ users|sort(attribute="age") }}
+    /// # Sort users by age attribute in descending order.
     /// {{ users|sort(attribute="age", reverse=true) }}
     /// ```
     #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
     pub fn sort(state: &State, value: Value, kwargs: Kwargs) -> Result<Value, Error> {
-        let mut items = ok!(state.undefined_behavior().try_iter(value).map_err(|err| {
+        let items = ok!(state.undefined_behavior().try_iter(value).map_err(|err| {
             Error::new(ErrorKind::InvalidOperation, "cannot convert value to list").with_source(err)
-        }))
-        .collect::<Vec<_>>();
+        }));
         let case_sensitive = ok!(kwargs.get::<Option<bool>>("case_sensitive")).unwrap_or(false);
-        if let Some(attr) = ok!(kwargs.get::<Option<&str>>("attribute")) {
-            items.sort_by(|a, b| match (a.get_path(attr), b.get_path(attr)) {
-                (Ok(a), Ok(b)) => cmp_helper(&a, &b, case_sensitive),
-                _ => Ordering::Equal,
-            });
-        } else {
-            items.sort_by(|a, b| cmp_helper(a, b, case_sensitive))
-        }
-        if let Some(true) = ok!(kwargs.get("reverse")) {
-            items.reverse();
-        }
+        let attr = ok!(kwargs.get::<Option<&str>>("attribute"));
+        let reverse = ok!(kwargs.get::<Option<bool>>("reverse")).unwrap_or(false);
         ok!(kwargs.assert_all_used());
-        Ok(Value::from(items))
+
+        let mut items = items
+            .map(|item| match attr {
+                Some(attr) => (sort_key(state, &item, attr), item),
+                None => (item.clone(), item),
+            })
+            .collect::<Vec<_>>();
+        items.sort_by(
+            |(a, _), (b, _)| match (a.is_undefined(), b.is_undefined()) {
+                (true, true) => Ordering::Equal,
+                (true, false) => Ordering::Greater,
+                (false, true) => Ordering::Less,
+                (false, false) if reverse => cmp_helper(b, a, case_sensitive),
+                (false, false) => cmp_helper(a, b, case_sensitive),
+            },
+        );
+        Ok(Value::from(
+            items.into_iter().map(|(_, item)| item).collect::<Vec<_>>(),
+        ))
     }
 
     /// Converts the input value into a list.
@@ -1012,14 +2143,26 @@ mod builtins {
     /// pretty printing or an integer to control the indentation of the pretty
     /// printing feature.
     ///
+    /// Additionally the `sort_keys` keyword argument can be set to `true` to sort
+    /// the keys of all maps (including nested ones) which produces deterministic
+    /// output independent of the order of the keys in the value.  It defaults to
+    /// `true` if
+    /// [`Environment::set_sort_map_keys`](crate::Environment::set_sort_map_keys)
+    /// is enabled.
+    ///
     /// ```jinja
     /// <script>
-    ///   const GLOBAL_CONFIG = {{ global_config|tojson(indent=2) }};
+    ///   const GLOBAL_CONFIG = {{ global_config|tojson(indent=2, sort_keys=true) }};
     /// </script>
     /// ```
     #[cfg_attr(docsrs, doc(cfg(all(feature = "builtins", feature = "json"))))]
     #[cfg(feature = "json")]
-    pub fn tojson(value: Value, indent: Option<Value>, args: Kwargs) -> Result<Value, Error> {
+    pub fn tojson(
+        state: &State,
+        value: Value,
+        indent: Option<Value>,
+        args: Kwargs,
+    ) -> Result<Value, Error> {
         let indent = match indent {
             Some(indent) => Some(indent),
             None => ok!(args.get("indent")),
@@ -1032,16 +2175,13 @@ mod builtins {
                 None => Some(ok!(usize::try_from(val.clone()))),
             },
         };
+        let sort_keys = ok!(args.get::<Option<bool>>("sort_keys"))
+            .unwrap_or_else(|| state.env().sort_map_keys());
         ok!(args.assert_all_used());
-        if let Some(indent) = indent {
-            let mut out = Vec::<u8>::new();
-            let indentation = " ".repeat(indent);
-            let formatter = serde_json::ser::PrettyFormatter::with_indent(indentation.as_bytes());
-            let mut s = serde_json::Serializer::with_formatter(&mut out, formatter);
-            serde::Serialize::serialize(&value, &mut s)
-                .map(|_| unsafe { String::from_utf8_unchecked(out) })
+        if sort_keys {
+            dump_json(&SortedKeys(&value), indent)
         } else {
-            serde_json::to_string(&value)
+            dump_json(&value, indent)
         }
         .map_err(|err| {
             Error::new(ErrorKind::InvalidOperation, "cannot serialize to JSON").with_source(err)
@@ -1062,56 +2202,116 @@ mod builtins {
         })
     }
 
+    #[cfg(feature = "json")]
+    fn dump_json<T: serde::Serialize>(
+        value: &T,
+        indent: Option<usize>,
+    ) -> Result<String, serde_json::Error> {
+        if let Some(indent) = indent {
+            let mut out = Vec::<u8>::new();
+            let indentation = " ".repeat(indent);
+            let formatter = serde_json::ser::PrettyFormatter::with_indent(indentation.as_bytes());
+            let mut s = serde_json::Serializer::with_formatter(&mut out, formatter);
+            serde::Serialize::serialize(value, &mut s)
+                .map(|_| unsafe { String::from_utf8_unchecked(out) })
+        } else {
+            serde_json::to_string(value)
+        }
+    }
+
+    /// Serializes a value with the keys of all maps sorted.
+    #[cfg(feature = "json")]
+    struct SortedKeys<'a>(&'a Value);
+
+    #[cfg(feature = "json")]
+    impl<'a> serde::Serialize for SortedKeys<'a> {
+        fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where
+            S: serde::Serializer,
+        {
+            use serde::ser::{SerializeMap, SerializeSeq};
+
+            match self.0.kind() {
+                ValueKind::Map => {
+                    let mut items = self
+                        .0
+                        .as_object()
+                        .and_then(|x| x.try_iter_pairs())
+                        .map(|x| x.collect::<Vec<_>>())
+                        .unwrap_or_default();
+                    items.sort_by(|a, b| a.0.cmp(&b.0));
+                    let mut map = ok!(serializer.serialize_map(Some(items.len())));
+                    for (key, value) in items.iter() {
+                        ok!(map.serialize_entry(key, &SortedKeys(value)));
+                    }
+                    map.end()
+                }
+                ValueKind::Seq | ValueKind::Iterable => {
+                    let mut seq = ok!(serializer.serialize_seq(self.0.len()));
+                    if let Ok(iter) = self.0.try_iter() {
+                        for item in iter {
+                            ok!(seq.serialize_element(&SortedKeys(&item)));
+                        }
+                    }
+                    seq.end()
+                }
+                _ => self.0.serialize(serializer),
+            }
+        }
+    }
+
     /// Indents Value with spaces
     ///
     /// The first optional parameter to the filter can be set to `true` to
     /// indent the first line. The parameter defaults to false.
     /// the second optional parameter to the filter can be set to `true`
     /// to indent blank lines. The parameter defaults to false.
+    /// Both can also be passed as keyword arguments named `first` and `blank`.
     /// This filter is useful, if you want to template yaml-files
     ///
+    /// Line endings (including `\r\n`) are preserved, a trailing newline is
+    /// removed.
+    ///
     /// ```jinja
     /// example:
     ///   config:
     /// {{ global_conifg|indent(2) }}          # does not indent first line
     /// {{ global_config|indent(2,true) }}     # indent whole Value with two spaces
     /// {{ global_config|indent(2,true,true)}} # indent whole Value and all blank lines
+    /// {{ global_config|indent(2, first=true, blank=true) }}
     /// ```
     #[cfg_attr(docsrs, doc(cfg(all(feature = "builtins"))))]
     pub fn indent(
-        mut value: String,
+        value: String,
         width: usize,
         indent_first_line: Option<bool>,
         indent_blank_lines: Option<bool>,
-    ) -> String {
-        fn strip_trailing_newline(input: &mut String) {
-            if input.ends_with('\n') {
-                input.truncate(input.len() - 1);
-            }
-            if input.ends_with('\r') {
-                input.truncate(input.len() - 1);
-            }
-        }
+        kwargs: Kwargs,
+    ) -> Result<String, Error> {
+        let first = ok!(kwargs.get::<Option<bool>>("first"))
+            .or(indent_first_line)
+            .unwrap_or(false);
+        let blank = ok!(kwargs.get::<Option<bool>>("blank"))
+            .or(indent_blank_lines)
+            .unwrap_or(false);
+        ok!(kwargs.assert_all_used());
+        Ok(indent_lines(&value, width, first, blank))
+    }
 
-        strip_trailing_newline(&mut value);
+    fn indent_lines(value: &str, width: usize, first: bool, blank: bool) -> String {
+        let value = value
+            .strip_suffix('\n')
+            .map(|x| x.strip_suffix('\r').unwrap_or(x))
+            .unwrap_or(value);
         let indent_with = " ".repeat(width);
-        let mut output = String::new();
-        let mut iterator = value.split('\n');
-        if !indent_first_line.unwrap_or(false) {
-            output.push_str(iterator.next().unwrap());
-            output.push('\n');
-        }
-        for line in iterator {
-            if line.is_empty() {
-                if indent_blank_lines.unwrap_or(false) {
-                    output.push_str(&indent_with);
-                }
-            } else {
-                write!(output, "{}{}", indent_with, line).ok();
+        let mut output = String::with_capacity(value.len());
+        for (idx, line) in value.split_inclusive('\n').enumerate() {
+            let is_blank = line.trim_end_matches(['\r', '\n']).is_empty();
+            if (idx > 0 || first) && (blank || !is_blank) {
+                output.push_str(&indent_with);
             }
-            output.push('\n');
+            output.push_str(line);
         }
-        strip_trailing_newline(&mut output);
         output
     }
 
@@ -1121,18 +2321,25 @@ mod builtins {
     /// encodes the stringified value.  If the value is none or undefined, an
     /// empty string is returned.
     ///
+    /// Strings are percent encoded so that only the unreserved characters of
+    /// RFC 3986 and `/` are retained.  This makes the result safe to use as a
+    /// path or as a query value.  Keys and values of maps are encoded the same
+    /// way except that `/` is encoded as well.  Pairs with a value of none or
+    /// undefined are skipped and the pairs are emitted in the iteration order
+    /// of the map.
+    ///
     /// ```jinja
     /// <a href="/search?{{ {"q": "my search", "lang": "fr"}|urlencode }}">Search</a>
     /// ```
     #[cfg_attr(docsrs, doc(cfg(all(feature = "builtins", feature = "urlencode"))))]
     #[cfg(feature = "urlencode")]
     pub fn urlencode(value: Value) -> Result<String, Error> {
-        const SET: &percent_encoding::AsciiSet = &percent_encoding::NON_ALPHANUMERIC
-            .remove(b'/')
+        const QUERY_SET: &percent_encoding::AsciiSet = &percent_encoding::NON_ALPHANUMERIC
             .remove(b'.')
             .remove(b'-')
             .remove(b'_')
-            .add(b' ');
+            .remove(b'~');
+        const SET: &percent_encoding::AsciiSet = &QUERY_SET.remove(b'/');
 
         if value.kind() == ValueKind::Map {
             let mut rv = String::new();
@@ -1147,8 +2354,8 @@ mod builtins {
                 write!(
                     rv,
                     "{}={}",
-                    percent_encoding::utf8_percent_encode(&k.to_string(), SET),
-                    percent_encoding::utf8_percent_encode(&v.to_string(), SET)
+                    percent_encoding::utf8_percent_encode(&k.to_string(), QUERY_SET),
+                    percent_encoding::utf8_percent_encode(&v.to_string(), QUERY_SET)
                 )
                 .unwrap();
             }
@@ -1175,18 +2382,20 @@ mod builtins {
     ) -> Result<Vec<Value>, Error> {
         let mut rv = vec![];
         let test = if let Some(test_name) = test_name {
-            Some(ok!(state
+            let test = ok!(state
                 .env
                 .get_test(&test_name)
-                .ok_or_else(|| Error::from(ErrorKind::UnknownTest))))
+                .ok_or_else(|| Error::from(ErrorKind::UnknownTest)));
+            ok!(state.env.check_call_policy(CallKind::Test, &test_name));
+            Some(test)
         } else {
             None
         };
-        for value in ok!(state.undefined_behavior().try_iter(value)) {
+        for value in ok!(state.undefined_behavior().try_iter_ref(&value)) {
             let test_value = if let Some(ref attr) = attr {
-                ok!(value.get_path(attr))
+                value.get_path(attr)
             } else {
-                value.clone()
+                value.as_ref().clone()
             };
             let passed = if let Some(test) = test {
                 let new_args = Some(test_value)
@@ -1198,7 +2407,7 @@ mod builtins {
                 test_value.is_true()
             };
             if passed != invert {
-                rv.push(value);
+                rv.push(value.into_owned());
             }
         }
         Ok(rv)
@@ -1326,7 +2535,7 @@ mod builtins {
             };
             for value in ok!(state.undefined_behavior().try_iter(value)) {
                 let sub_val = match attr.as_str() {
-                    Some(path) => value.get_path(path),
+                    Some(path) => Ok(value.get_path(path)),
                     None => value.get_item(&attr),
                 };
                 rv.push(match (sub_val, &default) {
@@ -1357,6 +2566,10 @@ mod builtins {
             .env
             .get_filter(filter_name)
             .ok_or_else(|| Error::from(ErrorKind::UnknownFilter)));
+        ok!(state.env.check_call_policy(CallKind::Filter, filter_name));
+        if state.env.strict_kwargs() {
+            ok!(kwargs.assert_all_used());
+        }
         for value in ok!(state.undefined_behavior().try_iter(value)) {
             let new_args = Some(value.clone())
                 .into_iter()
@@ -1498,6 +2711,8 @@ mod builtins {
     /// The unique items are yielded in the same order as their first occurrence
     /// in the iterable passed to the filter.  The filter will not detect
     /// duplicate objects or arrays, only primitives such as strings or numbers.
+    /// Like other sequence filters, undefined values are treated as empty
+    /// sequences unless the undefined behavior is strict.
     ///
     /// Optionally the `attribute` keyword argument can be used to make the filter
     /// operate on an attribute instead of the value itself.  In this case only
@@ -1512,9 +2727,10 @@ mod builtins {
     /// the resulting list will have `["CA", "NY"]`.  This can be disabled by
     /// passing `case_sensitive=True`.
     #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
-    pub fn unique(values: Vec<Value>, kwargs: Kwargs) -> Result<Value, Error> {
+    pub fn unique(state: &State, values: Value, kwargs: Kwargs) -> Result<Value, Error> {
         use std::collections::BTreeSet;
 
+        let values = ok!(state.undefined_behavior().try_iter(values));
         let attr = ok!(kwargs.get::<Option<&str>>("attribute"));
         let case_sensitive = ok!(kwargs.get::<Option<bool>>("case_sensitive")).unwrap_or(false);
         ok!(kwargs.assert_all_used());
@@ -1545,9 +2761,40 @@ mod builtins {
         Ok(Value::from(rv))
     }
 
+    /// Converts the value into a set.
+    ///
+    /// The set holds the unique items of the value in the order of their
+    /// first occurrence.  It behaves like a list except that membership
+    /// checks with `in` are answered in constant time, which is useful
+    /// when checking against the same values many times:
+    ///
+    /// ```jinja
+    /// {% set allowed = user.permissions|set %}
+    /// {% for item in items if item.permission in allowed %}
+    ///   <li>{{ item.title }}
+    /// {% endfor %}
+    /// ```
+    ///
+    /// See [`Value::from_set`] for details on how items are compared.
+    #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
+    pub fn set(state: &State, value: Value) -> Result<Value, Error> {
+        let iter = ok!(state.undefined_behavior().try_iter(value).map_err(|err| {
+            Error::new(ErrorKind::InvalidOperation, "cannot convert value to set").with_source(err)
+        }));
+        Ok(Value::from_set(iter))
+    }
+
     /// Pretty print a variable.
     ///
     /// This is useful for debugging as it better shows what's inside an object.
+    /// Nested sequences and maps are indented, strings are quoted and special
+    /// values such as `undefined` and `none` are shown by name.  Unlike
+    /// `tojson` this works for all values and does not require the `json`
+    /// feature.  The output is intended for humans and not to be parsed.
+    ///
+    /// ```jinja
+    /// <pre>{{ user|pprint }}</pre>
+    /// ```
     #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
     pub fn pprint(value: &Value) -> String {
         format!("{:#?}", value)
@@ -98,6 +98,8 @@ env.add_function("is_adult", is_adult);
 //! might change from one MiniJinja version to another.
 use std::fmt;
 use std::sync::Arc;
+#[cfg(feature = "async")]
+use std::{future::Future, pin::Pin, sync::Mutex};
 
 use crate::error::Error;
 use crate::utils::SealedMarker;
@@ -205,6 +207,7 @@ tuple_impls! { A B }
 tuple_impls! { A B C }
 tuple_impls! { A B C D }
 tuple_impls! { A B C D E }
+tuple_impls! { A B C D E F }
 
 impl BoxedFunction {
     /// Creates a new boxed filter.
@@ -257,6 +260,139 @@ impl Object for BoxedFunction {
     }
 }
 
+/// A utility trait that represents async functions and filters.
+///
+/// This works like [`Function`] but the callable returns a future which
+/// resolves to the return value.  Async callables are registered with
+/// [`add_async_function`](crate::Environment::add_async_function) and
+/// [`add_async_filter`](crate::Environment::add_async_filter) and are only
+/// awaited when a template is rendered with
+/// [`render_async`](crate::Template::render_async).
+///
+/// Because the future outlives the call, it cannot borrow from the
+/// arguments and neither can it hold on to the [`State`].  Arguments thus
+/// have to be owned values like `String` or [`Value`].
+///
+/// ```
+/// # use minijinja::Environment;
+/// # let mut env = Environment::new();
+/// env.add_async_function("fetch_user", |id: i64| async move {
+///     format!("user #{id}")
+/// });
+/// ```
+#[cfg(feature = "async")]
+pub trait AsyncFunction<Fut, Args>: Send + Sync + 'static {
+    /// Calls the function with the given arguments to create the future.
+    #[doc(hidden)]
+    fn invoke(&self, args: Args, _: SealedMarker) -> Fut;
+}
+
+#[cfg(feature = "async")]
+macro_rules! async_tuple_impls {
+    ( $( $name:ident )* ) => {
+        impl<Func, Fut, $($name),*> AsyncFunction<Fut, ($($name,)*)> for Func
+        where
+            Func: Fn($($name),*) -> Fut + Send + Sync + 'static,
+            Fut: Future + Send + 'static,
+            Fut::Output: FunctionResult,
+            $($name: for<'a> ArgType<'a>,)*
+        {
+            fn invoke(&self, args: ($($name,)*), _: SealedMarker) -> Fut {
+                #[allow(non_snake_case)]
+                let ($($name,)*) = args;
+                (self)($($name,)*)
+            }
+        }
+    };
+}
+
+#[cfg(feature = "async")]
+mod async_impls {
+    use super::*;
+
+    async_tuple_impls! {}
+    async_tuple_impls! { A }
+    async_tuple_impls! { A B }
+    async_tuple_impls! { A B C }
+    async_tuple_impls! { A B C D }
+    async_tuple_impls! { A B C D E }
+    async_tuple_impls! { A B C D E F }
+}
+
+/// The boxed future returned by async functions and filters.
+#[cfg(feature = "async")]
+pub(crate) type BoxFuture = Pin<Box<dyn Future<Output = Result<Value, Error>> + Send>>;
+
+/// Creates a callable that returns a [`PendingFuture`] when invoked.
+#[cfg(feature = "async")]
+pub(crate) fn make_async_callable<F, Fut, Args>(
+    f: F,
+) -> impl Fn(&State, &[Value]) -> Result<Value, Error> + Send + Sync + 'static
+where
+    F: AsyncFunction<Fut, Args> + for<'a> AsyncFunction<Fut, <Args as FunctionArgs<'a>>::Output>,
+    Fut: Future + Send + 'static,
+    Fut::Output: FunctionResult,
+    Args: for<'a> FunctionArgs<'a>,
+{
+    move |state, args| -> Result<Value, Error> {
+        let fut = f.invoke(ok!(Args::from_values(Some(state), args)), SealedMarker);
+        state
+            .pending_futures
+            .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        Ok(Value::from_object(PendingFuture(Mutex::new(Some(
+            Box::pin(async move { fut.await.into_result() }),
+        )))))
+    }
+}
+
+impl BoxedFunction {
+    /// Creates a new boxed function from an async function.
+    #[cfg(feature = "async")]
+    pub(crate) fn new_async<F, Fut, Args>(f: F) -> BoxedFunction
+    where
+        F: AsyncFunction<Fut, Args>
+            + for<'a> AsyncFunction<Fut, <Args as FunctionArgs<'a>>::Output>,
+        Fut: Future + Send + 'static,
+        Fut::Output: FunctionResult,
+        Args: for<'a> FunctionArgs<'a>,
+    {
+        BoxedFunction(
+            Arc::new(make_async_callable(f)),
+            #[cfg(feature = "debug")]
+            std::any::type_name::<F>(),
+        )
+    }
+}
+
+/// The not yet awaited result of an async function or filter.
+///
+/// The engine awaits these values right after the call that created them
+/// when rendering with [`render_async`](crate::Template::render_async).
+#[cfg(feature = "async")]
+pub(crate) struct PendingFuture(Mutex<Option<BoxFuture>>);
+
+#[cfg(feature = "async")]
+impl PendingFuture {
+    /// Takes the future out so that it can be awaited.
+    pub(crate) fn take(&self) -> Option<BoxFuture> {
+        self.0.lock().unwrap().take()
+    }
+}
+
+#[cfg(feature = "async")]
+impl fmt::Debug for PendingFuture {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("<pending future>")
+    }
+}
+
+#[cfg(feature = "async")]
+impl Object for PendingFuture {
+    fn repr(self: &Arc<Self>) -> ObjectRepr {
+        ObjectRepr::Plain
+    }
+}
+
 #[cfg(feature = "builtins")]
 mod builtins {
     use super::*;
@@ -415,7 +551,144 @@ mod builtins {
         }
         Ok(Value::from_object(ns))
     }
+
+    /// Creates a new counter that hands out increasing numbers.
+    ///
+    /// Every call to the `next()` method of the counter returns the current
+    /// number and advances the counter by one.  Counting starts at `0` unless
+    /// a different start is given.  Each call to `counter()` creates an
+    /// independent counter, but a counter stored in a variable is shared by
+    /// everything that sees the variable, including loop bodies and included
+    /// templates.  This is useful to generate unique IDs:
+    ///
+    /// ```jinja
+    /// {% set ids = counter(1) %}
+    /// {% for item in items %}
+    ///   <input id="field-{{ ids.next() }}" value="{{ item }}">
+    /// {% endfor %}
+    /// ```
+    #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
+    pub fn counter(start: Option<i64>) -> Value {
+        Value::from_object(crate::value::counter_object::Counter::new(
+            start.unwrap_or(0),
+        ))
+    }
 }
 
 #[cfg(feature = "builtins")]
 pub use self::builtins::*;
+
+#[cfg(feature = "i18n")]
+mod i18n {
+    use super::*;
+
+    use crate::error::ErrorKind;
+    use crate::utils::AutoEscape;
+    use crate::value::Kwargs;
+
+    fn translate(state: &State, msgid: &str) -> String {
+        match state.env().translator {
+            Some(ref translator) => translator(msgid),
+            None => msgid.to_string(),
+        }
+    }
+
+    fn interpolate(
+        state: &State,
+        msg: &str,
+        lookup: &dyn Fn(&str) -> Result<Option<Value>, Error>,
+    ) -> Result<Value, Error> {
+        let mut rv = String::with_capacity(msg.len());
+        let mut rest = msg;
+        while let Some(idx) = rest.find('%') {
+            rv.push_str(&rest[..idx]);
+            rest = &rest[idx..];
+            if let Some(after) = rest.strip_prefix("%%") {
+                rv.push('%');
+                rest = after;
+            } else if let Some((name, after)) = rest
+                .strip_prefix("%(")
+                .and_then(|x| x.split_once(')'))
+                .filter(|(_, after)| after.starts_with(['s', 'd']))
+            {
+                let value = ok!(ok!(lookup(name)).ok_or_else(|| {
+                    Error::new(
+                        ErrorKind::UndefinedError,
+                        format!("missing variable '{}' for translation", name),
+                    )
+                }));
+                rv.push_str(&ok!(state.format(value)));
+                rest = &after[1..];
+            } else {
+                rv.push('%');
+                rest = &rest[1..];
+            }
+        }
+        rv.push_str(rest);
+        Ok(if state.auto_escape() == AutoEscape::None {
+            Value::from(rv)
+        } else {
+            Value::from_safe_string(rv)
+        })
+    }
+
+    /// Translates a message.
+    ///
+    /// The message is passed to the translator configured with
+    /// [`set_translator`](crate::Environment::set_translator).  Afterwards
+    /// `%(name)s` placeholders are substituted with the passed keyword arguments
+    /// and `%%` is replaced with a literal percent sign.  When auto escaping is
+    /// enabled the substituted values are escaped and the result is marked as
+    /// safe.  This function is also available as `_`.  It is registered with
+    /// [`add_gettext_functions`](crate::Environment::add_gettext_functions).
+    ///
+    /// ```jinja
+    /// <p>{{ _("Hello %(name)s!", name=user.name) }}</p>
+    /// ```
+    #[cfg_attr(docsrs, doc(cfg(feature = "i18n")))]
+    pub fn gettext(state: &State, msgid: &str, kwargs: Kwargs) -> Result<Value, Error> {
+        interpolate(state, &translate(state, msgid), &|name| {
+            if kwargs.has(name) {
+                kwargs.get(name).map(Some)
+            } else {
+                Ok(None)
+            }
+        })
+    }
+
+    /// Translates a message with a plural form.
+    ///
+    /// Depending on `n` either the singular or the plural message is
+    /// translated.  Placeholders are substituted like with [`gettext`], the
+    /// count is additionally available as `%(num)s`.
+    ///
+    /// ```jinja
+    /// <p>{{ ngettext("%(num)s apple", "%(num)s apples", apples|length) }}</p>
+    /// ```
+    #[cfg_attr(docsrs, doc(cfg(feature = "i18n")))]
+    pub fn ngettext(
+        state: &State,
+        singular: &str,
+        plural: &str,
+        n: Value,
+        kwargs: Kwargs,
+    ) -> Result<Value, Error> {
+        let msgid = if n == Value::from(1) {
+            singular
+        } else {
+            plural
+        };
+        interpolate(state, &translate(state, msgid), &|name| {
+            if kwargs.has(name) {
+                kwargs.get(name).map(Some)
+            } else if name == "num" {
+                Ok(Some(n.clone()))
+            } else {
+                Ok(None)
+            }
+        })
+    }
+}
+
+#[cfg(feature = "i18n")]
+pub use self::i18n::*;
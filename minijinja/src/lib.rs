@@ -169,9 +169,13 @@
 //!
 //! - `fuel`: enables the `fuel` feature which makes the engine track fuel consumption which
 //!   can be used to better protect against expensive templates.
+//! - `stats`: makes the engine collect statistics about every render which can be
+//!   retrieved with [`State::stats`].
 //! - `loader`: enables owned and dynamic template loading of templates.
 //! - `custom_syntax`: when this feature is enabled, custom delimiters are supported by
 //!   the parser.
+//! - `custom_operators`: enables registering custom binary operators with
+//!   [`Environment::add_binary_operator`].
 //! - `preserve_order`: When enable the internal value implementation uses an indexmap
 //!   which preserves the original order of maps and structs.
 //! - `json`: When enabled the `tojson` filter is added as builtin filter as well as
@@ -179,6 +183,12 @@
 //! - `urlencode`: When enabled the `urlencode` filter is added as builtin filter.
 //! - `loop_controls`: enables the `{% break %}` and `{% continue %}` loop control flow
 //!    tags.
+//! - `i18n`: enables the `{% trans %}` tag, [`Environment::set_translator`] and
+//!   [`Environment::add_gettext_functions`] which registers the `gettext`, `_`
+//!   and `ngettext` functions.
+//! - `async`: enables async functions and filters
+//!   ([`Environment::add_async_function`], [`Environment::add_async_filter`])
+//!   as well as [`Template::render_async`].
 //!
 //! Performance and memory related features:
 //!
@@ -220,6 +230,7 @@ mod output;
 mod template;
 mod utils;
 mod vm;
+mod warning;
 
 pub mod filters;
 pub mod functions;
@@ -236,20 +247,34 @@ pub use loader::path_loader;
 #[cfg(feature = "debug")]
 mod debug;
 
+#[cfg(feature = "custom_operators")]
+mod operators;
+
+pub use self::compiler::lexer::{TrimReason, WhitespaceTrim};
 pub use self::defaults::{default_auto_escape_callback, escape_formatter};
 pub use self::environment::Environment;
-pub use self::error::{Error, ErrorKind};
+pub use self::error::{Error, ErrorKind, TemplateFrame};
 pub use self::expression::Expression;
-pub use self::output::Output;
-pub use self::template::Template;
-pub use self::utils::{AutoEscape, HtmlEscape, UndefinedBehavior};
+pub use self::output::{Output, Tee};
+pub use self::template::{RenderChunks, RenderMany, Template, TemplateMetrics};
+pub use self::utils::{
+    AutoEscape, CallKind, HtmlEscape, LoopCycleBehavior, UndefinedBehavior, UndefinedContext,
+};
 
 /// Re-export for convenience.
 pub use self::value::Value;
+pub use self::warning::{Warning, WarningKind};
 
 pub use self::macros::__context;
 pub use self::vm::State;
 
+#[cfg(feature = "custom_operators")]
+pub use self::operators::OperatorPrecedence;
+#[cfg(feature = "fuel")]
+pub use self::vm::FuelBudget;
+#[cfg(feature = "stats")]
+pub use self::vm::RenderStats;
+
 // fowards compatibility
 #[cfg(not(feature = "serde"))]
 const _: () = {
@@ -272,6 +297,38 @@ const _: () = {
 /// This module is only provided by the `unstable_machinery` feature and does not
 /// have a stable interface.  It mostly exists for internal testing purposes and
 /// for debugging.
+///
+/// It is also the entry point for tooling such as formatters, linters or
+/// language servers that need the structure of a template rather than the
+/// compiled instructions.  [`parse`](machinery::parse) returns the syntax tree
+/// of a template, and every statement and expression node in the
+/// [`ast`](machinery::ast) carries the [`Span`](machinery::Span) it was parsed
+/// from:
+///
+/// ```
+/// use minijinja::machinery::{ast, parse};
+///
+/// let tmpl = parse(
+///     "{% for item in seq %}{{ item }}{% endfor %}",
+///     "hello.txt",
+///     Default::default(),
+///     Default::default(),
+/// ).unwrap();
+/// if let ast::Stmt::Template(tmpl) = tmpl {
+///     if let ast::Stmt::ForLoop(ref for_loop) = tmpl.children[0] {
+///         assert_eq!(for_loop.span().start_line, 1);
+///         assert_eq!(for_loop.body.len(), 1);
+///     }
+/// }
+/// ```
+///
+/// **Stability:** the syntax tree mirrors what the compiler needs and it
+/// changes whenever the template language gains new features.  Nodes and
+/// fields can be added, renamed or removed in any release, including patch
+/// releases.  Tools depending on this module should pin the exact version of
+/// MiniJinja.  With the `unstable_machinery_serde` feature the tree can be
+/// serialized which is a good option for tools that do not want to match
+/// on the Rust types directly.
 #[cfg(feature = "unstable_machinery")]
 #[cfg_attr(docsrs, doc(cfg(feature = "unstable_machinery")))]
 pub mod machinery {
@@ -294,6 +351,49 @@ pub mod machinery {
         &tmpl.compiled
     }
 
+    /// Returns the [`Instructions`] currently executed by a [`State`](crate::State).
+    ///
+    /// Within a [trace callback](crate::Environment::set_trace_callback) this
+    /// is the instruction set that the reported program counter refers to.
+    /// When a block is rendered these are the instructions of the block
+    /// rather than the ones of the root template.  Comparing the executed
+    /// program counters against [`Instructions::iter_with_spans`] lets tools
+    /// find instructions that were never executed:
+    ///
+    /// ```
+    /// # use std::collections::{BTreeSet, HashSet};
+    /// # use std::sync::{Arc, Mutex};
+    /// # use minijinja::{context, Environment};
+    /// use minijinja::machinery::{get_compiled_template, get_state_instructions};
+    ///
+    /// let executed = Arc::new(Mutex::new(HashSet::new()));
+    /// let mut env = Environment::new();
+    /// let sink = executed.clone();
+    /// env.set_trace_callback(move |state, pc| {
+    ///     let instructions = get_state_instructions(state);
+    ///     if instructions.name() == state.name() {
+    ///         sink.lock().unwrap().insert(pc);
+    ///     }
+    /// });
+    /// env.add_template("hello.txt", "{% if flag -%}\n{{ never }}\n{%- endif %}").unwrap();
+    /// let tmpl = env.get_template("hello.txt").unwrap();
+    /// tmpl.render(context! { flag => false }).unwrap();
+    ///
+    /// let executed = executed.lock().unwrap();
+    /// let uncovered_lines = get_compiled_template(&tmpl)
+    ///     .instructions
+    ///     .iter_with_spans()
+    ///     .filter(|(idx, ..)| !executed.contains(idx))
+    ///     .filter_map(|(_, _, line, _)| line)
+    ///     .collect::<BTreeSet<_>>();
+    /// assert_eq!(uncovered_lines.into_iter().collect::<Vec<_>>(), [2]);
+    /// ```
+    pub fn get_state_instructions<'x, 'env>(
+        state: &'x crate::State<'_, 'env>,
+    ) -> &'x Instructions<'env> {
+        state.instructions
+    }
+
     /// Creates an [`Output`] that writes into a string.
     pub fn make_string_output(s: &mut String) -> Output<'_> {
         Output::with_string(s)
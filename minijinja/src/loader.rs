@@ -5,7 +5,7 @@ use std::fs;
 use std::io;
 use std::path::Path;
 use std::path::PathBuf;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 
 use memo_map::MemoMap;
 use self_cell::self_cell;
@@ -16,6 +16,7 @@ use crate::template::CompiledTemplate;
 use crate::template::TemplateConfig;
 
 type LoadFunc = dyn for<'a> Fn(&'a str) -> Result<Option<String>, Error> + Send + Sync;
+type VersionFunc = dyn for<'a> Fn(&'a str) -> Option<String> + Send + Sync;
 
 /// Internal utility for dynamic template loading.
 ///
@@ -23,24 +24,37 @@ type LoadFunc = dyn for<'a> Fn(&'a str) -> Result<Option<String>, Error> + Send
 /// source lifetime it borrows templates from, it becomes very inconvenient when
 /// it is shared. This object provides a solution for such cases. First templates
 /// are loaded into the source to decouple the lifetimes from the environment.
-#[derive(Clone)]
 pub(crate) struct LoaderStore<'source> {
     pub template_config: TemplateConfig,
     loader: Option<Arc<LoadFunc>>,
+    version_fn: Option<Arc<VersionFunc>>,
     owned_templates: MemoMap<Arc<str>, Arc<LoadedTemplate>>,
     borrowed_templates: BTreeMap<&'source str, Arc<CompiledTemplate<'source>>>,
+    // templates from the loader keyed by name and version.  Outdated versions
+    // might still be borrowed and are only dropped once the store is modified.
+    loaded_templates: MemoMap<(Arc<str>, Option<String>), Arc<LoadedTemplate>>,
+    current_versions: Mutex<BTreeMap<Arc<str>, Option<String>>>,
+}
+
+impl<'source> Clone for LoaderStore<'source> {
+    fn clone(&self) -> Self {
+        LoaderStore {
+            template_config: self.template_config.clone(),
+            loader: self.loader.clone(),
+            version_fn: self.version_fn.clone(),
+            owned_templates: self.owned_templates.clone(),
+            borrowed_templates: self.borrowed_templates.clone(),
+            loaded_templates: self.loaded_templates.clone(),
+            current_versions: Mutex::new(self.current_versions.lock().unwrap().clone()),
+        }
+    }
 }
 
 impl<'source> fmt::Debug for LoaderStore<'source> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let mut l = f.debug_list();
-        for key in self.owned_templates.keys() {
-            l.entry(key);
-        }
-        for key in self.borrowed_templates.keys() {
-            if !self.owned_templates.contains_key(*key) {
-                l.entry(key);
-            }
+        for (key, _) in self.iter() {
+            l.entry(&key);
         }
         l.finish()
     }
@@ -73,8 +87,11 @@ impl<'source> LoaderStore<'source> {
         LoaderStore {
             template_config,
             loader: None,
+            version_fn: None,
             owned_templates: MemoMap::default(),
             borrowed_templates: BTreeMap::default(),
+            loaded_templates: MemoMap::default(),
+            current_versions: Mutex::default(),
         }
     }
 
@@ -90,6 +107,7 @@ impl<'source> LoaderStore<'source> {
         match (source, name) {
             (Cow::Borrowed(source), Cow::Borrowed(name)) => {
                 self.owned_templates.remove(name);
+                self.forget_loaded(name);
                 self.borrowed_templates.insert(
                     name,
                     Arc::new(ok!(CompiledTemplate::new(
@@ -101,6 +119,7 @@ impl<'source> LoaderStore<'source> {
             }
             (source, name) => {
                 self.borrowed_templates.remove(&name as &str);
+                self.forget_loaded(&name);
                 let name: Arc<str> = name.into();
                 self.owned_templates.replace(
                     name.clone(),
@@ -115,29 +134,41 @@ impl<'source> LoaderStore<'source> {
     pub fn remove(&mut self, name: &str) {
         self.borrowed_templates.remove(name);
         self.owned_templates.remove(name);
+        self.forget_loaded(name);
     }
 
     pub fn clear(&mut self) {
         self.borrowed_templates.clear();
         self.owned_templates.clear();
+        self.loaded_templates.clear();
+        self.current_versions.get_mut().unwrap().clear();
     }
 
     pub fn get(&self, name: &str) -> Result<&CompiledTemplate<'_>, Error> {
         if let Some(rv) = self.borrowed_templates.get(name) {
-            Ok(&**rv)
-        } else {
-            let name: Arc<str> = name.into();
-            self.owned_templates
-                .get_or_try_insert(&name.clone(), || -> Result<_, Error> {
-                    let loader_result = match self.loader {
-                        Some(ref loader) => ok!(loader(&name)),
-                        None => None,
-                    }
-                    .ok_or_else(|| Error::new_not_found(&name));
-                    self.make_owned_template(name, ok!(loader_result))
-                })
-                .map(|x| x.borrow_dependent())
+            return Ok(&**rv);
+        } else if let Some(rv) = self.owned_templates.get(name) {
+            return Ok(rv.borrow_dependent());
+        }
+
+        let name: Arc<str> = name.into();
+        let version = self.version_fn.as_ref().and_then(|f| f(&name));
+        let track_version = self.version_fn.is_some();
+        let rv = ok!(self.loaded_templates.get_or_try_insert_owned(
+            (name.clone(), version.clone()),
+            || {
+                let loader_result = match self.loader {
+                    Some(ref loader) => ok!(loader(&name)),
+                    None => None,
+                }
+                .ok_or_else(|| Error::new_not_found(&name));
+                self.make_owned_template(name.clone(), ok!(loader_result))
+            }
+        ));
+        if track_version {
+            self.current_versions.lock().unwrap().insert(name, version);
         }
+        Ok(rv.borrow_dependent())
     }
 
     pub fn set_loader<F>(&mut self, f: F)
@@ -147,6 +178,40 @@ impl<'source> LoaderStore<'source> {
         self.loader = Some(Arc::new(f));
     }
 
+    pub fn set_version_fn<F>(&mut self, f: F)
+    where
+        F: Fn(&str) -> Option<String> + Send + Sync + 'static,
+    {
+        self.version_fn = Some(Arc::new(f));
+        self.drop_outdated();
+    }
+
+    fn forget_loaded(&mut self, name: &str) {
+        if self.version_fn.is_none() {
+            self.loaded_templates.remove(&(Arc::from(name), None));
+        } else {
+            self.current_versions.get_mut().unwrap().remove(name);
+            self.drop_outdated();
+        }
+    }
+
+    /// Drops the loaded templates that are not the current version.
+    ///
+    /// This can only happen with exclusive access as outdated versions might
+    /// otherwise still be borrowed.
+    fn drop_outdated(&mut self) {
+        let current_versions = self.current_versions.get_mut().unwrap();
+        let outdated = self
+            .loaded_templates
+            .keys()
+            .filter(|(name, version)| current_versions.get(name) != Some(version))
+            .cloned()
+            .collect::<Vec<_>>();
+        for key in outdated {
+            self.loaded_templates.remove(&key);
+        }
+    }
+
     fn make_owned_template(
         &self,
         name: Arc<str>,
@@ -172,7 +237,20 @@ impl<'source> LoaderStore<'source> {
             .iter()
             .map(|(name, template)| (&**name, template.borrow_dependent()));
 
-        borrowed.chain(owned)
+        let current_versions = self
+            .version_fn
+            .as_ref()
+            .map(|_| self.current_versions.lock().unwrap().clone());
+        let loaded = self
+            .loaded_templates
+            .iter()
+            .filter(move |((name, version), _)| match current_versions {
+                Some(ref current_versions) => current_versions.get(name) == Some(version),
+                None => true,
+            })
+            .map(|((name, _), template)| (&**name, template.borrow_dependent()));
+
+        borrowed.chain(owned).chain(loaded)
     }
 }
 
@@ -0,0 +1,101 @@
+use std::fmt;
+use std::sync::Arc;
+
+use crate::error::Error;
+use crate::value::Value;
+
+/// The characters that custom operator symbols can be made of.
+const OPERATOR_CHARS: &str = "!#$%&*+-./:;<=>?@\\^`|~";
+
+/// Type alias for the function of a custom binary operator.
+pub(crate) type BinaryOperatorFunc = dyn Fn(&Value, &Value) -> Result<Value, Error> + Sync + Send;
+
+/// Defines how tightly a custom binary operator binds.
+///
+/// Custom operators slot into the precedence levels of the built-in
+/// operators.  Like the built-in ones they are left associative.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "unstable_machinery_serde",
+    derive(serde::Serialize),
+    serde(rename_all = "snake_case")
+)]
+#[non_exhaustive]
+pub enum OperatorPrecedence {
+    /// Binds like the comparison operators (`==`, `<`, `in`, …).
+    Comparison,
+    /// Binds like `+` and `-`.
+    Additive,
+    /// Binds like `*`, `/`, `//` and `%`.
+    Multiplicative,
+    /// Binds like `**`.
+    Power,
+}
+
+/// A registered custom binary operator.
+#[derive(Clone)]
+pub(crate) struct CustomOperator {
+    pub symbol: String,
+    pub precedence: OperatorPrecedence,
+    pub func: Arc<BinaryOperatorFunc>,
+}
+
+/// The custom binary operators known to an environment.
+///
+/// Operators are referred to by their index which is stable: registering a
+/// symbol a second time replaces the function but keeps the index so that
+/// templates compiled earlier keep working.
+#[derive(Clone, Default)]
+pub(crate) struct CustomOperators {
+    ops: Vec<CustomOperator>,
+}
+
+impl fmt::Debug for CustomOperators {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list()
+            .entries(self.ops.iter().map(|x| &x.symbol))
+            .finish()
+    }
+}
+
+impl CustomOperators {
+    /// Registers an operator or replaces the one with the same symbol.
+    pub fn insert(
+        &mut self,
+        symbol: &str,
+        precedence: OperatorPrecedence,
+        func: Arc<BinaryOperatorFunc>,
+    ) {
+        assert!(
+            !symbol.is_empty() && symbol.chars().all(|c| OPERATOR_CHARS.contains(c)),
+            "invalid operator symbol {symbol:?}"
+        );
+        let op = CustomOperator {
+            symbol: symbol.to_string(),
+            precedence,
+            func,
+        };
+        match self.ops.iter_mut().find(|x| x.symbol == symbol) {
+            Some(existing) => *existing = op,
+            None => self.ops.push(op),
+        }
+    }
+
+    /// Looks up an operator by id.
+    pub fn get(&self, id: u32) -> Option<&CustomOperator> {
+        self.ops.get(id as usize)
+    }
+
+    /// Finds the longest operator at the start of `rest`.
+    ///
+    /// Only operators longer than `min_len` are considered, which is how the
+    /// built-in operators win over custom operators with the same symbol.
+    pub fn find_prefix(&self, rest: &str, min_len: usize) -> Option<(u32, &CustomOperator)> {
+        self.ops
+            .iter()
+            .enumerate()
+            .filter(|(_, op)| op.symbol.len() > min_len && rest.starts_with(op.symbol.as_str()))
+            .max_by_key(|(_, op)| op.symbol.len())
+            .map(|(idx, op)| (idx as u32, op))
+    }
+}
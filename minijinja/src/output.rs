@@ -22,6 +22,8 @@ pub enum CaptureMode {
 pub struct Output<'a> {
     w: &'a mut (dyn fmt::Write + 'a),
     capture_stack: Vec<Option<String>>,
+    #[cfg(feature = "fuel")]
+    bytes_written: usize,
 }
 
 impl<'a> Output<'a> {
@@ -30,6 +32,8 @@ impl<'a> Output<'a> {
         Self {
             w: buf,
             capture_stack: Vec::new(),
+            #[cfg(feature = "fuel")]
+            bytes_written: 0,
         }
     }
 
@@ -37,6 +41,8 @@ impl<'a> Output<'a> {
         Self {
             w,
             capture_stack: Vec::new(),
+            #[cfg(feature = "fuel")]
+            bytes_written: 0,
         }
     }
 
@@ -48,6 +54,8 @@ impl<'a> Output<'a> {
         Self {
             w: NullWriter::get_mut(),
             capture_stack: vec![None],
+            #[cfg(feature = "fuel")]
+            bytes_written: 0,
         }
     }
 
@@ -59,6 +67,18 @@ impl<'a> Output<'a> {
         });
     }
 
+    /// Returns the number of active captures.
+    #[cfg(feature = "multi_template")]
+    pub(crate) fn capture_depth(&self) -> usize {
+        self.capture_stack.len()
+    }
+
+    /// Ends all captures above the given depth and discards what they captured.
+    #[cfg(feature = "multi_template")]
+    pub(crate) fn end_captures_above(&mut self, depth: usize) {
+        self.capture_stack.truncate(depth);
+    }
+
     /// Ends capturing and returns the captured string as value.
     pub(crate) fn end_capture(&mut self, auto_escape: AutoEscape) -> Value {
         if let Some(captured) = self.capture_stack.pop().unwrap() {
@@ -88,33 +108,149 @@ impl<'a> Output<'a> {
         matches!(self.capture_stack.last(), Some(None))
     }
 
+    /// Returns `true` if the output is captured or discarded.
+    #[inline(always)]
+    pub(crate) fn is_capturing(&self) -> bool {
+        !self.capture_stack.is_empty()
+    }
+
+    /// Returns the number of bytes written so far.
+    ///
+    /// This counts all bytes, including the ones that were captured or
+    /// discarded.
+    #[cfg(feature = "fuel")]
+    #[inline(always)]
+    pub(crate) fn bytes_written(&self) -> usize {
+        self.bytes_written
+    }
+
+    /// Takes the capture stack so it can be restored into another output.
+    #[cfg(feature = "async")]
+    pub(crate) fn take_capture_stack(&mut self) -> Vec<Option<String>> {
+        std::mem::take(&mut self.capture_stack)
+    }
+
+    /// Restores a capture stack previously taken from another output.
+    #[cfg(feature = "async")]
+    pub(crate) fn restore_capture_stack(&mut self, capture_stack: Vec<Option<String>>) {
+        self.capture_stack = capture_stack;
+    }
+
     /// Writes some data to the underlying buffer contained within this output.
     #[inline]
     pub fn write_str(&mut self, s: &str) -> fmt::Result {
+        #[cfg(feature = "fuel")]
+        {
+            self.bytes_written += s.len();
+        }
         self.target().write_str(s)
     }
 
     /// Writes some formatted information into this instance.
     #[inline]
     pub fn write_fmt(&mut self, a: fmt::Arguments<'_>) -> fmt::Result {
-        self.target().write_fmt(a)
+        // with fuel the bytes need to be counted which happens in write_str
+        #[cfg(feature = "fuel")]
+        {
+            fmt::write(self, a)
+        }
+        #[cfg(not(feature = "fuel"))]
+        {
+            self.target().write_fmt(a)
+        }
     }
 }
 
 impl fmt::Write for Output<'_> {
     #[inline]
     fn write_str(&mut self, s: &str) -> fmt::Result {
-        fmt::Write::write_str(self.target(), s)
+        Output::write_str(self, s)
     }
 
     #[inline]
     fn write_char(&mut self, c: char) -> fmt::Result {
+        #[cfg(feature = "fuel")]
+        {
+            self.bytes_written += c.len_utf8();
+        }
         fmt::Write::write_char(self.target(), c)
     }
 
     #[inline]
     fn write_fmt(&mut self, args: fmt::Arguments<'_>) -> fmt::Result {
-        fmt::Write::write_fmt(self.target(), args)
+        Output::write_fmt(self, args)
+    }
+}
+
+/// A writer that forwards everything written to two writers.
+///
+/// This implements [`io::Write`] if both writers implement it and
+/// [`fmt::Write`] if both implement that.  Writes go to the first writer
+/// and then to the second and fail as soon as either of them fails.
+///
+/// Passed to [`Template::render_to_write`](crate::Template::render_to_write)
+/// it receives the rendered output once it is final.  Output that is captured
+/// internally (for instance by `{% set %}` blocks or filter blocks) and later
+/// discarded never reaches either writer.  This is useful to keep a copy of
+/// the generated content, for instance for audit logging.
+///
+/// ```
+/// # use minijinja::{Environment, Tee};
+/// # let mut env = Environment::new();
+/// # env.add_template("hello", "Hello {{ name }}!").unwrap();
+/// let mut rv = Vec::new();
+/// let mut log = Vec::new();
+/// let tmpl = env.get_template("hello").unwrap();
+/// tmpl.render_to_write(minijinja::context!(name => "John"), Tee::new(&mut rv, &mut log))
+///     .unwrap();
+/// assert_eq!(rv, b"Hello John!");
+/// assert_eq!(log, b"Hello John!");
+/// ```
+#[derive(Debug)]
+pub struct Tee<A, B> {
+    a: A,
+    b: B,
+}
+
+impl<A, B> Tee<A, B> {
+    /// Creates a writer that writes to both `a` and `b`.
+    pub fn new(a: A, b: B) -> Tee<A, B> {
+        Tee { a, b }
+    }
+
+    /// Returns the two writers.
+    pub fn into_inner(self) -> (A, B) {
+        (self.a, self.b)
+    }
+}
+
+impl<A: io::Write, B: io::Write> io::Write for Tee<A, B> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // both writers need to see the same bytes, so always write fully.
+        ok!(self.write_all(buf));
+        Ok(buf.len())
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        ok!(self.a.write_all(buf));
+        self.b.write_all(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        ok!(self.a.flush());
+        self.b.flush()
+    }
+}
+
+impl<A: fmt::Write, B: fmt::Write> fmt::Write for Tee<A, B> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        ok!(self.a.write_str(s));
+        self.b.write_str(s)
+    }
+
+    fn write_char(&mut self, c: char) -> fmt::Result {
+        ok!(self.a.write_char(c));
+        self.b.write_char(c)
     }
 }
 
@@ -148,11 +284,15 @@ pub struct WriteWrapper<W> {
 
 impl<W> WriteWrapper<W> {
     /// Replaces the given error with the held error if available.
+    ///
+    /// The location of the original error is retained so that it's possible
+    /// to tell which template was writing when the writer failed.
     pub fn take_err(&mut self, original: Error) -> Error {
         self.err
             .take()
             .map(|io_err| {
                 Error::new(ErrorKind::WriteFailure, "I/O error during rendering")
+                    .with_location_of(&original)
                     .with_source(io_err)
             })
             .unwrap_or(original)
@@ -27,13 +27,16 @@
 //!   - [`{% import %}`](#-import-)
 //!   - [`{% with %}`](#-with-)
 //!   - [`{% set %}`](#-set-)
+//!   - [`{% const %}`](#-const-)
 //!   - [`{% filter %}`](#-filter-)
 //!   - [`{% macro %}`](#-macro-)
 //!   - [`{% call %}`](#-call-)
 //!   - [`{% do %}`](#-do-)
 //!   - [`{% autoescape %}`](#-autoescape-)
+//!   - [`{% undefined %}`](#-undefined-)
 //!   - [`{% raw %}`](#-raw-)
 //!   - [`{% break %} / {% continue %}`](#-break----continue-)
+//!   - [`{% trans %}`](#-trans-)
 #![cfg_attr(
     feature = "custom_syntax",
     doc = "- [Custom Delimiters](#custom-delimiters)"
@@ -231,6 +234,11 @@
 //! will happily serialize once into a a list etc.  However when iterating over an actual iterator,
 //! `last`, `revindex` and `revindex0` will always be undefined.
 //!
+//! The `loop` variable only exists in the body of the loop itself.  Macros declared
+//! outside of the loop do not see it when they are called from the loop body, so
+//! it has to be passed to them explicitly (`{{ render_row(row, loop) }}`).  Calling
+//! `loop()` to recurse always has to happen directly in the loop body.
+//!
 //! Within a for-loop, it’s possible to cycle among a list of strings/variables each time through
 //! the loop by using the special `loop.cycle` helper:
 //!
@@ -293,6 +301,20 @@
 //! </ul>
 //! ```
 //!
+//! When iterating over values of unknown length (for instance lazy iterators
+//! provided by the application), `loop.length`, `loop.revindex`,
+//! `loop.revindex0` and `loop.last` are not available.  The ``precount``
+//! modifier makes the loop buffer up all items before the iteration starts so
+//! that the length is known.  Unlike converting the value with the `list`
+//! filter, this does not change the value itself.  Keep in mind that this holds
+//! all items in memory at once.
+//!
+//! ```jinja
+//! {% for user in active_users precount %}
+//!   {{ user.name }}{% if not loop.last %}, {% endif %}
+//! {% endfor %}
+//! ```
+//!
 //! **Special note:** the `previtem` and `nextitem` attributes are available by default
 //! but can be disabled by removing the `adjacent_loop_items` crate feature.  Removing
 //! these attributes can provide meaningful speedups for templates with a lot of loops.
@@ -441,6 +463,24 @@
 //! ```
 //!  
 //! Included templates have access to the variables of the active context.
+//! Alternatively a map of variables can be passed with `with`.  In that case
+//! the included template only sees these variables and the globals.  The map
+//! is evaluated in the scope of the including template:
+//!
+//! ```jinja
+//! {% include "card.html" with {"title": item.title, "body": item.body} %}
+//! ```
+//!
+//! With `ignore errors` an error while rendering the included template does
+//! not fail the including template.  Instead the include renders nothing and
+//! the error is reported as a [warning](crate::Environment::set_warning_sink).
+//! A template that cannot be found is still an error unless `ignore missing`
+//! is also given:
+//!
+//! ```jinja
+//! {% include "widget.html" ignore errors %}
+//! {% include "widget.html" ignore missing ignore errors %}
+//! ```
 //!
 //! ## `{% import %}`
 //!
@@ -542,6 +582,41 @@
 //! objects; attempting to assign an attribute on any other object will cause
 //! an error.
 //!
+//! Assignments can also reach into nested namespaces.  Intermediate namespaces
+//! that do not exist yet are created automatically which makes namespaces
+//! usable as small trees for aggregating data:
+//!
+//! ```jinja
+//! {% set ns = namespace() %}
+//! {% for user in users %}
+//!   {% set ns.last.name = user.name %}
+//!   {% set ns.last.group = user.group %}
+//! {% endfor %}
+//! Last user: {{ ns.last.name }} ({{ ns.last.group }})
+//! ```
+//!
+//! ## `{% const %}`
+//!
+//! The `const` statement works like `set` but the expression is only evaluated
+//! once.  The value is computed the first time the template is rendered and then
+//! cached for as long as the compiled template is kept alive, so later renders
+//! reuse it.  This is useful for values that are expensive to compute but do not
+//! change between renders:
+//!
+//! ```jinja
+//! {% const countries = load_countries()|sort(attribute="name") %}
+//! ```
+//!
+//! As the value is shared between renders, the expression can only refer to
+//! globals and to constants declared earlier in the same template.  This is
+//! checked when the template is compiled: referencing any other variable, such
+//! as one passed in the render context or one the template assigns itself with
+//! `set`, `macro` or `import`, is a syntax error.  For this reason globals used
+//! by constants must be registered before the template is loaded.  Changes to
+//! globals made after the constant was first evaluated are not picked up.
+//! Constants can only be declared on the top level of a template and every name
+//! can only be declared once.
+//!
 //! ## `{% filter %}`
 //!
 //! Filter sections allow you to apply regular [filters](crate::filters) on a
@@ -553,6 +628,16 @@
 //! {% endfilter %}
 //! ```
 //!
+//! Filters can be passed arguments and chained like in expressions.  The
+//! captured block is passed to the filter as first argument.  With auto escaping
+//! enabled, the block is already escaped at that point:
+//!
+//! ```jinja
+//! {% filter replace("Hello", "Goodbye")|upper %}
+//!   Hello World!
+//! {% endfilter %}
+//! ```
+//!
 //! ## `{% macro %}`
 //!
 //! **Feature:** `macros` (included by default)
@@ -704,6 +789,25 @@
 //! The exact auto escaping behavior is determined by the value of
 //! [`AutoEscape`](crate::AutoEscape) set to the template.
 //!
+//! ## `{% undefined %}`
+//!
+//! Switches the [undefined behavior](crate::UndefinedBehavior) for a section
+//! of a template.  This is useful if the environment is configured to be
+//! strict but a section deals with optional data.  Valid values are
+//! `"lenient"`, `"chainable"` and `"strict"`.
+//!
+//! Example:
+//!
+//! ```jinja
+//! {% undefined "lenient" %}
+//!   {{ user.nickname }} renders empty if the attribute is missing
+//! {% endundefined %}
+//! ```
+//!
+//! After an `endundefined` the behavior is reverted to what it was before.
+//! Macros called and templates included from within the block use the
+//! changed behavior too.
+//!
 //! ## `{% raw %}`
 //!
 //! A raw block is a special construct that lets you ignore the embedded template
@@ -746,6 +850,37 @@
 //! {%- endfor %}
 //! ```
 //!
+//! ## `{% trans %}`
+//!
+//! If MiniJinja was compiled with the `i18n` feature, the `trans` tag marks a
+//! block for translation.  The body is turned into a message id where variables
+//! become `%(name)s` placeholders and passed to the translator configured with
+//! [`set_translator`](crate::Environment::set_translator).  Only plain variables
+//! are allowed in the body, more complex expressions have to be bound in the
+//! tag:
+//!
+//! ```jinja
+//! {% trans user=user.name %}Hello {{ user }}!{% endtrans %}
+//! ```
+//!
+//! With `{% pluralize %}` a plural form can be provided.  The count is taken
+//! from the variable passed to `pluralize` or otherwise from the first
+//! variable in the block:
+//!
+//! ```jinja
+//! {% trans count=users|length %}
+//! There is {{ count }} user.
+//! {% pluralize %}
+//! There are {{ count }} users.
+//! {% endtrans %}
+//! ```
+//!
+//! `{% trans trimmed %}` collapses the whitespace around line breaks in the
+//! message.  Translated messages respect auto escaping: the variables are
+//! escaped, the message itself is not.  Once a translator is set, the same
+//! translation functions are also available as `gettext` (or `_`) and
+//! `ngettext`.
+//!
 #![cfg_attr(
     feature = "custom_syntax",
     doc = r###"
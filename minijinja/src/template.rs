@@ -1,4 +1,5 @@
-use std::collections::{BTreeMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::iter::FusedIterator;
 use std::ops::Deref;
 use std::sync::Arc;
 use std::{fmt, io};
@@ -6,17 +7,29 @@ use std::{fmt, io};
 use serde::Serialize;
 
 use crate::compiler::codegen::CodeGenerator;
-use crate::compiler::instructions::Instructions;
-use crate::compiler::lexer::WhitespaceConfig;
-use crate::compiler::meta::find_undeclared;
+use crate::compiler::instructions::{Instruction, Instructions};
+use crate::compiler::lexer::{whitespace_trims, WhitespaceConfig, WhitespaceTrim};
+use crate::compiler::meta::{check_const_decls, find_undeclared};
+#[cfg(not(feature = "custom_operators"))]
 use crate::compiler::parser::parse;
-use crate::environment::Environment;
+#[cfg(feature = "custom_operators")]
+use crate::compiler::parser::parse_with_operators;
+use crate::environment::{clamp_recursion_limit, Environment};
+#[cfg(feature = "async")]
+use crate::error::ErrorKind;
 use crate::error::{attach_basic_debug_info, Error};
+#[cfg(feature = "multi_template")]
+use crate::output::CaptureMode;
 use crate::output::{Output, WriteWrapper};
 use crate::syntax::SyntaxConfig;
-use crate::utils::AutoEscape;
+#[cfg(feature = "multi_template")]
+use crate::utils::run_in_threads;
+use crate::utils::{AutoEscape, CallableIds};
+use crate::value::merge_object::MergeObject;
 use crate::value::{self, Value};
-use crate::vm::{prepare_blocks, Context, State, Vm};
+#[cfg(feature = "fuel")]
+use crate::vm::FuelBudget;
+use crate::vm::{prepare_blocks, BlockStack, Context, Registers, State, Suspend, Vm};
 
 /// Callback for auto escape determination
 pub type AutoEscapeFunc = dyn Fn(&str) -> AutoEscape + Sync + Send;
@@ -30,6 +43,13 @@ pub struct TemplateConfig {
     pub ws_config: WhitespaceConfig,
     /// The callback that determines the initial auto escaping for templates.
     pub default_auto_escape: Arc<AutoEscapeFunc>,
+    /// The ids of the filter and test names known to the environment.
+    pub(crate) callable_ids: Arc<CallableIds>,
+    /// The names of the globals known to the environment.
+    pub(crate) global_names: Arc<BTreeSet<String>>,
+    /// The custom binary operators known to the environment.
+    #[cfg(feature = "custom_operators")]
+    pub(crate) custom_operators: Arc<crate::operators::CustomOperators>,
 }
 
 impl TemplateConfig {
@@ -38,6 +58,10 @@ impl TemplateConfig {
             syntax_config: SyntaxConfig::default(),
             ws_config: WhitespaceConfig::default(),
             default_auto_escape,
+            callable_ids: Default::default(),
+            global_names: Default::default(),
+            #[cfg(feature = "custom_operators")]
+            custom_operators: Default::default(),
         }
     }
 }
@@ -118,6 +142,31 @@ impl<'env, 'source> Template<'env, 'source> {
         self._render(Value::from_serialize(&ctx)).map(|x| x.0)
     }
 
+    /// Renders the template with a shared base context and per-render overrides.
+    ///
+    /// This is useful when the same template is rendered many times with a
+    /// large, mostly static context.  The base context is only referenced and
+    /// never copied so it can be created once (eg: with
+    /// [`Value::from_serialize`]) and shared across renders and threads.
+    /// Values in `ctx` shadow values of the same name in `base`.
+    ///
+    /// ```
+    /// # use minijinja::{Environment, context, value::Value};
+    /// # let mut env = Environment::new();
+    /// let base = Value::from_serialize(context! { site => "Example", user => "Anonymous" });
+    /// let tmpl = env.template_from_str("{{ site }}: Hello {{ user }}!").unwrap();
+    /// let rv = tmpl.render_with_base_context(&base, context!(user => "John")).unwrap();
+    /// assert_eq!(rv, "Example: Hello John!");
+    /// ```
+    pub fn render_with_base_context<S: Serialize>(
+        &self,
+        base: &Value,
+        ctx: S,
+    ) -> Result<String, Error> {
+        let root = Value::from_object(MergeObject(vec![Value::from_serialize(&ctx), base.clone()]));
+        self._render(root).map(|x| x.0)
+    }
+
     /// Like [`render`](Self::render) but also return the evaluated [`State`].
     ///
     /// This can be used to inspect the [`State`] of the template post evaluation
@@ -144,10 +193,197 @@ impl<'env, 'source> Template<'env, 'source> {
         self._render(Value::from_serialize(&ctx))
     }
 
+    /// Like [`render`](Self::render) but with a different recursion limit.
+    ///
+    /// This renders the template with the given recursion limit instead of the
+    /// one configured on the environment via
+    /// [`Environment::set_recursion_limit`].  The limit can be both higher
+    /// and lower than the one of the environment and it applies to macros
+    /// and included templates as well.  The same restrictions as for
+    /// [`Environment::set_recursion_limit`] apply.  In particular the limit
+    /// cannot be raised above the default unless the `stacker` feature is
+    /// enabled.
+    ///
+    /// ```
+    /// # use minijinja::{Environment, context, ErrorKind};
+    /// # let mut env = Environment::new();
+    /// let tmpl = env.template_from_str("{% for x in seq %}{% for y in x %}{{ y }}{% endfor %}{% endfor %}").unwrap();
+    /// let ctx = context!{ seq => vec![vec![1, 2], vec![3]] };
+    /// assert_eq!(tmpl.render_with_recursion_limit(&ctx, 10).unwrap(), "123");
+    /// let err = tmpl.render_with_recursion_limit(&ctx, 1).unwrap_err();
+    /// assert_eq!(err.kind(), ErrorKind::InvalidOperation);
+    /// ```
+    pub fn render_with_recursion_limit<S: Serialize>(
+        &self,
+        ctx: S,
+        recursion_limit: usize,
+    ) -> Result<String, Error> {
+        let capacity = self.compiled.buffer_size_hint;
+        self._render_with_state(Value::from_serialize(&ctx), capacity, |state| {
+            state
+                .ctx
+                .set_recursion_limit(clamp_recursion_limit(recursion_limit));
+        })
+    }
+
+    /// Like [`render`](Self::render) but consumes fuel from a shared budget.
+    ///
+    /// Normally every render starts out with the fuel configured on the
+    /// environment via [`Environment::set_fuel`].  This instead charges the
+    /// fuel to the given [`FuelBudget`] which can be passed to multiple
+    /// renders.  The fuel consumed by one render is then no longer available
+    /// to the following ones and once the budget is exhausted, rendering
+    /// fails with [`OutOfFuel`](crate::ErrorKind::OutOfFuel).  The budget is
+    /// used even if no fuel is configured on the environment.
+    ///
+    /// ```
+    /// # use minijinja::{Environment, context, ErrorKind, FuelBudget};
+    /// # let mut env = Environment::new();
+    /// let tmpl = env.template_from_str("{% for x in seq %}{{ x }}{% endfor %}").unwrap();
+    /// let budget = FuelBudget::new(100);
+    /// let rv = tmpl.render_with_fuel_budget(context!(seq => [1, 2, 3]), &budget).unwrap();
+    /// assert_eq!(rv, "123");
+    /// println!("remaining fuel: {}", budget.remaining());
+    /// ```
+    #[cfg(feature = "fuel")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "fuel")))]
+    pub fn render_with_fuel_budget<S: Serialize>(
+        &self,
+        ctx: S,
+        budget: &FuelBudget,
+    ) -> Result<String, Error> {
+        let capacity = self.compiled.buffer_size_hint;
+        self._render_with_state(Value::from_serialize(&ctx), capacity, |state| {
+            state.fuel_tracker = Some(budget.tracker());
+        })
+    }
+
+    /// Like [`render`](Self::render) but with a size hint for the output.
+    ///
+    /// Normally the string that is rendered into starts out with a capacity
+    /// that is estimated from the template source.  If it's known upfront
+    /// that the template produces a lot more (or a lot less) output, the
+    /// capacity can be provided here to avoid repeated reallocations while
+    /// rendering.  The hint does not limit the output in any way.
+    ///
+    /// ```
+    /// # use minijinja::{Environment, context};
+    /// # let mut env = Environment::new();
+    /// let tmpl = env.template_from_str("{% for x in seq %}{{ x }}{% endfor %}").unwrap();
+    /// let rv = tmpl.render_with_capacity(context!(seq => (0..1000).collect::<Vec<_>>()), 4096).unwrap();
+    /// assert_eq!(rv.len(), 2890);
+    /// ```
+    pub fn render_with_capacity<S: Serialize>(
+        &self,
+        ctx: S,
+        capacity: usize,
+    ) -> Result<String, Error> {
+        self._render_with_state(Value::from_serialize(&ctx), capacity, |_| {})
+    }
+
+    /// Like [`render`](Self::render) but with additional globals for this render.
+    ///
+    /// The globals are resolved like the ones registered with
+    /// [`Environment::add_global`] and take precedence over them.  Unlike
+    /// values in the context they are also visible from within macros.  This
+    /// is useful for request specific values such as the current user that
+    /// should not require rebuilding the environment.
+    ///
+    #[cfg_attr(
+        feature = "macros",
+        doc = r#"
+```
+# use minijinja::{Environment, context};
+# let mut env = Environment::new();
+let tmpl = env.template_from_str(
+    "{% macro greet() %}Hello {{ user }}!{% endmacro %}{{ greet() }}"
+).unwrap();
+let rv = tmpl.render_with_globals(context!(), context!(user => "Peter")).unwrap();
+assert_eq!(rv, "Hello Peter!");
+```
+"#
+    )]
+    pub fn render_with_globals<S: Serialize, G: Serialize>(
+        &self,
+        ctx: S,
+        globals: G,
+    ) -> Result<String, Error> {
+        let capacity = self.compiled.buffer_size_hint;
+        let globals = Value::from_serialize(&globals);
+        self._render_with_state(Value::from_serialize(&ctx), capacity, |state| {
+            state.ctx.set_globals(globals);
+        })
+    }
+
+    /// Renders the template once for every context of an iterator.
+    ///
+    /// This returns an iterator that yields the result of every render in
+    /// order.  Every item is identical to what [`render`](Self::render)
+    /// would return for that context, but the renders share setup where
+    /// possible.  In particular the output buffer of every render is sized
+    /// after the previous output which avoids repeatedly growing it.  This
+    /// is useful when rendering the same template for many rows of data.
+    ///
+    /// ```
+    /// # use minijinja::{Environment, context};
+    /// # let mut env = Environment::new();
+    /// let tmpl = env.template_from_str("Hello {{ name }}!").unwrap();
+    /// let rv = tmpl
+    ///     .render_many(["John", "Jane"].iter().map(|name| context!(name)))
+    ///     .collect::<Result<Vec<_>, _>>()
+    ///     .unwrap();
+    /// assert_eq!(rv, ["Hello John!", "Hello Jane!"]);
+    /// ```
+    pub fn render_many<I, S>(&self, contexts: I) -> RenderMany<'_, 'env, 'source, I::IntoIter>
+    where
+        I: IntoIterator<Item = S>,
+        S: Serialize,
+    {
+        RenderMany {
+            template: self,
+            contexts: contexts.into_iter(),
+            capacity: self.compiled.buffer_size_hint,
+            blocks: prepare_blocks(&self.compiled.blocks),
+            ctx: None,
+        }
+    }
+
+    fn _render_with_state<F>(&self, root: Value, capacity: usize, f: F) -> Result<String, Error>
+    where
+        F: FnOnce(&mut State<'_, 'env>),
+    {
+        let mut rv = String::with_capacity(capacity);
+        let _guard = value::value_optimization();
+        let vm = Vm::new(self.env);
+        let mut state = ok!(vm.new_state(
+            &self.compiled.instructions,
+            root,
+            &self.compiled.blocks,
+            self.compiled.initial_auto_escape,
+        ));
+        f(&mut state);
+        match vm.eval_state(&mut state, &mut Output::with_string(&mut rv)) {
+            Ok(_) => Ok(rv),
+            Err(mut err) => {
+                if self.env.keep_partial_output_on_error() {
+                    err.set_partial_output(rv);
+                }
+                Err(err)
+            }
+        }
+    }
+
     fn _render(&self, root: Value) -> Result<(String, State<'_, 'env>), Error> {
         let mut rv = String::with_capacity(self.compiled.buffer_size_hint);
-        self._eval(root, &mut Output::with_string(&mut rv))
-            .map(|(_, state)| (rv, state))
+        match self._eval(root, &mut Output::with_string(&mut rv)) {
+            Ok((_, state)) => Ok((rv, state)),
+            Err(mut err) => {
+                if self.env.keep_partial_output_on_error() {
+                    err.set_partial_output(rv);
+                }
+                Err(err)
+            }
+        }
     }
 
     /// Renders the template into an [`io::Write`].
@@ -182,6 +418,227 @@ impl<'env, 'source> Template<'env, 'source> {
         .map_err(|err| wrapper.take_err(err))
     }
 
+    /// Renders the template while rendering some blocks in parallel.
+    ///
+    /// This works like [`render`](Self::render) but the blocks named in
+    /// `blocks` are first rendered concurrently on up to as many threads as
+    /// there is available parallelism.  The template is then rendered as usual
+    /// and the output of the parallel blocks is stitched in where the blocks
+    /// appear.  Blocks of the same name in included templates are not affected.
+    /// This is useful for pages that are composed of independent and expensive
+    /// blocks.
+    ///
+    /// ```
+    /// # use minijinja::{Environment, context};
+    /// let env = Box::leak(Box::new(Environment::new()));
+    /// env.add_template(
+    ///     "dashboard.html",
+    ///     "{% block left %}{{ left }}{% endblock %}|{% block right %}{{ right }}{% endblock %}",
+    /// ).unwrap();
+    /// let tmpl = env.get_template("dashboard.html").unwrap();
+    /// let rv = tmpl.render_blocks_parallel(
+    ///     context!(left => "L", right => "R"),
+    ///     &["left", "right"],
+    /// ).unwrap();
+    /// assert_eq!(rv, "L|R");
+    /// ```
+    ///
+    /// Parallel blocks are rendered like with [`State::render_block`], that is
+    /// with a separate state that only sees the context and the top-level
+    /// variables of the template.  For this to produce the same output as
+    /// [`render`](Self::render), the blocks must be independent from each
+    /// other and from the code around them:
+    ///
+    /// * they must not depend on variables set by enclosing tags such as
+    ///   loops in the parent template.
+    /// * changes to shared mutable objects (eg: a `namespace()` created at the
+    ///   top of the template, or [`State::append_temp_value`]) are not
+    ///   visible across blocks as every block renders with its own copy.
+    ///
+    /// If any of the blocks fails to render, the first error in the order
+    /// of `blocks` is returned.
+    ///
+    /// As the blocks are rendered on threads that may outlive this call,
+    /// this is only available if the environment lives for the rest of the
+    /// program, for instance because it was leaked or placed in a static.
+    #[cfg(feature = "multi_template")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "multi_template")))]
+    pub fn render_blocks_parallel<S: Serialize>(
+        &self,
+        ctx: S,
+        blocks: &[&str],
+    ) -> Result<String, Error>
+    where
+        'env: 'static,
+        'source: 'static,
+    {
+        let root = Value::from_serialize(&ctx);
+        let jobs = blocks
+            .iter()
+            .map(|&name| {
+                let tmpl = self.clone();
+                let root = root.clone();
+                let name = name.to_string();
+                Box::new(move || {
+                    let mut state = ok!(tmpl.eval_to_state(root));
+                    state.render_block(&name)
+                }) as Box<dyn FnOnce() -> Result<String, Error> + Send>
+            })
+            .collect();
+        let mut prerendered = BTreeMap::new();
+        for (name, rv) in blocks.iter().zip(ok!(run_in_threads(jobs))) {
+            prerendered.insert(name.to_string(), ok!(rv));
+        }
+
+        let _guard = value::value_optimization();
+        let vm = Vm::new(self.env);
+        let mut state = ok!(vm.new_state(
+            &self.compiled.instructions,
+            root,
+            &self.compiled.blocks,
+            self.compiled.initial_auto_escape,
+        ));
+        state.prerendered_blocks = prerendered;
+        let mut registers = Registers::new(Default::default(), 0, state.auto_escape());
+        let mut rv = String::with_capacity(self.compiled.buffer_size_hint);
+        match vm.resume(
+            &mut state,
+            &mut registers,
+            &mut Output::with_string(&mut rv),
+            Suspend::Never,
+        ) {
+            Ok(_) => Ok(rv),
+            Err(mut err) => {
+                if self.env.keep_partial_output_on_error() {
+                    err.set_partial_output(rv);
+                }
+                Err(err)
+            }
+        }
+    }
+
+    /// Renders the template incrementally, chunk by chunk.
+    ///
+    /// This works like [`render`](Self::render) but instead of rendering
+    /// the entire template up front it returns an iterator that evaluates the
+    /// template on demand.  Every call to `next` runs the template until it
+    /// produced some output and then suspends the evaluation until the next
+    /// chunk is requested.  This makes it possible to stream output with
+    /// backpressure and without the use of threads.  Concatenating all the
+    /// chunks yields the same output as [`render`](Self::render).
+    ///
+    /// Top level output of the template is emitted in small chunks.  Output
+    /// produced by included templates, blocks, macro calls or while output
+    /// is captured (eg: within `{% filter %}` or `{% set %}` blocks) is
+    /// emitted as a single chunk.
+    ///
+    /// ```
+    /// # use minijinja::{Environment, context};
+    /// # let mut env = Environment::new();
+    /// let tmpl = env.template_from_str("{% for x in seq %}[{{ x }}]{% endfor %}").unwrap();
+    /// let chunks = tmpl.render_chunks(context!(seq => [1, 2])).unwrap();
+    /// let chunks = chunks.collect::<Result<Vec<_>, _>>().unwrap();
+    /// assert_eq!(chunks, ["[", "1", "]", "[", "2", "]"]);
+    /// ```
+    ///
+    /// Once an error was returned, the iterator is exhausted.
+    pub fn render_chunks<S: Serialize>(&self, ctx: S) -> Result<RenderChunks<'_, 'env>, Error> {
+        let vm = Vm::new(self.env);
+        let state = ok!(vm.new_state(
+            &self.compiled.instructions,
+            Value::from_serialize(&ctx),
+            &self.compiled.blocks,
+            self.compiled.initial_auto_escape,
+        ));
+        Ok(RenderChunks {
+            registers: Registers::new(Default::default(), 0, state.auto_escape()),
+            state,
+            finished: false,
+        })
+    }
+
+    /// Renders the template asynchronously.
+    ///
+    /// This works like [`render`](Self::render) but awaits the futures
+    /// returned by async functions and filters (see
+    /// [`Environment::add_async_function`] and
+    /// [`Environment::add_async_filter`]) as they are called.  The evaluation
+    /// of the template itself stays synchronous between the awaits.  The
+    /// returned future does not depend on a specific async runtime.
+    ///
+    /// Async callables can only be awaited when they are called directly at
+    /// the top level of the rendered template.  Calling them from within
+    /// macros, included templates or blocks, or through filters like `map`
+    /// fails with an error.  As the body of a template that extends another
+    /// template only exists in blocks, this means that such templates cannot
+    /// use async callables at all.
+    ///
+    /// ```
+    /// # use minijinja::{Environment, context};
+    /// # async fn test() -> Result<(), minijinja::Error> {
+    /// let mut env = Environment::new();
+    /// env.add_async_function("load_name", |id: i64| async move {
+    ///     // imagine a database query here
+    ///     format!("user {id}")
+    /// });
+    /// let tmpl = env.template_from_str("Hello {{ load_name(id) }}!")?;
+    /// let rv = tmpl.render_async(context!(id => 42)).await?;
+    /// assert_eq!(rv, "Hello user 42!");
+    /// # Ok(()) }
+    /// ```
+    #[cfg(feature = "async")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "async")))]
+    pub async fn render_async<S: Serialize>(&self, ctx: S) -> Result<String, Error> {
+        let root = Value::from_serialize(&ctx);
+        drop(ctx);
+        let mut rv = String::with_capacity(self.compiled.buffer_size_hint);
+        match self._render_async(root, &mut rv).await {
+            Ok(()) => Ok(rv),
+            Err(mut err) => {
+                if self.env.keep_partial_output_on_error() {
+                    err.set_partial_output(rv);
+                }
+                Err(err)
+            }
+        }
+    }
+
+    #[cfg(feature = "async")]
+    async fn _render_async(&self, root: Value, rv: &mut String) -> Result<(), Error> {
+        let vm = Vm::new(self.env);
+        let mut state = ok!(vm.new_state(
+            &self.compiled.instructions,
+            root,
+            &self.compiled.blocks,
+            self.compiled.initial_auto_escape,
+        ));
+        let mut registers = Registers::new(Default::default(), 0, state.auto_escape());
+        let mut capture_stack = Vec::new();
+        loop {
+            // the output is only alive while the engine is running so that
+            // the future does not hold on to it across awaits.
+            let finished = {
+                let _guard = value::value_optimization();
+                let mut out = Output::with_string(rv);
+                out.restore_capture_stack(capture_stack);
+                let finished = vm.resume(&mut state, &mut registers, &mut out, Suspend::OnAwait);
+                capture_stack = out.take_capture_stack();
+                ok!(finished)
+            };
+            if finished {
+                return Ok(());
+            }
+            let result = match registers.take_pending_future() {
+                Some(fut) => fut.await,
+                None => Err(Error::new(
+                    ErrorKind::InvalidOperation,
+                    "async result was already awaited",
+                )),
+            };
+            ok!(vm.resolve_pending_future(&state, &mut registers, result));
+        }
+    }
+
     /// Evaluates the template into a [`State`].
     ///
     /// This evaluates the template, discards the output and returns the final
@@ -252,18 +709,152 @@ impl<'env, 'source> Template<'env, 'source> {
     /// // returns ["foo", "bar.baz"]
     /// ```
     pub fn undeclared_variables(&self, nested: bool) -> HashSet<String> {
-        match parse(
+        #[cfg(feature = "custom_operators")]
+        let ast = parse_with_operators(
+            self.compiled.instructions.source(),
+            self.name(),
+            self.compiled.syntax_config.clone(),
+            Default::default(),
+            self.env.custom_operators().clone(),
+        );
+        #[cfg(not(feature = "custom_operators"))]
+        let ast = parse(
             self.compiled.instructions.source(),
             self.name(),
             self.compiled.syntax_config.clone(),
             // TODO: this is not entirely great, but good enough for this use case.
             Default::default(),
-        ) {
+        );
+        match ast {
             Ok(ast) => find_undeclared(&ast, nested),
             Err(_) => HashSet::new(),
         }
     }
 
+    /// Returns the whitespace that was removed from the template source.
+    ///
+    /// Whitespace is removed when the template is compiled, either because
+    /// of `-` markers on delimiters or because of the whitespace settings of
+    /// the environment (see [`Environment::set_trim_blocks`],
+    /// [`Environment::set_lstrip_blocks`] and
+    /// [`Environment::set_keep_trailing_newline`]).  This returns every
+    /// stretch of removed whitespace with the reason why it was removed in
+    /// the order of the source.  This is useful for debugging or tooling and
+    /// does not affect rendering.
+    ///
+    /// ```
+    /// # use minijinja::{Environment, TrimReason};
+    /// let mut env = Environment::new();
+    /// env.set_trim_blocks(true);
+    /// let tmpl = env
+    ///     .template_from_str("{% if true %}\n  {{- 42 }}{% endif %}")
+    ///     .unwrap();
+    /// let report = tmpl.whitespace_report();
+    /// assert_eq!(report[0].reason(), TrimReason::TrimBlocks);
+    /// assert_eq!(report[0].range(), 13..14);
+    /// assert_eq!(report[1].reason(), TrimReason::TrimMarker);
+    /// assert_eq!(report[1].range(), 14..16);
+    /// ```
+    pub fn whitespace_report(&self) -> Vec<WhitespaceTrim> {
+        whitespace_trims(
+            self.compiled.instructions.source(),
+            self.compiled.syntax_config.clone(),
+            self.compiled.ws_config,
+        )
+        .unwrap_or_default()
+    }
+
+    /// Returns the template that provides each block.
+    ///
+    /// This resolves the inheritance chain the same way rendering does: a
+    /// block defined by a template overrides the block of the same name in
+    /// all templates it extends.  The returned map contains every block
+    /// name of the chain and the name of the template whose definition
+    /// wins.  For blocks overridden at multiple levels that is the topmost
+    /// override.
+    ///
+    /// As the template is not rendered, the chain is only followed through
+    /// `{% extends %}` tags with a constant template name.  It stops at the
+    /// first parent that cannot be resolved that way or that cannot be
+    /// loaded.
+    ///
+    /// ```
+    /// # use minijinja::Environment;
+    /// let mut env = Environment::new();
+    /// env.add_template("base.html", "{% block title %}{% endblock %}{% block body %}{% endblock %}").unwrap();
+    /// env.add_template("page.html", "{% extends 'base.html' %}{% block body %}...{% endblock %}").unwrap();
+    /// let blocks = env.get_template("page.html").unwrap().resolved_blocks();
+    /// assert_eq!(blocks["title"], "base.html");
+    /// assert_eq!(blocks["body"], "page.html");
+    /// ```
+    #[cfg(feature = "multi_template")]
+    pub fn resolved_blocks(&self) -> BTreeMap<String, String> {
+        let mut rv = BTreeMap::new();
+        for name in self.compiled.blocks.keys() {
+            rv.insert(name.to_string(), self.name().to_string());
+        }
+
+        let mut seen = vec![self.name().to_string()];
+        let mut parent = self
+            .compiled
+            .instructions
+            .static_parent_name()
+            .map(|x| x.to_string());
+        while let Some(name) = parent.take() {
+            let current = seen.last().unwrap();
+            let tmpl = match self
+                .env
+                .get_template(&self.env.join_template_path(&name, current))
+            {
+                Ok(tmpl) => tmpl,
+                Err(_) => break,
+            };
+            if seen.iter().any(|x| x == tmpl.name()) {
+                break;
+            }
+            for block in tmpl.compiled.blocks.keys() {
+                rv.entry(block.to_string())
+                    .or_insert_with(|| tmpl.name().to_string());
+            }
+            parent = tmpl
+                .compiled
+                .instructions
+                .static_parent_name()
+                .map(|x| x.to_string());
+            seen.push(tmpl.name().to_string());
+        }
+        rv
+    }
+
+    /// Returns static size and complexity metrics of the compiled template.
+    ///
+    /// The metrics are computed from the compiled instructions of the
+    /// template and its blocks without rendering it.  Included, imported and
+    /// extended templates are not followed.  This is useful for tooling that
+    /// wants to flag overly complex templates.
+    ///
+    /// ```
+    /// # use minijinja::Environment;
+    /// let env = Environment::new();
+    /// let tmpl = env.template_from_str(
+    ///     "{% for row in rows %}{% for col in row %}{{ col }}{% endfor %}{% endfor %}"
+    /// ).unwrap();
+    /// let metrics = tmpl.metrics();
+    /// assert_eq!(metrics.max_loop_depth, 2);
+    /// assert_eq!(metrics.blocks, 0);
+    /// ```
+    pub fn metrics(&self) -> TemplateMetrics {
+        let mut rv = TemplateMetrics {
+            blocks: self.compiled.blocks.len(),
+            ..TemplateMetrics::default()
+        };
+        collect_metrics(&self.compiled.instructions, &mut rv);
+        for instructions in self.compiled.blocks.values() {
+            collect_metrics(instructions, &mut rv);
+        }
+        rv
+    }
+
     /// Creates an empty [`State`] for this template.
     ///
     /// It's very rare that you need to actually do this but it can be useful when
@@ -331,6 +922,180 @@ impl<'env, 'source> Deref for CompiledTemplateRef<'env, 'source> {
     }
 }
 
+/// Static size and complexity metrics of a template.
+///
+/// These are returned by [`Template::metrics`].  All counts cover the root
+/// of the template as well as all of its blocks, including nested blocks.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct TemplateMetrics {
+    /// The number of compiled instructions.
+    pub instructions: usize,
+    /// The number of blocks defined.
+    pub blocks: usize,
+    /// The number of macros defined.  Call blocks are not counted.
+    pub macros: usize,
+    /// The deepest nesting of `{% for %}` loops within the root or a block.
+    pub max_loop_depth: usize,
+    /// The number of `{% include %}` tags.  Imports are not counted.
+    pub includes: usize,
+    /// The number of `{% extends %}` tags.
+    pub extends: usize,
+}
+
+#[cfg_attr(not(feature = "macros"), allow(unused_variables))]
+fn collect_metrics(instructions: &Instructions<'_>, metrics: &mut TemplateMetrics) {
+    // frames are pushed for loops and with blocks, captures for set blocks,
+    // filter blocks and imports.  Both are strictly nested in the instructions.
+    let mut frames = Vec::new();
+    #[cfg(feature = "multi_template")]
+    let mut captures = Vec::new();
+    metrics.instructions += instructions.len();
+    let instrs = &instructions.instructions;
+    for (idx, instr) in instrs.iter().enumerate() {
+        match instr {
+            Instruction::PushLoop(_) => {
+                frames.push(true);
+                let depth = frames.iter().filter(|x| **x).count();
+                metrics.max_loop_depth = metrics.max_loop_depth.max(depth);
+            }
+            Instruction::PushWith => frames.push(false),
+            Instruction::PopFrame => {
+                frames.pop();
+            }
+            #[cfg(feature = "multi_template")]
+            Instruction::BeginCapture(mode) => captures.push(*mode),
+            #[cfg(feature = "multi_template")]
+            Instruction::EndCapture => {
+                captures.pop();
+            }
+            // imports are the only includes that discard their output
+            #[cfg(feature = "multi_template")]
+            Instruction::Include(_) | Instruction::IncludeWithContext(_)
+                if captures.last() != Some(&CaptureMode::Discard) =>
+            {
+                metrics.includes += 1;
+            }
+            #[cfg(feature = "multi_template")]
+            Instruction::LoadBlocks => metrics.extends += 1,
+            // macro declarations store the macro, call blocks pass it on
+            #[cfg(feature = "macros")]
+            Instruction::BuildMacro(..) => {
+                if let Some(Instruction::StoreLocal(_)) = instrs.get(idx + 1) {
+                    metrics.macros += 1;
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// An iterator over the rendered chunks of a template.
+///
+/// This is returned from [`Template::render_chunks`].
+pub struct RenderChunks<'template, 'env> {
+    state: State<'template, 'env>,
+    registers: Registers<'env>,
+    finished: bool,
+}
+
+impl<'template, 'env> RenderChunks<'template, 'env> {
+    /// Returns the current state of the evaluation.
+    ///
+    /// After all chunks were consumed, this can be used to inspect the
+    /// state the same way as with
+    /// [`render_and_return_state`](Template::render_and_return_state).
+    pub fn state(&self) -> &State<'template, 'env> {
+        &self.state
+    }
+}
+
+impl<'template, 'env> Iterator for RenderChunks<'template, 'env> {
+    type Item = Result<String, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let _guard = value::value_optimization();
+        let vm = Vm::new(self.state.env);
+        let mut chunk = String::new();
+        while !self.finished {
+            match vm.resume(
+                &mut self.state,
+                &mut self.registers,
+                &mut Output::with_string(&mut chunk),
+                Suspend::OnEmit,
+            ) {
+                Ok(finished) => self.finished = finished,
+                Err(err) => {
+                    self.finished = true;
+                    return Some(Err(err));
+                }
+            }
+            if !chunk.is_empty() {
+                return Some(Ok(chunk));
+            }
+        }
+        None
+    }
+}
+
+impl<'template, 'env> FusedIterator for RenderChunks<'template, 'env> {}
+
+/// An iterator over the results of rendering a template for many contexts.
+///
+/// This is returned from [`Template::render_many`].
+pub struct RenderMany<'a, 'env, 'source, I> {
+    template: &'a Template<'env, 'source>,
+    contexts: I,
+    capacity: usize,
+    blocks: BTreeMap<&'env str, BlockStack<'a, 'env>>,
+    ctx: Option<Context<'env>>,
+}
+
+impl<'a, 'env, 'source, I, S> Iterator for RenderMany<'a, 'env, 'source, I>
+where
+    I: Iterator<Item = S>,
+    S: Serialize,
+{
+    type Item = Result<String, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let root = Value::from_serialize(&some!(self.contexts.next()));
+        let template = self.template;
+        let mut rv = String::with_capacity(self.capacity);
+        let _guard = value::value_optimization();
+        let vm = Vm::new(template.env);
+        let mut state = match vm.new_state_reusing(
+            &template.compiled.instructions,
+            root,
+            self.blocks.clone(),
+            template.compiled.initial_auto_escape,
+            self.ctx.take(),
+        ) {
+            Ok(state) => state,
+            Err(err) => return Some(Err(err)),
+        };
+        let result = vm.eval_state(&mut state, &mut Output::with_string(&mut rv));
+        // hold on to the context so that the next render can reuse its stack
+        self.ctx = Some(state.ctx);
+        Some(match result {
+            Ok(_) => {
+                self.capacity = self.capacity.max(rv.len());
+                Ok(rv)
+            }
+            Err(mut err) => {
+                if template.env.keep_partial_output_on_error() {
+                    err.set_partial_output(rv);
+                }
+                Err(err)
+            }
+        })
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.contexts.size_hint()
+    }
+}
+
 /// Represents a compiled template in memory.
 pub struct CompiledTemplate<'source> {
     /// The root instructions.
@@ -341,6 +1106,8 @@ pub struct CompiledTemplate<'source> {
     pub buffer_size_hint: usize,
     /// The syntax config that created it.
     pub syntax_config: SyntaxConfig,
+    /// The whitespace config that created it.
+    pub ws_config: WhitespaceConfig,
     /// The initial setting of auto escaping.
     pub initial_auto_escape: AutoEscape,
 }
@@ -375,21 +1142,36 @@ impl<'source> CompiledTemplate<'source> {
         // the parser/compiler combination can create constants in which case
         // we can probably benefit from the value optimization a bit.
         let _guard = value::value_optimization();
+        #[cfg(feature = "custom_operators")]
+        let ast = ok!(parse_with_operators(
+            source,
+            name,
+            config.syntax_config.clone(),
+            config.ws_config,
+            config.custom_operators.clone(),
+        ));
+        #[cfg(not(feature = "custom_operators"))]
         let ast = ok!(parse(
             source,
             name,
             config.syntax_config.clone(),
             config.ws_config
         ));
+        ok!(check_const_decls(&ast, name, &config.global_names));
         let mut gen = CodeGenerator::new(name, source);
         gen.compile_stmt(&ast);
         let buffer_size_hint = gen.buffer_size_hint();
-        let (instructions, blocks) = gen.finish();
+        let (mut instructions, mut blocks) = gen.finish();
+        instructions.resolve_callable_ids(&config.callable_ids);
+        for block in blocks.values_mut() {
+            block.resolve_callable_ids(&config.callable_ids);
+        }
         Ok(CompiledTemplate {
             instructions,
             blocks,
             buffer_size_hint,
             syntax_config: config.syntax_config.clone(),
+            ws_config: config.ws_config,
             initial_auto_escape: (config.default_auto_escape)(name),
         })
     }
@@ -63,7 +63,10 @@ use crate::vm::State;
 type TestFunc = dyn Fn(&State, &[Value]) -> Result<bool, Error> + Sync + Send + 'static;
 
 #[derive(Clone)]
-pub(crate) struct BoxedTest(Arc<TestFunc>);
+pub(crate) struct BoxedTest {
+    func: Arc<TestFunc>,
+    handles_undefined: bool,
+}
 
 /// A utility trait that represents the return value of filters.
 ///
@@ -176,6 +179,7 @@ tuple_impls! { A B }
 tuple_impls! { A B C }
 tuple_impls! { A B C D }
 tuple_impls! { A B C D E }
+tuple_impls! { A B C D E F }
 
 impl BoxedTest {
     /// Creates a new boxed filter.
@@ -185,15 +189,32 @@ impl BoxedTest {
         Rv: TestResult,
         Args: for<'a> FunctionArgs<'a>,
     {
-        BoxedTest(Arc::new(move |state, args| -> Result<bool, Error> {
-            f.perform(ok!(Args::from_values(Some(state), args)), SealedMarker)
-                .into_result()
-        }))
+        BoxedTest {
+            func: Arc::new(move |state, args| -> Result<bool, Error> {
+                f.perform(ok!(Args::from_values(Some(state), args)), SealedMarker)
+                    .into_result()
+            }),
+            handles_undefined: false,
+        }
+    }
+
+    /// Marks the test as one that handles undefined values.
+    ///
+    /// Such tests are also performed on undefined values with deferred
+    /// errors instead of failing with the error.
+    pub(crate) fn handling_undefined(mut self) -> BoxedTest {
+        self.handles_undefined = true;
+        self
+    }
+
+    /// Returns `true` if the test handles undefined values.
+    pub(crate) fn handles_undefined(&self) -> bool {
+        self.handles_undefined
     }
 
     /// Applies the filter to a value and argument.
     pub fn perform(&self, state: &State, args: &[Value]) -> Result<bool, Error> {
-        (self.0)(state, args)
+        (self.func)(state, args)
     }
 }
 
@@ -345,13 +366,17 @@ mod builtins {
 
     /// Checks if this value can be iterated over.
     ///
+    /// Like in Jinja2 strings and undefined values are iterable, `none` is not.
+    ///
     /// ```jinja
     /// {{ [1, 2, 3] is iterable }} -> true
+    /// {{ "abc" is iterable }} -> true
     /// {{ 42 is iterable }} -> false
+    /// {{ none is iterable }} -> false
     /// ```
     #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
     pub fn is_iterable(v: Value) -> bool {
-        v.try_iter().is_ok()
+        !v.is_none() && v.try_iter().is_ok()
     }
 
     /// Checks if this value is a mapping
@@ -500,6 +525,32 @@ mod builtins {
             .unwrap_or(false))
     }
 
+    /// Checks if a value is the same as another value.
+    ///
+    /// Unlike [`eq`](is_eq) this does not check for equality but identity.
+    /// Objects (such as lists and maps) are only the same if they are the
+    /// very same object.  Primitive values are the same if they are of the
+    /// same type and equal, so `1` is not the same as `1.0`.
+    ///
+    /// ```jinja
+    /// {% set a = [1, 2] %}
+    /// {{ a is sameas a }} -> true
+    /// {{ a is sameas [1, 2] }} -> false
+    /// {{ none is sameas none }} -> true
+    /// ```
+    #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
+    pub fn is_sameas(value: &Value, other: &Value) -> bool {
+        match (value.as_object(), other.as_object()) {
+            (Some(_), Some(_)) => value.ptr_eq(other),
+            (None, None) => {
+                value.kind() == other.kind()
+                    && value.is_integer() == other.is_integer()
+                    && value == other
+            }
+            _ => false,
+        }
+    }
+
     /// Checks if a value is `true`.
     ///
     /// ```jinja
@@ -1,10 +1,14 @@
+use std::borrow::Cow;
 use std::char::decode_utf16;
 use std::collections::BTreeMap;
 use std::fmt;
 use std::iter::{once, repeat};
 use std::str::Chars;
+use std::sync::Mutex;
 
 use crate::error::{Error, ErrorKind};
+#[cfg(feature = "builtins")]
+use crate::value::ValueIterRef;
 use crate::value::{StringType, Value, ValueIter, ValueKind, ValueRepr};
 use crate::Output;
 
@@ -40,6 +44,92 @@ fn write_with_html_escaping(out: &mut Output, value: &Value) -> fmt::Result {
     }
 }
 
+/// Adapts an [`Output`] so that serde can serialize straight into it.
+///
+/// serde_json emits many tiny writes so they are collected in a small
+/// buffer before they are forwarded to the output.
+#[cfg(feature = "json")]
+struct JsonWriter<'o, 'a> {
+    out: &'o mut Output<'a>,
+    buf: [u8; 1024],
+    len: usize,
+    failed: bool,
+}
+
+#[cfg(feature = "json")]
+impl JsonWriter<'_, '_> {
+    fn forward(out: &mut Output, failed: &mut bool, buf: &[u8]) -> std::io::Result<()> {
+        // serde_json only ever hands out complete UTF-8 sequences
+        let s = ok!(std::str::from_utf8(buf)
+            .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err)));
+        out.write_str(s).map_err(|_| {
+            *failed = true;
+            std::io::Error::new(std::io::ErrorKind::Other, "failed to write output")
+        })
+    }
+
+    fn flush_buffer(&mut self) -> std::io::Result<()> {
+        let len = std::mem::replace(&mut self.len, 0);
+        if len > 0 {
+            ok!(Self::forward(self.out, &mut self.failed, &self.buf[..len]));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "json")]
+impl std::io::Write for JsonWriter<'_, '_> {
+    #[inline]
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        ok!(self.write_all(buf));
+        Ok(buf.len())
+    }
+
+    #[inline]
+    fn write_all(&mut self, buf: &[u8]) -> std::io::Result<()> {
+        if let Some(target) = self.buf.get_mut(self.len..self.len + buf.len()) {
+            target.copy_from_slice(buf);
+            self.len += buf.len();
+            return Ok(());
+        }
+        ok!(self.flush_buffer());
+        if buf.len() > self.buf.len() {
+            Self::forward(self.out, &mut self.failed, buf)
+        } else {
+            self.buf[..buf.len()].copy_from_slice(buf);
+            self.len = buf.len();
+            Ok(())
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.flush_buffer()
+    }
+}
+
+/// Serializes a value to JSON directly into the output.
+///
+/// As the JSON is streamed, a value that fails to serialize halfway through
+/// leaves the JSON written up to that point in the output.  The render fails
+/// regardless, so this is only observable through the partial output of the
+/// error or when rendering into a writer.
+#[cfg(feature = "json")]
+fn write_json(out: &mut Output, value: &Value) -> Result<(), Error> {
+    let mut writer = JsonWriter {
+        out,
+        buf: [0; 1024],
+        len: 0,
+        failed: false,
+    };
+    match serde_json::to_writer(&mut writer, value) {
+        Ok(()) => writer.flush_buffer().map_err(|_| Error::from(fmt::Error)),
+        Err(_) if writer.failed => Err(Error::from(fmt::Error)),
+        Err(err) => Err(
+            Error::new(ErrorKind::BadSerialization, "unable to format to JSON").with_source(err),
+        ),
+    }
+}
+
 fn invalid_autoescape(name: &str) -> Result<(), Error> {
     Err(Error::new(
         ErrorKind::InvalidOperation,
@@ -61,15 +151,16 @@ pub fn write_escaped(
     }
 
     match auto_escape {
+        AutoEscape::None | AutoEscape::Html if matches!(value.0, ValueRepr::Bytes(_)) => {
+            Err(Error::new(
+                ErrorKind::InvalidOperation,
+                "cannot print bytes, use an encoding filter such as b64encode or hex",
+            ))
+        }
         AutoEscape::None => write!(out, "{value}").map_err(Error::from),
         AutoEscape::Html => write_with_html_escaping(out, value).map_err(Error::from),
         #[cfg(feature = "json")]
-        AutoEscape::Json => {
-            let value = ok!(serde_json::to_string(&value).map_err(|err| {
-                Error::new(ErrorKind::BadSerialization, "unable to format to JSON").with_source(err)
-            }));
-            write!(out, "{value}").map_err(Error::from)
-        }
+        AutoEscape::Json => write_json(out, value),
         AutoEscape::Custom(name) => invalid_autoescape(name),
     }
 }
@@ -104,6 +195,70 @@ pub enum AutoEscape {
     Custom(&'static str),
 }
 
+/// The kind of call checked by a call policy.
+///
+/// See [`set_call_policy`](crate::Environment::set_call_policy).
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum CallKind {
+    /// A filter is applied (eg: `{{ value|upper }}`).
+    Filter,
+    /// A test is performed (eg: `{% if value is odd %}`).
+    Test,
+    /// A function, macro or other callable is invoked (eg: `{{ range(10) }}`).
+    Function,
+}
+
+/// Describes the access that produced an undefined value.
+///
+/// This is passed to the handler registered with
+/// [`set_undefined_handler`](crate::Environment::set_undefined_handler).
+#[derive(Debug, Copy, Clone)]
+#[non_exhaustive]
+pub enum UndefinedContext<'a> {
+    /// A variable that does not exist was looked up (eg: `{{ name }}`).
+    Lookup {
+        /// The name of the variable.
+        name: &'a str,
+    },
+    /// An attribute that does not exist was accessed (eg: `{{ value.name }}`).
+    GetAttr {
+        /// The value the attribute was looked up on.
+        parent: &'a Value,
+        /// The name of the attribute.
+        name: &'a str,
+    },
+    /// An item that does not exist was accessed (eg: `{{ value[key] }}`).
+    GetItem {
+        /// The value the item was looked up on.
+        parent: &'a Value,
+        /// The key of the item.
+        key: &'a Value,
+    },
+}
+
+/// Defines what happens when a recursive loop recurses into a cycle.
+///
+/// See [`set_loop_cycle_behavior`](crate::Environment::set_loop_cycle_behavior).
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum LoopCycleBehavior {
+    /// The default: no cycle detection is performed.
+    Ignore,
+    /// Fails with an [`InvalidOperation`](crate::ErrorKind::InvalidOperation)
+    /// error when a cycle is detected.
+    Error,
+    /// Silently stops recursing when a cycle is detected.  The recursive
+    /// call to `loop()` then renders nothing.
+    Stop,
+}
+
+impl Default for LoopCycleBehavior {
+    fn default() -> LoopCycleBehavior {
+        LoopCycleBehavior::Ignore
+    }
+}
+
 /// Defines the behavior of undefined values in the engine.
 ///
 /// At present there are three types of behaviors available which mirror the behaviors
@@ -178,6 +333,14 @@ impl UndefinedBehavior {
         self.assert_iterable(&value).and_then(|_| value.try_iter())
     }
 
+    /// Like [`try_iter`](Self::try_iter) but borrows the items where possible.
+    #[cfg(feature = "builtins")]
+    #[inline]
+    pub(crate) fn try_iter_ref(self, value: &Value) -> Result<ValueIterRef<'_>, Error> {
+        self.assert_iterable(value)
+            .and_then(|_| value.try_iter_ref())
+    }
+
     /// Are we strict on iteration?
     #[inline]
     pub(crate) fn assert_iterable(self, value: &Value) -> Result<(), Error> {
@@ -330,6 +493,119 @@ impl<'a, K: fmt::Debug, V> fmt::Debug for BTreeMapKeysDebug<'a, K, V> {
     }
 }
 
+/// Interns the names of filters and tests.
+///
+/// Names are only interned when a filter or test is registered, the compiler
+/// merely looks up the ids of names it encounters.  This keeps the interner
+/// bounded by the number of registered callables.
+#[derive(Default)]
+pub(crate) struct CallableIds(Mutex<BTreeMap<String, u32>>);
+
+impl CallableIds {
+    /// Returns the id for a name, assigning a new one if necessary.
+    pub fn intern(&self, name: &str) -> u32 {
+        let mut ids = self.0.lock().unwrap();
+        if let Some(id) = ids.get(name) {
+            return *id;
+        }
+        let id = ids.len() as u32;
+        ids.insert(name.to_string(), id);
+        id
+    }
+
+    /// Returns the id of a name if it was interned.
+    pub fn get(&self, name: &str) -> Option<u32> {
+        self.0.lock().unwrap().get(name).copied()
+    }
+}
+
+/// Holds named callables that can also be looked up by their interned id.
+#[derive(Clone)]
+pub(crate) struct Callables<'source, T> {
+    by_name: BTreeMap<Cow<'source, str>, T>,
+    by_id: Vec<Option<T>>,
+    aliases: BTreeMap<Cow<'source, str>, Cow<'source, str>>,
+}
+
+impl<'source, T> Default for Callables<'source, T> {
+    fn default() -> Self {
+        Callables {
+            by_name: BTreeMap::new(),
+            by_id: Vec::new(),
+            aliases: BTreeMap::new(),
+        }
+    }
+}
+
+impl<'source, T: Clone> Callables<'source, T> {
+    pub fn new(ids: &CallableIds, map: BTreeMap<Cow<'source, str>, T>) -> Self {
+        let mut rv = Callables::default();
+        for (name, value) in map {
+            rv.insert(ids, name, value);
+        }
+        rv
+    }
+
+    pub fn insert(&mut self, ids: &CallableIds, name: Cow<'source, str>, value: T) {
+        let id = ids.intern(&name) as usize;
+        if self.by_id.len() <= id {
+            self.by_id.resize(id + 1, None);
+        }
+        self.by_id[id] = Some(value.clone());
+        self.aliases.remove(&name);
+        self.by_name.insert(name, value);
+    }
+
+    pub fn remove(&mut self, ids: &CallableIds, name: &str) {
+        self.aliases.remove(name);
+        if self.by_name.remove(name).is_some() {
+            if let Some(slot) = ids.get(name).and_then(|id| self.by_id.get_mut(id as usize)) {
+                *slot = None;
+            }
+        }
+    }
+
+    /// Registers `alias` as an alternative name that resolves to `target`.
+    ///
+    /// Aliases are resolved on lookup so they always point to what is
+    /// currently registered as `target`.
+    pub fn insert_alias(
+        &mut self,
+        ids: &CallableIds,
+        alias: Cow<'source, str>,
+        target: Cow<'source, str>,
+    ) {
+        self.remove(ids, &alias);
+        self.aliases.insert(alias, target);
+    }
+
+    /// Returns the target name if `name` is an alias.
+    pub fn alias_target(&self, name: &str) -> Option<&str> {
+        self.aliases.get(name).map(|x| &**x)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&T> {
+        match self.by_name.get(name) {
+            Some(rv) => Some(rv),
+            None => self.by_name.get(some!(self.alias_target(name))),
+        }
+    }
+
+    pub fn get_by_id(&self, id: u32) -> Option<&T> {
+        self.by_id.get(id as usize).and_then(|x| x.as_ref())
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.by_name.keys().map(|x| &**x)
+    }
+}
+
+impl<'source, T> fmt::Debug for Callables<'source, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        BTreeMapKeysDebug(&self.by_name).fmt(f)
+    }
+}
+
 pub struct OnDrop<F: FnOnce()>(Option<F>);
 
 impl<F: FnOnce()> OnDrop<F> {
@@ -384,6 +660,113 @@ pub fn splitn_whitespace(s: &str, maxsplits: usize) -> impl Iterator<Item = &str
     })
 }
 
+/// Computes the Levenshtein distance between two strings.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut prev = (0..=b.len()).collect::<Vec<_>>();
+    let mut cur = vec![0; b.len() + 1];
+    for (i, ca) in a.chars().enumerate() {
+        cur[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let cost = if ca == *cb { 0 } else { 1 };
+            cur[j + 1] = (prev[j] + cost).min(prev[j + 1] + 1).min(cur[j] + 1);
+        }
+        std::mem::swap(&mut prev, &mut cur);
+    }
+    prev[b.len()]
+}
+
+/// Finds the candidate most similar to `name` for "did you mean" hints.
+///
+/// Only candidates within a small edit distance relative to the length
+/// of the name are considered.
+pub(crate) fn find_similar<'a, I>(name: &str, candidates: I) -> Option<&'a str>
+where
+    I: Iterator<Item = &'a str>,
+{
+    // never suggest names that have nothing in common with the input
+    let name_len = name.chars().count();
+    let max_distance = (name_len / 3).max(1).min(name_len.saturating_sub(1));
+    let mut rv = None;
+    for candidate in candidates {
+        let distance = edit_distance(name, candidate);
+        if distance <= max_distance && rv.map_or(true, |(_, best)| distance < best) {
+            rv = Some((candidate, distance));
+        }
+    }
+    rv.map(|x| x.0)
+}
+
+/// Runs the jobs on a pool of threads and returns the results in order.
+///
+/// At most as many threads as there is available parallelism are spawned.
+/// Panics of jobs are propagated to the caller once all threads were joined.
+#[cfg(feature = "multi_template")]
+pub(crate) fn run_in_threads<T: Send + 'static>(
+    jobs: Vec<Box<dyn FnOnce() -> T + Send>>,
+) -> Result<Vec<T>, Error> {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::thread::{available_parallelism, Builder};
+
+    let count = jobs.len();
+    let threads = available_parallelism().map_or(1, |x| x.get()).min(count);
+    let queue = Arc::new(Mutex::new(jobs.into_iter().enumerate()));
+    let cancelled = Arc::new(AtomicBool::new(false));
+    let mut handles = Vec::with_capacity(threads);
+    let mut spawn_err = None;
+    for _ in 0..threads {
+        let worker = {
+            let queue = queue.clone();
+            let cancelled = cancelled.clone();
+            move || {
+                let mut rv = Vec::new();
+                while !cancelled.load(Ordering::Relaxed) {
+                    let next = queue.lock().unwrap().next();
+                    match next {
+                        Some((idx, job)) => rv.push((idx, job())),
+                        None => break,
+                    }
+                }
+                rv
+            }
+        };
+        match Builder::new().spawn(worker) {
+            Ok(handle) => handles.push(handle),
+            Err(err) => {
+                cancelled.store(true, Ordering::Relaxed);
+                spawn_err = Some(err);
+                break;
+            }
+        }
+    }
+
+    let mut slots: Vec<Option<T>> = (0..count).map(|_| None).collect();
+    let mut panic = None;
+    for handle in handles {
+        match handle.join() {
+            Ok(results) => {
+                for (idx, rv) in results {
+                    slots[idx] = Some(rv);
+                }
+            }
+            Err(payload) => {
+                panic.get_or_insert(payload);
+            }
+        }
+    }
+    if let Some(payload) = panic {
+        std::panic::resume_unwind(payload);
+    }
+    if let Some(err) = spawn_err {
+        return Err(
+            Error::new(ErrorKind::InvalidOperation, "failed to spawn thread").with_source(err),
+        );
+    }
+
+    Ok(slots.into_iter().map(|slot| slot.unwrap()).collect())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -405,6 +788,27 @@ mod tests {
         assert_eq!(unescape(r"\ud83d\udca9").unwrap(), "💩");
     }
 
+    #[test]
+    fn test_find_similar() {
+        let names = ["lower", "upper", "title", "items"];
+        assert_eq!(find_similar("uppr", names.iter().copied()), Some("upper"));
+        assert_eq!(find_similar("lowr", names.iter().copied()), Some("lower"));
+        assert_eq!(find_similar("item", names.iter().copied()), Some("items"));
+        assert_eq!(find_similar("x", names.iter().copied()), None);
+        assert_eq!(find_similar("something", names.iter().copied()), None);
+    }
+
+    #[test]
+    #[cfg(feature = "multi_template")]
+    fn test_run_in_threads() {
+        let words = ["a".to_string(), "b".to_string(), "c".to_string()];
+        let jobs = words
+            .into_iter()
+            .map(|word| Box::new(move || word.repeat(2)) as Box<dyn FnOnce() -> String + Send>)
+            .collect();
+        assert_eq!(run_in_threads(jobs).unwrap(), vec!["aa", "bb", "cc"]);
+    }
+
     #[test]
     #[cfg(feature = "builtins")]
     fn test_splitn_whitespace() {
@@ -189,6 +189,24 @@ pub trait ArgType<'a> {
     }
 }
 
+/// Creates the error for an argument that was not consumed.
+///
+/// With [strict kwargs](crate::Environment::set_strict_kwargs) the error
+/// names the keyword argument if the extra argument holds keyword arguments.
+fn too_many_arguments(state: Option<&State>, extra: &Value) -> Error {
+    if state.map_or(false, |x| x.env().strict_kwargs()) {
+        if let Some(key) =
+            Kwargs::extract(extra).and_then(|kwargs| kwargs.values.keys().next().cloned())
+        {
+            return Error::new(
+                ErrorKind::TooManyArguments,
+                format!("unknown keyword argument '{}'", key),
+            );
+        }
+    }
+    Error::from(ErrorKind::TooManyArguments)
+}
+
 macro_rules! tuple_impls {
     ( $( $name:ident )* * $rest_name:ident ) => {
         impl<'a, $($name,)* $rest_name> FunctionArgs<'a> for ($($name,)* $rest_name,)
@@ -224,8 +242,8 @@ macro_rules! tuple_impls {
                     idx += offset;
                 }
 
-                if values.get(idx).is_some() {
-                    Err(Error::from(ErrorKind::TooManyArguments))
+                if let Some(extra) = values.get(idx) {
+                    Err(too_many_arguments(state, extra))
                 } else {
                     // SAFETY: this is safe because both no matter what `rest_first` is set to
                     // the rest_name variable is set at this point.
@@ -239,11 +257,10 @@ macro_rules! tuple_impls {
 impl<'a> FunctionArgs<'a> for () {
     type Output = ();
 
-    fn from_values(_state: Option<&'a State>, values: &'a [Value]) -> Result<Self::Output, Error> {
-        if values.is_empty() {
-            Ok(())
-        } else {
-            Err(Error::from(ErrorKind::TooManyArguments))
+    fn from_values(state: Option<&'a State>, values: &'a [Value]) -> Result<Self::Output, Error> {
+        match values.first() {
+            None => Ok(()),
+            Some(extra) => Err(too_many_arguments(state, extra)),
         }
     }
 }
@@ -253,6 +270,7 @@ tuple_impls! { A *B }
 tuple_impls! { A B *C }
 tuple_impls! { A B C *D }
 tuple_impls! { A B C D *E }
+tuple_impls! { A B C D E *F }
 
 impl From<ValueRepr> for Value {
     #[inline(always)]
@@ -0,0 +1,52 @@
+use std::fmt;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::Arc;
+
+use crate::error::{Error, ErrorKind};
+use crate::value::{Object, ObjectRepr, Value};
+use crate::vm::State;
+
+/// This object exists for the `counter` function.
+///
+/// Every call to its `next` method returns the current number and advances
+/// it.  As the number is atomic the counter can be shared freely, for
+/// instance with included templates.
+pub(crate) struct Counter {
+    next: AtomicI64,
+}
+
+impl Counter {
+    pub(crate) fn new(start: i64) -> Counter {
+        Counter {
+            next: AtomicI64::new(start),
+        }
+    }
+}
+
+impl fmt::Debug for Counter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<counter {}>", self.next.load(Ordering::Relaxed))
+    }
+}
+
+impl Object for Counter {
+    fn repr(self: &Arc<Self>) -> ObjectRepr {
+        ObjectRepr::Plain
+    }
+
+    fn call_method(
+        self: &Arc<Self>,
+        _state: &State,
+        name: &str,
+        args: &[Value],
+    ) -> Result<Value, Error> {
+        if name == "next" {
+            if !args.is_empty() {
+                return Err(Error::from(ErrorKind::TooManyArguments));
+            }
+            Ok(Value::from(self.next.fetch_add(1, Ordering::Relaxed)))
+        } else {
+            Err(Error::from(ErrorKind::UnknownMethod))
+        }
+    }
+}
@@ -50,7 +50,10 @@ impl<'de> Visitor<'de> for ValueVisitor {
     visit_value_primitive!(visit_str, &str);
     visit_value_primitive!(visit_string, String);
     visit_value_primitive!(visit_bytes, &[u8]);
-    visit_value_primitive!(visit_byte_buf, Vec<u8>);
+
+    fn visit_byte_buf<E: de::Error>(self, v: Vec<u8>) -> Result<Value, E> {
+        Ok(Value::from_bytes(v))
+    }
 
     fn visit_none<E: de::Error>(self) -> Result<Value, E> {
         Ok(Value::from(()))
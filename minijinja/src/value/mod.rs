@@ -153,6 +153,21 @@ let vec = Vec::<i32>::deserialize(value).unwrap();
 //! let value = Value::from_dyn_object(Arc::new(Foo));
 //! ```
 //!
+//! # Comparisons
+//!
+//! Values have a total order which is used by the comparison operators in
+//! templates as well as by filters such as `sort`.  Values of different kinds
+//! are ordered by their [`ValueKind`] in this order: undefined, none, bools,
+//! numbers, strings, bytes, sequences, maps, iterables and plain objects.  This
+//! means that for instance `"10" < 9` is `false` because every string sorts
+//! after every number.  Numbers compare by value independent of their type,
+//! strings and bytes compare lexicographically and sequences and maps compare
+//! item by item.
+//!
+//! As ordering values of different kinds is rarely intended, the environment
+//! can be configured to reject it with
+//! [`Environment::set_strict_comparisons`](crate::Environment::set_strict_comparisons).
+//!
 //! # Invalid Values
 //!
 //! MiniJinja knows the concept of an "invalid value".  These are rare in practice
@@ -182,11 +197,13 @@ let vec = Vec::<i32>::deserialize(value).unwrap();
 // this module is based on the content module in insta which in turn is based
 // on the content module in serde::private::ser.
 
+use std::borrow::Cow;
 use std::cell::{Cell, RefCell};
 use std::cmp::Ordering;
 use std::collections::BTreeMap;
 use std::fmt;
 use std::hash::{Hash, Hasher};
+use std::rc::Rc;
 use std::sync::{Arc, Mutex};
 
 use serde::ser::{Serialize, Serializer};
@@ -204,13 +221,17 @@ pub use crate::value::object::{DynObject, Enumerator, Object, ObjectExt, ObjectR
 #[macro_use]
 mod type_erase;
 mod argtypes;
+#[cfg(feature = "builtins")]
+pub(crate) mod counter_object;
 #[cfg(feature = "deserialization")]
 mod deserialize;
 pub(crate) mod merge_object;
 pub(crate) mod namespace_object;
 mod object;
+pub(crate) mod one_shot_object;
 pub(crate) mod ops;
 mod serialize;
+pub(crate) mod set_object;
 #[cfg(feature = "key_interning")]
 mod string_interning;
 
@@ -240,6 +261,8 @@ pub(crate) fn value_map_with_capacity(capacity: usize) -> ValueMap {
     }
 }
 
+type SerializationHook = dyn Fn(Value) -> Value;
+
 thread_local! {
     static INTERNAL_SERIALIZATION: Cell<bool> = const { Cell::new(false) };
 
@@ -247,6 +270,7 @@ thread_local! {
     // AtomicU64 available.
     static LAST_VALUE_HANDLE: Cell<u32> = const { Cell::new(0) };
     static VALUE_HANDLES: RefCell<BTreeMap<u32, Value>> = RefCell::new(BTreeMap::new());
+    static SERIALIZATION_HOOK: RefCell<Option<Rc<SerializationHook>>> = const { RefCell::new(None) };
 }
 
 /// Function that returns true when serialization for [`Value`] is taking place.
@@ -269,6 +293,63 @@ pub fn serializing_for_value() -> bool {
     INTERNAL_SERIALIZATION.with(|flag| flag.get())
 }
 
+/// Invokes a function with a serialization hook installed.
+///
+/// While `f` runs, every map or struct that is serialized into a [`Value`]
+/// through serde (for instance by [`Value::from_serialize`], the
+/// [`context!`](crate::context) macro or when a template is rendered) is
+/// passed to `hook` after it was serialized.  The hook returns the value that
+/// should be used instead.  Nested values are converted before the values
+/// containing them, so the hook sees them already transformed.  Values that
+/// already are a [`Value`] are passed through unchanged.
+///
+/// This makes it possible to turn domain types into richer values than what
+/// their [`Serialize`] implementation produces, for instance by mapping a
+/// map carrying a type tag to a custom [`Object`]:
+///
+/// ```
+/// # use minijinja::{context, Environment};
+/// # use minijinja::value::{with_serialization_hook, Value, ValueKind};
+/// let env = Environment::new();
+/// let rv = with_serialization_hook(
+///     |value| match value.get_attr("$type").ok().as_ref().and_then(|x| x.as_str()) {
+///         Some("money") => Value::from(format!(
+///             "{} {}",
+///             value.get_attr("amount").unwrap(),
+///             value.get_attr("currency").unwrap()
+///         )),
+///         _ => value,
+///     },
+///     || {
+///         let price = serde_json::json!({"$type": "money", "amount": 42, "currency": "EUR"});
+///         env.render_str("{{ price }}", context! { price })
+///     },
+/// );
+/// assert_eq!(rv.unwrap(), "42 EUR");
+/// ```
+///
+/// Hooks do not stack: installing a hook while another one is active
+/// replaces the outer hook until `f` returns.
+pub fn with_serialization_hook<H, F, R>(hook: H, f: F) -> R
+where
+    H: Fn(Value) -> Value + 'static,
+    F: FnOnce() -> R,
+{
+    let old = SERIALIZATION_HOOK.with(|cell| cell.replace(Some(Rc::new(hook))));
+    let _guard = OnDrop::new(move || {
+        SERIALIZATION_HOOK.with(|cell| *cell.borrow_mut() = old);
+    });
+    f()
+}
+
+/// Passes a freshly serialized map or struct through the serialization hook.
+pub(crate) fn apply_serialization_hook(value: Value) -> Value {
+    match SERIALIZATION_HOOK.with(|cell| cell.borrow().clone()) {
+        Some(hook) => hook(value),
+        None => value,
+    }
+}
+
 /// Enables value optimizations.
 ///
 /// If `key_interning` is enabled, this turns on that feature, otherwise
@@ -500,6 +581,11 @@ impl PartialEq for Value {
                     if let (Some(a), Some(b)) = (self.as_object(), other.as_object()) {
                         if a.is_same_object(b) {
                             return true;
+                        } else if let Some(rv) = a
+                            .custom_cmp(b)
+                            .or_else(|| b.custom_cmp(a).map(Ordering::reverse))
+                        {
+                            return rv == Ordering::Equal;
                         }
                         match (a.repr(), b.repr()) {
                             (ObjectRepr::Map, ObjectRepr::Map) => {
@@ -590,6 +676,11 @@ impl Ord for Value {
                     if let (Some(a), Some(b)) = (self.as_object(), other.as_object()) {
                         if a.is_same_object(b) {
                             Ordering::Equal
+                        } else if let Some(rv) = a
+                            .custom_cmp(b)
+                            .or_else(|| b.custom_cmp(a).map(Ordering::reverse))
+                        {
+                            rv
                         } else {
                             match (a.repr(), b.repr()) {
                                 (ObjectRepr::Map, ObjectRepr::Map) => {
@@ -758,10 +849,12 @@ impl Value {
     /// Creates a value from a byte vector.
     ///
     /// MiniJinja can hold on to bytes and has some limited built-in support for
-    /// working with them.  They are non iterable and not particularly useful
-    /// in the context of templates.  When they are stringified, they are assumed
-    /// to contain UTF-8 and will be treated as such.  They become more useful
-    /// when a filter can do something with them (eg: base64 encode them etc.).
+    /// working with them.  They have a length, can be indexed and iterating over
+    /// them yields the individual bytes as integers.  Bytes cannot be printed
+    /// directly to the output of a template, instead they need to be passed
+    /// through an encoding filter such as `b64encode` or `hex`.  When they are
+    /// stringified, they are assumed to contain UTF-8 and will be treated as
+    /// such.
     ///
     /// This method exists so that a value can be constructed as creating a
     /// value from a `Vec<u8>` would normally just create a sequence.
@@ -842,7 +935,11 @@ impl Value {
     /// Creates a value that is an iterable.
     ///
     /// The function is invoked to create a new iterator every time the value is
-    /// iterated over.
+    /// iterated over.  This can happen more than once per render (for instance
+    /// when the value is looped over twice or passed through a filter like
+    /// `length`), so the function must be able to produce a fresh iterator on
+    /// every call.  The items are produced lazily, the sequence is never
+    /// materialized unless the template asks for it.
     ///
     /// ```
     /// # use minijinja::value::Value;
@@ -963,11 +1060,36 @@ impl Value {
         I: Iterator<Item = T> + Send + Sync + 'static,
         T: Into<Value> + Send + Sync + 'static,
     {
-        let iter = Arc::new(Mutex::new(iter.fuse()));
-        Value::make_iterable(move || {
-            let iter = iter.clone();
-            std::iter::from_fn(move || iter.lock().unwrap().next())
-        })
+        Value::from_object(one_shot_object::OneShotIterator::new(iter))
+    }
+
+    /// Creates a set from the given values.
+    ///
+    /// The resulting value behaves like a sequence of the unique values in
+    /// the order of their first occurrence, but the `in` operator checks for
+    /// membership in constant time rather than by scanning the items.  This
+    /// makes it a good fit for values that templates check membership in
+    /// repeatedly.  Values are compared the same way as with `==`, so `1`,
+    /// `1.0` and `true` are considered the same item.
+    ///
+    /// ```
+    /// # use minijinja::{render, value::Value};
+    /// let allowed = Value::from_set([1, 2, 3, 2]);
+    /// assert_eq!(allowed.to_string(), "[1, 2, 3]");
+    /// let rv = render!("{{ 2 in allowed }}|{{ 4 in allowed }}", allowed);
+    /// assert_eq!(rv, "true|false");
+    /// ```
+    pub fn from_set<I, T>(values: I) -> Value
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<Value>,
+    {
+        Value::from_object(
+            values
+                .into_iter()
+                .map(Into::into)
+                .collect::<set_object::ValueSet>(),
+        )
     }
 
     /// Creates a callable value from a function.
@@ -1095,6 +1217,23 @@ impl Value {
         }
     }
 
+    /// Returns the value as string, borrowing it if the value is a string.
+    ///
+    /// Other values are converted to their string representation.
+    ///
+    /// ```
+    /// # use std::borrow::Cow;
+    /// # use minijinja::value::Value;
+    /// assert!(matches!(Value::from("foo").as_cow_str(), Cow::Borrowed("foo")));
+    /// assert_eq!(Value::from(42).as_cow_str(), "42");
+    /// ```
+    pub fn as_cow_str(&self) -> Cow<'_, str> {
+        match self.as_str() {
+            Some(s) => Cow::Borrowed(s),
+            None => Cow::Owned(self.to_string()),
+        }
+    }
+
     /// If this is an i64 return it
     pub fn as_usize(&self) -> Option<usize> {
         usize::try_from(self.clone()).ok()
@@ -1127,6 +1266,49 @@ impl Value {
         }
     }
 
+    /// Checks if two values are structurally equal.
+    ///
+    /// This is the equality the `==` operator in templates and the
+    /// [`PartialEq`] implementation use.  Numbers compare by value across
+    /// integers and floats (`1 == 1.0`), sequences and maps compare their
+    /// items recursively and strings compare their contents.  `none` is only
+    /// equal to `none` and undefined is only equal to undefined.
+    ///
+    /// ```
+    /// # use minijinja::value::Value;
+    /// assert!(Value::from(1).deep_eq(&Value::from(1.0)));
+    /// assert!(Value::from(vec![1, 2]).deep_eq(&Value::from(vec![1, 2])));
+    /// assert!(!Value::from(()).deep_eq(&Value::UNDEFINED));
+    /// ```
+    pub fn deep_eq(&self, other: &Value) -> bool {
+        self == other
+    }
+
+    /// Checks if two values share the same underlying object.
+    ///
+    /// This is identity rather than equality: objects (which includes
+    /// sequences and maps) as well as reference counted strings and bytes
+    /// are only identical to clones of themselves.  Values without a
+    /// backing allocation such as numbers, booleans, small strings, `none`
+    /// and undefined are never identical, use [`deep_eq`](Self::deep_eq)
+    /// for those.
+    ///
+    /// ```
+    /// # use minijinja::value::Value;
+    /// let a = Value::from(vec![1, 2]);
+    /// assert!(a.ptr_eq(&a.clone()));
+    /// assert!(!a.ptr_eq(&Value::from(vec![1, 2])));
+    /// assert!(!Value::from(1).ptr_eq(&Value::from(1)));
+    /// ```
+    pub fn ptr_eq(&self, other: &Value) -> bool {
+        match (&self.0, &other.0) {
+            (ValueRepr::Object(a), ValueRepr::Object(b)) => a.is_same_object(b),
+            (ValueRepr::String(a, _), ValueRepr::String(b, _)) => Arc::ptr_eq(a, b),
+            (ValueRepr::Bytes(a), ValueRepr::Bytes(b)) => Arc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
+
     /// Returns the length of the contained value.
     ///
     /// Values without a length will return `None`.
@@ -1140,6 +1322,7 @@ impl Value {
         match self.0 {
             ValueRepr::String(ref s, _) => Some(s.chars().count()),
             ValueRepr::SmallStr(ref s) => Some(s.as_str().chars().count()),
+            ValueRepr::Bytes(ref b) => Some(b.len()),
             ValueRepr::Object(ref dy) => dy.enumerator_len(),
             _ => None,
         }
@@ -1229,6 +1412,7 @@ impl Value {
     /// * [`ValueKind::Map`]: the iterator yields the keys of the map.
     /// * [`ValueKind::Seq`] / [`ValueKind::Iterable`]: the iterator yields the items in the sequence.
     /// * [`ValueKind::String`]: the iterator yields characters in a string.
+    /// * [`ValueKind::Bytes`]: the iterator yields the bytes as integers.
     /// * [`ValueKind::None`] / [`ValueKind::Undefined`]: the iterator is empty.
     ///
     /// ```
@@ -1257,6 +1441,7 @@ impl Value {
                 s.as_str().chars().count(),
                 Arc::from(s.as_str()),
             )),
+            ValueRepr::Bytes(ref b) => Some(ValueIterImpl::Bytes(0, Arc::clone(b))),
             ValueRepr::Object(ref obj) => obj.try_iter().map(ValueIterImpl::Dyn),
             _ => None,
         }
@@ -1269,6 +1454,35 @@ impl Value {
         })
     }
 
+    /// Returns the items of the value as a slice if it is backed by a list.
+    ///
+    /// This only succeeds for values that directly hold a `Vec<Value>` such
+    /// as sequences created by serialization or from a vector of values.  It
+    /// permits reading the items without having to clone them.
+    ///
+    /// ```
+    /// # use minijinja::value::Value;
+    /// let value = Value::from(vec![Value::from(1), Value::from(2), Value::from(3)]);
+    /// let items = value.as_slice().unwrap();
+    /// assert_eq!(items.len(), 3);
+    /// assert_eq!(items[0], Value::from(1));
+    /// ```
+    pub fn as_slice(&self) -> Option<&[Value]> {
+        self.downcast_object_ref::<Vec<Value>>().map(|x| &x[..])
+    }
+
+    /// Iterates over the value yielding borrowed items where possible.
+    ///
+    /// For values backed by a list this does not clone the items, for all
+    /// other values this falls back to [`try_iter`](Self::try_iter).
+    #[cfg(feature = "builtins")]
+    pub(crate) fn try_iter_ref(&self) -> Result<ValueIterRef<'_>, Error> {
+        match self.as_slice() {
+            Some(items) => Ok(ValueIterRef::Slice(items.iter())),
+            None => self.try_iter().map(ValueIterRef::Owned),
+        }
+    }
+
     /// Returns a reversed view of this value.
     ///
     /// This is implemented for the following types with the following behaviors:
@@ -1422,6 +1636,10 @@ impl Value {
                 let idx = some!(index(key, || Some(s.as_str().chars().count())));
                 s.as_str().chars().nth(idx).map(Value::from)
             }
+            ValueRepr::Bytes(ref b) => {
+                let idx = some!(index(key, || Some(b.len())));
+                b.get(idx).copied().map(Value::from)
+            }
             _ => None,
         }
     }
@@ -1513,25 +1731,54 @@ impl Value {
         }
     }
 
-    #[cfg(feature = "builtins")]
-    pub(crate) fn get_path(&self, path: &str) -> Result<Value, Error> {
+    /// Looks up a dotted path on the value.
+    ///
+    /// The path is split on `.` and every segment is looked up in turn.
+    /// Segments that are integers index into sequences, all other segments
+    /// are looked up as attributes.  This is the same access the engine
+    /// performs for `foo.bar` and `foo.0` in templates.  If any segment cannot
+    /// be resolved the return value is undefined.
+    ///
+    /// ```
+    /// # use minijinja::{context, Value};
+    /// let value = context! {
+    ///     items => vec![context! { name => "first" }],
+    /// };
+    /// assert_eq!(value.get_path("items.0.name"), Value::from("first"));
+    /// assert!(value.get_path("items.1.name").is_undefined());
+    /// ```
+    pub fn get_path(&self, path: &str) -> Value {
+        self.get_path_checked(path, |_, _| true)
+    }
+
+    /// Like [`get_path`](Self::get_path) but consults `can_access` before
+    /// every attribute lookup.
+    pub(crate) fn get_path_checked<F>(&self, path: &str, can_access: F) -> Value
+    where
+        F: Fn(&Value, &str) -> bool,
+    {
         let mut rv = self.clone();
         for part in path.split('.') {
-            if let Ok(num) = part.parse::<usize>() {
-                rv = ok!(rv.get_item_by_index(num));
+            let value = if let Ok(num) = part.parse::<usize>() {
+                rv.get_item_opt(&Value::from(num))
+            } else if can_access(&rv, part) {
+                rv.get_attr_fast(part)
             } else {
-                rv = ok!(rv.get_attr(part));
-            }
+                None
+            };
+            rv = match value {
+                Some(value) => value,
+                None => return Value::UNDEFINED,
+            };
         }
-        Ok(rv)
+        rv
     }
 
     #[cfg(feature = "builtins")]
     pub(crate) fn get_path_or_default(&self, path: &str, default: &Value) -> Value {
         match self.get_path(path) {
-            Err(_) => default.clone(),
-            Ok(val) if val.is_undefined() => default.clone(),
-            Ok(val) => val,
+            val if val.is_undefined() => default.clone(),
+            val => val,
         }
     }
 }
@@ -1620,6 +1867,10 @@ impl Iterator for ValueIter {
                     Value::from(c)
                 })
             }
+            ValueIterImpl::Bytes(offset, ref b) => b.get(*offset).map(|&x| {
+                *offset += 1;
+                Value::from(x)
+            }),
             ValueIterImpl::Dyn(iter) => iter.next(),
         }
     }
@@ -1628,6 +1879,10 @@ impl Iterator for ValueIter {
         match self.imp {
             ValueIterImpl::Empty => (0, Some(0)),
             ValueIterImpl::Chars(_, len, _) => (0, Some(len)),
+            ValueIterImpl::Bytes(offset, ref b) => {
+                let len = b.len() - offset;
+                (len, Some(len))
+            }
             ValueIterImpl::Dyn(ref iter) => iter.size_hint(),
         }
     }
@@ -1639,9 +1894,36 @@ impl fmt::Debug for ValueIter {
     }
 }
 
+/// Utility to iterate over values by reference.
+#[cfg(feature = "builtins")]
+pub(crate) enum ValueIterRef<'a> {
+    Slice(std::slice::Iter<'a, Value>),
+    Owned(ValueIter),
+}
+
+#[cfg(feature = "builtins")]
+impl<'a> Iterator for ValueIterRef<'a> {
+    type Item = Cow<'a, Value>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            ValueIterRef::Slice(iter) => iter.next().map(Cow::Borrowed),
+            ValueIterRef::Owned(iter) => iter.next().map(Cow::Owned),
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        match self {
+            ValueIterRef::Slice(iter) => iter.size_hint(),
+            ValueIterRef::Owned(iter) => iter.size_hint(),
+        }
+    }
+}
+
 enum ValueIterImpl {
     Empty,
     Chars(usize, usize, Arc<str>),
+    Bytes(usize, Arc<Vec<u8>>),
     Dyn(Box<dyn Iterator<Item = Value> + Send + Sync>),
 }
 
@@ -29,4 +29,19 @@ impl Namespace {
     pub(crate) fn set_value(&self, key: &str, value: Value) {
         self.data.lock().unwrap().insert(key.into(), value);
     }
+
+    /// Returns the value of the key, creating a child namespace if it's missing.
+    ///
+    /// This is used for nested assignments like `{% set ns.a.b = 42 %}`.
+    pub(crate) fn get_or_create_namespace(&self, key: &str) -> Value {
+        let mut data = self.data.lock().unwrap();
+        match data.get(key) {
+            Some(value) if !value.is_undefined() => value.clone(),
+            _ => {
+                let rv = Value::from_object(Namespace::default());
+                data.insert(key.into(), rv.clone());
+                rv
+            }
+        }
+    }
 }
@@ -1,4 +1,5 @@
 use std::borrow::Cow;
+use std::cmp::Ordering;
 use std::collections::BTreeMap;
 use std::fmt;
 use std::hash::Hash;
@@ -243,6 +244,33 @@ pub trait Object: fmt::Debug + Send + Sync {
         Err(Error::from(ErrorKind::UnknownMethod))
     }
 
+    /// Compares this object to another object.
+    ///
+    /// This is consulted when two objects are compared for equality or ordered
+    /// (eg: with `==`, `<` or the `sort` filter).  The left operand is asked
+    /// first, if it returns `None` the right operand is asked with the
+    /// arguments swapped.  If neither returns an ordering the default
+    /// comparison behavior based on the [`repr`](Self::repr) is used.
+    /// The default implementation returns `None`.  This is typically used by
+    /// objects that wrap a type with a natural ordering such as a timestamp:
+    ///
+    /// ```
+    /// # use std::{cmp::Ordering, sync::Arc};
+    /// # use minijinja::value::{DynObject, Object};
+    /// #[derive(Debug)]
+    /// struct Timestamp(u64);
+    ///
+    /// impl Object for Timestamp {
+    ///     fn custom_cmp(self: &Arc<Self>, other: &DynObject) -> Option<Ordering> {
+    ///         Some(self.0.cmp(&other.downcast_ref::<Self>()?.0))
+    ///     }
+    /// }
+    /// ```
+    fn custom_cmp(self: &Arc<Self>, other: &DynObject) -> Option<Ordering> {
+        let _ = other;
+        None
+    }
+
     /// Formats the object for stringification.
     ///
     /// The default implementation is specific to the behavior of
@@ -633,6 +661,8 @@ type_erase! {
             args: &[Value]
         ) -> Result<Value, Error>;
 
+        fn custom_cmp(&self, other: &DynObject) -> Option<Ordering>;
+
         fn render(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result;
 
         impl fmt::Debug {
@@ -0,0 +1,60 @@
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use crate::value::{Enumerator, Object, ObjectRepr, Value};
+
+/// The object behind [`Value::make_one_shot_iterator`].
+///
+/// Unlike other iterables it remembers if it ran out of items so that an
+/// empty iterator can be told apart from one that was already consumed.
+pub(crate) struct OneShotIterator {
+    iter: Mutex<Box<dyn Iterator<Item = Value> + Send + Sync>>,
+    exhausted: AtomicBool,
+}
+
+impl OneShotIterator {
+    pub fn new<I, T>(iter: I) -> OneShotIterator
+    where
+        I: Iterator<Item = T> + Send + Sync + 'static,
+        T: Into<Value> + Send + Sync + 'static,
+    {
+        OneShotIterator {
+            iter: Mutex::new(Box::new(iter.map(Into::into))),
+            exhausted: AtomicBool::new(false),
+        }
+    }
+
+    /// Returns `true` once the iterator did not produce any more items.
+    pub fn is_exhausted(&self) -> bool {
+        self.exhausted.load(Ordering::Relaxed)
+    }
+
+    fn next_item(&self) -> Option<Value> {
+        if self.is_exhausted() {
+            return None;
+        }
+        let rv = self.iter.lock().unwrap().next();
+        if rv.is_none() {
+            self.exhausted.store(true, Ordering::Relaxed);
+        }
+        rv
+    }
+}
+
+impl fmt::Debug for OneShotIterator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("<iterator>").finish()
+    }
+}
+
+impl Object for OneShotIterator {
+    fn repr(self: &Arc<Self>) -> ObjectRepr {
+        ObjectRepr::Iterable
+    }
+
+    fn enumerate(self: &Arc<Self>) -> Enumerator {
+        let this = self.clone();
+        Enumerator::Iter(Box::new(std::iter::from_fn(move || this.next_item())))
+    }
+}
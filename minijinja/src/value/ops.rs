@@ -1,4 +1,5 @@
 use crate::error::{Error, ErrorKind};
+use crate::value::set_object::ValueSet;
 use crate::value::{DynObject, ObjectRepr, Value, ValueKind, ValueRepr};
 
 const MIN_I128_AS_POS_U128: u128 = 170141183460469231731687303715884105728;
@@ -371,6 +372,23 @@ pub fn string_concat(left: Value, right: &Value) -> Value {
     Value::from(format!("{left}{right}"))
 }
 
+/// Concatenates many values in one go.
+///
+/// This produces the same result as folding the values with [`string_concat`]
+/// but builds the string in a single buffer.
+pub fn string_concat_many(values: &[Value]) -> Value {
+    use std::fmt::Write;
+    let capacity = values
+        .iter()
+        .map(|x| x.as_str().map_or(8, |s| s.len()))
+        .sum();
+    let mut rv = String::with_capacity(capacity);
+    for value in values {
+        write!(rv, "{value}").unwrap();
+    }
+    Value::from(rv)
+}
+
 /// Implements a containment operation on values.
 pub fn contains(container: &Value, value: &Value) -> Result<Value, Error> {
     // Special case where if the container is undefined, it cannot hold
@@ -384,6 +402,8 @@ pub fn contains(container: &Value, value: &Value) -> Result<Value, Error> {
         } else {
             s.contains(&value.to_string())
         }
+    } else if let Some(set) = container.downcast_object_ref::<ValueSet>() {
+        set.contains(value)
     } else if let ValueRepr::Object(ref obj) = container.0 {
         match obj.repr() {
             ObjectRepr::Plain => false,
@@ -437,6 +457,27 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_string_concat_many() {
+        let values = [
+            Value::from("a"),
+            Value::UNDEFINED,
+            Value::from(()),
+            Value::from_safe_string("<b>".into()),
+            Value::from(42),
+            Value::from(1.5),
+            Value::from(vec![1, 2]),
+            Value::from(true),
+            Value::from(""),
+            Value::from("z"),
+        ];
+        let folded = values[1..].iter().fold(values[0].clone(), string_concat);
+        let many = string_concat_many(&values);
+        assert_eq!(many, folded);
+        assert_eq!(many.is_safe(), folded.is_safe());
+        assert_eq!(many.as_str(), Some("anone<b>421.5[1, 2]truez"));
+    }
+
     #[test]
     fn test_subtracting() {
         let err = sub(&Value::from("a"), &Value::from(42)).unwrap_err();
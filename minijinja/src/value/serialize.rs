@@ -6,8 +6,8 @@ use serde::{ser, Serialize, Serializer};
 use crate::error::{Error, ErrorKind};
 use crate::utils::untrusted_size_hint;
 use crate::value::{
-    value_map_with_capacity, Arc, Packed, Value, ValueMap, ValueRepr, VALUE_HANDLES,
-    VALUE_HANDLE_MARKER,
+    apply_serialization_hook, value_map_with_capacity, Arc, Packed, Value, ValueMap, ValueRepr,
+    VALUE_HANDLES, VALUE_HANDLE_MARKER,
 };
 
 #[derive(Debug)]
@@ -366,7 +366,7 @@ impl ser::SerializeMap for SerializeMap {
     }
 
     fn end(self) -> Result<Value, InvalidValue> {
-        Ok(Value::from_object(self.entries))
+        Ok(apply_serialization_hook(Value::from_object(self.entries)))
     }
 
     fn serialize_entry<K, V>(&mut self, key: &K, value: &V) -> Result<(), InvalidValue>
@@ -398,7 +398,7 @@ impl ser::SerializeStruct for SerializeStruct {
     }
 
     fn end(self) -> Result<Value, InvalidValue> {
-        Ok(Value::from_object(self.fields))
+        Ok(apply_serialization_hook(Value::from_object(self.fields)))
     }
 }
 
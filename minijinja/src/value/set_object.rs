@@ -0,0 +1,65 @@
+use std::collections::HashSet;
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+
+use crate::value::{Enumerator, Object, ObjectRepr, Value, ValueRepr};
+
+/// This object backs [`Value::from_set`] and the `set` filter.
+///
+/// It behaves like a sequence of unique values in insertion order but
+/// answers containment checks in constant time.  The `in` operator finds
+/// it via downcasting.
+#[derive(Debug, Default)]
+pub(crate) struct ValueSet {
+    items: Vec<Value>,
+    index: HashSet<SetKey>,
+}
+
+/// Key into the set index.
+///
+/// Booleans compare equal to `0` and `1` but hash differently, so they are
+/// hashed as integers here.
+#[derive(Debug, PartialEq, Eq)]
+struct SetKey(Value);
+
+impl Hash for SetKey {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        match self.0 .0 {
+            ValueRepr::Bool(b) => Value::from(b as i64).hash(state),
+            _ => self.0.hash(state),
+        }
+    }
+}
+
+impl ValueSet {
+    /// Returns `true` if the set holds the value.
+    pub(crate) fn contains(&self, value: &Value) -> bool {
+        self.index.contains(&SetKey(value.clone()))
+    }
+}
+
+impl FromIterator<Value> for ValueSet {
+    fn from_iter<T: IntoIterator<Item = Value>>(iter: T) -> Self {
+        let mut rv = ValueSet::default();
+        for item in iter {
+            if rv.index.insert(SetKey(item.clone())) {
+                rv.items.push(item);
+            }
+        }
+        rv
+    }
+}
+
+impl Object for ValueSet {
+    fn repr(self: &Arc<Self>) -> ObjectRepr {
+        ObjectRepr::Seq
+    }
+
+    fn get_value(self: &Arc<Self>, key: &Value) -> Option<Value> {
+        self.items.get(some!(key.as_usize())).cloned()
+    }
+
+    fn enumerate(self: &Arc<Self>) -> Enumerator {
+        Enumerator::Seq(self.items.len())
+    }
+}
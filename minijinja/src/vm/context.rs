@@ -20,6 +20,13 @@ pub(crate) struct LoopState {
     // first item is the target jump instruction, the second argument
     // tells us if we need to end capturing.
     pub(crate) current_recursion_jump: Option<(usize, bool)>,
+    // the value iterated by a recursive loop.  This is only retained if
+    // cycle detection is enabled.
+    pub(crate) recursion_source: Option<Value>,
+    // the depth of the undefined behavior stack when the loop was entered.
+    // `break` and `continue` can jump past the end of undefined blocks so
+    // the behavior is restored to this depth on every iteration and on exit.
+    pub(crate) undefined_behavior_depth: usize,
     pub(crate) iterator: ValueIter,
     pub(crate) object: Arc<Loop>,
 }
@@ -95,6 +102,16 @@ impl Default for Stack {
 }
 
 impl Stack {
+    #[cfg(any(feature = "stats", feature = "internal_debug"))]
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    #[cfg(feature = "internal_debug")]
+    pub fn try_peek(&self) -> Option<&Value> {
+        self.values.last()
+    }
+
     pub fn push(&mut self, arg: Value) {
         self.values.push(arg);
     }
@@ -137,6 +154,7 @@ pub(crate) struct Context<'env> {
     stack: Vec<Frame<'env>>,
     outer_stack_depth: usize,
     recursion_limit: usize,
+    globals: Value,
 }
 
 impl<'env> fmt::Debug for Context<'env> {
@@ -191,6 +209,7 @@ impl<'env> Context<'env> {
             stack: Vec::with_capacity(32),
             outer_stack_depth: 0,
             recursion_limit,
+            globals: Value::UNDEFINED,
         }
     }
 
@@ -201,6 +220,69 @@ impl<'env> Context<'env> {
         rv
     }
 
+    /// Resets the context to only hold the given frame.
+    ///
+    /// This keeps the allocated stack around so that a context can be reused
+    /// for another evaluation.
+    pub fn reset_with_frame(&mut self, frame: Frame<'env>) {
+        self.stack.clear();
+        self.stack.push(frame);
+        self.outer_stack_depth = 0;
+        self.globals = Value::UNDEFINED;
+    }
+
+    /// Returns the recursion limit of this context.
+    #[cfg(any(feature = "macros", feature = "multi_template"))]
+    pub fn recursion_limit(&self) -> usize {
+        self.recursion_limit
+    }
+
+    /// Returns the per-render globals of this context.
+    #[cfg(any(feature = "macros", feature = "multi_template"))]
+    pub fn globals(&self) -> &Value {
+        &self.globals
+    }
+
+    /// Sets per-render globals that are looked up before the environment's.
+    pub fn set_globals(&mut self, globals: Value) {
+        self.globals = globals;
+    }
+
+    /// Changes the recursion limit of this context.
+    pub fn set_recursion_limit(&mut self, recursion_limit: usize) {
+        self.recursion_limit = recursion_limit;
+    }
+
+    /// Creates a copy of the context with the same variables visible.
+    ///
+    /// The loop state is not copied, instead the loop objects are exposed
+    /// as `loop` variables of the frames.
+    pub fn snapshot(&self) -> Context<'env> {
+        Context {
+            stack: self
+                .stack
+                .iter()
+                .map(|frame| {
+                    let mut locals = frame.locals.clone();
+                    if let Some(ref l) = frame.current_loop {
+                        if l.with_loop_var {
+                            locals
+                                .entry("loop")
+                                .or_insert_with(|| Value::from_dyn_object(l.object.clone()));
+                        }
+                    }
+                    Frame {
+                        locals,
+                        ..Frame::new(frame.ctx.clone())
+                    }
+                })
+                .collect(),
+            outer_stack_depth: self.outer_stack_depth,
+            recursion_limit: self.recursion_limit,
+            globals: self.globals.clone(),
+        }
+    }
+
     /// Stores a variable in the context.
     pub fn store(&mut self, key: &'env str, value: Value) {
         let top = self.stack.last_mut().unwrap();
@@ -267,6 +349,15 @@ impl<'env> Context<'env> {
 
     /// Looks up a variable in the context.
     pub fn load(&self, env: &Environment, key: &str) -> Option<Value> {
+        if let Some(rv) = self.load_without_env(key) {
+            return Some(rv);
+        }
+        env.get_global(key)
+    }
+
+    /// Like [`load`](Self::load) but does not fall back to the globals of
+    /// the environment.
+    pub fn load_without_env(&self, key: &str) -> Option<Value> {
         for frame in self.stack.iter().rev() {
             // look at locals first
             if let Some(value) = frame.locals.get(key) {
@@ -287,7 +378,43 @@ impl<'env> Context<'env> {
             }
         }
 
-        env.get_global(key)
+        self.globals.get_attr_fast(key)
+    }
+
+    /// Returns all local variables visible from the current frame.
+    ///
+    /// Inner frames shadow outer frames.  The context values and globals
+    /// are not included.
+    #[cfg(feature = "debug")]
+    pub fn visible_locals(&self) -> BTreeMap<&'env str, Value> {
+        let mut rv = BTreeMap::new();
+        for frame in self.stack.iter().rev() {
+            for (key, value) in frame.locals.iter() {
+                rv.entry(*key).or_insert_with(|| value.clone());
+            }
+            if let Some(ref l) = frame.current_loop {
+                if l.with_loop_var {
+                    rv.entry("loop")
+                        .or_insert_with(|| Value::from_dyn_object(l.object.clone()));
+                }
+            }
+        }
+        rv
+    }
+
+    /// Returns a checkpoint of the frames and the depth of the context.
+    #[cfg(feature = "multi_template")]
+    pub fn checkpoint(&self) -> (usize, usize) {
+        (self.stack.len(), self.outer_stack_depth)
+    }
+
+    /// Drops all frames and depth added since the checkpoint was taken.
+    ///
+    /// This is used to recover the context after an evaluation failed half-way.
+    #[cfg(feature = "multi_template")]
+    pub fn restore_checkpoint(&mut self, checkpoint: (usize, usize)) {
+        self.stack.truncate(checkpoint.0);
+        self.outer_stack_depth = checkpoint.1;
     }
 
     /// Pushes a new layer.
@@ -325,6 +452,35 @@ impl<'env> Context<'env> {
             .next()
     }
 
+    /// Checks if a recursive loop is already iterating over the given object.
+    ///
+    /// This walks the chain of active recursions of the recursive loop that
+    /// starts at `jump_target` and compares the iterated objects by identity.
+    pub fn is_recursing_into(&self, jump_target: usize, value: &Value) -> bool {
+        if value.as_object().is_none() {
+            return false;
+        }
+        for loop_state in self
+            .stack
+            .iter()
+            .rev()
+            .filter_map(|x| x.current_loop.as_ref())
+        {
+            if loop_state.recurse_jump_target != Some(jump_target) {
+                continue;
+            }
+            if let Some(ref other) = loop_state.recursion_source {
+                if value.ptr_eq(other) {
+                    return true;
+                }
+            }
+            if loop_state.object.depth == 0 {
+                break;
+            }
+        }
+        false
+    }
+
     /// The real depth of the context.
     pub fn depth(&self) -> usize {
         self.outer_stack_depth + self.stack.len()
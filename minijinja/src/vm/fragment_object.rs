@@ -0,0 +1,35 @@
+use std::sync::Arc;
+
+use crate::utils::AutoEscape;
+use crate::value::{Enumerator, Object, Value};
+
+/// A captured piece of output together with where it was captured.
+///
+/// This is returned by [`State::capture_fragment`](crate::State::capture_fragment).
+#[derive(Debug)]
+pub(crate) struct Fragment {
+    pub html: Value,
+    pub block: Option<String>,
+    pub auto_escape: AutoEscape,
+}
+
+impl Object for Fragment {
+    fn enumerate(self: &Arc<Self>) -> Enumerator {
+        Enumerator::Str(&["html", "block", "auto_escape"])
+    }
+
+    fn get_value(self: &Arc<Self>, key: &Value) -> Option<Value> {
+        Some(match some!(key.as_str()) {
+            "html" => self.html.clone(),
+            "block" => Value::from(self.block.clone()),
+            "auto_escape" => Value::from(match self.auto_escape {
+                AutoEscape::None => "none",
+                AutoEscape::Html => "html",
+                #[cfg(feature = "json")]
+                AutoEscape::Json => "json",
+                AutoEscape::Custom(name) => name,
+            }),
+            _ => return None,
+        })
+    }
+}
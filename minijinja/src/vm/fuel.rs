@@ -1,6 +1,7 @@
 use crate::compiler::instructions::Instruction;
 use crate::error::{Error, ErrorKind};
 
+use std::fmt;
 use std::sync::atomic::{AtomicIsize, Ordering};
 use std::sync::Arc;
 
@@ -37,9 +38,23 @@ impl FuelTracker {
         Ok(())
     }
 
+    /// Tracks bytes written to the output.  If it runs out of fuel an error
+    /// is returned.
+    pub fn track_output(&self, bytes: usize) -> Result<(), Error> {
+        if bytes != 0 {
+            let fuel_to_consume = bytes.min(isize::MAX as usize) as isize;
+            let old_fuel = self.remaining.fetch_sub(fuel_to_consume, Ordering::Relaxed);
+            if old_fuel.saturating_sub(fuel_to_consume) <= 0 {
+                return Err(Error::from(ErrorKind::OutOfFuel));
+            }
+        }
+        Ok(())
+    }
+
     /// Returns the remaining fuel.
     pub fn remaining(&self) -> u64 {
-        self.remaining.load(Ordering::Relaxed) as _
+        // once the fuel ran out, the remaining fuel can be negative
+        self.remaining.load(Ordering::Relaxed).max(0) as _
     }
 
     /// Returns the consumed fuel.
@@ -48,6 +63,61 @@ impl FuelTracker {
     }
 }
 
+/// A fuel budget that can be shared across renders.
+///
+/// A budget is created with an initial amount of fuel and can then be passed
+/// to [`Template::render_with_fuel_budget`](crate::Template::render_with_fuel_budget)
+/// any number of times.  All renders consume fuel from the same budget.
+/// Cloning the budget does not copy the fuel, the clones share the same
+/// fuel.
+///
+/// ```
+/// # use minijinja::{Environment, ErrorKind, FuelBudget};
+/// # let env = Environment::new();
+/// let tmpl = env.template_from_str("{{ 1 }}{{ 2 }}").unwrap();
+/// let budget = FuelBudget::new(5);
+/// tmpl.render_with_fuel_budget((), &budget).unwrap();
+/// assert_eq!(budget.remaining(), 1);
+/// let err = tmpl.render_with_fuel_budget((), &budget).unwrap_err();
+/// assert_eq!(err.kind(), ErrorKind::OutOfFuel);
+/// ```
+#[derive(Clone)]
+pub struct FuelBudget {
+    tracker: Arc<FuelTracker>,
+}
+
+impl FuelBudget {
+    /// Creates a new budget with the given amount of fuel.
+    pub fn new(fuel: u64) -> FuelBudget {
+        FuelBudget {
+            tracker: FuelTracker::new(fuel),
+        }
+    }
+
+    /// Returns the remaining fuel.
+    pub fn remaining(&self) -> u64 {
+        self.tracker.remaining()
+    }
+
+    /// Returns the fuel consumed so far.
+    pub fn consumed(&self) -> u64 {
+        self.tracker.consumed()
+    }
+
+    pub(crate) fn tracker(&self) -> Arc<FuelTracker> {
+        self.tracker.clone()
+    }
+}
+
+impl fmt::Debug for FuelBudget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FuelBudget")
+            .field("consumed", &self.consumed())
+            .field("remaining", &self.remaining())
+            .finish()
+    }
+}
+
 /// How much fuel does an instruction consume?
 fn fuel_for_instruction(instruction: &Instruction) -> isize {
     match instruction {
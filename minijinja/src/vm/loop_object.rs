@@ -3,7 +3,7 @@ use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
 
 use crate::error::{Error, ErrorKind};
-use crate::value::{Enumerator, Object, Value};
+use crate::value::{Enumerator, Object, Value, ValueIter};
 use crate::vm::state::State;
 
 pub(crate) struct Loop {
@@ -15,6 +15,52 @@ pub(crate) struct Loop {
     pub last_changed_value: Mutex<Option<Vec<Value>>>,
 }
 
+impl Loop {
+    /// Moves the loop forward and returns the next item.
+    ///
+    /// Unless the template holds on to the `loop` variable, the engine holds
+    /// the only reference to the loop object.  In that case the object is
+    /// updated in place without taking the lock or atomic increments.
+    pub fn advance(this: &mut Arc<Loop>, iterator: &mut ValueIter) -> Option<Value> {
+        match Arc::get_mut(this) {
+            Some(this) => {
+                let idx = this.idx.get_mut();
+                *idx = idx.wrapping_add(1);
+                #[cfg(feature = "adjacent_loop_items")]
+                {
+                    advance_triple(this.value_triple.get_mut().unwrap(), iterator)
+                }
+                #[cfg(not(feature = "adjacent_loop_items"))]
+                {
+                    iterator.next()
+                }
+            }
+            None => {
+                this.idx.fetch_add(1, Ordering::Relaxed);
+                #[cfg(feature = "adjacent_loop_items")]
+                {
+                    advance_triple(&mut this.value_triple.lock().unwrap(), iterator)
+                }
+                #[cfg(not(feature = "adjacent_loop_items"))]
+                {
+                    iterator.next()
+                }
+            }
+        }
+    }
+}
+
+#[cfg(feature = "adjacent_loop_items")]
+fn advance_triple(
+    triple: &mut (Option<Value>, Option<Value>, Option<Value>),
+    iterator: &mut ValueIter,
+) -> Option<Value> {
+    triple.0 = triple.1.take();
+    triple.1 = triple.2.take();
+    triple.2 = iterator.next();
+    triple.1.clone()
+}
+
 impl fmt::Debug for Loop {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("Loop")
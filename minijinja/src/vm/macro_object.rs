@@ -116,7 +116,7 @@ impl Object for Macro {
             }
         }
 
-        let (instructions, offset) = &state.macros[self.macro_ref_id];
+        let (instructions, offset) = state.macros.get(self.macro_ref_id);
         let vm = Vm::new(state.env());
         let mut rv = String::new();
         let mut out = Output::with_string(&mut rv);
@@ -128,8 +128,9 @@ impl Object for Macro {
         // can't return other macros this is however not an issue, as modifications in the
         // macro cannot leak out.
         ok!(vm.eval_macro(
+            self.name.as_str().unwrap_or_default(),
             instructions,
-            *offset,
+            offset,
             self.closure.clone(),
             state.ctx.clone_base(),
             caller,
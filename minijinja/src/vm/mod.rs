@@ -1,20 +1,32 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 use std::mem;
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
 
 use crate::compiler::instructions::{
-    Instruction, Instructions, LOOP_FLAG_RECURSIVE, LOOP_FLAG_WITH_LOOP_VAR, MAX_LOCALS,
+    Instruction, Instructions, LOOP_FLAG_PRECOUNT, LOOP_FLAG_RECURSIVE, LOOP_FLAG_WITH_LOOP_VAR,
+    MAX_LOCALS,
 };
 use crate::environment::Environment;
 use crate::error::{Error, ErrorKind};
+use crate::filters::BoxedFilter;
+#[cfg(feature = "i18n")]
+use crate::functions::BoxedFunction;
+#[cfg(feature = "async")]
+use crate::functions::{BoxFuture, PendingFuture};
 use crate::output::{CaptureMode, Output};
-use crate::utils::{untrusted_size_hint, AutoEscape, UndefinedBehavior};
+use crate::tests::BoxedTest;
+use crate::utils::{
+    find_similar, untrusted_size_hint, AutoEscape, CallKind, LoopCycleBehavior, UndefinedBehavior,
+    UndefinedContext,
+};
 use crate::value::namespace_object::Namespace;
-use crate::value::{ops, value_map_with_capacity, value_optimization, Kwargs, Value};
+use crate::value::{ops, value_map_with_capacity, value_optimization, Kwargs, Value, ValueKind};
 use crate::vm::context::{Frame, LoopState, Stack};
 use crate::vm::loop_object::Loop;
-use crate::vm::state::BlockStack;
+pub(crate) use crate::vm::state::BlockStack;
+use crate::vm::undefined_object::PendingUndefined;
+use crate::warning::{Warning, WarningKind};
 
 #[cfg(feature = "macros")]
 use crate::vm::closure_object::Closure;
@@ -22,15 +34,24 @@ use crate::vm::closure_object::Closure;
 pub(crate) use crate::vm::context::Context;
 pub use crate::vm::state::State;
 
+#[cfg(feature = "fuel")]
+pub use crate::vm::fuel::FuelBudget;
+#[cfg(feature = "stats")]
+pub use crate::vm::stats::RenderStats;
+
 #[cfg(feature = "macros")]
 mod closure_object;
 mod context;
+mod fragment_object;
 #[cfg(feature = "fuel")]
 mod fuel;
 mod loop_object;
 #[cfg(feature = "macros")]
 mod macro_object;
 mod state;
+#[cfg(feature = "stats")]
+mod stats;
+pub(crate) mod undefined_object;
 
 // the cost of a single include against the stack limit.
 #[cfg(feature = "multi_template")]
@@ -40,6 +61,69 @@ const INCLUDE_RECURSION_COST: usize = 10;
 #[cfg(feature = "macros")]
 const MACRO_RECURSION_COST: usize = 4;
 
+/// The outcome of running the evaluation loop.
+pub(crate) enum Step {
+    /// The evaluation finished with an optional return value.
+    Done(Option<Value>),
+    /// The evaluation was suspended and can be resumed.
+    Suspended,
+}
+
+/// Controls when the evaluation loop suspends.
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub(crate) enum Suspend {
+    /// Never suspend, the evaluation runs to the end.
+    Never,
+    /// Suspend after every instruction that wrote into the output.
+    OnEmit,
+    /// Suspend whenever an async function or filter must be awaited.
+    #[cfg(feature = "async")]
+    OnAwait,
+}
+
+/// The registers of the evaluation loop.
+///
+/// They are kept outside of the loop so that a suspended evaluation can
+/// be picked up again with [`Vm::resume`].
+pub(crate) struct Registers<'env> {
+    stack: Stack,
+    pc: usize,
+    initial_auto_escape: AutoEscape,
+    auto_escape_stack: Vec<AutoEscape>,
+    undefined_behavior_stack: Vec<UndefinedBehavior>,
+    next_loop_recursion_jump: Option<(usize, bool)>,
+    loaded_filters: [Option<&'env BoxedFilter>; MAX_LOCALS],
+    loaded_tests: [Option<&'env BoxedTest>; MAX_LOCALS],
+    #[cfg(feature = "multi_template")]
+    parent_instructions: Option<&'env Instructions<'env>>,
+}
+
+impl<'env> Registers<'env> {
+    /// Takes the future of the async call the evaluation was suspended on.
+    #[cfg(feature = "async")]
+    pub(crate) fn take_pending_future(&self) -> Option<BoxFuture> {
+        self.stack
+            .peek()
+            .downcast_object_ref::<PendingFuture>()
+            .and_then(|x| x.take())
+    }
+
+    pub(crate) fn new(stack: Stack, pc: usize, initial_auto_escape: AutoEscape) -> Registers<'env> {
+        Registers {
+            stack,
+            pc,
+            initial_auto_escape,
+            auto_escape_stack: vec![],
+            undefined_behavior_stack: vec![],
+            next_loop_recursion_jump: None,
+            loaded_filters: [None; MAX_LOCALS],
+            loaded_tests: [None; MAX_LOCALS],
+            #[cfg(feature = "multi_template")]
+            parent_instructions: None,
+        }
+    }
+}
+
 /// Helps to evaluate something.
 #[cfg_attr(feature = "internal_debug", derive(Debug))]
 pub struct Vm<'env> {
@@ -90,14 +174,85 @@ impl<'env> Vm<'env> {
         auto_escape: AutoEscape,
     ) -> Result<(Option<Value>, State<'template, 'env>), Error> {
         let _guard = value_optimization();
-        let mut state = State::new(
-            self.env,
-            Context::new_with_frame(ok!(Frame::new_checked(root)), self.env.recursion_limit()),
-            auto_escape,
+        let mut state = ok!(self.new_state(instructions, root, blocks, auto_escape));
+        self.eval_state(&mut state, out).map(|x| (x, state))
+    }
+
+    /// Creates a fresh state for the evaluation of the given inputs.
+    pub(crate) fn new_state<'template>(
+        &self,
+        instructions: &'template Instructions<'env>,
+        root: Value,
+        blocks: &'template BTreeMap<&'env str, Instructions<'env>>,
+        auto_escape: AutoEscape,
+    ) -> Result<State<'template, 'env>, Error> {
+        self.new_state_reusing(
             instructions,
+            root,
             prepare_blocks(blocks),
-        );
-        self.eval_state(&mut state, out).map(|x| (x, state))
+            auto_escape,
+            None,
+        )
+    }
+
+    /// Like [`new_state`](Self::new_state) but takes already prepared blocks
+    /// and reuses the context of an earlier evaluation if one is given.
+    pub(crate) fn new_state_reusing<'template>(
+        &self,
+        instructions: &'template Instructions<'env>,
+        root: Value,
+        blocks: BTreeMap<&'env str, BlockStack<'template, 'env>>,
+        auto_escape: AutoEscape,
+        ctx: Option<Context<'env>>,
+    ) -> Result<State<'template, 'env>, Error> {
+        let frame = ok!(Frame::new_checked(root));
+        let ctx = match ctx {
+            Some(mut ctx) => {
+                ctx.reset_with_frame(frame);
+                ctx
+            }
+            None => Context::new_with_frame(frame, self.env.recursion_limit()),
+        };
+        Ok(State::new(self.env, ctx, auto_escape, instructions, blocks))
+    }
+
+    /// Resumes a suspended evaluation until it suspends again.
+    ///
+    /// Returns `true` once the evaluation finished.
+    pub(crate) fn resume(
+        &self,
+        state: &mut State<'_, 'env>,
+        registers: &mut Registers<'env>,
+        out: &mut Output,
+        suspend: Suspend,
+    ) -> Result<bool, Error> {
+        self.do_eval(state, out, registers, suspend)
+            .map(|step| matches!(step, Step::Done(_)))
+    }
+
+    /// Resolves the async call the evaluation was suspended on.
+    ///
+    /// The awaited future must have been taken from the registers with
+    /// [`Registers::take_pending_future`].  Its result replaces the pending
+    /// value on the stack.
+    #[cfg(feature = "async")]
+    pub(crate) fn resolve_pending_future(
+        &self,
+        state: &State,
+        registers: &mut Registers<'env>,
+        rv: Result<Value, Error>,
+    ) -> Result<(), Error> {
+        match rv {
+            Ok(rv) => {
+                registers.stack.pop();
+                registers.stack.push(rv);
+                Ok(())
+            }
+            Err(mut err) => {
+                process_err(&mut err, registers.pc - 1, state);
+                Err(err)
+            }
+        }
     }
 
     /// Evaluate a macro in a state.
@@ -105,6 +260,7 @@ impl<'env> Vm<'env> {
     #[allow(clippy::too_many_arguments)]
     pub fn eval_macro(
         &self,
+        name: &str,
         instructions: &Instructions<'env>,
         pc: usize,
         closure: Value,
@@ -114,21 +270,35 @@ impl<'env> Vm<'env> {
         state: &State,
         args: Vec<Value>,
     ) -> Result<Option<Value>, Error> {
-        let mut ctx = Context::new_with_frame(Frame::new(context_base), self.env.recursion_limit());
+        let mut ctx =
+            Context::new_with_frame(Frame::new(context_base), state.ctx.recursion_limit());
+        ctx.set_globals(state.ctx.globals().clone());
         ok!(ctx.push_frame(Frame::new(closure)));
         if let Some(caller) = caller {
             ctx.store("caller", caller);
         }
-        ok!(ctx.incr_depth(state.ctx.depth() + MACRO_RECURSION_COST));
-        self.do_eval(
+        ok!(ctx
+            .incr_depth(state.ctx.depth() + MACRO_RECURSION_COST)
+            .map_err(|_| {
+                Error::new(
+                    ErrorKind::InvalidOperation,
+                    format!("recursion limit exceeded while calling macro {name:?}"),
+                )
+            }));
+        self.do_eval_to_end(
             &mut State {
                 env: self.env,
                 ctx,
                 current_block: None,
                 auto_escape: state.auto_escape(),
+                undefined_behavior: state.undefined_behavior(),
                 instructions,
                 blocks: BTreeMap::default(),
                 loaded_templates: Default::default(),
+                #[cfg(feature = "multi_template")]
+                include_depth: state.include_depth,
+                #[cfg(feature = "multi_template")]
+                prerendered_blocks: BTreeMap::new(),
                 #[cfg(feature = "macros")]
                 id: state.id,
                 #[cfg(feature = "macros")]
@@ -137,21 +307,85 @@ impl<'env> Vm<'env> {
                 closure_tracker: state.closure_tracker.clone(),
                 #[cfg(feature = "fuel")]
                 fuel_tracker: state.fuel_tracker.clone(),
+                #[cfg(feature = "stats")]
+                stats_tracker: state.stats_tracker.clone(),
+                temps: state.temps.clone(),
+                #[cfg(feature = "internal_debug")]
+                trace_stack: None,
+                #[cfg(feature = "async")]
+                pending_futures: Default::default(),
             },
             out,
-            Stack::from(args),
-            pc,
+            Registers::new(Stack::from(args), pc, state.auto_escape()),
         )
     }
 
+    /// Evaluates an expression against a snapshot of an existing state.
+    pub(crate) fn eval_expr_in_state(
+        &self,
+        instructions: &Instructions<'env>,
+        state: &State<'_, 'env>,
+    ) -> Result<Value, Error> {
+        let mut sub_state = State {
+            env: self.env,
+            ctx: state.ctx.snapshot(),
+            current_block: state.current_block,
+            auto_escape: state.auto_escape(),
+            undefined_behavior: state.undefined_behavior(),
+            instructions,
+            blocks: BTreeMap::default(),
+            loaded_templates: Default::default(),
+            #[cfg(feature = "multi_template")]
+            include_depth: state.include_depth,
+            #[cfg(feature = "multi_template")]
+            prerendered_blocks: BTreeMap::new(),
+            #[cfg(feature = "macros")]
+            id: state.id,
+            #[cfg(feature = "macros")]
+            macros: state.macros.clone(),
+            #[cfg(feature = "macros")]
+            closure_tracker: state.closure_tracker.clone(),
+            #[cfg(feature = "fuel")]
+            fuel_tracker: state.fuel_tracker.clone(),
+            #[cfg(feature = "stats")]
+            stats_tracker: state.stats_tracker.clone(),
+            temps: state.temps.clone(),
+            #[cfg(feature = "internal_debug")]
+            trace_stack: None,
+            #[cfg(feature = "async")]
+            pending_futures: Default::default(),
+        };
+        let auto_escape = sub_state.auto_escape;
+        Ok(ok!(self.do_eval_to_end(
+            &mut sub_state,
+            &mut Output::null(),
+            Registers::new(Stack::default(), 0, auto_escape),
+        ))
+        .expect("expression evaluation did not leave value on stack"))
+    }
+
     /// This is the actual evaluation loop that works with a specific context.
     #[inline(always)]
-    fn eval_state(
+    pub(crate) fn eval_state(
         &self,
         state: &mut State<'_, 'env>,
         out: &mut Output,
     ) -> Result<Option<Value>, Error> {
-        self.do_eval(state, out, Stack::default(), 0)
+        let registers = Registers::new(Stack::default(), 0, state.auto_escape);
+        self.do_eval_to_end(state, out, registers)
+    }
+
+    /// Evaluates without ever suspending.
+    fn do_eval_to_end(
+        &self,
+        state: &mut State<'_, 'env>,
+        out: &mut Output,
+        mut registers: Registers<'env>,
+    ) -> Result<Option<Value>, Error> {
+        match ok!(self.do_eval(state, out, &mut registers, Suspend::Never)) {
+            Step::Done(rv) => Ok(rv),
+            Step::Suspended => unreachable!("evaluation suspended unexpectedly"),
+        }
     }
 
     /// Performs the actual evaluation, optionally with stack growth functionality.
@@ -159,18 +393,18 @@ impl<'env> Vm<'env> {
         &self,
         state: &mut State<'_, 'env>,
         out: &mut Output,
-        stack: Stack,
-        pc: usize,
-    ) -> Result<Option<Value>, Error> {
+        registers: &mut Registers<'env>,
+        suspend: Suspend,
+    ) -> Result<Step, Error> {
         #[cfg(feature = "stacker")]
         {
             stacker::maybe_grow(32 * 1024, 1024 * 1024, || {
-                self.eval_impl(state, out, stack, pc)
+                self.eval_impl(state, out, registers, suspend)
             })
         }
         #[cfg(not(feature = "stacker"))]
         {
-            self.eval_impl(state, out, stack, pc)
+            self.eval_impl(state, out, registers, suspend)
         }
     }
 
@@ -179,21 +413,29 @@ impl<'env> Vm<'env> {
         &self,
         state: &mut State<'_, 'env>,
         out: &mut Output,
-        mut stack: Stack,
-        mut pc: usize,
-    ) -> Result<Option<Value>, Error> {
-        let initial_auto_escape = state.auto_escape;
-        let undefined_behavior = state.undefined_behavior();
-        let mut auto_escape_stack = vec![];
-        let mut next_loop_recursion_jump = None;
-        let mut loaded_filters = [None; MAX_LOCALS];
-        let mut loaded_tests = [None; MAX_LOCALS];
+        registers: &mut Registers<'env>,
+        suspend: Suspend,
+    ) -> Result<Step, Error> {
+        let initial_auto_escape = registers.initial_auto_escape;
+        let mut undefined_behavior = state.undefined_behavior();
+        let strict_comparisons = state.env.strict_comparisons();
+        let defer_undefined_errors = state.env.defer_undefined_errors();
+        let mut stack = mem::take(&mut registers.stack);
+        let mut pc = registers.pc;
+        let mut auto_escape_stack = mem::take(&mut registers.auto_escape_stack);
+        let mut undefined_behavior_stack = mem::take(&mut registers.undefined_behavior_stack);
+        let mut next_loop_recursion_jump = registers.next_loop_recursion_jump.take();
+        let mut loaded_filters = registers.loaded_filters;
+        let mut loaded_tests = registers.loaded_tests;
+        // the instruction that may look up attributes on the undefined value
+        // produced by reading a missing attribute of a namespace.
+        let mut namespace_miss_pc = None;
 
         // If we are extending we are holding the instructions of the target parent
         // template here.  This is used to detect multiple extends and the evaluation
         // uses these instructions when it makes it to the end of the instructions.
         #[cfg(feature = "multi_template")]
-        let mut parent_instructions = None;
+        let mut parent_instructions = registers.parent_instructions.take();
 
         macro_rules! recurse_loop {
             ($capture:expr) => {{
@@ -279,6 +521,31 @@ impl<'env> Vm<'env> {
                 };
             }
 
+            macro_rules! op_cmp {
+                ($op:tt) => {{
+                    b = stack.pop();
+                    a = stack.pop();
+                    if strict_comparisons && a.kind() != b.kind() {
+                        bail!(Error::new(
+                            ErrorKind::InvalidOperation,
+                            format!("cannot compare {} with {}", a.kind(), b.kind())
+                        ));
+                    }
+                    stack.push(Value::from(a $op b));
+                }};
+            }
+
+            macro_rules! restore_undefined_behavior {
+                ($depth:expr) => {{
+                    let depth = $depth;
+                    if undefined_behavior_stack.len() > depth {
+                        undefined_behavior = undefined_behavior_stack[depth];
+                        undefined_behavior_stack.truncate(depth);
+                        state.undefined_behavior = undefined_behavior;
+                    }
+                }};
+            }
+
             macro_rules! assert_valid {
                 ($expr:expr) => {{
                     let val = $expr;
@@ -296,6 +563,25 @@ impl<'env> Vm<'env> {
                 ctx_ok!(tracker.track(instr));
             }
 
+            #[cfg(feature = "stats")]
+            state
+                .stats_tracker
+                .track(instr, stack.len(), state.ctx.depth());
+
+            #[cfg(feature = "internal_debug")]
+            if let Some(ref callback) = self.env.trace_callback {
+                state.trace_stack = Some((stack.len(), stack.try_peek().cloned()));
+                callback(state, pc);
+                state.trace_stack = None;
+            }
+
+            // undefined values with deferred errors fail once they are used
+            // and are otherwise passed on.
+            if defer_undefined_errors && ctx_ok!(pass_on_pending_undefined(instr, &mut stack)) {
+                pc += 1;
+                continue;
+            }
+
             match instr {
                 Instruction::Swap => {
                     let a = stack.pop();
@@ -304,20 +590,54 @@ impl<'env> Vm<'env> {
                     stack.push(b);
                 }
                 Instruction::EmitRaw(val) => {
-                    // this only produces a format error, no need to attach
-                    // location information.
-                    ok!(out.write_str(val).map_err(Error::from));
+                    #[cfg(feature = "fuel")]
+                    ctx_ok!(self.track_output_fuel(state, out, val.len()));
+                    // this only fails if the underlying writer fails.  For IO
+                    // errors the location is carried over once the error is
+                    // translated so we attach it here.
+                    ctx_ok!(out.write_str(val).map_err(Error::from));
                 }
                 Instruction::Emit => {
-                    ctx_ok!(self.env.format(&stack.pop(), state, out));
+                    #[cfg(feature = "fuel")]
+                    let bytes_written = out.bytes_written();
+                    let mut value = stack.pop();
+                    if value.is_undefined()
+                        && self.env.mark_undefined_in_output()
+                        && !matches!(undefined_behavior, UndefinedBehavior::Strict)
+                    {
+                        value = Value::from(match state.instructions.get_expr_path(pc) {
+                            Some(path) => format!("<undefined:{path}>"),
+                            None => "<undefined>".into(),
+                        });
+                    }
+                    ctx_ok!(self.env.format(&value, state, out));
+                    #[cfg(feature = "fuel")]
+                    ctx_ok!(self.track_output_fuel(
+                        state,
+                        out,
+                        out.bytes_written() - bytes_written
+                    ));
                 }
                 Instruction::StoreLocal(name) => {
                     state.ctx.store(name, stack.pop());
                 }
                 Instruction::Lookup(name) => {
-                    stack.push(assert_valid!(state
-                        .lookup(name)
-                        .unwrap_or(Value::UNDEFINED)));
+                    stack.push(match state.lookup(name) {
+                        Some(value) => assert_valid!(value),
+                        None => {
+                            let ctx = UndefinedContext::Lookup { name };
+                            let value = ctx_ok!(self
+                                .env
+                                .handle_undefined(state, ctx)
+                                .map_err(|err| with_loop_var_hint(err, name)));
+                            if value.is_undefined() {
+                                warn(state, pc, WarningKind::Undefined, || {
+                                    format!("undefined variable {name:?}")
+                                });
+                            }
+                            value
+                        }
+                    });
                 }
                 Instruction::GetAttr(name) => {
                     a = stack.pop();
@@ -326,9 +646,44 @@ impl<'env> Vm<'env> {
                     // do not need to pass down the error object for the more common success case.
                     // Only when we cannot look up something, we start to consider the undefined
                     // special case.
-                    stack.push(match a.get_attr_fast(name) {
+                    let value = if state.env.is_attr_accessible(&a, name) {
+                        a.get_attr_fast(name)
+                    } else {
+                        None
+                    };
+                    stack.push(match value {
                         Some(value) => assert_valid!(value),
-                        None => ctx_ok!(undefined_behavior.handle_undefined(a.is_undefined())),
+                        // like nested assignments, reads through missing
+                        // intermediate namespaces resolve to undefined
+                        None if a.is_undefined() && namespace_miss_pc == Some(pc) => {
+                            namespace_miss_pc = Some(pc + 1);
+                            Value::UNDEFINED
+                        }
+                        None => {
+                            let ctx = UndefinedContext::GetAttr { parent: &a, name };
+                            let value =
+                                ctx_ok!(self.env.handle_undefined(state, ctx).map_err(|err| {
+                                    match state.instructions.get(pc.wrapping_sub(1)) {
+                                        Some(Instruction::Lookup(parent)) if a.is_undefined() => {
+                                            with_loop_var_hint(err, parent)
+                                        }
+                                        _ => err,
+                                    }
+                                }));
+                            // lookups on undefined values were already reported
+                            if value.is_undefined() && !a.is_undefined() {
+                                warn(state, pc, WarningKind::Undefined, || {
+                                    format!("undefined attribute {name:?}")
+                                });
+                            }
+                            if value.is_undefined()
+                                && !matches!(undefined_behavior, UndefinedBehavior::Strict)
+                                && a.downcast_object_ref::<Namespace>().is_some()
+                            {
+                                namespace_miss_pc = Some(pc + 1);
+                            }
+                            value
+                        }
                     });
                 }
                 Instruction::SetAttr(name) => {
@@ -343,12 +698,39 @@ impl<'env> Vm<'env> {
                         ));
                     }
                 }
+                Instruction::GetOrCreateNamespace(name) => {
+                    a = stack.pop();
+                    if let Some(ns) = a.downcast_object_ref::<Namespace>() {
+                        stack.push(ns.get_or_create_namespace(name));
+                    } else {
+                        bail!(Error::new(
+                            ErrorKind::InvalidOperation,
+                            format!("can only assign to namespaces, not {}", a.kind())
+                        ));
+                    }
+                }
                 Instruction::GetItem => {
                     a = stack.pop();
                     b = stack.pop();
-                    stack.push(match b.get_item_opt(&a) {
+                    let value = match a.as_str() {
+                        Some(name) if !state.env.is_attr_accessible(&b, name) => None,
+                        _ => b.get_item_opt(&a),
+                    };
+                    stack.push(match value {
                         Some(value) => assert_valid!(value),
-                        None => ctx_ok!(undefined_behavior.handle_undefined(b.is_undefined())),
+                        None => {
+                            let ctx = UndefinedContext::GetItem {
+                                parent: &b,
+                                key: &a,
+                            };
+                            let value = ctx_ok!(self.env.handle_undefined(state, ctx));
+                            if value.is_undefined() && !b.is_undefined() {
+                                warn(state, pc, WarningKind::Undefined, || {
+                                    format!("undefined item {a:?}")
+                                });
+                            }
+                            value
+                        }
                     });
                 }
                 Instruction::Slice => {
@@ -358,12 +740,16 @@ impl<'env> Vm<'env> {
                     a = stack.pop();
                     if a.is_undefined() && matches!(undefined_behavior, UndefinedBehavior::Strict) {
                         bail!(Error::from(ErrorKind::UndefinedError));
+                    } else {
+                        stack.push(ctx_ok!(ops::slice(a, b, stop, step)));
                     }
-                    stack.push(ctx_ok!(ops::slice(a, b, stop, step)));
                 }
                 Instruction::LoadConst(value) => {
                     stack.push(value.clone());
                 }
+                Instruction::LoadTemplateConst(idx) => {
+                    stack.push(ctx_ok!(self.load_template_const(state, *idx)));
+                }
                 Instruction::BuildMap(pair_count) => {
                     let mut map = value_map_with_capacity(*pair_count);
                     for _ in 0..*pair_count {
@@ -386,7 +772,8 @@ impl<'env> Vm<'env> {
                     let count = n.unwrap_or_else(|| stack.pop().try_into().unwrap());
                     let mut v = Vec::with_capacity(untrusted_size_hint(count));
                     for _ in 0..count {
-                        v.push(stack.pop());
+                        let item = stack.pop();
+                        v.push(item);
                     }
                     v.reverse();
                     stack.push(Value::from_object(v))
@@ -403,10 +790,10 @@ impl<'env> Vm<'env> {
                 Instruction::Pow => func_binop!(pow),
                 Instruction::Eq => op_binop!(==),
                 Instruction::Ne => op_binop!(!=),
-                Instruction::Gt => op_binop!(>),
-                Instruction::Gte => op_binop!(>=),
-                Instruction::Lt => op_binop!(<),
-                Instruction::Lte => op_binop!(<=),
+                Instruction::Gt => op_cmp!(>),
+                Instruction::Gte => op_cmp!(>=),
+                Instruction::Lt => op_cmp!(<),
+                Instruction::Lte => op_cmp!(<=),
                 Instruction::Not => {
                     a = stack.pop();
                     stack.push(Value::from(!a.is_true()));
@@ -416,6 +803,17 @@ impl<'env> Vm<'env> {
                     b = stack.pop();
                     stack.push(ops::string_concat(b, &a));
                 }
+                Instruction::StringConcatMany(n) => {
+                    a = ops::string_concat_many(stack.slice_top(*n));
+                    stack.drop_top(*n);
+                    stack.push(a);
+                }
+                #[cfg(feature = "custom_operators")]
+                Instruction::CustomBinop(id) => {
+                    b = stack.pop();
+                    a = stack.pop();
+                    stack.push(ctx_ok!(self.env.apply_custom_operator(*id, &a, &b)));
+                }
                 Instruction::In => {
                     a = stack.pop();
                     b = stack.pop();
@@ -433,6 +831,7 @@ impl<'env> Vm<'env> {
                 }
                 Instruction::PopFrame => {
                     if let Some(mut loop_ctx) = state.ctx.pop_frame().current_loop {
+                        restore_undefined_behavior!(loop_ctx.undefined_behavior_depth);
                         if let Some((target, end_capture)) = loop_ctx.current_recursion_jump.take()
                         {
                             pc = target;
@@ -450,26 +849,21 @@ impl<'env> Vm<'env> {
                 }
                 Instruction::PushLoop(flags) => {
                     a = stack.pop();
-                    ctx_ok!(self.push_loop(state, a, *flags, pc, next_loop_recursion_jump.take()));
+                    ctx_ok!(self.push_loop(
+                        state,
+                        a,
+                        *flags,
+                        pc,
+                        next_loop_recursion_jump.take(),
+                        undefined_behavior_stack.len(),
+                    ));
                 }
                 Instruction::Iterate(jump_target) => {
+                    restore_undefined_behavior!(
+                        state.ctx.current_loop().unwrap().undefined_behavior_depth
+                    );
                     let l = state.ctx.current_loop().unwrap();
-                    l.object.idx.fetch_add(1, Ordering::Relaxed);
-
-                    let next = {
-                        #[cfg(feature = "adjacent_loop_items")]
-                        {
-                            let mut triple = l.object.value_triple.lock().unwrap();
-                            triple.0 = triple.1.take();
-                            triple.1 = triple.2.take();
-                            triple.2 = l.iterator.next();
-                            triple.1.clone()
-                        }
-                        #[cfg(not(feature = "adjacent_loop_items"))]
-                        {
-                            l.iterator.next()
-                        }
-                    };
+                    let next = Loop::advance(&mut l.object, &mut l.iterator);
                     match next {
                         Some(item) => stack.push(assert_valid!(item)),
                         None => {
@@ -512,7 +906,7 @@ impl<'env> Vm<'env> {
                 #[cfg(feature = "multi_template")]
                 Instruction::CallBlock(name) => {
                     if parent_instructions.is_none() && !out.is_discarding() {
-                        self.call_block(name, state, out)?;
+                        ctx_ok!(self.call_block(name, state, out));
                     }
                 }
                 Instruction::PushAutoEscape => {
@@ -523,6 +917,16 @@ impl<'env> Vm<'env> {
                 Instruction::PopAutoEscape => {
                     state.auto_escape = auto_escape_stack.pop().unwrap();
                 }
+                Instruction::PushUndefinedBehavior => {
+                    a = stack.pop();
+                    undefined_behavior_stack.push(state.undefined_behavior);
+                    undefined_behavior = ctx_ok!(self.derive_undefined_behavior(a));
+                    state.undefined_behavior = undefined_behavior;
+                }
+                Instruction::PopUndefinedBehavior => {
+                    undefined_behavior = undefined_behavior_stack.pop().unwrap();
+                    state.undefined_behavior = undefined_behavior;
+                }
                 Instruction::BeginCapture(mode) => {
                     out.begin_capture(*mode);
                 }
@@ -532,28 +936,41 @@ impl<'env> Vm<'env> {
                 Instruction::ApplyFilter(name, arg_count, local_id) => {
                     let filter =
                         ctx_ok!(get_or_lookup_local(&mut loaded_filters, *local_id, || {
-                            state.env.get_filter(name)
+                            state
+                                .instructions
+                                .filter_id(*local_id)
+                                .and_then(|id| state.env.get_filter_by_id(id))
+                                .or_else(|| state.env.get_filter(name))
                         })
-                        .ok_or_else(|| {
-                            Error::new(
-                                ErrorKind::UnknownFilter,
-                                format!("filter {name} is unknown"),
-                            )
-                        }));
+                        .ok_or_else(|| { unknown_name_error(state.env, CallKind::Filter, name) }));
+                    ctx_ok!(state.env.check_call_policy(CallKind::Filter, name));
+                    warn_alias(state, pc, CallKind::Filter, name);
                     let args = stack.slice_top(*arg_count);
-                    a = ctx_ok!(filter.apply_to(state, args));
+                    a = if defer_undefined_errors {
+                        ctx_ok!(apply_filter_deferring_undefined(state, filter, args))
+                    } else {
+                        ctx_ok!(filter.apply_to(state, args))
+                    };
                     stack.drop_top(*arg_count);
                     stack.push(a);
                 }
                 Instruction::PerformTest(name, arg_count, local_id) => {
                     let test = ctx_ok!(get_or_lookup_local(&mut loaded_tests, *local_id, || {
-                        state.env.get_test(name)
+                        state
+                            .instructions
+                            .test_id(*local_id)
+                            .and_then(|id| state.env.get_test_by_id(id))
+                            .or_else(|| state.env.get_test(name))
                     })
-                    .ok_or_else(|| {
-                        Error::new(ErrorKind::UnknownTest, format!("test {name} is unknown"))
-                    }));
+                    .ok_or_else(|| { unknown_name_error(state.env, CallKind::Test, name) }));
+                    ctx_ok!(state.env.check_call_policy(CallKind::Test, name));
+                    warn_alias(state, pc, CallKind::Test, name);
                     let args = stack.slice_top(*arg_count);
-                    let rv = ctx_ok!(test.perform(state, args));
+                    let rv = if defer_undefined_errors {
+                        ctx_ok!(perform_test_deferring_undefined(state, test, args))
+                    } else {
+                        ctx_ok!(test.perform(state, args))
+                    };
                     stack.drop_top(*arg_count);
                     stack.push(Value::from(rv));
                 }
@@ -578,25 +995,50 @@ impl<'env> Vm<'env> {
                         // leave the one argument on the stack for the recursion
                         recurse_loop!(true);
                     } else if let Some(func) = state.lookup(name) {
+                        ctx_ok!(check_callable_policy(state, &func, Some(name)));
+                        warn_alias(state, pc, CallKind::Function, name);
                         let args = stack.slice_top(*arg_count);
                         a = ctx_ok!(func.call(state, args));
                         stack.drop_top(*arg_count);
                         stack.push(a);
                     } else {
-                        bail!(Error::new(
-                            ErrorKind::UnknownFunction,
-                            format!("{name} is unknown"),
-                        ));
+                        bail!(unknown_name_error(state.env, CallKind::Function, name));
                     }
                 }
+                #[cfg(feature = "i18n")]
+                Instruction::Translate(plural, arg_count) => {
+                    // resolved directly so that context variables cannot
+                    // shadow the translation functions.
+                    let func = if *plural {
+                        BoxedFunction::new(crate::functions::ngettext)
+                    } else {
+                        BoxedFunction::new(crate::functions::gettext)
+                    };
+                    let args = stack.slice_top(*arg_count);
+                    a = ctx_ok!(func.invoke(state, args));
+                    stack.drop_top(*arg_count);
+                    stack.push(a);
+                }
                 Instruction::CallMethod(name, arg_count) => {
                     let args = stack.slice_top(*arg_count);
+                    if !state.env.is_attr_accessible(&args[0], name) {
+                        bail!(Error::new(
+                            ErrorKind::UnknownMethod,
+                            format!("{} has no method named {}", args[0].kind(), name)
+                        ));
+                    }
+                    if state.env.has_call_policy() {
+                        if let Ok(attr) = args[0].get_attr(name) {
+                            ctx_ok!(check_callable_policy(state, &attr, None));
+                        }
+                    }
                     a = ctx_ok!(args[0].call_method(state, name, &args[1..]));
                     stack.drop_top(*arg_count);
                     stack.push(a);
                 }
                 Instruction::CallObject(arg_count) => {
                     let args = stack.slice_top(*arg_count);
+                    ctx_ok!(check_callable_policy(state, &args[0], None));
                     a = ctx_ok!(args[0].call(state, &args[1..]));
                     stack.drop_top(*arg_count);
                     stack.push(a);
@@ -649,9 +1091,15 @@ impl<'env> Vm<'env> {
                     out.begin_capture(CaptureMode::Discard);
                 }
                 #[cfg(feature = "multi_template")]
-                Instruction::Include(ignore_missing) => {
+                Instruction::Include(flags) => {
                     a = stack.pop();
-                    ctx_ok!(self.perform_include(a, state, out, *ignore_missing));
+                    ctx_ok!(self.perform_include(a, None, state, out, *flags, pc));
+                }
+                #[cfg(feature = "multi_template")]
+                Instruction::IncludeWithContext(flags) => {
+                    b = stack.pop();
+                    a = stack.pop();
+                    ctx_ok!(self.perform_include(a, Some(b), state, out, *flags, pc));
                 }
                 #[cfg(feature = "multi_template")]
                 Instruction::ExportLocals => {
@@ -691,19 +1139,133 @@ impl<'env> Vm<'env> {
                 }
             }
             pc += 1;
+
+            macro_rules! suspend {
+                () => {{
+                    *registers = Registers {
+                        stack,
+                        pc,
+                        initial_auto_escape,
+                        auto_escape_stack,
+                        undefined_behavior_stack,
+                        next_loop_recursion_jump,
+                        loaded_filters,
+                        loaded_tests,
+                        #[cfg(feature = "multi_template")]
+                        parent_instructions,
+                    };
+                    return Ok(Step::Suspended);
+                }};
+            }
+
+            // when streaming, we stop after every instruction that produced
+            // output that was not captured and stash away the registers.
+            if suspend == Suspend::OnEmit && instr.may_emit() && !out.is_capturing() {
+                suspend!();
+            }
+
+            // async callables return pending futures which can only be
+            // awaited if the driver of the evaluation is async and if they
+            // are the direct result of a call.  Futures created in any other
+            // way (eg: by the `map` filter) would end up unawaited in values.
+            #[cfg(feature = "async")]
+            if state.pending_futures.load(Ordering::Relaxed) != 0 {
+                let awaitable = state.pending_futures.swap(0, Ordering::Relaxed) == 1
+                    && matches!(
+                        instr,
+                        Instruction::ApplyFilter(..)
+                            | Instruction::CallFunction(..)
+                            | Instruction::CallMethod(..)
+                            | Instruction::CallObject(..)
+                    )
+                    && stack
+                        .peek()
+                        .downcast_object_ref::<PendingFuture>()
+                        .is_some();
+                if !awaitable || suspend != Suspend::OnAwait {
+                    pc -= 1;
+                    bail!(Error::new(
+                        ErrorKind::InvalidOperation,
+                        "async functions and filters can only be awaited when \
+                         called directly at the top level of templates rendered \
+                         with render_async"
+                    ));
+                }
+                suspend!();
+            }
         }
 
-        Ok(stack.try_pop())
+        Ok(Step::Done(stack.try_pop()))
+    }
+
+    /// Charges fuel for bytes written to the output if enabled.
+    ///
+    /// Only output that is not captured is charged.  Captured output is
+    /// charged once it's emitted and discarded output is free.
+    #[cfg(feature = "fuel")]
+    fn track_output_fuel(&self, state: &State, out: &Output, bytes: usize) -> Result<(), Error> {
+        match state.fuel_tracker {
+            Some(ref tracker) if self.env.charge_fuel_for_output() && !out.is_capturing() => {
+                tracker.track_output(bytes)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Returns the value of a template constant.
+    ///
+    /// The constant is evaluated on first use in an isolated context that
+    /// only sees the globals and the values of earlier constants.  The
+    /// result is cached on the compiled template.
+    fn load_template_const(&self, state: &State<'_, 'env>, idx: u32) -> Result<Value, Error> {
+        let consts = state.instructions.consts();
+        let c = &consts[idx as usize];
+        if let Some(ref value) = *c.value.lock().unwrap() {
+            return Ok(value.clone());
+        }
+
+        let earlier = &consts[..idx as usize];
+        let mut root = value_map_with_capacity(c.refs.len());
+        for name in &c.refs {
+            if let Some(other) = earlier.iter().find(|x| x.name == *name) {
+                let value = other.value.lock().unwrap().clone();
+                root.insert(Value::from(*name), value.unwrap_or_default());
+            } else if self.env.get_global(name).is_none() {
+                return Err(Error::new(
+                    ErrorKind::InvalidOperation,
+                    format!(
+                        "const {:?} references {:?} which is neither a constant nor a global",
+                        c.name, name
+                    ),
+                ));
+            }
+        }
+
+        let (value, _) = ok!(Vm::new(self.env).eval(
+            &c.instructions,
+            Value::from_object(root),
+            &BTreeMap::new(),
+            &mut Output::null(),
+            state.auto_escape,
+        ));
+        let mut cached = c.value.lock().unwrap();
+        Ok(cached.get_or_insert(value.unwrap_or_default()).clone())
     }
 
     #[cfg(feature = "multi_template")]
+    #[allow(clippy::too_many_arguments)]
     fn perform_include(
         &self,
         name: Value,
+        mut context: Option<Value>,
         state: &mut State<'_, 'env>,
         out: &mut Output,
-        ignore_missing: bool,
+        flags: u8,
+        pc: usize,
     ) -> Result<(), Error> {
+        use crate::compiler::instructions::{INCLUDE_IGNORE_ERRORS, INCLUDE_IGNORE_MISSING};
+
+        let ignore_errors = flags & INCLUDE_IGNORE_ERRORS != 0;
         let obj = name.as_object();
         let choices = obj
             .as_ref()
@@ -712,6 +1274,15 @@ impl<'env> Vm<'env> {
             .flatten()
             .chain(obj.is_none().then(|| name.clone()));
 
+        if let Some(ref vars) = context {
+            if vars.kind() != ValueKind::Map {
+                return Err(Error::new(
+                    ErrorKind::InvalidOperation,
+                    "include context must be a map",
+                ));
+            }
+        }
+
         let mut templates_tried = vec![];
         for choice in choices {
             let name = ok!(choice.as_str().ok_or_else(|| {
@@ -721,7 +1292,10 @@ impl<'env> Vm<'env> {
                 )
             }));
             let tmpl = match state.get_template(name) {
-                Ok(tmpl) => tmpl,
+                Ok(tmpl) => {
+                    state.env.notify_template_load(tmpl.name());
+                    tmpl
+                }
                 Err(err) => {
                     if err.kind() == ErrorKind::TemplateNotFound {
                         templates_tried.push(choice);
@@ -732,15 +1306,71 @@ impl<'env> Vm<'env> {
                 }
             };
 
+            if !templates_tried.is_empty() {
+                warn(state, pc, WarningKind::IncludeFallback, || {
+                    format!(
+                        "included {:?} because {} could not be found",
+                        tmpl.name(),
+                        templates_tried
+                            .iter()
+                            .map(|x| format!("{x:?}"))
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    )
+                });
+            }
+
+            if state.include_depth >= self.env.max_include_depth() {
+                return Err(Error::new(
+                    ErrorKind::InvalidOperation,
+                    format!(
+                        "include depth limit of {} exceeded when including {:?}",
+                        self.env.max_include_depth(),
+                        tmpl.name()
+                    ),
+                ));
+            }
+
             let (new_instructions, new_blocks) = ok!(tmpl.instructions_and_blocks());
             let old_escape = mem::replace(&mut state.auto_escape, tmpl.initial_auto_escape());
             let old_instructions = mem::replace(&mut state.instructions, new_instructions);
             let old_blocks = mem::replace(&mut state.blocks, prepare_blocks(new_blocks));
-            // we need to make a copy of the loaded templates here as we want
-            // to forget about the templates that an include triggered by the
-            // time the include finishes.
-            let old_loaded_templates = state.loaded_templates.clone();
-            ok!(state.ctx.incr_depth(INCLUDE_RECURSION_COST));
+            // we want to forget about the templates that an include triggered
+            // by the time the include finishes.  As templates are only ever
+            // added while the include runs, it's enough to remember how many
+            // were loaded and to truncate back to that afterwards.
+            let loaded_templates_marker = state.loaded_templates.len();
+            ok!(state.ctx.incr_depth(INCLUDE_RECURSION_COST).map_err(|_| {
+                Error::new(
+                    ErrorKind::InvalidOperation,
+                    format!("recursion limit exceeded while including {:?}", tmpl.name()),
+                )
+            }));
+            state.include_depth += 1;
+            // an explicit context replaces the variables of the caller for
+            // the duration of the include.  Globals stay visible.
+            let old_ctx = match context.take() {
+                Some(vars) => {
+                    let mut ctx = Context::new_with_frame(
+                        ok!(Frame::new_checked(vars)),
+                        state.ctx.recursion_limit(),
+                    );
+                    ctx.set_globals(state.ctx.globals().clone());
+                    ok!(ctx.incr_depth(state.ctx.depth()));
+                    Some(mem::replace(&mut state.ctx, ctx))
+                }
+                None => None,
+            };
+            // when errors are ignored, the output of the include is held back
+            // until it finished rendering so that a failure emits nothing.
+            // A failure can also leave frames and captures behind which have
+            // to be cleaned up before rendering continues.
+            let checkpoint = state.ctx.checkpoint();
+            let capture_depth = out.capture_depth();
+            let old_undefined_behavior = state.undefined_behavior;
+            if ignore_errors {
+                out.begin_capture(CaptureMode::Capture);
+            }
             let rv;
             #[cfg(feature = "macros")]
             {
@@ -752,21 +1382,47 @@ impl<'env> Vm<'env> {
             {
                 rv = self.eval_state(state, out);
             }
+            if ignore_errors {
+                if rv.is_err() {
+                    state.ctx.restore_checkpoint(checkpoint);
+                    out.end_captures_above(capture_depth + 1);
+                    state.undefined_behavior = old_undefined_behavior;
+                }
+                let captured = out.end_capture(AutoEscape::None);
+                if rv.is_ok() {
+                    ok!(out
+                        .write_str(captured.as_str().unwrap_or_default())
+                        .map_err(Error::from));
+                }
+            }
+            if let Some(old_ctx) = old_ctx {
+                state.ctx = old_ctx;
+            }
             state.ctx.decr_depth(INCLUDE_RECURSION_COST);
-            state.loaded_templates = old_loaded_templates;
+            state.include_depth -= 1;
+            state.loaded_templates.truncate(loaded_templates_marker);
             state.auto_escape = old_escape;
             state.instructions = old_instructions;
             state.blocks = old_blocks;
-            ok!(rv.map_err(|err| {
-                Error::new(
-                    ErrorKind::BadInclude,
-                    format!("error in \"{}\"", tmpl.name()),
-                )
-                .with_source(err)
-            }));
+            match rv {
+                Ok(_) => {}
+                Err(err) if ignore_errors => {
+                    warn(state, pc, WarningKind::IncludeError, || {
+                        format!("ignored error in {:?}: {}", tmpl.name(), err)
+                    });
+                }
+                Err(err) => {
+                    return Err(Error::new(
+                        ErrorKind::BadInclude,
+                        format!("error in \"{}\"", tmpl.name()),
+                    )
+                    .with_template_stack_of(&err)
+                    .with_source(err))
+                }
+            }
             return Ok(());
         }
-        if !templates_tried.is_empty() && !ignore_missing {
+        if !templates_tried.is_empty() && flags & INCLUDE_IGNORE_MISSING == 0 {
             Err(Error::new(
                 ErrorKind::TemplateNotFound,
                 if templates_tried.len() == 1 {
@@ -798,10 +1454,16 @@ impl<'env> Vm<'env> {
 
         let block_stack = state.blocks.get_mut(name).unwrap();
         if !block_stack.push() {
-            return Err(Error::new(
-                ErrorKind::InvalidOperation,
-                "no parent block exists",
-            ));
+            return if !self.env.lenient_super() {
+                Err(Error::new(
+                    ErrorKind::InvalidOperation,
+                    "no parent block exists",
+                ))
+            } else if capture {
+                Ok(Value::from(""))
+            } else {
+                Ok(Value::UNDEFINED)
+            };
         }
 
         if capture {
@@ -816,7 +1478,9 @@ impl<'env> Vm<'env> {
         state.blocks.get_mut(name).unwrap().pop();
 
         ok!(rv.map_err(|err| {
-            Error::new(ErrorKind::EvalBlock, "error in super block").with_source(err)
+            Error::new(ErrorKind::EvalBlock, "error in super block")
+                .with_template_stack_of(&err)
+                .with_source(err)
         }));
         if capture {
             Ok(out.end_capture(state.auto_escape))
@@ -835,6 +1499,17 @@ impl<'env> Vm<'env> {
                     "cannot recurse outside of recursive loop",
                 ))
             }
+        } else if state
+            .lookup("loop")
+            .map_or(false, |x| x.downcast_object_ref::<Loop>().is_some())
+        {
+            // the loop object was captured by a macro closure or passed
+            // along, but the recursion can only happen in the loop body.
+            Err(Error::new(
+                ErrorKind::InvalidOperation,
+                "cannot recurse a loop from a macro, loop() must be called \
+                 directly in the body of the recursive loop",
+            ))
         } else {
             Err(Error::new(
                 ErrorKind::InvalidOperation,
@@ -865,8 +1540,9 @@ impl<'env> Vm<'env> {
             ));
         }
         let tmpl = ok!(state.get_template(name));
+        state.env.notify_template_load(tmpl.name());
         let (new_instructions, new_blocks) = ok!(tmpl.instructions_and_blocks());
-        state.loaded_templates.insert(new_instructions.name());
+        state.loaded_templates.push(new_instructions.name());
         for (name, instr) in new_blocks.iter() {
             state
                 .blocks
@@ -884,6 +1560,13 @@ impl<'env> Vm<'env> {
         state: &mut State<'_, 'env>,
         out: &mut Output,
     ) -> Result<Option<Value>, Error> {
+        // blocks of included templates are always evaluated
+        if state.include_depth == 0 {
+            if let Some(rendered) = state.prerendered_blocks.get(name) {
+                ok!(out.write_str(rendered).map_err(Error::from));
+                return Ok(None);
+            }
+        }
         if let Some((name, block_stack)) = state.blocks.get_key_value(name) {
             let old_block = mem::replace(&mut state.current_block, Some(name));
             let old_instructions =
@@ -898,7 +1581,8 @@ impl<'env> Vm<'env> {
             Err(Error::new(
                 ErrorKind::UnknownBlock,
                 format!("block '{}' not found", name),
-            ))
+            )
+            .with_available_names(state.blocks.keys().map(|x| x.to_string()).collect()))
         }
     }
 
@@ -924,36 +1608,88 @@ impl<'env> Vm<'env> {
         }
     }
 
+    fn derive_undefined_behavior(&self, value: Value) -> Result<UndefinedBehavior, Error> {
+        match value.as_str() {
+            Some("lenient") => Ok(UndefinedBehavior::Lenient),
+            Some("chainable") => Ok(UndefinedBehavior::Chainable),
+            Some("strict") => Ok(UndefinedBehavior::Strict),
+            _ => Err(Error::new(
+                ErrorKind::InvalidOperation,
+                "invalid value to undefined tag",
+            )),
+        }
+    }
+
     fn push_loop(
         &self,
         state: &mut State<'_, 'env>,
-        iterable: Value,
+        mut iterable: Value,
         flags: u8,
         pc: usize,
         current_recursion_jump: Option<(usize, bool)>,
+        undefined_behavior_depth: usize,
     ) -> Result<(), Error> {
+        let recursive = flags & LOOP_FLAG_RECURSIVE != 0;
+        let detect_cycles =
+            recursive && !matches!(state.env.loop_cycle_behavior(), LoopCycleBehavior::Ignore);
+        let mut recursion_source = None;
+        if detect_cycles {
+            if current_recursion_jump.is_some() && state.ctx.is_recursing_into(pc, &iterable) {
+                if matches!(state.env.loop_cycle_behavior(), LoopCycleBehavior::Error) {
+                    return Err(Error::new(
+                        ErrorKind::InvalidOperation,
+                        "recursive loop detected a cycle",
+                    ));
+                }
+                iterable = Value::from(Vec::<Value>::new());
+            }
+            recursion_source = Some(iterable.clone());
+        }
         #[allow(unused_mut)]
-        let mut iterator = ok!(state.undefined_behavior().try_iter(iterable));
+        let mut iterator = if state.env.sort_map_keys() && iterable.kind() == ValueKind::Map {
+            let mut keys = ok!(iterable.try_iter()).collect::<Vec<_>>();
+            keys.sort();
+            ok!(Value::from(keys).try_iter())
+        } else {
+            ok!(state.undefined_behavior().try_iter(iterable))
+        };
         // for an iterator where the lower and upper bound are matching we can
         // consider them to have ExactSizeIterator semantics.  We do however not
         // expect ExactSizeIterator bounds themselves to support iteration by
         // other means.
-        let len = match iterator.size_hint() {
+        let mut len = match iterator.size_hint() {
             (lower, Some(upper)) if lower == upper => Some(lower),
             _ => None,
         };
+        // if the loop asked for it, we buffer up the items of iterators with
+        // unknown length so that the loop knows its length.
+        if len.is_none() && flags & LOOP_FLAG_PRECOUNT != 0 {
+            let items = iterator.collect::<Vec<_>>();
+            len = Some(items.len());
+            iterator = ok!(Value::from(items).try_iter());
+        }
         let depth = state
             .ctx
             .current_loop()
             .filter(|x| x.recurse_jump_target.is_some())
             .map_or(0, |x| x.object.depth + 1);
-        let recursive = flags & LOOP_FLAG_RECURSIVE != 0;
+        if depth > state.env.max_loop_recursion_depth() {
+            return Err(Error::new(
+                ErrorKind::InvalidOperation,
+                format!(
+                    "recursive loop exceeded maximum loop recursion depth ({})",
+                    state.env.max_loop_recursion_depth()
+                ),
+            ));
+        }
         let with_loop_var = flags & LOOP_FLAG_WITH_LOOP_VAR != 0;
         ok!(state.ctx.push_frame(Frame {
             current_loop: Some(LoopState {
                 with_loop_var,
                 recurse_jump_target: if recursive { Some(pc) } else { None },
                 current_recursion_jump,
+                recursion_source,
+                undefined_behavior_depth,
                 object: Arc::new(Loop {
                     idx: AtomicUsize::new(!0usize),
                     len,
@@ -1002,12 +1738,13 @@ impl<'env> Vm<'env> {
         name: &str,
         flags: u8,
     ) {
-        use crate::{compiler::instructions::MACRO_CALLER, vm::macro_object::Macro};
+        use crate::{
+            compiler::instructions::MACRO_CALLER, vm::macro_object::Macro, vm::state::MacroDefs,
+        };
 
         let arg_spec = stack.pop().try_iter().unwrap().collect();
         let closure = stack.pop();
-        let macro_ref_id = state.macros.len();
-        Arc::make_mut(&mut state.macros).push((state.instructions, offset));
+        let macro_ref_id = MacroDefs::push(&mut state.macros, state.instructions, offset);
         stack.push(Value::from_object(Macro {
             name: Value::from(name),
             arg_spec,
@@ -1019,6 +1756,219 @@ impl<'env> Vm<'env> {
     }
 }
 
+/// Explains undefined errors caused by accessing `loop` outside of a loop.
+///
+/// The loop variable only exists in the body of a for loop.  Macros do not
+/// see the loop they are called from unless they were declared within it, so
+/// referencing `loop` from such a macro is a common source of confusion.
+#[inline(never)]
+#[cold]
+fn with_loop_var_hint(mut err: Error, name: &str) -> Error {
+    if name == "loop" && err.kind() == ErrorKind::UndefinedError && err.detail().is_none() {
+        err.set_detail(
+            "loop is only defined in the body of a for loop; \
+             pass it as argument to macros called from the loop",
+        );
+    }
+    err
+}
+
+/// Reports a warning for the instruction at `pc` if a warning sink is set.
+#[inline(always)]
+fn warn<F: FnOnce() -> String>(state: &State, pc: usize, kind: WarningKind, detail: F) {
+    if state.env.has_warning_sink() {
+        emit_warning(state, pc, kind, detail());
+    }
+}
+
+#[inline(never)]
+#[cold]
+fn emit_warning(state: &State, pc: usize, kind: WarningKind, detail: String) {
+    let mut warning = Warning::new(kind, detail);
+    warning.set_filename_and_line(
+        state.instructions.name(),
+        state.instructions.get_line(pc).unwrap_or(0),
+    );
+    state.env.emit_warning(warning);
+}
+
+/// Remembers the call sites that already reported a deprecated alias.
+#[derive(Default)]
+struct WarnedAliasSites(Mutex<BTreeSet<(usize, usize)>>);
+
+/// Handles undefined values with deferred errors for an instruction.
+///
+/// Fails with the deferred error if the instruction uses such a value.  If
+/// the instruction would look up something on such a value instead, the
+/// value is passed on and `true` is returned to skip the instruction.
+///
+/// See [`Environment::set_defer_undefined_errors`].
+#[inline(never)]
+fn pass_on_pending_undefined(instr: &Instruction, stack: &mut Stack) -> Result<bool, Error> {
+    // the number of used values at the top of the stack and whether the value
+    // below them is looked up on.
+    let (used, looked_up_on) = match *instr {
+        Instruction::GetAttr(_) => (0, true),
+        Instruction::GetItem => (1, true),
+        Instruction::Slice => (3, true),
+        Instruction::Emit
+        | Instruction::UnpackList(_)
+        | Instruction::Not
+        | Instruction::Neg
+        | Instruction::PushLoop(_)
+        | Instruction::JumpIfFalse(_)
+        | Instruction::JumpIfFalseOrPop(_)
+        | Instruction::JumpIfTrueOrPop(_) => (1, false),
+        Instruction::Add
+        | Instruction::Sub
+        | Instruction::Mul
+        | Instruction::Div
+        | Instruction::IntDiv
+        | Instruction::Rem
+        | Instruction::Pow
+        | Instruction::Eq
+        | Instruction::Ne
+        | Instruction::Gt
+        | Instruction::Gte
+        | Instruction::Lt
+        | Instruction::Lte
+        | Instruction::StringConcat
+        | Instruction::In => (2, false),
+        #[cfg(feature = "custom_operators")]
+        Instruction::CustomBinop(_) => (2, false),
+        Instruction::BuildMap(n) | Instruction::BuildKwargs(n) => (n * 2, false),
+        Instruction::BuildList(Some(n))
+        | Instruction::StringConcatMany(n)
+        | Instruction::CallFunction(_, n)
+        | Instruction::CallMethod(_, n)
+        | Instruction::CallObject(n) => (n, false),
+        Instruction::BuildList(None) => (stack.peek().as_usize().unwrap_or(0) + 1, false),
+        #[cfg(feature = "i18n")]
+        Instruction::Translate(_, n) => (n, false),
+        _ => return Ok(false),
+    };
+    if let Some(err) = stack
+        .slice_top(used)
+        .iter()
+        .find_map(PendingUndefined::error_of)
+    {
+        return Err(err);
+    }
+    if looked_up_on && PendingUndefined::is_pending(&stack.slice_top(used + 1)[0]) {
+        stack.drop_top(used);
+        return Ok(true);
+    }
+    Ok(false)
+}
+
+/// Applies a filter while undefined errors are deferred.
+///
+/// Undefined values with deferred errors are passed on unless the filter
+/// handles undefined values.  A filter failing on an undefined value
+/// passes on the error instead.
+#[inline(never)]
+fn apply_filter_deferring_undefined(
+    state: &State,
+    filter: &BoxedFilter,
+    args: &[Value],
+) -> Result<Value, Error> {
+    if let Some(err) = args[1..].iter().find_map(PendingUndefined::error_of) {
+        return Err(err);
+    }
+    if PendingUndefined::is_pending(&args[0]) {
+        if !filter.handles_undefined() {
+            return Ok(args[0].clone());
+        }
+        let mut args = args.to_vec();
+        args[0] = Value::UNDEFINED;
+        return filter.apply_to(state, &args);
+    }
+    match filter.apply_to(state, args) {
+        Err(err) if err.kind() == ErrorKind::UndefinedError && args[0].is_undefined() => {
+            Ok(PendingUndefined::wrap(err))
+        }
+        rv => rv,
+    }
+}
+
+/// Performs a test while undefined errors are deferred.
+///
+/// Tests that handle undefined values see undefined values with deferred
+/// errors as undefined, all other tests fail with the deferred error.
+#[inline(never)]
+fn perform_test_deferring_undefined(
+    state: &State,
+    test: &BoxedTest,
+    args: &[Value],
+) -> Result<bool, Error> {
+    if let Some(err) = args[1..].iter().find_map(PendingUndefined::error_of) {
+        return Err(err);
+    }
+    if let Some(err) = PendingUndefined::error_of(&args[0]) {
+        if !test.handles_undefined() {
+            return Err(err);
+        }
+        let mut args = args.to_vec();
+        args[0] = Value::UNDEFINED;
+        return test.perform(state, &args);
+    }
+    test.perform(state, args)
+}
+
+/// Checks the call policy for a callable that is called by `name`, if any.
+#[inline(always)]
+fn check_callable_policy(state: &State, callable: &Value, name: Option<&str>) -> Result<(), Error> {
+    if !state.env.has_call_policy() {
+        return Ok(());
+    }
+    #[cfg(feature = "macros")]
+    {
+        if let Some(m) = callable.downcast_object_ref::<macro_object::Macro>() {
+            return state
+                .env
+                .check_callable_policy(callable, m.name.as_str().or(name));
+        }
+    }
+    state.env.check_callable_policy(callable, name)
+}
+
+/// Reports a deprecation warning if `name` is an alias.
+#[inline(always)]
+fn warn_alias(state: &State, pc: usize, kind: CallKind, name: &str) {
+    if state.env.has_warning_sink() {
+        if let Some(target) = state.env.alias_target(kind, name) {
+            // functions can be shadowed by variables of the same name in
+            // which case the alias is not what is being called.
+            if kind == CallKind::Function && state.ctx.load_without_env(name).is_some() {
+                return;
+            }
+            emit_alias_warning(state, pc, kind, name, target);
+        }
+    }
+}
+
+#[inline(never)]
+#[cold]
+fn emit_alias_warning(state: &State, pc: usize, kind: CallKind, name: &str, target: &str) {
+    // only warn once for every call site within a render
+    let sites = state.get_or_insert_temp(WarnedAliasSites::default);
+    let site = (state.instructions as *const Instructions as usize, pc);
+    if !sites.0.lock().unwrap().insert(site) {
+        return;
+    }
+    let kind = match kind {
+        CallKind::Filter => "filter",
+        CallKind::Test => "test",
+        CallKind::Function => "function",
+    };
+    emit_warning(
+        state,
+        pc,
+        WarningKind::Deprecated,
+        format!("{kind} {name:?} is deprecated, use {target:?} instead"),
+    );
+}
+
 #[inline(never)]
 #[cold]
 fn process_err(err: &mut Error, pc: usize, state: &State) {
@@ -1030,6 +1980,11 @@ fn process_err(err: &mut Error, pc: usize, state: &State) {
             err.set_filename_and_line(state.instructions.name(), lineno);
         }
     }
+    // every evaluation level the error passes through adds a frame
+    err.push_template_frame(
+        state.instructions.name(),
+        state.instructions.get_line(pc).unwrap_or(0),
+    );
     // only attach debug info if we don't have one yet and we are in debug mode.
     #[cfg(feature = "debug")]
     {
@@ -1038,3 +1993,21 @@ fn process_err(err: &mut Error, pc: usize, state: &State) {
         }
     }
 }
+
+#[inline(never)]
+#[cold]
+fn unknown_name_error(env: &Environment, kind: CallKind, name: &str) -> Error {
+    let (error_kind, mut detail) = match kind {
+        CallKind::Filter => (
+            ErrorKind::UnknownFilter,
+            format!("filter {name} is unknown"),
+        ),
+        CallKind::Test => (ErrorKind::UnknownTest, format!("test {name} is unknown")),
+        CallKind::Function => (ErrorKind::UnknownFunction, format!("{name} is unknown")),
+    };
+    let names = env.registered_names(kind).collect::<Vec<_>>();
+    if let Some(similar) = find_similar(name, names.iter().copied()) {
+        detail.push_str(&format!(" (did you mean `{similar}`?)"));
+    }
+    Error::new(error_kind, detail).with_available_names(names.into_iter().map(Into::into).collect())
+}
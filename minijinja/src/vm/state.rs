@@ -1,17 +1,31 @@
-use std::collections::{BTreeMap, BTreeSet};
+use std::any::{Any, TypeId};
+use std::collections::BTreeMap;
 use std::fmt;
+use std::sync::{Arc, Mutex};
 
 use crate::compiler::instructions::Instructions;
 use crate::environment::Environment;
 use crate::error::{Error, ErrorKind};
-use crate::output::Output;
+use crate::output::{CaptureMode, Output};
 use crate::template::Template;
-use crate::utils::{AutoEscape, UndefinedBehavior};
+use crate::utils::{AutoEscape, CallKind, UndefinedBehavior};
 use crate::value::{ArgType, Value};
 use crate::vm::context::Context;
+use crate::vm::fragment_object::Fragment;
+use crate::warning::Warning;
 
 #[cfg(feature = "fuel")]
 use crate::vm::fuel::FuelTracker;
+#[cfg(feature = "macros")]
+use crate::vm::macro_object::Macro;
+#[cfg(feature = "stats")]
+use crate::vm::stats::{RenderStats, StatsTracker};
+
+pub(crate) type Temps = Arc<Mutex<BTreeMap<TypeId, Arc<dyn Any + Send + Sync>>>>;
+
+/// Backing storage for [`State::append_temp_value`].
+#[derive(Default)]
+struct TempCollections(Mutex<BTreeMap<String, Vec<Value>>>);
 
 /// When macros are used, the state carries an `id` counter.  Whenever a state is
 /// created, the counter is incremented.  This exists because macros can keep a reference
@@ -21,6 +35,52 @@ use crate::vm::fuel::FuelTracker;
 #[cfg(feature = "macros")]
 static STATE_ID: std::sync::atomic::AtomicIsize = std::sync::atomic::AtomicIsize::new(0);
 
+/// The macros defined during a render.
+///
+/// Macros refer to their definition by index.  States created for macro
+/// calls share the definitions of their parent and only record the macros
+/// they define themselves on top, so that defining a macro never has to copy
+/// the definitions of the parent.
+#[cfg(feature = "macros")]
+#[derive(Default)]
+pub(crate) struct MacroDefs<'template, 'env> {
+    parent: Option<Arc<MacroDefs<'template, 'env>>>,
+    offset: usize,
+    defs: Vec<(&'template Instructions<'env>, usize)>,
+}
+
+#[cfg(feature = "macros")]
+impl<'template, 'env> MacroDefs<'template, 'env> {
+    /// Registers a macro definition and returns its index.
+    pub fn push(
+        this: &mut Arc<Self>,
+        instructions: &'template Instructions<'env>,
+        offset: usize,
+    ) -> usize {
+        let macro_ref_id = this.offset + this.defs.len();
+        match Arc::get_mut(this) {
+            Some(defs) => defs.defs.push((instructions, offset)),
+            None => {
+                *this = Arc::new(MacroDefs {
+                    parent: Some(this.clone()),
+                    offset: macro_ref_id,
+                    defs: vec![(instructions, offset)],
+                });
+            }
+        }
+        macro_ref_id
+    }
+
+    /// Looks up the instructions and offset of a macro by index.
+    pub fn get(&self, macro_ref_id: usize) -> (&'template Instructions<'env>, usize) {
+        let mut defs = self;
+        while macro_ref_id < defs.offset {
+            defs = defs.parent.as_deref().unwrap();
+        }
+        defs.defs[macro_ref_id - defs.offset]
+    }
+}
+
 /// Provides access to the current execution state of the engine.
 ///
 /// A read only reference is passed to filter functions and similar objects to
@@ -43,18 +103,33 @@ pub struct State<'template, 'env> {
     pub(crate) ctx: Context<'env>,
     pub(crate) current_block: Option<&'env str>,
     pub(crate) auto_escape: AutoEscape,
+    pub(crate) undefined_behavior: UndefinedBehavior,
     pub(crate) instructions: &'template Instructions<'env>,
     pub(crate) blocks: BTreeMap<&'env str, BlockStack<'template, 'env>>,
     #[allow(unused)]
-    pub(crate) loaded_templates: BTreeSet<&'env str>,
+    pub(crate) loaded_templates: Vec<&'env str>,
+    #[cfg(feature = "multi_template")]
+    pub(crate) include_depth: usize,
+    /// Blocks of the rendered template that were rendered ahead of time
+    /// by [`Template::render_blocks_parallel`](crate::Template::render_blocks_parallel).
+    #[cfg(feature = "multi_template")]
+    pub(crate) prerendered_blocks: BTreeMap<String, String>,
     #[cfg(feature = "macros")]
     pub(crate) id: isize,
     #[cfg(feature = "macros")]
-    pub(crate) macros: std::sync::Arc<Vec<(&'template Instructions<'env>, usize)>>,
+    pub(crate) macros: Arc<MacroDefs<'template, 'env>>,
     #[cfg(feature = "macros")]
     pub(crate) closure_tracker: std::sync::Arc<crate::vm::closure_object::ClosureTracker>,
     #[cfg(feature = "fuel")]
     pub(crate) fuel_tracker: Option<std::sync::Arc<FuelTracker>>,
+    #[cfg(feature = "stats")]
+    pub(crate) stats_tracker: Arc<StatsTracker>,
+    pub(crate) temps: Temps,
+    #[cfg(feature = "internal_debug")]
+    pub(crate) trace_stack: Option<(usize, Option<Value>)>,
+    // the number of async calls made during the current instruction.
+    #[cfg(feature = "async")]
+    pub(crate) pending_futures: std::sync::atomic::AtomicUsize,
 }
 
 impl<'template, 'env> fmt::Debug for State<'template, 'env> {
@@ -85,15 +160,27 @@ impl<'template, 'env> State<'template, 'env> {
             ctx,
             current_block: None,
             auto_escape,
+            undefined_behavior: env.undefined_behavior(),
             instructions,
             blocks,
-            loaded_templates: BTreeSet::new(),
+            loaded_templates: Vec::new(),
+            #[cfg(feature = "multi_template")]
+            include_depth: 0,
+            #[cfg(feature = "multi_template")]
+            prerendered_blocks: BTreeMap::new(),
             #[cfg(feature = "macros")]
             macros: Default::default(),
             #[cfg(feature = "macros")]
             closure_tracker: Default::default(),
             #[cfg(feature = "fuel")]
             fuel_tracker: env.fuel().map(FuelTracker::new),
+            #[cfg(feature = "stats")]
+            stats_tracker: StatsTracker::new(),
+            temps: Default::default(),
+            #[cfg(feature = "internal_debug")]
+            trace_stack: None,
+            #[cfg(feature = "async")]
+            pending_futures: Default::default(),
         }
     }
 
@@ -115,6 +202,10 @@ impl<'template, 'env> State<'template, 'env> {
     }
 
     /// Returns the name of the current template.
+    ///
+    /// This is the name of the template that is currently executing, so
+    /// within an included template or a block inherited from a parent
+    /// template, this is the name of that template.
     pub fn name(&self) -> &str {
         self.instructions.name()
     }
@@ -126,12 +217,61 @@ impl<'template, 'env> State<'template, 'env> {
     }
 
     /// Returns the current undefined behavior.
+    ///
+    /// This is the behavior configured on the environment unless it was
+    /// changed for a section of the template with `{% undefined %}`.
     #[inline(always)]
     pub fn undefined_behavior(&self) -> UndefinedBehavior {
-        self.env.undefined_behavior()
+        self.undefined_behavior
+    }
+
+    /// Reports a non-fatal warning.
+    ///
+    /// The warning is passed to the sink registered with
+    /// [`set_warning_sink`](crate::Environment::set_warning_sink) and is
+    /// otherwise discarded.  If the warning does not carry a template name
+    /// yet, the name of the current template is attached.
+    ///
+    /// ```
+    /// # use minijinja::{Environment, State, Warning, WarningKind};
+    /// # let mut env = Environment::new();
+    /// env.add_filter("old_upper", |state: &State, value: String| {
+    ///     state.warn(Warning::new(WarningKind::Deprecated, "use upper instead"));
+    ///     value.to_uppercase()
+    /// });
+    /// ```
+    pub fn warn(&self, mut warning: Warning) {
+        if self.env.has_warning_sink() {
+            warning.set_filename(self.name());
+            self.env.emit_warning(warning);
+        }
+    }
+
+    /// Returns the depth of the operand stack while a trace callback runs.
+    ///
+    /// Outside of a [trace callback](crate::Environment::set_trace_callback)
+    /// this returns `None`.
+    ///
+    /// This is an unstable debugging API and might change or go away.
+    #[cfg(feature = "internal_debug")]
+    pub fn operand_stack_depth(&self) -> Option<usize> {
+        self.trace_stack.as_ref().map(|x| x.0)
+    }
+
+    /// Returns the value on top of the operand stack while a trace callback runs.
+    ///
+    /// Returns `None` outside of a [trace callback](crate::Environment::set_trace_callback)
+    /// or if the operand stack is empty.
+    ///
+    /// This is an unstable debugging API and might change or go away.
+    #[cfg(feature = "internal_debug")]
+    pub fn peek_operand_stack(&self) -> Option<&Value> {
+        self.trace_stack.as_ref().and_then(|x| x.1.as_ref())
     }
 
     /// Returns the name of the innermost block.
+    ///
+    /// Templates included from within a block still report that block.
     #[inline(always)]
     pub fn current_block(&self) -> Option<&str> {
         self.current_block
@@ -143,13 +283,81 @@ impl<'template, 'env> State<'template, 'env> {
     ///
     /// Macros and call blocks analyze which variables are referenced and
     /// create closures for them.  This means that unless a variable is defined
-    /// as a [global](Environment::add_global) in the environment or it was
-    /// referenced by a macro, this method won't be able to find it.
+    /// as a [global](Environment::add_global) in the environment, passed to
+    /// [`Template::render_with_globals`](crate::Template::render_with_globals)
+    /// or it was referenced by a macro, this method won't be able to find it.
     #[inline(always)]
     pub fn lookup(&self, name: &str) -> Option<Value> {
         self.ctx.load(self.env, name)
     }
 
+    /// Evaluates an expression against the current state.
+    ///
+    /// The expression is compiled like with
+    /// [`Environment::compile_expression`] and evaluated with the same
+    /// variables visible as at the current position of the template, including
+    /// loop variables, macros and globals.  The evaluation does not produce
+    /// output and does not affect the state.  This is useful for building
+    /// debugging tools such as an interactive prompt.
+    ///
+    /// ```
+    /// # use minijinja::{Environment, State};
+    /// # let mut env = Environment::new();
+    /// env.add_function("inspect", |state: &State| {
+    ///     state.eval_expr("item * 2 ~ '/' ~ loop.length")
+    /// });
+    /// let tmpl = env
+    ///     .template_from_str("{% for item in [1, 2] %}{{ inspect() }} {% endfor %}")
+    ///     .unwrap();
+    /// assert_eq!(tmpl.render(()).unwrap(), "2/2 4/2 ");
+    /// ```
+    ///
+    /// The note on closures for [`lookup`](Self::lookup) applies here as
+    /// well.
+    pub fn eval_expr(&self, source: &str) -> Result<Value, Error> {
+        let instructions = ok!(self.env._compile_expression(source));
+        crate::vm::Vm::new(self.env).eval_expr_in_state(&instructions, self)
+    }
+
+    /// Looks up a macro by name.
+    ///
+    /// This looks up a value as [`lookup`](Self::lookup) does but only returns
+    /// it if it's a macro defined in a template.  The returned value can be
+    /// invoked with [`Value::call`] and the given state which renders the macro
+    /// into a string.  This is useful for rendering components defined as
+    /// macros from Rust code after the template was evaluated.
+    ///
+    /// ```
+    /// # use minijinja::{Environment, Value};
+    /// # let mut env = Environment::new();
+    /// env.add_template(
+    ///     "components.html",
+    ///     "{% macro button(label, kind='primary') %}<button class={{ kind }}>{{ label }}</button>{% endmacro %}",
+    /// ).unwrap();
+    /// let tmpl = env.get_template("components.html").unwrap();
+    /// let state = tmpl.eval_to_state(()).unwrap();
+    /// let button = state.get_macro("button").unwrap();
+    /// let rv = button.call(&state, &[Value::from("<Save>")]).unwrap();
+    /// assert_eq!(rv.to_string(), "<button class=primary>&lt;Save&gt;</button>");
+    /// ```
+    ///
+    /// # Note on Closures
+    ///
+    /// Macros keep a reference to the variables they reference from the
+    /// template they were defined in.  This includes top-level variables and
+    /// imports of that template, even if they are assigned after the macro
+    /// was defined.  As a result, a macro that calls a macro imported at the
+    /// top of its template works when invoked from Rust as long as the template
+    /// was fully evaluated.  Variables that the macro does not reference are
+    /// not carried along, and the macro cannot see any of the variables of
+    /// the state it's invoked with except for globals.
+    #[cfg(feature = "macros")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "macros")))]
+    pub fn get_macro(&self, name: &str) -> Option<Value> {
+        self.lookup(name)
+            .filter(|value| value.downcast_object_ref::<Macro>().is_some())
+    }
+
     /// Looks up a global macro and calls it.
     ///
     /// This looks up a value as [`lookup`](Self::lookup) does and calls it
@@ -245,7 +453,10 @@ impl<'template, 'env> State<'template, 'env> {
     /// ```
     pub fn apply_filter(&self, filter: &str, args: &[Value]) -> Result<Value, Error> {
         match self.env.get_filter(filter) {
-            Some(filter) => filter.apply_to(self, args),
+            Some(f) => {
+                ok!(self.env.check_call_policy(CallKind::Filter, filter));
+                f.apply_to(self, args)
+            }
             None => Err(Error::from(ErrorKind::UnknownFilter)),
         }
     }
@@ -263,7 +474,10 @@ impl<'template, 'env> State<'template, 'env> {
     /// ```
     pub fn perform_test(&self, test: &str, args: &[Value]) -> Result<bool, Error> {
         match self.env.get_test(test) {
-            Some(test) => test.perform(self, args),
+            Some(t) => {
+                ok!(self.env.check_call_policy(CallKind::Test, test));
+                t.perform(self, args)
+            }
             None => Err(Error::from(ErrorKind::UnknownTest)),
         }
     }
@@ -284,6 +498,230 @@ impl<'template, 'env> State<'template, 'env> {
         self.env.format(&value, self, &mut out).map(|_| rv)
     }
 
+    /// Captures the output written by a closure into a value.
+    ///
+    /// This works like capturing output in templates does (eg: for `{% set %}`
+    /// blocks or macro calls).  The closure is invoked with an [`Output`] it
+    /// can write into and once it returns, the captured string is returned as
+    /// value.  If auto escaping is enabled, the value is a [safe
+    /// string](Value::from_safe_string) so that it's not escaped again when
+    /// emitted.  This means that values written into the output should be
+    /// escaped, for instance by using [`escape_formatter`](crate::escape_formatter)
+    /// or [`format`](Self::format).
+    ///
+    /// ```
+    /// # use minijinja::{Environment, State, Error, escape_formatter, value::Value};
+    /// let mut env = Environment::new();
+    /// env.add_function("bold", |state: &State, value: Value| -> Result<Value, Error> {
+    ///     state.capture(|out| {
+    ///         out.write_str("<b>")?;
+    ///         escape_formatter(out, state, &value)?;
+    ///         out.write_str("</b>")?;
+    ///         Ok(())
+    ///     })
+    /// });
+    /// env.add_template("hello.html", "{{ bold(name) }}").unwrap();
+    /// let ctx = minijinja::context! { name => "<World>" };
+    /// let rv = env.get_template("hello.html").unwrap().render(&ctx).unwrap();
+    /// assert_eq!(rv, "<b>&lt;World&gt;</b>");
+    /// ```
+    pub fn capture<F>(&self, f: F) -> Result<Value, Error>
+    where
+        F: FnOnce(&mut Output) -> Result<(), Error>,
+    {
+        let mut out = Output::null();
+        out.begin_capture(CaptureMode::Capture);
+        ok!(f(&mut out));
+        Ok(out.end_capture(self.auto_escape))
+    }
+
+    /// Captures the output written by a closure into a fragment object.
+    ///
+    /// This works like [`capture`](Self::capture) but instead of the captured
+    /// string it returns an object that also records where the output was
+    /// produced.  The object has the following attributes:
+    ///
+    /// * `html`: the captured output as it would be returned by
+    ///   [`capture`](Self::capture).
+    /// * `block`: the name of the [current block](Self::current_block) or
+    ///   `none` if the output was not captured within a block.
+    /// * `auto_escape`: the auto escape mode that was active as string
+    ///   (`"none"`, `"html"`, `"json"` or the name of a custom mode).
+    ///
+    #[cfg_attr(
+        feature = "multi_template",
+        doc = r#"
+```
+# use minijinja::{Environment, State, Error, value::Value};
+let mut env = Environment::new();
+env.add_function("card", |state: &State, title: String| -> Result<Value, Error> {
+    state.capture_fragment(|out| Ok(write!(out, "<h1>{}</h1>", title)?))
+});
+env.add_template(
+    "hello.html",
+    "{% block main %}{% set f = card('Hi') %}{{ f.html }} from {{ f.block }}{% endblock %}",
+).unwrap();
+let rv = env.get_template("hello.html").unwrap().render(()).unwrap();
+assert_eq!(rv, "<h1>Hi</h1> from main");
+```
+"#
+    )]
+    pub fn capture_fragment<F>(&self, f: F) -> Result<Value, Error>
+    where
+        F: FnOnce(&mut Output) -> Result<(), Error>,
+    {
+        Ok(Value::from_object(Fragment {
+            html: ok!(self.capture(f)),
+            block: self.current_block.map(|x| x.to_string()),
+            auto_escape: self.auto_escape,
+        }))
+    }
+
+    /// Marks a string as already escaped for the given auto escape mode.
+    ///
+    /// This is useful for filters and functions that generate output in a
+    /// specific format themselves (for instance a filter that renders HTML).
+    /// If `escaped_for` matches the [current auto escape mode](Self::auto_escape)
+    /// the string is returned as a [safe string](Value::from_safe_string) and
+    /// the default formatter writes it out unchanged.  Otherwise it's returned
+    /// as a regular string so that it's escaped for the actual output format
+    /// like any other value.  This means that HTML returned from a filter is
+    /// not accidentally emitted unescaped into a JSON template.
+    ///
+    /// ```
+    /// # use minijinja::{Environment, State, AutoEscape, value::Value};
+    /// let mut env = Environment::new();
+    /// env.add_filter("bold", |state: &State, value: String| -> Value {
+    ///     let html = format!("<b>{}</b>", value.replace('<', "&lt;"));
+    ///     state.safe_string_for(html, AutoEscape::Html)
+    /// });
+    /// env.add_template("hello.html", "{{ name|bold }}").unwrap();
+    /// env.add_template("hello.txt", "{{ name|bold }}").unwrap();
+    /// let ctx = minijinja::context! { name => "<World>" };
+    /// let rv = env.get_template("hello.html").unwrap().render(&ctx).unwrap();
+    /// assert_eq!(rv, "<b>&lt;World></b>");
+    /// ```
+    pub fn safe_string_for(&self, value: String, escaped_for: AutoEscape) -> Value {
+        if self.auto_escape == escaped_for {
+            Value::from_safe_string(value)
+        } else {
+            Value::from(value)
+        }
+    }
+
+    /// Returns the temporary value of type `T` or creates it.
+    ///
+    /// Temporary values are a type keyed storage that lives as long as a
+    /// single render.  This lets cooperating functions, filters and tests share
+    /// state within a render without having to resort to thread locals.  As
+    /// values are shared, interior mutability (eg: a [`Mutex`]) is needed to
+    /// modify them.
+    ///
+    /// Every render (and every state created with
+    /// [`Template::new_state`](crate::Template::new_state) or
+    /// [`Template::eval_to_state`](crate::Template::eval_to_state)) starts out
+    /// with empty storage.  Included templates and called macros share the
+    /// storage of the render they are invoked from.  Templates that are
+    /// imported are evaluated with their own storage, but macros imported from
+    /// them use the storage of the render that calls them.
+    ///
+    /// ```
+    /// # use minijinja::{Environment, State};
+    /// use std::sync::Mutex;
+    ///
+    /// #[derive(Default)]
+    /// struct Assets(Mutex<Vec<String>>);
+    ///
+    /// let mut env = Environment::new();
+    /// env.add_function("require", |state: &State, asset: String| {
+    ///     state.get_or_insert_temp(Assets::default).0.lock().unwrap().push(asset);
+    ///     String::new()
+    /// });
+    /// env.add_function("assets", |state: &State| {
+    ///     state.temp::<Assets>().map_or(String::new(), |x| x.0.lock().unwrap().join(","))
+    /// });
+    /// let rv = env.render_str("{{ require('a.js') }}{{ require('b.js') }}{{ assets() }}", ());
+    /// assert_eq!(rv.unwrap(), "a.js,b.js");
+    /// ```
+    pub fn get_or_insert_temp<T, F>(&self, f: F) -> Arc<T>
+    where
+        T: Send + Sync + 'static,
+        F: FnOnce() -> T,
+    {
+        if let Some(rv) = self.temp::<T>() {
+            return rv;
+        }
+        // the lock is not held while creating the value so that the
+        // initializer can itself access the temporary storage.
+        let value = Arc::new(f());
+        let mut temps = self.temps.lock().unwrap();
+        temps
+            .entry(TypeId::of::<T>())
+            .or_insert_with(|| value)
+            .clone()
+            .downcast::<T>()
+            .unwrap()
+    }
+
+    /// Returns the temporary value of type `T` if it exists.
+    ///
+    /// See [`get_or_insert_temp`](Self::get_or_insert_temp) for more information.
+    pub fn temp<T>(&self) -> Option<Arc<T>>
+    where
+        T: Send + Sync + 'static,
+    {
+        let temps = self.temps.lock().unwrap();
+        temps
+            .get(&TypeId::of::<T>())
+            .map(|x| x.clone().downcast::<T>().unwrap())
+    }
+
+    /// Appends a value to a named render-wide collection.
+    ///
+    /// This builds on the temporary storage of
+    /// [`get_or_insert_temp`](Self::get_or_insert_temp) and has the same
+    /// lifetime: values appended are visible for the remainder of the render,
+    /// including included templates and macros, but never leak into another
+    /// render.  Values are appended in evaluation order, even if they are
+    /// appended from within a capturing block such as `{% set %}` or `{% filter %}`.
+    ///
+    /// A common use is to let templates register assets in one place and emit
+    /// them later:
+    ///
+    /// ```
+    /// # use minijinja::{Environment, State, value::Value};
+    /// let mut env = Environment::new();
+    /// env.add_function("require_js", |state: &State, src: Value| {
+    ///     state.append_temp_value("scripts", src);
+    ///     String::new()
+    /// });
+    /// env.add_function("scripts", |state: &State| state.temp_values("scripts"));
+    /// let rv = env.render_str(
+    ///     "{{ require_js('a.js') }}{{ require_js('b.js') }}\
+    ///      {% for src in scripts() %}[{{ src }}]{% endfor %}",
+    ///     (),
+    /// );
+    /// assert_eq!(rv.unwrap(), "[a.js][b.js]");
+    /// ```
+    pub fn append_temp_value(&self, name: &str, value: Value) {
+        let collections = self.get_or_insert_temp(TempCollections::default);
+        let mut map = collections.0.lock().unwrap();
+        map.entry(name.to_string()).or_default().push(value);
+    }
+
+    /// Returns the values of a named render-wide collection as sequence.
+    ///
+    /// The returned value is a snapshot, values appended later are not
+    /// reflected.  If nothing was appended an empty sequence is returned.
+    /// See [`append_temp_value`](Self::append_temp_value).
+    pub fn temp_values(&self, name: &str) -> Value {
+        Value::from(
+            self.temp::<TempCollections>()
+                .and_then(|x| x.0.lock().unwrap().get(name).cloned())
+                .unwrap_or_default(),
+        )
+    }
+
     /// Returns the fuel levels.
     ///
     /// When the fuel feature is enabled, during evaluation the template will keep
@@ -298,6 +736,28 @@ impl<'template, 'env> State<'template, 'env> {
             .map(|x| (x.consumed(), x.remaining()))
     }
 
+    /// Returns statistics about the evaluation so far.
+    ///
+    /// When the `stats` feature is enabled, the engine counts the executed
+    /// instructions, the filter and function calls and records the highest
+    /// recursion depth and value stack size reached.  The counters are shared
+    /// with macros and included templates.
+    ///
+    /// ```
+    /// # use minijinja::Environment;
+    /// let env = Environment::new();
+    /// let tmpl = env.template_from_str("{{ 'a'|upper ~ range(3)|length }}").unwrap();
+    /// let (_, state) = tmpl.render_and_return_state(()).unwrap();
+    /// let stats = state.stats();
+    /// assert_eq!(stats.filter_calls, 2);
+    /// assert_eq!(stats.function_calls, 1);
+    /// ```
+    #[cfg(feature = "stats")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "stats")))]
+    pub fn stats(&self) -> RenderStats {
+        self.stats_tracker.stats()
+    }
+
     #[cfg(feature = "debug")]
     pub(crate) fn make_debug_info(
         &self,
@@ -311,6 +771,12 @@ impl<'template, 'env> State<'template, 'env> {
                 .into_iter()
                 .filter_map(|n| Some((n.to_string(), some!(self.lookup(n)))))
                 .collect(),
+            locals: self
+                .ctx
+                .visible_locals()
+                .into_iter()
+                .map(|(k, v)| (k.to_string(), crate::debug::summarize_value(v)))
+                .collect(),
         }
     }
 }
@@ -337,7 +803,7 @@ impl<'a> ArgType<'a> for &State<'_, '_> {
 }
 
 /// Tracks a block and it's parents for super.
-#[derive(Default)]
+#[derive(Default, Clone)]
 pub(crate) struct BlockStack<'template, 'env> {
     instructions: Vec<&'template Instructions<'env>>,
     depth: usize,
@@ -0,0 +1,82 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use crate::compiler::instructions::Instruction;
+
+/// Statistics about the evaluation of a template.
+///
+/// These are returned by [`State::stats`](crate::State::stats).  The
+/// counters include the evaluation of macros, included and imported
+/// templates.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct RenderStats {
+    /// The number of instructions executed.
+    pub instructions: usize,
+    /// The number of filters applied.
+    pub filter_calls: usize,
+    /// The number of functions, methods and other callables invoked.
+    pub function_calls: usize,
+    /// The highest recursion depth reached.
+    pub max_depth: usize,
+    /// The largest size of the value stack of a single evaluation.
+    pub max_stack_size: usize,
+}
+
+/// Helper for accumulating render statistics.
+///
+/// Like the fuel tracker this is shared across nested invocations of the
+/// template evaluation.
+#[derive(Default)]
+pub struct StatsTracker {
+    instructions: AtomicUsize,
+    filter_calls: AtomicUsize,
+    function_calls: AtomicUsize,
+    max_depth: AtomicUsize,
+    max_stack_size: AtomicUsize,
+}
+
+impl StatsTracker {
+    /// Creates a new stats tracker.
+    pub fn new() -> Arc<StatsTracker> {
+        Arc::default()
+    }
+
+    /// Tracks an instruction about to be executed.
+    #[inline(always)]
+    pub fn track(&self, instr: &Instruction, stack_size: usize, depth: usize) {
+        self.instructions.fetch_add(1, Ordering::Relaxed);
+        match instr {
+            Instruction::ApplyFilter(..) => {
+                self.filter_calls.fetch_add(1, Ordering::Relaxed);
+            }
+            Instruction::CallFunction(..)
+            | Instruction::CallMethod(..)
+            | Instruction::CallObject(..) => {
+                self.function_calls.fetch_add(1, Ordering::Relaxed);
+            }
+            _ => {}
+        }
+        update_max(&self.max_stack_size, stack_size);
+        update_max(&self.max_depth, depth);
+    }
+
+    /// Returns the current statistics.
+    pub fn stats(&self) -> RenderStats {
+        RenderStats {
+            instructions: self.instructions.load(Ordering::Relaxed),
+            filter_calls: self.filter_calls.load(Ordering::Relaxed),
+            function_calls: self.function_calls.load(Ordering::Relaxed),
+            max_depth: self.max_depth.load(Ordering::Relaxed),
+            max_stack_size: self.max_stack_size.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[inline(always)]
+fn update_max(counter: &AtomicUsize, value: usize) {
+    // only the first time a new maximum is reached is a store needed
+    if value > counter.load(Ordering::Relaxed) {
+        counter.fetch_max(value, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,46 @@
+use std::fmt;
+use std::sync::Arc;
+
+use crate::error::Error;
+use crate::value::{Object, ObjectRepr, Value};
+
+/// An undefined value that carries a deferred undefined error.
+///
+/// These are only created when
+/// [`set_defer_undefined_errors`](crate::Environment::set_defer_undefined_errors)
+/// is enabled.  The error is raised once the value is used.
+#[derive(Debug)]
+pub(crate) struct PendingUndefined(Error);
+
+impl PendingUndefined {
+    /// Wraps a deferred undefined error in a value.
+    pub fn wrap(err: Error) -> Value {
+        Value::from_object(PendingUndefined(err))
+    }
+
+    /// Returns the pending undefined error of a value, if it holds one.
+    pub fn error_of(value: &Value) -> Option<Error> {
+        value
+            .downcast_object_ref::<PendingUndefined>()
+            .map(|pending| pending.0.internal_clone())
+    }
+
+    /// Returns `true` if the value carries a deferred undefined error.
+    pub fn is_pending(value: &Value) -> bool {
+        value.downcast_object_ref::<PendingUndefined>().is_some()
+    }
+}
+
+impl Object for PendingUndefined {
+    fn repr(self: &Arc<Self>) -> ObjectRepr {
+        ObjectRepr::Plain
+    }
+
+    fn is_true(self: &Arc<Self>) -> bool {
+        false
+    }
+
+    fn render(self: &Arc<Self>, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("")
+    }
+}
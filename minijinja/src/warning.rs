@@ -0,0 +1,111 @@
+use std::borrow::Cow;
+use std::fmt;
+
+/// Represents a non-fatal issue encountered during rendering.
+///
+/// Warnings are reported to the sink registered with
+/// [`set_warning_sink`](crate::Environment::set_warning_sink).  Unlike
+/// errors they never abort rendering.  Like errors they carry a
+/// [`WarningKind`], a detail message and, where known, the template name
+/// and line that caused them.
+///
+/// Custom filters, tests and functions can report their own warnings via
+/// [`State::warn`](crate::State::warn).
+#[derive(Debug, Clone)]
+pub struct Warning {
+    kind: WarningKind,
+    detail: Cow<'static, str>,
+    name: Option<String>,
+    lineno: usize,
+}
+
+/// An enum describing the kind of a warning.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum WarningKind {
+    /// An include did not find a template and fell back to a later choice.
+    IncludeFallback,
+    /// An include ignored an error while rendering the included template.
+    IncludeError,
+    /// A variable, attribute or item lookup produced an undefined value.
+    Undefined,
+    /// Deprecated functionality was used.
+    Deprecated,
+}
+
+impl WarningKind {
+    fn description(self) -> &'static str {
+        match self {
+            WarningKind::IncludeFallback => "include fallback",
+            WarningKind::IncludeError => "include error",
+            WarningKind::Undefined => "undefined value",
+            WarningKind::Deprecated => "deprecated",
+        }
+    }
+}
+
+impl fmt::Display for WarningKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.description())
+    }
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        ok!(write!(f, "{}: {}", self.kind, self.detail));
+        if let Some(ref name) = self.name {
+            match self.line() {
+                Some(line) => ok!(write!(f, " (in {}:{})", name, line)),
+                None => ok!(write!(f, " (in {})", name)),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Warning {
+    /// Creates a new warning with kind and detail.
+    pub fn new<D: Into<Cow<'static, str>>>(kind: WarningKind, detail: D) -> Warning {
+        Warning {
+            kind,
+            detail: detail.into(),
+            name: None,
+            lineno: 0,
+        }
+    }
+
+    /// Returns the warning kind.
+    pub fn kind(&self) -> WarningKind {
+        self.kind
+    }
+
+    /// Returns the detail message of the warning.
+    pub fn detail(&self) -> &str {
+        &self.detail
+    }
+
+    /// Returns the name of the template that caused the warning.
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// Returns the line number where the warning occurred, if known.
+    pub fn line(&self) -> Option<usize> {
+        if self.lineno > 0 {
+            Some(self.lineno)
+        } else {
+            None
+        }
+    }
+
+    pub(crate) fn set_filename_and_line(&mut self, name: &str, lineno: usize) {
+        self.name = Some(name.into());
+        self.lineno = lineno;
+    }
+
+    pub(crate) fn set_filename(&mut self, name: &str) {
+        if self.name.is_none() {
+            self.name = Some(name.into());
+        }
+    }
+}
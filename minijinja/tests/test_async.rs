@@ -0,0 +1,132 @@
+#![cfg(all(feature = "async", feature = "builtins", feature = "macros"))]
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll, Wake};
+
+use minijinja::value::Value;
+use minijinja::{context, Environment, Error, ErrorKind};
+use similar_asserts::assert_eq;
+
+struct NoopWaker;
+
+impl Wake for NoopWaker {
+    fn wake(self: Arc<Self>) {}
+}
+
+fn block_on<F: Future>(fut: F) -> F::Output {
+    let waker = Arc::new(NoopWaker).into();
+    let mut cx = Context::from_waker(&waker);
+    let mut fut = Box::pin(fut);
+    loop {
+        if let Poll::Ready(rv) = fut.as_mut().poll(&mut cx) {
+            return rv;
+        }
+    }
+}
+
+/// A future that is pending once before it resolves.
+struct YieldOnce(bool);
+
+impl Future for YieldOnce {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if self.0 {
+            Poll::Ready(())
+        } else {
+            self.0 = true;
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        }
+    }
+}
+
+fn make_env() -> Environment<'static> {
+    let mut env = Environment::new();
+    env.add_async_function("load_user", |id: i64| async move {
+        YieldOnce(false).await;
+        context! { id, name => format!("user{id}") }
+    });
+    env.add_async_filter("shout", |value: String| async move {
+        YieldOnce(false).await;
+        format!("{}!", value.to_uppercase())
+    });
+    env.add_async_function("fail", || async move {
+        Err::<Value, _>(Error::new(ErrorKind::InvalidOperation, "backend down"))
+    });
+    env
+}
+
+#[test]
+fn test_render_async() {
+    let env = make_env();
+    let tmpl = env
+        .template_from_str(
+            "{% for id in ids %}{% set user = load_user(id) %}\
+             [{{ user.name|shout }}]{% endfor %}\
+             {% filter upper %}{{ load_user(3).name }}{% endfilter %}",
+        )
+        .unwrap();
+    let rv = block_on(tmpl.render_async(context! { ids => [1, 2] })).unwrap();
+    assert_eq!(rv, "[USER1!][USER2!]USER3");
+}
+
+#[test]
+fn test_render_async_is_send() {
+    fn assert_send<T: Send>(_: T) {}
+    let env = make_env();
+    let tmpl = env.template_from_str("{{ load_user(1) }}").unwrap();
+    assert_send(tmpl.render_async(()));
+}
+
+#[test]
+fn test_render_async_errors() {
+    let env = make_env();
+
+    let tmpl = env.template_from_str("a\n{{ fail() }}").unwrap();
+    let err = block_on(tmpl.render_async(())).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidOperation);
+    assert_eq!(err.detail(), Some("backend down"));
+    assert_eq!(err.line(), Some(2));
+
+    // async callables cannot be awaited from the synchronous render path
+    let tmpl = env.template_from_str("{{ load_user(1) }}").unwrap();
+    let err = tmpl.render(()).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidOperation);
+
+    // nor from within macros
+    let tmpl = env
+        .template_from_str("{% macro m() %}{{ load_user(1) }}{% endmacro %}{{ m() }}")
+        .unwrap();
+    let err = block_on(tmpl.render_async(())).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidOperation);
+
+    // nor when called indirectly through other filters
+    for source in [
+        "{{ ['a', 'b']|map('shout')|join(',') }}",
+        "{% for x in ['a']|map('shout') %}{{ x }}{% endfor %}",
+        "{{ ['a']|map('shout')|first }}",
+    ] {
+        let tmpl = env.template_from_str(source).unwrap();
+        let err = block_on(tmpl.render_async(())).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidOperation, "{source}");
+    }
+}
+
+#[test]
+#[cfg(feature = "multi_template")]
+fn test_render_async_blocks_unsupported() {
+    let mut env = make_env();
+    env.add_template("layout.txt", "<{% block body %}{% endblock %}>")
+        .unwrap();
+    env.add_template(
+        "child.txt",
+        "{% extends 'layout.txt' %}{% block body %}{{ load_user(1).name }}{% endblock %}",
+    )
+    .unwrap();
+    let tmpl = env.get_template("child.txt").unwrap();
+    let err = block_on(tmpl.render_async(())).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidOperation);
+    assert!(err.to_string().contains("top level"));
+}
@@ -0,0 +1,85 @@
+#![cfg(feature = "custom_operators")]
+use minijinja::{context, Environment, Error, ErrorKind, OperatorPrecedence, Value};
+
+fn make_env() -> Environment<'static> {
+    let mut env = Environment::new();
+    env.add_binary_operator("<=>", OperatorPrecedence::Comparison, |a, b| {
+        Ok(Value::from(a.cmp(b) as i32))
+    });
+    env.add_binary_operator("@", OperatorPrecedence::Multiplicative, |a, b| {
+        Ok(Value::from(format!("{a}@{b}")))
+    });
+    env.add_binary_operator("?:", OperatorPrecedence::Additive, |a, b| {
+        Ok(if a.is_true() { a.clone() } else { b.clone() })
+    });
+    env
+}
+
+#[test]
+fn test_basic() {
+    let env = make_env();
+    assert_eq!(env.render_str("{{ 1 <=> 2 }}", ()).unwrap(), "-1");
+    assert_eq!(env.render_str("{{ 2 <=> 2 }}", ()).unwrap(), "0");
+    assert_eq!(
+        env.render_str("{{ a @ b }}", context!(a => 1, b => 2))
+            .unwrap(),
+        "1@2"
+    );
+    assert_eq!(env.render_str("{{ none ?: 'x' }}", ()).unwrap(), "x");
+}
+
+#[test]
+fn test_precedence() {
+    let env = make_env();
+    assert_eq!(env.render_str("{{ 'a' ~ 2 @ 3 }}", ()).unwrap(), "a2@3");
+    assert_eq!(env.render_str("{{ 2 * 3 @ 4 }}", ()).unwrap(), "6@4");
+    assert_eq!(
+        env.render_str("{{ 1 <=> 1 + 1 }}", ()).unwrap(),
+        env.render_str("{{ 1 <=> 2 }}", ()).unwrap()
+    );
+    assert_eq!(env.render_str("{{ 1 @ 2 @ 3 }}", ()).unwrap(), "1@2@3");
+}
+
+#[test]
+fn test_builtin_wins() {
+    let mut env = make_env();
+    env.add_binary_operator("+", OperatorPrecedence::Additive, |_, _| {
+        Ok(Value::from("custom"))
+    });
+    env.add_binary_operator("+++", OperatorPrecedence::Additive, |_, _| {
+        Ok(Value::from("custom"))
+    });
+    assert_eq!(env.render_str("{{ 1 + 2 }}", ()).unwrap(), "3");
+    assert_eq!(env.render_str("{{ 1 +++ 2 }}", ()).unwrap(), "custom");
+    // `<=` is built-in and longer than any custom prefix
+    assert_eq!(env.render_str("{{ 1 <= 2 }}", ()).unwrap(), "true");
+}
+
+#[test]
+fn test_errors() {
+    let mut env = make_env();
+    env.add_binary_operator("!!", OperatorPrecedence::Power, |_, _| {
+        Err(Error::new(ErrorKind::InvalidOperation, "nope"))
+    });
+    let err = env.render_str("{{ 1 !! 2 }}", ()).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidOperation);
+    assert_eq!(err.line(), Some(1));
+
+    let err = env.render_str("{{ @ 2 }}", ()).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::SyntaxError);
+    assert_eq!(err.detail(), Some("unexpected `@`"));
+}
+
+#[test]
+fn test_expression() {
+    let env = make_env();
+    let expr = env.compile_expression("a <=> b").unwrap();
+    assert_eq!(expr.eval(context!(a => 3, b => 1)).unwrap(), Value::from(1));
+}
+
+#[test]
+#[should_panic = "invalid operator symbol"]
+fn test_invalid_symbol() {
+    let mut env = Environment::new();
+    env.add_binary_operator("foo", OperatorPrecedence::Additive, |a, _| Ok(a.clone()));
+}
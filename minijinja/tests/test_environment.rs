@@ -28,6 +28,43 @@ fn test_expression() {
     assert_eq!(expr.eval(&ctx).unwrap(), Value::from(65));
 }
 
+#[test]
+#[cfg(feature = "builtins")]
+fn test_expression_reuse() {
+    let env = Environment::new();
+    let expr = env
+        .compile_expression("score > 10 and tags|length > 0")
+        .unwrap();
+    let results = (0..1000)
+        .map(|idx| {
+            expr.eval(minijinja::context! {
+                score => idx % 20,
+                tags => if idx % 3 == 0 { vec!["x"] } else { vec![] },
+            })
+            .unwrap()
+            .is_true()
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        results,
+        (0..1000)
+            .map(|idx| idx % 20 > 10 && idx % 3 == 0)
+            .collect::<Vec<_>>()
+    );
+    assert_eq!(
+        expr.eval(minijinja::context!(score => 11, tags => vec!["a"]))
+            .unwrap(),
+        Value::from(true)
+    );
+    // a failed evaluation does not affect later ones
+    assert!(expr.eval(minijinja::context!(score => 11)).is_err());
+    assert_eq!(
+        expr.eval(minijinja::context!(score => 11, tags => vec!["a"]))
+            .unwrap(),
+        Value::from(true)
+    );
+}
+
 #[test]
 #[cfg(feature = "loader")]
 fn test_expression_owned() {
@@ -158,10 +195,10 @@ fn test_unknown_method_callback() {
     use minijinja::{Error, ErrorKind};
 
     let mut env = Environment::new();
-    env.set_unknown_method_callback(|_state, value, method, args| {
+    env.set_unknown_method_callback(|state, value, method, args| {
         if value.kind() == ValueKind::Map && method == "items" {
             from_args::<()>(args)?;
-            minijinja::filters::items(value.clone())
+            minijinja::filters::items(state, value.clone())
         } else {
             Err(Error::from(ErrorKind::UnknownMethod))
         }
@@ -188,3 +225,950 @@ fn test_iter() {
     assert!(renders.contains(&("hello", "Hello World!".into())));
     assert!(renders.contains(&("goodbye", "Goodbye World!".into())));
 }
+
+#[test]
+#[cfg(all(feature = "builtins", feature = "macros"))]
+fn test_call_policy() {
+    use minijinja::{CallKind, ErrorKind};
+
+    let mut env = Environment::new();
+    env.set_call_policy(|kind, name| match kind {
+        CallKind::Filter => name != "upper",
+        CallKind::Test => name != "odd",
+        CallKind::Function => name != "range",
+        _ => true,
+    });
+
+    let rv = env
+        .render_str("{{ 'a'|lower }} {{ 2 is even }} {{ dict(a=1) }}", ())
+        .unwrap();
+    assert_eq!(rv, r#"a true {"a": 1}"#);
+
+    for (source, detail) in [
+        ("{{ 'a'|upper }}", "filter upper is not allowed"),
+        ("{{ 1 is odd }}", "test odd is not allowed"),
+        ("{{ range(3) }}", "function range is not allowed"),
+        (
+            "{{ ['a']|map('upper')|list }}",
+            "filter upper is not allowed",
+        ),
+        ("{{ [1, 2]|select('odd')|list }}", "test odd is not allowed"),
+        // aliased functions are checked under their registered name
+        (
+            "{% set r = range %}{{ r(3)|list }}",
+            "function range is not allowed",
+        ),
+        ("{{ [range][0](3)|list }}", "function range is not allowed"),
+        (
+            "{% set ns = namespace(r=range) %}{{ ns.r(3)|list }}",
+            "function range is not allowed",
+        ),
+        (
+            "{% macro range() %}{% endmacro %}{% set m = range %}{{ m() }}",
+            "function range is not allowed",
+        ),
+    ] {
+        let err = env.render_str(source, ()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidOperation);
+        assert_eq!(err.detail(), Some(detail));
+    }
+
+    // unknown items keep failing as unknown
+    let err = env.render_str("{{ 'a'|missing }}", ()).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::UnknownFilter);
+    let err = env.render_str("{{ missing() }}", ()).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::UnknownFunction);
+}
+
+#[test]
+fn test_attr_access_filter() {
+    use minijinja::{context, ErrorKind, UndefinedBehavior};
+
+    let mut env = Environment::new();
+    env.set_attr_access_filter(|value, attr| {
+        // only objects flagged as protected hide their private attributes
+        !(attr.starts_with('_') && value.get_attr("protected").map_or(false, |x| x.is_true()))
+    });
+    let ctx = context! {
+        user => context! { protected => true, name => "Peter", _secret => "s3cr3t" },
+        other => context! { _secret => "visible" },
+        key => "_secret",
+    };
+
+    let rv = env
+        .render_str(
+            "{{ user.name }}|{{ user._secret }}|{{ user['_secret'] }}|{{ user[key] }}|{{ other._secret }}",
+            &ctx,
+        )
+        .unwrap();
+    assert_eq!(rv, "Peter||||visible");
+
+    let rv = env
+        .render_str("{{ user._secret is defined }}", &ctx)
+        .unwrap();
+    assert_eq!(rv, "false");
+
+    env.set_undefined_behavior(UndefinedBehavior::Strict);
+    for source in [
+        "{{ user._secret }}",
+        "{{ user['_secret'] }}",
+        "{{ user[key] }}",
+    ] {
+        let err = env.render_str(source, &ctx).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UndefinedError);
+    }
+
+    let err = env.render_str("{{ user._secret() }}", &ctx).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::UnknownMethod);
+}
+
+#[test]
+#[cfg(feature = "builtins")]
+fn test_unknown_suggestions() {
+    let env = Environment::new();
+    for (source, detail) in [
+        (
+            "{{ 'a'|uppr }}",
+            "filter uppr is unknown (did you mean `upper`?)",
+        ),
+        ("{{ 1 is od }}", "test od is unknown (did you mean `odd`?)"),
+        ("{{ rnge(3) }}", "rnge is unknown (did you mean `range`?)"),
+        ("{{ 'a'|frobnicate }}", "filter frobnicate is unknown"),
+        ("{{ 1 is x }}", "test x is unknown"),
+        ("{{ missing_function() }}", "missing_function is unknown"),
+    ] {
+        let err = env.render_str(source, ()).unwrap_err();
+        assert_eq!(err.detail(), Some(detail));
+    }
+}
+
+#[test]
+fn test_keep_partial_output_on_error() {
+    let mut env = Environment::new();
+    let source = "a{% for x in [1, 2] %}{{ x }}{% endfor %}\
+                  {% set captured %}b{{ 1 + 'x' }}{% endset %}c";
+    let err = env.render_str(source, ()).unwrap_err();
+    assert_eq!(err.partial_output(), None);
+
+    env.set_keep_partial_output_on_error(true);
+    let err = env.render_str(source, ()).unwrap_err();
+    assert_eq!(err.partial_output(), Some("a12"));
+
+    #[cfg(feature = "multi_template")]
+    {
+        env.add_template("include.txt", "inner{{ 1 + 'x' }}")
+            .unwrap();
+        let err = env
+            .render_str("outer|{% include 'include.txt' %}", ())
+            .unwrap_err();
+        assert_eq!(err.partial_output(), Some("outer|inner"));
+    }
+}
+
+#[test]
+#[cfg(all(feature = "debug", feature = "builtins"))]
+fn test_error_local_variables() {
+    let mut env = Environment::new();
+    env.set_debug(true);
+    let err = env
+        .render_str(
+            "{% set title = 'x' * 300 %}{% set items = range(100)|list %}\
+             {% for user in ['a', 'b'] %}{% for x in [1] %}{{ user + x }}{% endfor %}{% endfor %}",
+            (),
+        )
+        .unwrap_err();
+    let locals = err
+        .local_variables()
+        .map(|(k, v)| (k, v.to_string()))
+        .collect::<BTreeMap<_, _>>();
+    assert_eq!(
+        locals.keys().copied().collect::<Vec<_>>(),
+        ["items", "loop", "title", "user", "x"]
+    );
+    assert_eq!(locals["user"], "a");
+    assert_eq!(locals["x"], "1");
+    assert_eq!(locals["items"], "<sequence of 100 items>");
+    assert_eq!(locals["title"], format!("{}...", "x".repeat(200)));
+    assert_eq!(locals["loop"], "<loop 0/1>");
+}
+
+#[test]
+fn test_unknown_available_names() {
+    use minijinja::ErrorKind;
+
+    let mut env = Environment::empty();
+    env.add_filter("upper", |x: String| x.to_uppercase());
+    env.add_test("short", |x: String| x.len() < 3);
+    env.add_function("greet", || "hi");
+
+    let err = env.render_str("{{ 'a'|lower }}", ()).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::UnknownFilter);
+    assert_eq!(err.available_names().unwrap(), ["upper"]);
+    assert_eq!(
+        err.to_string(),
+        "unknown filter: filter lower is unknown (in <string>:1)"
+    );
+
+    let err = env.render_str("{{ 'a' is long }}", ()).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::UnknownTest);
+    assert_eq!(err.available_names().unwrap(), ["short"]);
+
+    let err = env.render_str("{{ wave() }}", ()).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::UnknownFunction);
+    assert_eq!(err.available_names().unwrap(), ["greet"]);
+
+    let err = env.render_str("{{ 1 + 'a' }}", ()).unwrap_err();
+    assert_eq!(err.available_names(), None);
+}
+
+#[test]
+#[cfg(all(feature = "macros", feature = "multi_template"))]
+fn test_add_filter_memoized() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    let calls = Arc::new(AtomicUsize::new(0));
+    let mut env = Environment::new();
+    env.add_filter_memoized("expensive", {
+        let calls = calls.clone();
+        move |value: Value, suffix: Option<String>| {
+            calls.fetch_add(1, Ordering::Relaxed);
+            format!("{}{}", value, suffix.unwrap_or_default())
+        }
+    });
+    env.add_template(
+        "macro.txt",
+        "{% macro m(x) %}{{ x|expensive }}{% endmacro %}",
+    )
+    .unwrap();
+    let tmpl = env
+        .template_from_str(
+            "{% from 'macro.txt' import m %}\
+             {{ 'a'|expensive }}{{ 'a'|expensive }}{{ 'a'|expensive('!') }}\
+             {{ m('a') }}{{ [1, 2]|expensive }}{{ [1, 2]|expensive }}\
+             {{ iter|expensive }}{{ iter|expensive }}",
+        )
+        .unwrap();
+
+    let ctx = minijinja::context! { iter => Value::make_iterable(|| 0..1) };
+    assert_eq!(tmpl.render(&ctx).unwrap(), "aaa!a[1, 2][1, 2][0][0]");
+    // 'a', 'a' + '!', [1, 2] and the two uncacheable iterators
+    assert_eq!(calls.load(Ordering::Relaxed), 5);
+
+    // the cache does not outlive a render
+    tmpl.render(&ctx).unwrap();
+    assert_eq!(calls.load(Ordering::Relaxed), 10);
+
+    // equal values of different types are cached separately
+    let rv = env
+        .render_str(
+            "{{ 1|expensive }}{{ 1.0|expensive }}{{ true|expensive }}",
+            (),
+        )
+        .unwrap();
+    assert_eq!(rv, "11.0true");
+    assert_eq!(calls.load(Ordering::Relaxed), 13);
+}
+
+#[test]
+fn test_filters_registered_after_compile() {
+    let mut env = Environment::new();
+    env.add_template("tmpl.txt", "{{ x|greet }} {{ x is short }}")
+        .unwrap();
+
+    // neither is known when the template is compiled
+    env.add_filter("greet", |x: String| format!("Hello {x}"));
+    env.add_test("short", |x: String| x.len() < 5);
+    let tmpl = env.get_template("tmpl.txt").unwrap();
+    assert_eq!(
+        tmpl.render(minijinja::context! { x => "Ann" }).unwrap(),
+        "Hello Ann true"
+    );
+
+    // replacing and removing filters is picked up by compiled templates
+    env.add_filter("greet", |x: String| format!("Bye {x}"));
+    let tmpl = env.get_template("tmpl.txt").unwrap();
+    assert_eq!(
+        tmpl.render(minijinja::context! { x => "Ann" }).unwrap(),
+        "Bye Ann true"
+    );
+    env.remove_test("short");
+    let tmpl = env.get_template("tmpl.txt").unwrap();
+    let err = tmpl.render(minijinja::context! { x => "Ann" }).unwrap_err();
+    assert_eq!(err.kind(), minijinja::ErrorKind::UnknownTest);
+}
+
+#[test]
+#[cfg(all(feature = "multi_template", feature = "macros"))]
+fn test_template_load_callback() {
+    use std::sync::{Arc, Mutex};
+
+    let mut env = Environment::new();
+    env.add_template("layout.html", "[{% block body %}{% endblock %}]")
+        .unwrap();
+    env.add_template("macros.html", "{% macro m() %}m{% endmacro %}")
+        .unwrap();
+    env.add_template("item.html", "{{ item }}").unwrap();
+    env.add_template(
+        "index.html",
+        "{% extends 'layout.html' %}{% import 'macros.html' as macros %}\
+         {% block body %}{{ macros.m() }}{% for item in items %}\
+         {% include [item ~ '.html', 'item.html'] %}{% endfor %}{% endblock %}",
+    )
+    .unwrap();
+
+    let loaded = Arc::new(Mutex::new(Vec::new()));
+    let loaded_ref = loaded.clone();
+    env.set_template_load_callback(move |name| loaded_ref.lock().unwrap().push(name.to_string()));
+
+    let tmpl = env.get_template("index.html").unwrap();
+    let rv = tmpl
+        .render(minijinja::context! { items => vec!["a", "macros"] })
+        .unwrap();
+    assert_eq!(rv, "[ma]");
+    assert_eq!(
+        *loaded.lock().unwrap(),
+        ["layout.html", "macros.html", "item.html", "macros.html"]
+    );
+}
+
+#[test]
+#[cfg(feature = "multi_template")]
+fn test_max_include_depth() {
+    let mut env = Environment::new();
+    env.add_template("a.txt", "a{% include 'b.txt' %}").unwrap();
+    env.add_template("b.txt", "b{% include 'c.txt' %}").unwrap();
+    env.add_template(
+        "c.txt",
+        "c{% for x in [1, 2] %}{% include 'd.txt' %}{% endfor %}",
+    )
+    .unwrap();
+    env.add_template("d.txt", "d").unwrap();
+    assert_eq!(env.max_include_depth(), usize::MAX);
+    let tmpl = env.get_template("a.txt").unwrap();
+    assert_eq!(tmpl.render(()).unwrap(), "abcdd");
+
+    env.set_max_include_depth(3);
+    let tmpl = env.get_template("a.txt").unwrap();
+    assert_eq!(tmpl.render(()).unwrap(), "abcdd");
+
+    env.set_max_include_depth(2);
+    let tmpl = env.get_template("a.txt").unwrap();
+    let err = tmpl.render(()).unwrap_err();
+    assert_eq!(err.kind(), minijinja::ErrorKind::BadInclude);
+    let mut source = std::error::Error::source(&err);
+    let mut deepest = None;
+    while let Some(err) = source {
+        deepest = err.downcast_ref::<minijinja::Error>();
+        source = err.source();
+    }
+    let deepest = deepest.unwrap();
+    assert_eq!(deepest.kind(), minijinja::ErrorKind::InvalidOperation);
+    assert_eq!(
+        deepest.detail(),
+        Some("include depth limit of 2 exceeded when including \"d.txt\"")
+    );
+}
+
+#[test]
+fn test_strict_comparisons() {
+    // one value per kind, in the order in which kinds sort
+    let values = [
+        Value::UNDEFINED,
+        Value::from(()),
+        Value::from(true),
+        Value::from(42),
+        Value::from("10"),
+        Value::from_bytes(b"bytes".to_vec()),
+        Value::from(vec![1, 2]),
+        Value::from_serialize(BTreeMap::from([("a", 1)])),
+    ];
+
+    let mut env = Environment::new();
+    let expr = env.compile_expression("a < b").unwrap();
+    for (a_idx, a) in values.iter().enumerate() {
+        for (b_idx, b) in values.iter().enumerate() {
+            let rv = expr
+                .eval(minijinja::context! { a => a.clone(), b => b.clone() })
+                .unwrap();
+            assert_eq!(rv.is_true(), a_idx < b_idx, "{a:?} < {b:?}");
+        }
+    }
+
+    env.set_strict_comparisons(true);
+    assert!(env.strict_comparisons());
+    for op in ["<", "<=", ">", ">="] {
+        let source = format!("a {op} b");
+        let expr = env.compile_expression(&source).unwrap();
+        for a in &values {
+            for b in &values {
+                let rv = expr.eval(minijinja::context! { a => a.clone(), b => b.clone() });
+                if a.kind() == b.kind() {
+                    assert!(rv.is_ok(), "{a:?} {op} {b:?}");
+                } else {
+                    let err = rv.unwrap_err();
+                    assert_eq!(err.kind(), minijinja::ErrorKind::InvalidOperation);
+                    assert_eq!(
+                        err.detail(),
+                        Some(format!("cannot compare {} with {}", a.kind(), b.kind()).as_str())
+                    );
+                }
+            }
+        }
+    }
+
+    // equality checks and numbers of different types are unaffected
+    let expr = env.compile_expression("'10' == 10 or 1 < 1.5").unwrap();
+    assert!(expr.eval(()).unwrap().is_true());
+}
+
+#[test]
+#[cfg(feature = "builtins")]
+fn test_sort_map_keys() {
+    use std::collections::HashMap;
+
+    let map: HashMap<String, i32> = (0..20).map(|x| (format!("k{x:02}"), x)).collect();
+    let sorted = (0..20).map(|x| format!("k{x:02}")).collect::<Vec<_>>();
+    let ctx = minijinja::context!(map);
+
+    let mut env = Environment::new();
+    env.set_sort_map_keys(true);
+    assert!(env.sort_map_keys());
+
+    let rv = env
+        .render_str("{% for k in map %}{{ k }},{% endfor %}", &ctx)
+        .unwrap();
+    assert_eq!(rv, format!("{},", sorted.join(",")));
+
+    let rv = env
+        .render_str("{% for k, v in map|items %}{{ k }},{% endfor %}", &ctx)
+        .unwrap();
+    assert_eq!(rv, format!("{},", sorted.join(",")));
+
+    let rv = env
+        .render_str("{% for k, v in map|dictsort %}{{ k }},{% endfor %}", &ctx)
+        .unwrap();
+    assert_eq!(rv, format!("{},", sorted.join(",")));
+
+    #[cfg(feature = "json")]
+    {
+        let rv = env.render_str("{{ map|tojson }}", &ctx).unwrap();
+        let expected = sorted
+            .iter()
+            .enumerate()
+            .map(|(idx, key)| format!("\"{key}\":{idx}"))
+            .collect::<Vec<_>>();
+        assert_eq!(rv, format!("{{{}}}", expected.join(",")));
+    }
+}
+
+#[test]
+#[cfg(feature = "builtins")]
+fn test_max_loop_recursion_depth() {
+    fn nested(depth: usize) -> Value {
+        let mut rv = Value::from(Vec::<Value>::new());
+        for idx in 0..depth {
+            rv = Value::from(vec![Value::from(idx), rv]);
+        }
+        rv
+    }
+
+    let tmpl = "{% for item in tree recursive %}\
+        {% if item is sequence %}{{ loop(item) }}{% else %}{{ loop.depth0 }}{% endif %}\
+        {% endfor %}";
+    let mut env = Environment::new();
+    assert_eq!(env.max_loop_recursion_depth(), usize::MAX);
+    let ctx = minijinja::context!(tree => nested(3));
+    assert_eq!(env.render_str(tmpl, ctx.clone()).unwrap(), "012");
+
+    env.set_max_loop_recursion_depth(3);
+    assert_eq!(env.render_str(tmpl, ctx.clone()).unwrap(), "012");
+
+    env.set_max_loop_recursion_depth(2);
+    let err = env.render_str(tmpl, ctx).unwrap_err();
+    assert_eq!(err.kind(), minijinja::ErrorKind::InvalidOperation);
+    assert_eq!(
+        err.detail(),
+        Some("recursive loop exceeded maximum loop recursion depth (2)")
+    );
+
+    // the limit is independent of the general recursion limit
+    env.set_max_loop_recursion_depth(usize::MAX);
+    let ctx = minijinja::context!(tree => nested(200));
+    assert!(env.render_str(tmpl, ctx).is_ok());
+}
+
+#[test]
+fn test_loop_cycle_behavior() {
+    use minijinja::value::Object;
+    use minijinja::{ErrorKind, LoopCycleBehavior};
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Debug)]
+    struct Node {
+        name: &'static str,
+        children: Mutex<Value>,
+    }
+
+    impl Object for Node {
+        fn get_value(self: &Arc<Self>, key: &Value) -> Option<Value> {
+            match key.as_str()? {
+                "name" => Some(Value::from(self.name)),
+                "children" => Some(self.children.lock().unwrap().clone()),
+                _ => None,
+            }
+        }
+    }
+
+    let a = Arc::new(Node {
+        name: "a",
+        children: Mutex::new(Value::UNDEFINED),
+    });
+    let b = Arc::new(Node {
+        name: "b",
+        children: Mutex::new(Value::from(vec![Value::from_dyn_object(a.clone())])),
+    });
+    *a.children.lock().unwrap() = Value::from(vec![Value::from_dyn_object(b.clone())]);
+
+    let tmpl = "{% for node in [root] recursive %}\
+        {{ node.name }}[{{ loop(node.children) }}]\
+        {% endfor %}";
+    let ctx = minijinja::context!(root => Value::from_dyn_object(a.clone()));
+    let mut env = Environment::new();
+    assert_eq!(env.loop_cycle_behavior(), LoopCycleBehavior::Ignore);
+    let err = env.render_str(tmpl, ctx.clone()).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidOperation);
+    assert!(err.to_string().contains("recursion limit exceeded"));
+
+    env.set_loop_cycle_behavior(LoopCycleBehavior::Error);
+    let err = env.render_str(tmpl, ctx.clone()).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidOperation);
+    assert_eq!(err.detail(), Some("recursive loop detected a cycle"));
+
+    env.set_loop_cycle_behavior(LoopCycleBehavior::Stop);
+    assert_eq!(env.render_str(tmpl, ctx).unwrap(), "a[b[a[]]]");
+
+    // break the reference cycle
+    *a.children.lock().unwrap() = Value::UNDEFINED;
+}
+
+#[test]
+fn test_undefined_handler() {
+    use minijinja::{ErrorKind, UndefinedContext};
+    use std::sync::{Arc, Mutex};
+
+    let log = Arc::new(Mutex::new(Vec::new()));
+    let mut env = Environment::new();
+    env.set_undefined_handler({
+        let log = log.clone();
+        move |state, ctx| {
+            let desc = match ctx {
+                UndefinedContext::Lookup { name } => name.to_string(),
+                UndefinedContext::GetAttr { parent, name } => format!("{parent:?}.{name}"),
+                UndefinedContext::GetItem { parent, key } => format!("{parent:?}[{key:?}]"),
+                _ => unreachable!(),
+            };
+            log.lock()
+                .unwrap()
+                .push(format!("{}: {}", state.name(), desc));
+            if desc == "fail" {
+                Err(minijinja::Error::new(
+                    ErrorKind::UndefinedError,
+                    "fail is missing",
+                ))
+            } else {
+                Ok(Value::UNDEFINED)
+            }
+        }
+    });
+    let ctx = minijinja::context!(user => minijinja::context!(name => "Peter"), seq => vec![1]);
+    let rv = env
+        .render_str(
+            "{{ user.name }}|{{ missing }}|{{ user.email }}|{{ seq[1] }}|{{ user.email.x }}",
+            ctx.clone(),
+        )
+        .unwrap();
+    assert_eq!(rv, "Peter||||");
+    assert_eq!(
+        *log.lock().unwrap(),
+        [
+            "<string>: missing",
+            "<string>: {\"name\": \"Peter\"}.email",
+            "<string>: [1][1]",
+            "<string>: {\"name\": \"Peter\"}.email",
+            "<string>: undefined.x",
+        ]
+    );
+
+    let err = env.render_str("{{ fail }}", ctx).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::UndefinedError);
+    assert_eq!(err.detail(), Some("fail is missing"));
+}
+
+#[test]
+#[cfg(feature = "builtins")]
+fn test_defer_undefined_errors() {
+    use minijinja::{ErrorKind, UndefinedBehavior};
+
+    let mut env = Environment::new();
+    env.set_undefined_behavior(UndefinedBehavior::Strict);
+    let ctx = minijinja::context!(user => minijinja::context!(name => "Peter"));
+    let tmpl = "{{ user.profile.email|lower|default('n/a') }}";
+    assert_eq!(
+        env.render_str(tmpl, ctx.clone()).unwrap_err().kind(),
+        ErrorKind::UndefinedError
+    );
+
+    env.set_defer_undefined_errors(true);
+    assert!(env.defer_undefined_errors());
+    assert_eq!(env.render_str(tmpl, ctx.clone()).unwrap(), "n/a");
+    assert_eq!(
+        env.render_str("{{ user.name|upper|default('?') }}", ctx.clone())
+            .unwrap(),
+        "PETER"
+    );
+    assert_eq!(
+        env.render_str("{{ user['x'][0] is defined }}", ctx.clone())
+            .unwrap(),
+        "false"
+    );
+    assert_eq!(
+        env.render_str(
+            "{{ user.profile.email is undefined }}|{{ user.profile.email|d('-') }}",
+            ctx.clone()
+        )
+        .unwrap(),
+        "true|-"
+    );
+    for tmpl in [
+        "{{ user.profile.email }}",
+        "{{ user.profile.email|lower }}",
+        "{% for x in user.profile %}{% endfor %}",
+        "{{ user.profile.email ~ '!' }}",
+        "{{ user.profile.email + 1 }}",
+        "{{ user.profile.email|pprint }}",
+        "{{ [user.profile.email]|join(',') }}",
+        "{{ {'email': user.profile.email} }}",
+        "{{ 'a' in user.profile.email }}",
+        "{{ user.profile.email or 'x' }}",
+        "{{ user.profile.email is string }}",
+        "{{ 'x'|default(user.profile.email) }}",
+        "{{ range(user.profile.email) }}",
+    ] {
+        let err = env.render_str(tmpl, ctx.clone()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UndefinedError, "{tmpl}");
+    }
+
+    // deferred errors are raised in lenient mode too
+    env.set_undefined_behavior(UndefinedBehavior::Lenient);
+    assert_eq!(
+        env.render_str("[{{ user.profile.email|default('x') }}]", ctx.clone())
+            .unwrap(),
+        "[x]"
+    );
+    let err = env
+        .render_str("[{{ user.profile.email }}]", ctx)
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::UndefinedError);
+}
+
+#[test]
+#[cfg(feature = "builtins")]
+fn test_defer_undefined_errors_registration() {
+    use minijinja::{Error, ErrorKind, UndefinedBehavior};
+
+    let mut env = Environment::new();
+    env.set_undefined_behavior(UndefinedBehavior::Strict);
+    env.set_defer_undefined_errors(true);
+    let ctx = minijinja::context!(user => minijinja::context!(name => "Peter"));
+
+    // aliases of the default filter handle undefined values too
+    env.add_filter_alias("fallback", "default");
+    assert_eq!(
+        env.render_str("{{ user.email|fallback('-') }}", ctx.clone())
+            .unwrap(),
+        "-"
+    );
+
+    // overriding the default filter does not make it handle them
+    env.add_filter("default", |value: Value, _other: Value| value);
+    let err = env
+        .render_str("{{ user.email|default('-') }}", ctx.clone())
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::UndefinedError);
+
+    // invalid values are not mistaken for undefined values
+    env.add_function("broken", || {
+        Value::from(Error::new(ErrorKind::InvalidOperation, "broken"))
+    });
+    assert_eq!(
+        env.render_str("{{ broken() is defined }}", ctx).unwrap(),
+        "true"
+    );
+}
+
+#[test]
+#[cfg(feature = "builtins")]
+fn test_mark_undefined_in_output() {
+    use minijinja::{AutoEscape, UndefinedBehavior};
+
+    let mut env = Environment::new();
+    let ctx = minijinja::context!(user => minijinja::context!(name => "Peter"), seq => vec![1]);
+    let tmpl = "[{{ none }}][{{ missing }}][{{ user.email }}][{{ user['x'] }}][{{ seq[3] }}][{{ user|attr('nope') }}][{{ missing|default('x') }}]";
+    assert_eq!(
+        env.render_str(tmpl, ctx.clone()).unwrap(),
+        "[none][][][][][][x]"
+    );
+
+    env.set_mark_undefined_in_output(true);
+    assert_eq!(
+        env.render_str(tmpl, ctx.clone()).unwrap(),
+        "[none][<undefined:missing>][<undefined:user.email>][<undefined:user[\"x\"]>]\
+         [<undefined:seq[3]>][<undefined>][x]"
+    );
+
+    // with branches the undefined value might come from either side
+    let ctx2 = minijinja::context!(user => minijinja::context!(), other => 42);
+    assert_eq!(
+        env.render_str(
+            "[{{ user.name if true else other }}][{{ user.name or other.x }}]",
+            ctx2
+        )
+        .unwrap(),
+        "[<undefined>][<undefined>]"
+    );
+
+    env.set_auto_escape_callback(|_| AutoEscape::Html);
+    assert_eq!(
+        env.render_str("{{ missing }}", ctx.clone()).unwrap(),
+        "&lt;undefined:missing&gt;"
+    );
+
+    env.set_undefined_behavior(UndefinedBehavior::Strict);
+    assert!(env.render_str("{{ missing }}", ctx).is_err());
+}
+
+#[test]
+#[cfg(all(feature = "multi_template", feature = "builtins"))]
+fn test_lenient_super() {
+    let mut env = Environment::new();
+    env.add_template("base.txt", "{% block title %}Base{% endblock %}")
+        .unwrap();
+    env.add_template(
+        "child.txt",
+        "{% extends 'base.txt' %}{% block title %}[{{ super() }}|{{ super()|lower }}]{% endblock %}",
+    )
+    .unwrap();
+    env.add_template(
+        "standalone.txt",
+        "{% block title %}[{{ super() }}|{{ super()|lower }}]{% endblock %}",
+    )
+    .unwrap();
+
+    let err = env
+        .get_template("standalone.txt")
+        .unwrap()
+        .render(())
+        .unwrap_err();
+    assert_eq!(err.kind(), minijinja::ErrorKind::InvalidOperation);
+
+    env.set_lenient_super(true);
+    let child = env.get_template("child.txt").unwrap();
+    assert_eq!(child.render(()).unwrap(), "[Base|base]");
+    let standalone = env.get_template("standalone.txt").unwrap();
+    assert_eq!(standalone.render(()).unwrap(), "[|]");
+}
+
+#[test]
+#[cfg(feature = "multi_template")]
+fn test_warning_sink() {
+    use minijinja::{State, Warning, WarningKind};
+    use std::sync::{Arc, Mutex};
+
+    let warnings = Arc::new(Mutex::new(Vec::<Warning>::new()));
+    let mut env = Environment::new();
+    let sink = warnings.clone();
+    env.set_warning_sink(move |warning| sink.lock().unwrap().push(warning));
+    env.add_filter("old_upper", |state: &State, value: String| {
+        state.warn(Warning::new(WarningKind::Deprecated, "use upper instead"));
+        value.to_uppercase()
+    });
+    env.add_template("fallback.html", "[fallback]").unwrap();
+    env.add_template(
+        "index.html",
+        "{% include ['missing.html', 'other.html', 'fallback.html'] %}\n\
+         {{ user.name }}{{ user.email }}{{ nope }}{{ seq[2] }}\n\
+         {{ 'x'|old_upper }}",
+    )
+    .unwrap();
+
+    let rv = env
+        .get_template("index.html")
+        .unwrap()
+        .render(minijinja::context!(user => minijinja::context!(name => "Peter"), seq => vec![1]))
+        .unwrap();
+    assert_eq!(rv, "[fallback]\nPeter\nX");
+
+    let warnings = warnings.lock().unwrap();
+    let rendered = warnings.iter().map(|x| x.to_string()).collect::<Vec<_>>();
+    assert_eq!(
+        rendered,
+        vec![
+            "include fallback: included \"fallback.html\" because \"missing.html\", \
+             \"other.html\" could not be found (in index.html:1)",
+            "undefined value: undefined attribute \"email\" (in index.html:2)",
+            "undefined value: undefined variable \"nope\" (in index.html:2)",
+            "undefined value: undefined item 2 (in index.html:2)",
+            "deprecated: use upper instead (in index.html)",
+        ]
+    );
+    assert_eq!(warnings[0].kind(), WarningKind::IncludeFallback);
+    assert_eq!(warnings[0].name(), Some("index.html"));
+    assert_eq!(warnings[0].line(), Some(1));
+    assert_eq!(warnings[4].kind(), WarningKind::Deprecated);
+    assert_eq!(warnings[4].line(), None);
+}
+
+#[test]
+fn test_callable_aliases() {
+    use minijinja::{Warning, WarningKind};
+    use std::sync::{Arc, Mutex};
+
+    let warnings = Arc::new(Mutex::new(Vec::<Warning>::new()));
+    let mut env = Environment::new();
+    env.add_filter("slugify", |value: &str| {
+        value.to_lowercase().replace(' ', "-")
+    });
+    env.add_filter_alias("slug", "slugify");
+    env.add_test("short", |value: &str| value.len() < 5);
+    env.add_test_alias("brief", "short");
+    env.add_function("greet", |name: &str| format!("Hello {name}!"));
+    env.add_function_alias("hello", "greet");
+
+    let tmpl = "{% for x in ['A B', 'C D', 'E F'] %}{{ x|slug }}{% endfor %}\n\
+                {{ 'X Y'|slug }}|{{ 'abc' is brief }}|{{ hello('Peter') }}";
+    let expected = "a-bc-de-f\nx-y|true|Hello Peter!";
+
+    // without a sink the aliases work silently
+    assert_eq!(env.render_str(tmpl, ()).unwrap(), expected);
+    assert_eq!(
+        env.render_str(tmpl, ()).unwrap(),
+        env.render_str(
+            &tmpl
+                .replace("|slug", "|slugify")
+                .replace("is brief", "is short")
+                .replace("hello(", "greet("),
+            ()
+        )
+        .unwrap()
+    );
+
+    let sink = warnings.clone();
+    env.set_warning_sink(move |warning| sink.lock().unwrap().push(warning));
+    assert_eq!(env.render_str(tmpl, ()).unwrap(), expected);
+
+    // variables shadowing an aliased function do not warn
+    let rv = env
+        .render_str(
+            "{{ hello() }}|{% set hello = greet %}{{ hello('Jane') }}",
+            minijinja::context! { hello => Value::from_function(|| "shadowed") },
+        )
+        .unwrap();
+    assert_eq!(rv, "shadowed|Hello Jane!");
+
+    let warnings = warnings.lock().unwrap();
+    let rendered = warnings.iter().map(|x| x.to_string()).collect::<Vec<_>>();
+    assert_eq!(
+        rendered,
+        vec![
+            "deprecated: filter \"slug\" is deprecated, use \"slugify\" instead (in <string>:1)",
+            "deprecated: filter \"slug\" is deprecated, use \"slugify\" instead (in <string>:2)",
+            "deprecated: test \"brief\" is deprecated, use \"short\" instead (in <string>:2)",
+            "deprecated: function \"hello\" is deprecated, use \"greet\" instead (in <string>:2)",
+        ]
+    );
+    assert!(warnings.iter().all(|x| x.kind() == WarningKind::Deprecated));
+    drop(warnings);
+
+    // registering the old name again replaces the alias
+    env.add_filter("slug", |value: &str| value.to_uppercase());
+    assert_eq!(env.render_str("{{ 'a b'|slug }}", ()).unwrap(), "A B");
+}
+
+#[test]
+#[cfg(feature = "internal_debug")]
+fn test_trace_callback_operand_stack() {
+    use minijinja::State;
+    use std::sync::{Arc, Mutex};
+
+    let trace = Arc::new(Mutex::new(Vec::new()));
+    let mut env = Environment::new();
+    let sink = trace.clone();
+    env.set_trace_callback(move |state, pc| {
+        sink.lock().unwrap().push((
+            pc,
+            state.operand_stack_depth().unwrap(),
+            state.peek_operand_stack().cloned(),
+        ));
+    });
+    env.add_filter("depth", |state: &State, value: Value| {
+        assert_eq!(state.operand_stack_depth(), None);
+        assert_eq!(state.peek_operand_stack(), None);
+        value
+    });
+
+    assert_eq!(env.render_str("{{ (1 + 2)|depth }}", ()).unwrap(), "3");
+    let trace = trace.lock().unwrap();
+    let pcs = trace.iter().map(|x| x.0).collect::<Vec<_>>();
+    assert_eq!(pcs, (0..pcs.len()).collect::<Vec<_>>());
+    // the stack starts out empty and the last instruction emits the result
+    assert_eq!(trace[0].1, 0);
+    assert_eq!(trace[0].2, None);
+    let (_, depth, top) = trace.last().unwrap();
+    assert_eq!(*depth, 1);
+    assert_eq!(top.as_ref(), Some(&Value::from(3)));
+}
+
+#[test]
+#[cfg(feature = "unstable_machinery")]
+fn test_trace_callback_coverage() {
+    use minijinja::machinery::{get_compiled_template, get_state_instructions};
+    use std::collections::BTreeSet;
+    use std::sync::{Arc, Mutex};
+
+    let executed = Arc::new(Mutex::new(BTreeSet::new()));
+    let mut env = Environment::new();
+    let sink = executed.clone();
+    env.set_trace_callback(move |state, pc| {
+        let instructions = get_state_instructions(state);
+        assert!(instructions.get(pc).is_some());
+        sink.lock()
+            .unwrap()
+            .insert((state.current_block().map(|x| x.to_string()), pc));
+    });
+    env.add_template(
+        "hello.txt",
+        "{% block body %}{% if flag %}{{ never }}{% endif %}{% endblock %}",
+    )
+    .unwrap();
+    let tmpl = env.get_template("hello.txt").unwrap();
+    assert_eq!(
+        tmpl.render(minijinja::context! { flag => false }).unwrap(),
+        ""
+    );
+
+    let compiled = get_compiled_template(&tmpl);
+    let executed = executed.lock().unwrap();
+    let root = compiled.instructions.iter_with_spans().collect::<Vec<_>>();
+    assert!(root
+        .iter()
+        .all(|(idx, ..)| executed.contains(&(None, *idx))));
+    assert!(root.iter().all(|(_, _, line, _)| *line == Some(1)));
+
+    let uncovered = compiled.blocks["body"]
+        .iter_with_spans()
+        .filter(|(idx, ..)| !executed.contains(&(Some("body".into()), *idx)))
+        .map(|(_, instr, ..)| format!("{:?}", instr))
+        .collect::<Vec<_>>();
+    assert_eq!(uncovered, [r#"Lookup("never")"#, "Emit"]);
+}
@@ -1,6 +1,6 @@
 #![cfg(feature = "builtins")]
-use minijinja::value::Value;
-use minijinja::{args, Environment};
+use minijinja::value::{Kwargs, Value};
+use minijinja::{args, Environment, ErrorKind};
 use similar_asserts::assert_eq;
 
 use minijinja::filters::{abs, indent};
@@ -31,23 +31,33 @@ fn test_filter_with_non() {
     assert_eq!(rv, Value::from("[wat]"));
 }
 
+fn no_kwargs() -> Kwargs {
+    Kwargs::from_iter(Vec::<(&str, Value)>::new())
+}
+
 #[test]
 fn test_indent_one_empty_line() {
     let teststring = String::from("\n");
-    assert_eq!(indent(teststring, 2, None, None), String::from(""));
+    assert_eq!(
+        indent(teststring, 2, None, None, no_kwargs()).unwrap(),
+        String::from("")
+    );
 }
 
 #[test]
 fn test_indent_one_line() {
     let teststring = String::from("test\n");
-    assert_eq!(indent(teststring, 2, None, None), String::from("test"));
+    assert_eq!(
+        indent(teststring, 2, None, None, no_kwargs()).unwrap(),
+        String::from("test")
+    );
 }
 
 #[test]
 fn test_indent() {
     let teststring = String::from("test\ntest1\n\ntest2\n");
     assert_eq!(
-        indent(teststring, 2, None, None),
+        indent(teststring, 2, None, None, no_kwargs()).unwrap(),
         String::from("test\n  test1\n\n  test2")
     );
 }
@@ -56,7 +66,7 @@ fn test_indent() {
 fn test_indent_with_indented_first_line() {
     let teststring = String::from("test\ntest1\n\ntest2\n");
     assert_eq!(
-        indent(teststring, 2, Some(true), None),
+        indent(teststring, 2, Some(true), None, no_kwargs()).unwrap(),
         String::from("  test\n  test1\n\n  test2")
     );
 }
@@ -65,7 +75,7 @@ fn test_indent_with_indented_first_line() {
 fn test_indent_with_indented_blank_line() {
     let teststring = String::from("test\ntest1\n\ntest2\n");
     assert_eq!(
-        indent(teststring, 2, None, Some(true)),
+        indent(teststring, 2, None, Some(true), no_kwargs()).unwrap(),
         String::from("test\n  test1\n  \n  test2")
     );
 }
@@ -74,7 +84,17 @@ fn test_indent_with_indented_blank_line() {
 fn test_indent_with_all_indented() {
     let teststring = String::from("test\ntest1\n\ntest2\n");
     assert_eq!(
-        indent(teststring, 2, Some(true), Some(true)),
+        indent(teststring, 2, Some(true), Some(true), no_kwargs()).unwrap(),
+        String::from("  test\n  test1\n  \n  test2")
+    );
+}
+
+#[test]
+fn test_indent_with_kwargs() {
+    let teststring = String::from("test\ntest1\n\ntest2\n");
+    let kwargs = Kwargs::from_iter([("first", Value::from(true)), ("blank", Value::from(true))]);
+    assert_eq!(
+        indent(teststring, 2, None, None, kwargs).unwrap(),
         String::from("  test\n  test1\n  \n  test2")
     );
 }
@@ -86,3 +106,379 @@ fn test_abs_overflow() {
     let err = abs(Value::from(i128::MIN)).unwrap_err();
     assert_eq!(err.to_string(), "invalid operation: overflow on abs");
 }
+
+#[test]
+fn test_min_max_empty() {
+    let env = Environment::new();
+    for filter in ["min", "max"] {
+        let err = env
+            .render_str(&format!("{{{{ []|{filter} }}}}"), ())
+            .unwrap_err();
+        assert_eq!(err.kind(), minijinja::ErrorKind::InvalidOperation);
+        let rv = env
+            .render_str(&format!("{{{{ []|{filter}(default='x') }}}}"), ())
+            .unwrap();
+        assert_eq!(rv, "x");
+    }
+}
+
+#[test]
+fn test_sum() {
+    let env = Environment::new();
+    let rv = env.render_str("{{ [1, 2.5]|sum is float }}", ()).unwrap();
+    assert_eq!(rv, "true");
+    let err = env.render_str("{{ [1, 'a']|sum }}", ()).unwrap_err();
+    assert_eq!(err.kind(), minijinja::ErrorKind::InvalidOperation);
+    assert_eq!(err.detail(), Some("can only sum numbers, got string"));
+}
+
+#[test]
+fn test_b64decode_padding() {
+    let env = Environment::new();
+    for (input, expected) in [
+        ("aGVsbG8=", "68656c6c6f"),
+        ("aGVsbG8", "68656c6c6f"),
+        ("aA==", "68"),
+        ("aA", "68"),
+        ("", ""),
+    ] {
+        let rv = env
+            .render_str(
+                "{{ value|b64decode|hex }}",
+                minijinja::context! { value => input },
+            )
+            .unwrap();
+        assert_eq!(rv, expected, "{input}");
+    }
+    for input in ["aGVsbG8==", "aA=", "aA===", "aGVs=", "aGVs====", "="] {
+        let err = env
+            .render_str(
+                "{{ value|b64decode }}",
+                minijinja::context! { value => input },
+            )
+            .unwrap_err();
+        assert_eq!(err.detail(), Some("invalid base64 data"), "{input}");
+    }
+}
+
+#[test]
+#[cfg(feature = "urlencode")]
+fn test_urlencode() {
+    let env = Environment::new();
+    let ctx = minijinja::context! {
+        params => minijinja::context! {
+            next => "/a/b?c=d&e=f",
+            p => 2,
+            q => "x y",
+            skip => (),
+        },
+    };
+    let render = |expr: &str| env.render_str(&format!("{{{{ {expr} }}}}"), &ctx).unwrap();
+
+    assert_eq!(render(r#""a b&c"|urlencode"#), "a%20b%26c");
+    assert_eq!(
+        render(r#""/foo bar/baz~qux.txt"|urlencode"#),
+        "/foo%20bar/baz~qux.txt"
+    );
+    assert_eq!(
+        render(r#""?#[]@!$&'()*+,;=%"|urlencode"#),
+        "%3F%23%5B%5D%40%21%24%26%27%28%29%2A%2B%2C%3B%3D%25"
+    );
+    assert_eq!(render(r#""käse"|urlencode"#), "k%C3%A4se");
+    assert_eq!(render("42|urlencode"), "42");
+    assert_eq!(render("none|urlencode"), "");
+    assert_eq!(
+        render("params|urlencode"),
+        "next=%2Fa%2Fb%3Fc%3Dd%26e%3Df&p=2&q=x%20y"
+    );
+    assert_eq!(
+        render(r#"{"a&b": "c=d", "x/y": "ä ö"}|urlencode"#),
+        "a%26b=c%3Dd&x%2Fy=%C3%A4%20%C3%B6"
+    );
+}
+
+#[test]
+fn test_sequence_filters_borrowed() {
+    let env = Environment::new();
+    let tmpl = "{{ items|sum }}|{{ items|min }}|{{ items|max }}|\
+        {{ items|select('odd') }}|{{ items|reject('odd') }}";
+    let values = Value::from((1..=10).map(Value::from).collect::<Vec<_>>());
+    assert_eq!(values.as_slice().map(|x| x.len()), Some(10));
+    let numbers = Value::from((1..=10).collect::<Vec<i64>>());
+    assert!(numbers.as_slice().is_none());
+
+    let expected = "55|1|10|[1, 3, 5, 7, 9]|[2, 4, 6, 8, 10]";
+    for items in [values, numbers] {
+        let rv = env.render_str(tmpl, minijinja::context! { items }).unwrap();
+        assert_eq!(rv, expected);
+    }
+}
+
+#[test]
+fn test_string_filters_unchanged() {
+    let env = Environment::new();
+    let render = |expr: &str, value: Value| {
+        env.render_str(&format!("{{{{ {expr} }}}}"), minijinja::context! { value })
+            .unwrap()
+    };
+
+    assert_eq!(render("value|trim", Value::from("  foo ")), "foo");
+    assert_eq!(render("value|trim", Value::from("foo")), "foo");
+    assert_eq!(render("value|trim('x')", Value::from("xfoox")), "foo");
+    assert_eq!(render("value|lower", Value::from("FÖÖ Bar")), "föö bar");
+    assert_eq!(render("value|lower", Value::from("föö bar")), "föö bar");
+    assert_eq!(render("value|lower", Value::from("ΣΑΣ")), "σας");
+    assert_eq!(render("value|upper", Value::from("föö Bar")), "FÖÖ BAR");
+    assert_eq!(render("value|upper", Value::from("straße")), "STRASSE");
+    assert_eq!(render("value|upper", Value::from("FOO")), "FOO");
+    assert_eq!(render("value|upper", Value::from(42)), "42");
+    assert_eq!(render("value|trim is string", Value::from(42)), "true");
+
+    // safe strings are not passed through, even if unchanged
+    let ctx = minijinja::context! { value => Value::from_safe_string("<b>".into()) };
+    let rv = env
+        .render_named_str("x.html", "{{ value|trim }}|{{ value|lower }}", ctx)
+        .unwrap();
+    assert_eq!(rv, "&lt;b&gt;|&lt;b&gt;");
+}
+
+#[test]
+#[cfg(feature = "json")]
+fn test_tojson_sort_keys() {
+    use minijinja::value::{Enumerator, Object};
+    use std::sync::Arc;
+
+    #[derive(Debug)]
+    struct Unordered;
+
+    impl Object for Unordered {
+        fn get_value(self: &Arc<Self>, key: &Value) -> Option<Value> {
+            match key.as_str()? {
+                "b" => Some(Value::from(1)),
+                "a" => Some(Value::from(2)),
+                _ => None,
+            }
+        }
+
+        fn enumerate(self: &Arc<Self>) -> Enumerator {
+            Enumerator::Str(&["b", "a"])
+        }
+    }
+
+    let env = Environment::new();
+    let ctx = minijinja::context!(obj => Value::from_object(Unordered));
+    let rv = env.render_str("{{ obj|tojson }}", &ctx).unwrap();
+    assert_eq!(rv, r#"{"b":1,"a":2}"#);
+    let rv = env
+        .render_str("{{ obj|tojson(sort_keys=true) }}", &ctx)
+        .unwrap();
+    assert_eq!(rv, r#"{"a":2,"b":1}"#);
+}
+
+#[test]
+fn test_format() {
+    let env = Environment::new();
+    let rv = env.render_str("{{ '%.3s'|format('abcdef') }}", ()).unwrap();
+    assert_eq!(rv, "abc");
+
+    for (tmpl, msg) in [
+        (
+            "{{ '%s and %s'|format(1) }}",
+            "not enough arguments for format string",
+        ),
+        (
+            "{{ '%s'|format(1, 2) }}",
+            "not all arguments converted during string formatting",
+        ),
+        (
+            "{{ '%d'|format('42') }}",
+            "%d format requires a number, got string",
+        ),
+        (
+            "{{ '%.2f'|format(none) }}",
+            "%f format requires a number, got none",
+        ),
+        ("{{ '%q'|format(1) }}", "unsupported format specifier %q"),
+        ("{{ 'foo %'|format(1) }}", "incomplete format specifier"),
+    ] {
+        let err = env.render_str(tmpl, ()).unwrap_err();
+        assert_eq!(err.kind(), minijinja::ErrorKind::InvalidOperation);
+        assert_eq!(err.detail(), Some(msg));
+    }
+}
+
+#[test]
+fn test_first_last_lazy() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    let pulled = Arc::new(AtomicUsize::new(0));
+    let counting = {
+        let pulled = pulled.clone();
+        move || {
+            let pulled = pulled.clone();
+            Value::make_one_shot_iterator((1..=5).inspect(move |_| {
+                pulled.fetch_add(1, Ordering::Relaxed);
+            }))
+        }
+    };
+    let env = Environment::new();
+
+    // first only pulls a single item
+    let rv = env
+        .render_str("{{ iter|first }}", minijinja::context!(iter => counting()))
+        .unwrap();
+    assert_eq!(rv, "1");
+    assert_eq!(pulled.swap(0, Ordering::Relaxed), 1);
+
+    // last has to exhaust the iterator
+    let rv = env
+        .render_str("{{ iter|last }}", minijinja::context!(iter => counting()))
+        .unwrap();
+    assert_eq!(rv, "5");
+    assert_eq!(pulled.swap(0, Ordering::Relaxed), 5);
+
+    // a consumed one-shot iterator errors, re-iterable values start over
+    let tmpl = "{% for x in iter %}{% endfor %}[{{ iter|first|default('empty') }}]";
+    let err = env
+        .render_str(tmpl, minijinja::context!(iter => counting()))
+        .unwrap_err();
+    assert_eq!(err.kind(), minijinja::ErrorKind::InvalidOperation);
+    assert_eq!(
+        err.detail(),
+        Some("cannot get first item from an exhausted one-shot iterator")
+    );
+    let rv = env
+        .render_str(
+            "[{{ iter|first is undefined }}]",
+            minijinja::context!(iter => Value::make_one_shot_iterator(0..0)),
+        )
+        .unwrap();
+    assert_eq!(rv, "[true]");
+    let rv = env
+        .render_str(
+            tmpl,
+            minijinja::context!(iter => Value::make_iterable(|| 1..=5)),
+        )
+        .unwrap();
+    assert_eq!(rv, "[1]");
+    let rv = env
+        .render_str(
+            "{{ []|first|default('none') }}|{{ []|last is undefined }}",
+            (),
+        )
+        .unwrap();
+    assert_eq!(rv, "none|true");
+}
+
+#[test]
+fn test_strict_kwargs() {
+    let mut env = Environment::new();
+    let render = |env: &Environment, source| {
+        env.render_str(source, ())
+            .map_err(|err| (err.kind(), err.detail().map(|x| x.to_string())))
+    };
+
+    // filters taking kwargs always catch typos
+    let err = render(
+        &env,
+        "{{ 'hello world'|truncate(length=10, killword=true) }}",
+    );
+    assert_eq!(
+        err,
+        Err((
+            ErrorKind::TooManyArguments,
+            Some("unknown keyword argument 'killword'".into())
+        ))
+    );
+    assert_eq!(
+        render(&env, "{{ 'a'|upper(fist=true) }}"),
+        Err((ErrorKind::TooManyArguments, None))
+    );
+    assert_eq!(
+        render(&env, "{{ ['1']|map('int', bse=2)|list }}").unwrap(),
+        "[1]"
+    );
+
+    env.set_strict_kwargs(true);
+    assert_eq!(
+        render(&env, "{{ 'a'|upper(fist=true) }}"),
+        Err((
+            ErrorKind::TooManyArguments,
+            Some("unknown keyword argument 'fist'".into())
+        ))
+    );
+    assert_eq!(
+        render(&env, "{{ ['1']|map('int', bse=2)|list }}"),
+        Err((
+            ErrorKind::TooManyArguments,
+            Some("unknown keyword argument 'bse'".into())
+        ))
+    );
+    assert_eq!(
+        render(&env, "{{ ['1']|map('int')|list }}|{{ 'hello world'|truncate(length=8, killwords=true, leeway=0) }}").unwrap(),
+        "[1]|hello..."
+    );
+}
+
+#[test]
+fn test_default_boolean() {
+    let mut env = Environment::new();
+    let ctx = minijinja::context! {
+        empty_str => "",
+        zero => 0,
+        zero_float => 0.0,
+        empty_list => Vec::<i32>::new(),
+        empty_map => minijinja::context! {},
+        false_ => false,
+        none => (),
+        value => "x",
+    };
+    let render = |env: &Environment, expr: &str| {
+        env.render_str(&format!("{{{{ {} }}}}", expr), &ctx)
+            .unwrap()
+    };
+
+    for name in [
+        "empty_str",
+        "zero",
+        "zero_float",
+        "empty_list",
+        "empty_map",
+        "false_",
+        "none",
+    ] {
+        let plain = env
+            .render_str(&format!("{{{{ {}|default('d') }}}}", name), &ctx)
+            .unwrap();
+        assert_ne!(plain, "d", "{name} should not be replaced");
+        for expr in [
+            format!("{}|default('d', true)", name),
+            format!("{}|default('d', boolean=true)", name),
+            format!("{}|d('d', boolean=true)", name),
+        ] {
+            assert_eq!(render(&env, &expr), "d", "{expr}");
+        }
+        assert_ne!(
+            render(&env, &format!("{}|default('d', boolean=false)", name)),
+            "d"
+        );
+    }
+
+    assert_eq!(render(&env, "value|default('d', boolean=true)"), "x");
+    assert_eq!(render(&env, "missing|default('d')"), "d");
+    assert_eq!(render(&env, "missing|default('d', boolean=true)"), "d");
+    assert_eq!(render(&env, "missing|default(boolean=true) == ''"), "true");
+    assert_eq!(render(&env, "none|default('d')"), "none");
+
+    // in strict mode undefined values are still replaced
+    env.set_undefined_behavior(minijinja::UndefinedBehavior::Strict);
+    assert_eq!(render(&env, "missing|default('d')"), "d");
+    assert_eq!(render(&env, "missing|default('d', boolean=true)"), "d");
+    assert_eq!(render(&env, "empty_str|default('d', boolean=true)"), "d");
+
+    let err = env
+        .render_str("{{ value|default('d', bool=true) }}", &ctx)
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::TooManyArguments);
+}
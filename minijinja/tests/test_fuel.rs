@@ -48,3 +48,94 @@ fn test_macro_fuel() {
     let err = t.render(context!(macros => 5)).unwrap_err();
     assert_eq!(err.kind(), ErrorKind::OutOfFuel);
 }
+
+#[test]
+fn test_output_fuel() {
+    let mut env = Environment::new();
+    env.set_fuel(Some(100));
+    env.add_template("test", "{{ 'x' * size }}").unwrap();
+
+    // by default only the instructions are charged
+    let t = env.get_template("test").unwrap();
+    let (rv, state) = t.render_and_return_state(context!(size => 1000)).unwrap();
+    assert_eq!(rv.len(), 1000);
+    let (consumed, _) = state.fuel_levels().unwrap();
+    assert!(consumed < 10);
+
+    // with output fuel, the output alone can exhaust the fuel
+    env.set_charge_fuel_for_output(true);
+    let t = env.get_template("test").unwrap();
+    let (rv, state) = t.render_and_return_state(context!(size => 50)).unwrap();
+    assert_eq!(rv.len(), 50);
+    let (consumed_with_output, _) = state.fuel_levels().unwrap();
+    assert_eq!(consumed_with_output, consumed + 50);
+    let err = t.render(context!(size => 1000)).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::OutOfFuel);
+}
+
+#[test]
+fn test_output_fuel_template_data() {
+    let source = "x".repeat(1000);
+    let mut env = Environment::new();
+    env.set_fuel(Some(100));
+    env.set_charge_fuel_for_output(true);
+    let t = env.template_from_str(&source).unwrap();
+    let err = t.render(()).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::OutOfFuel);
+}
+
+#[test]
+fn test_output_fuel_captured() {
+    let mut env = Environment::new();
+    env.set_fuel(Some(100));
+    env.set_charge_fuel_for_output(true);
+
+    // captured output is only charged when it's emitted
+    let t = env
+        .template_from_str("{% set x %}{{ 'x' * 200 }}{% endset %}{{ x|length }}")
+        .unwrap();
+    assert_eq!(t.render(()).unwrap(), "200");
+    let t = env
+        .template_from_str("{% set x %}{{ 'x' * 200 }}{% endset %}{{ x }}")
+        .unwrap();
+    let err = t.render(()).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::OutOfFuel);
+}
+
+#[test]
+fn test_fuel_budget() {
+    use minijinja::FuelBudget;
+
+    let mut env = Environment::new();
+    env.set_fuel(Some(1000));
+    env.add_template("test", "{% for x in seq %}{{ x }}{% endfor %}")
+        .unwrap();
+    let t = env.get_template("test").unwrap();
+    let ctx = context!(seq => (0..10).collect::<Vec<_>>());
+
+    // a single render consumes the same fuel as with the environment
+    let (_, state) = t.render_and_return_state(&ctx).unwrap();
+    let (consumed, _) = state.fuel_levels().unwrap();
+
+    // fuel carries over between renders
+    let budget = FuelBudget::new(consumed * 2 + consumed / 2);
+    assert_eq!(
+        t.render_with_fuel_budget(&ctx, &budget).unwrap(),
+        "0123456789"
+    );
+    assert_eq!(budget.consumed(), consumed);
+    assert_eq!(
+        t.render_with_fuel_budget(&ctx, &budget).unwrap(),
+        "0123456789"
+    );
+    assert_eq!(budget.consumed(), consumed * 2);
+    assert_eq!(budget.remaining(), consumed / 2);
+
+    // and the third render runs out of fuel partway through
+    let err = t.render_with_fuel_budget(&ctx, &budget).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::OutOfFuel);
+    assert_eq!(budget.remaining(), 0);
+
+    // the environment's fuel is not affected
+    t.render(&ctx).unwrap();
+}
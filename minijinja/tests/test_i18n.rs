@@ -0,0 +1,191 @@
+#![cfg(feature = "i18n")]
+use std::sync::{Arc, Mutex};
+
+use minijinja::{context, Environment, ErrorKind};
+use similar_asserts::assert_eq;
+
+fn german_env() -> Environment<'static> {
+    let mut env = Environment::new();
+    env.set_translator(|msgid| {
+        match msgid {
+            "Hello %(name)s!" => "Hallo %(name)s!",
+            "There is %(count)s user." => "Es gibt %(count)s Benutzer.",
+            "There are %(count)s users." => "Es gibt %(count)s Benutzer.",
+            "%(num)s apple" => "%(num)s Apfel",
+            "%(num)s apples" => "%(num)s Äpfel",
+            other => other,
+        }
+        .to_string()
+    });
+    env.add_gettext_functions();
+    env
+}
+
+#[test]
+fn test_trans_basic() {
+    let env = german_env();
+    let rv = env
+        .render_str(
+            "{% trans %}Hello {{ name }}!{% endtrans %}",
+            context! { name => "Peter" },
+        )
+        .unwrap();
+    assert_eq!(rv, "Hallo Peter!");
+
+    let rv = env
+        .render_str(
+            "{% trans name=user.name %}Hello {{ name }}!{% endtrans %}",
+            context! { user => context! { name => "Peter" } },
+        )
+        .unwrap();
+    assert_eq!(rv, "Hallo Peter!");
+
+    let rv = env
+        .render_str("{% trans %}100% untranslated{% endtrans %}", ())
+        .unwrap();
+    assert_eq!(rv, "100% untranslated");
+}
+
+#[test]
+fn test_trans_msgid() {
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let mut env = Environment::new();
+    env.set_translator({
+        let seen = seen.clone();
+        move |msgid| {
+            seen.lock().unwrap().push(msgid.to_string());
+            msgid.to_string()
+        }
+    });
+    env.render_str(
+        "{% trans trimmed %}\n  Hello {{ name }},\n  50% off!\n{% endtrans %}",
+        context! { name => "Peter" },
+    )
+    .unwrap();
+    assert_eq!(
+        *seen.lock().unwrap(),
+        vec!["Hello %(name)s, 50%% off!".to_string()]
+    );
+}
+
+#[test]
+fn test_trans_pluralize() {
+    let env = german_env();
+    let tmpl = "{% trans count=users|length %}There is {{ count }} user.\
+                {% pluralize %}There are {{ count }} users.{% endtrans %}";
+    let rv = env.render_str(tmpl, context! { users => vec![1] }).unwrap();
+    assert_eq!(rv, "Es gibt 1 Benutzer.");
+    let rv = env
+        .render_str(tmpl, context! { users => vec![1, 2, 3] })
+        .unwrap();
+    assert_eq!(rv, "Es gibt 3 Benutzer.");
+
+    let rv = env
+        .render_str(
+            "{% trans %}{{ n }} item{% pluralize n %}{{ n }} items{% endtrans %}",
+            context! { n => 2 },
+        )
+        .unwrap();
+    assert_eq!(rv, "2 items");
+}
+
+#[test]
+fn test_trans_auto_escape() {
+    let mut env = german_env();
+    env.add_template("hello.html", "{% trans %}Hello {{ name }}!{% endtrans %}")
+        .unwrap();
+    let rv = env
+        .get_template("hello.html")
+        .unwrap()
+        .render(context! { name => "<Peter>" })
+        .unwrap();
+    assert_eq!(rv, "Hallo &lt;Peter&gt;!");
+}
+
+#[test]
+fn test_gettext_functions() {
+    let env = german_env();
+    let rv = env
+        .render_str(
+            "{{ _('Hello %(name)s!', name='Peter') }}|{{ gettext('Unknown') }}",
+            (),
+        )
+        .unwrap();
+    assert_eq!(rv, "Hallo Peter!|Unknown");
+
+    let rv = env
+        .render_str(
+            "{{ ngettext('%(num)s apple', '%(num)s apples', 1) }}|\
+             {{ ngettext('%(num)s apple', '%(num)s apples', 5) }}",
+            (),
+        )
+        .unwrap();
+    assert_eq!(rv, "1 Apfel|5 Äpfel");
+}
+
+#[test]
+fn test_trans_ignores_shadowed_functions() {
+    let env = german_env();
+    let rv = env
+        .render_str(
+            "{% trans %}Hello {{ name }}!{% endtrans %}|\
+             {% trans count=n %}{{ count }} apple{% pluralize %}{{ count }} apples{% endtrans %}",
+            context! {
+                name => "Peter",
+                n => 5,
+                gettext => "not a function",
+                ngettext => "not a function",
+            },
+        )
+        .unwrap();
+    assert_eq!(rv, "Hallo Peter!|5 apples");
+}
+
+#[test]
+fn test_gettext_requires_registration() {
+    let env = Environment::new();
+    let err = env.render_str("{{ gettext('Hello') }}", ()).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::UnknownFunction);
+
+    let mut env = Environment::new();
+    env.add_global("_", "underscore");
+    env.set_translator(|msgid| msgid.to_uppercase());
+    let err = env.render_str("{{ gettext('Hello') }}", ()).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::UnknownFunction);
+    let rv = env
+        .render_str("{{ _ }}|{% trans %}Hello{% endtrans %}", ())
+        .unwrap();
+    assert_eq!(rv, "underscore|HELLO");
+}
+
+#[test]
+fn test_trans_errors() {
+    let env = Environment::new();
+    let err = env
+        .render_str("{% trans %}{% if x %}{% endif %}{% endtrans %}", ())
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::SyntaxError);
+    assert_eq!(
+        err.detail(),
+        Some("control structures in translation blocks are not allowed")
+    );
+
+    let err = env
+        .render_str("{% trans %}{{ x.y }}{% endtrans %}", ())
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::SyntaxError);
+
+    let err = env
+        .render_str("{% trans %}foo{% pluralize %}bar{% endtrans %}", ())
+        .unwrap_err();
+    assert_eq!(err.detail(), Some("pluralize requires a count variable"));
+
+    let err = german_env()
+        .render_str("{{ _('Hello %(name)s') }}", ())
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::UndefinedError);
+    assert_eq!(
+        err.detail(),
+        Some("missing variable 'name' for translation")
+    );
+}
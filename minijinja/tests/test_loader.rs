@@ -56,3 +56,190 @@ fn test_source_replace_dynamic() {
     let rv = env.get_template("a").unwrap().render(()).unwrap();
     assert_eq!(rv, "2");
 }
+
+#[test]
+fn test_template_version_fn() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+
+    let loads = Arc::new(AtomicUsize::new(0));
+    let version = Arc::new(Mutex::new(Some(1)));
+
+    let mut env = Environment::new();
+    env.set_loader({
+        let loads = loads.clone();
+        let version = version.clone();
+        move |name| {
+            loads.fetch_add(1, Ordering::Relaxed);
+            Ok(match name {
+                "layout.txt" => Some(format!("v{:?}", version.lock().unwrap())),
+                "index.txt" => Some("[{% include 'layout.txt' %}]".into()),
+                _ => None,
+            })
+        }
+    });
+    env.set_template_version_fn({
+        let version = version.clone();
+        move |name| match name {
+            "layout.txt" => version.lock().unwrap().map(|x| x.to_string()),
+            _ => None,
+        }
+    });
+
+    let render = || env.get_template("index.txt").unwrap().render(()).unwrap();
+
+    // a stable version uses the cache
+    assert_eq!(render(), "[vSome(1)]");
+    assert_eq!(render(), "[vSome(1)]");
+    assert_eq!(loads.load(Ordering::Relaxed), 2);
+
+    // a changed version recompiles the template
+    *version.lock().unwrap() = Some(2);
+    assert_eq!(render(), "[vSome(2)]");
+    assert_eq!(render(), "[vSome(2)]");
+    assert_eq!(loads.load(Ordering::Relaxed), 3);
+
+    // an unchanged version is still cached after a change
+    *version.lock().unwrap() = Some(1);
+    assert_eq!(render(), "[vSome(1)]");
+    assert_eq!(loads.load(Ordering::Relaxed), 3);
+    assert_eq!(env.templates().count(), 2);
+
+    // outdated versions are dropped once the templates are modified
+    env.add_template("other.txt", "").unwrap();
+    *version.lock().unwrap() = Some(2);
+    assert_eq!(
+        env.get_template("index.txt").unwrap().render(()).unwrap(),
+        "[vSome(2)]"
+    );
+    assert_eq!(loads.load(Ordering::Relaxed), 4);
+}
+
+#[test]
+fn test_loader_without_version_fn() {
+    let mut env = Environment::new();
+    env.set_loader(|name| Ok(Some(format!("<{name}>"))));
+    assert_eq!(env.get_template("a").unwrap().render(()).unwrap(), "<a>");
+    assert_eq!(env.templates().count(), 1);
+    env.add_template("a", "new").unwrap();
+    assert_eq!(env.get_template("a").unwrap().render(()).unwrap(), "new");
+    env.remove_template("a");
+    assert_eq!(env.get_template("a").unwrap().render(()).unwrap(), "<a>");
+}
+
+#[test]
+fn test_template_version_fn_outdated_in_use() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    let version = Arc::new(AtomicUsize::new(1));
+
+    let mut env = Environment::new();
+    env.set_loader({
+        let version = version.clone();
+        move |name| {
+            Ok(match name {
+                "layout.txt" => Some(format!(
+                    "{{% macro m() %}}m{}{{% endmacro %}}\
+                     <{{% block body %}}{{% endblock %}}|v{}>",
+                    version.load(Ordering::Relaxed),
+                    version.load(Ordering::Relaxed)
+                )),
+                "index.txt" => Some(
+                    "{% extends 'layout.txt' %}{% block body %}\
+                     {% from 'layout.txt' import m as old %}{% include 'layout.txt' %}\
+                     {{ bump() }}{% include 'layout.txt' %}{{ old() }}\
+                     {% endblock %}"
+                        .into(),
+                ),
+                _ => None,
+            })
+        }
+    });
+    env.set_template_version_fn({
+        let version = version.clone();
+        move |name| match name {
+            "layout.txt" => Some(version.load(Ordering::Relaxed).to_string()),
+            _ => None,
+        }
+    });
+    env.add_function("bump", {
+        let version = version.clone();
+        move || {
+            version.fetch_add(1, Ordering::Relaxed);
+            String::new()
+        }
+    });
+
+    // templates loaded before a new version stay usable
+    let layout = env.get_template("layout.txt").unwrap();
+    let rv = env.get_template("index.txt").unwrap().render(()).unwrap();
+    assert_eq!(rv, "<<|v1><|v2>m1|v1>");
+    assert_eq!(layout.render(()).unwrap(), "<|v1>");
+    assert_eq!(
+        env.get_template("layout.txt").unwrap().render(()).unwrap(),
+        "<|v2>"
+    );
+}
+
+#[test]
+#[cfg(feature = "unstable_machinery")]
+fn test_template_version_fn_outdated_source() {
+    use minijinja::machinery::get_compiled_template;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    let version = Arc::new(AtomicUsize::new(1));
+
+    let mut env = Environment::new();
+    env.set_loader({
+        let version = version.clone();
+        move |_| Ok(Some(format!("v{}", version.load(Ordering::Relaxed))))
+    });
+    env.set_template_version_fn({
+        let version = version.clone();
+        move |_| Some(version.load(Ordering::Relaxed).to_string())
+    });
+
+    let tmpl = env.get_template("hello.txt").unwrap();
+    let source = get_compiled_template(&tmpl).instructions.source();
+    drop(tmpl);
+
+    // references into the old version outlive the loading of a new one
+    version.store(2, Ordering::Relaxed);
+    let tmpl = env.get_template("hello.txt").unwrap();
+    assert_eq!(tmpl.source(), "v2");
+    assert_eq!(source, "v1");
+}
+
+#[test]
+fn test_loader_include_cached() {
+    use std::sync::{Arc, Mutex};
+
+    let loaded = Arc::new(Mutex::new(Vec::new()));
+    let mut env = Environment::new();
+    env.set_loader({
+        let loaded = loaded.clone();
+        move |name| {
+            loaded.lock().unwrap().push(name.to_string());
+            Ok(match name {
+                "index.txt" => Some(
+                    "{% for _ in range(3) %}{% include 'item.txt' %}\
+                     {% include 'missing.txt' ignore missing %}{% endfor %}"
+                        .into(),
+                ),
+                "item.txt" => Some("[item]".into()),
+                _ => None,
+            })
+        }
+    });
+
+    for _ in 0..2 {
+        let rv = env.get_template("index.txt").unwrap().render(()).unwrap();
+        assert_eq!(rv, "[item][item][item]");
+    }
+
+    let mut loaded = loaded.lock().unwrap().clone();
+    loaded.retain(|x| x != "missing.txt");
+    assert_eq!(loaded, ["index.txt", "item.txt"]);
+}
@@ -247,3 +247,44 @@ fn test_unenclosed_resolve() {
         .unwrap();
     assert_snapshot!(rv, @"render global|ctx global|");
 }
+
+#[test]
+#[cfg(feature = "multi_template")]
+fn test_get_macro() {
+    let mut env = Environment::new();
+    env.add_template(
+        "helpers.txt",
+        "{% macro shout(x) %}{{ x|upper }}!{% endmacro %}",
+    )
+    .unwrap();
+    env.add_template(
+        "components.txt",
+        "{% import 'helpers.txt' as helpers %}\
+         {% macro greet(name, greeting='Hello') %}{{ helpers.shout(greeting ~ ' ' ~ name) }}{{ suffix }}{% endmacro %}\
+         {% set suffix = '?' %}",
+    )
+    .unwrap();
+    let tmpl = env.get_template("components.txt").unwrap();
+    let state = tmpl.eval_to_state(()).unwrap();
+
+    let greet = state.get_macro("greet").unwrap();
+    assert_eq!(
+        greet
+            .call(&state, &[Value::from("Peter")])
+            .unwrap()
+            .as_str(),
+        Some("HELLO PETER!?")
+    );
+    assert_eq!(
+        greet
+            .call(&state, args!("Peter", greeting => "Bye"))
+            .unwrap()
+            .as_str(),
+        Some("BYE PETER!?")
+    );
+
+    // only macros are returned
+    assert!(state.get_macro("helpers").is_none());
+    assert!(state.get_macro("suffix").is_none());
+    assert!(state.get_macro("missing").is_none());
+}
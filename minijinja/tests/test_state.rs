@@ -1,5 +1,5 @@
 use minijinja::value::Value;
-use minijinja::{Environment, State};
+use minijinja::{AutoEscape, Environment, State};
 
 #[test]
 fn test_state_lookup_global() {
@@ -11,3 +11,204 @@ fn test_state_lookup_global() {
     let rv = env.render_str("[{{ lookup_global() }}]", ()).unwrap();
     assert_eq!(rv, "[true]");
 }
+
+#[test]
+#[cfg(all(feature = "macros", feature = "multi_template"))]
+fn test_state_temps() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[derive(Default)]
+    struct Counter(AtomicUsize);
+
+    let mut env = Environment::new();
+    env.add_function("incr", |state: &State| {
+        state
+            .get_or_insert_temp(Counter::default)
+            .0
+            .fetch_add(1, Ordering::Relaxed)
+            + 1
+    });
+    env.add_function("current", |state: &State| {
+        state
+            .temp::<Counter>()
+            .map_or(0, |x| x.0.load(Ordering::Relaxed))
+    });
+    env.add_template("include.txt", "{{ incr() }}").unwrap();
+    env.add_template("macros.txt", "{% macro bump() %}{{ incr() }}{% endmacro %}")
+        .unwrap();
+    env.add_template(
+        "main.txt",
+        "{% from 'macros.txt' import bump %}{{ current() }}|{{ incr() }}|\
+         {% include 'include.txt' %}|{{ bump() }}|{{ current() }}",
+    )
+    .unwrap();
+
+    let tmpl = env.get_template("main.txt").unwrap();
+    assert_eq!(tmpl.render(()).unwrap(), "0|1|2|3|3");
+    // a new render starts out with fresh storage
+    assert_eq!(tmpl.render(()).unwrap(), "0|1|2|3|3");
+}
+
+#[test]
+#[cfg(all(feature = "macros", feature = "multi_template", feature = "builtins"))]
+fn test_state_temp_values() {
+    let mut env = Environment::new();
+    env.add_function("require", |state: &State, name: Value| {
+        state.append_temp_value("assets", name);
+        String::new()
+    });
+    env.add_function("assets", |state: &State| state.temp_values("assets"));
+    env.add_template(
+        "include.txt",
+        "{{ require('include.js') }}{{ assets()|join(',') }}",
+    )
+    .unwrap();
+    env.add_template(
+        "main.txt",
+        "{% macro widget() %}{{ require('widget.js') }}{% endmacro %}\
+         {{ require('head.js') }}\
+         {% set captured %}{{ require('captured.js') }}{% endset %}\
+         {{ widget() }}\
+         [{% include 'include.txt' %}]\
+         {% for asset in assets() %}<{{ asset }}>{% endfor %}",
+    )
+    .unwrap();
+
+    let tmpl = env.get_template("main.txt").unwrap();
+    let expected = "[head.js,captured.js,widget.js,include.js]\
+                    <head.js><captured.js><widget.js><include.js>";
+    assert_eq!(tmpl.render(()).unwrap(), expected);
+    assert_eq!(tmpl.render(()).unwrap(), expected);
+    assert_eq!(env.render_str("{{ assets() }}", ()).unwrap(), "[]");
+}
+
+#[test]
+fn test_state_safe_string_for() {
+    let mut env = Environment::new();
+    env.add_filter("bold", |state: &State, value: String| -> Value {
+        state.safe_string_for(format!("<b>{value}</b>"), AutoEscape::Html)
+    });
+    env.add_template("hello.html", "{{ 'x'|bold }}|{{ 'x'|bold is safe }}")
+        .unwrap();
+    env.add_template("hello.txt", "{{ 'x'|bold }}|{{ 'x'|bold is safe }}")
+        .unwrap();
+    env.add_template(
+        "nested.html",
+        "{% autoescape false %}{{ 'x'|bold|escape }}{% endautoescape %}",
+    )
+    .unwrap();
+
+    let render = |name| env.get_template(name).unwrap().render(()).unwrap();
+    assert_eq!(render("hello.html"), "<b>x</b>|true");
+    assert_eq!(render("hello.txt"), "<b>x</b>|false");
+    assert_eq!(render("nested.html"), "&lt;b&gt;x&lt;&#x2f;b&gt;");
+}
+
+#[test]
+fn test_state_capture() {
+    use minijinja::{escape_formatter, Error};
+
+    let mut env = Environment::new();
+    env.add_function(
+        "wrap",
+        |state: &State, tag: String, value: Value| -> Result<Value, Error> {
+            state.capture(|out| {
+                write!(out, "<{tag}>")?;
+                escape_formatter(out, state, &value)?;
+                write!(out, "</{tag}>")?;
+                Ok(())
+            })
+        },
+    );
+    env.add_function("nested", |state: &State| -> Result<Value, Error> {
+        state.capture(|out| {
+            let inner = state.capture(|out| Ok(out.write_str("<i>")?))?;
+            escape_formatter(out, state, &inner)?;
+            escape_formatter(out, state, &Value::from("<i>"))?;
+            Ok(())
+        })
+    });
+    env.add_template(
+        "hello.html",
+        "{{ wrap('b', '<x>') }}|{{ wrap('b', 'x') is safe }}",
+    )
+    .unwrap();
+    env.add_template(
+        "hello.txt",
+        "{{ wrap('b', '<x>') }}|{{ wrap('b', 'x') is safe }}",
+    )
+    .unwrap();
+    env.add_template(
+        "nested.html",
+        "{{ wrap('b', wrap('i', '<x>')) }}|{{ nested() }}|\
+         {% autoescape false %}{{ wrap('b', '<x>') is safe }}{% endautoescape %}",
+    )
+    .unwrap();
+
+    let render = |name| env.get_template(name).unwrap().render(()).unwrap();
+    assert_eq!(render("hello.html"), "<b>&lt;x&gt;</b>|true");
+    assert_eq!(render("hello.txt"), "<b><x></b>|false");
+    assert_eq!(
+        render("nested.html"),
+        "<b><i>&lt;x&gt;</i></b>|<i>&lt;i&gt;|false"
+    );
+}
+
+#[test]
+#[cfg(all(feature = "multi_template", feature = "builtins"))]
+fn test_state_capture_fragment() {
+    use minijinja::Error;
+
+    let mut env = Environment::new();
+    env.add_function(
+        "fragment",
+        |state: &State, value: String| -> Result<Value, Error> {
+            state.capture_fragment(|out| Ok(write!(out, "<p>{value}</p>")?))
+        },
+    );
+    env.add_template(
+        "hello.html",
+        "{% set f = fragment('a') %}{{ f.html }}|{{ f.html is safe }}|{{ f.block }}|{{ f.auto_escape }}|\
+         {% block body %}{% with f = fragment('b') %}{{ f.html }}|{{ f.block }}{% endwith %}{% endblock %}",
+    )
+    .unwrap();
+    env.add_template(
+        "hello.txt",
+        "{% set f = fragment('a') %}{{ f.html }}|{{ f.html is safe }}|{{ f.auto_escape }}",
+    )
+    .unwrap();
+
+    let render = |name| env.get_template(name).unwrap().render(()).unwrap();
+    assert_eq!(
+        render("hello.html"),
+        "<p>a</p>|true|none|html|<p>b</p>|body"
+    );
+    assert_eq!(render("hello.txt"), "<p>a</p>|false|none");
+}
+
+#[test]
+#[cfg(all(feature = "macros", feature = "builtins"))]
+fn test_state_eval_expr() {
+    let mut env = Environment::new();
+    env.add_global("greeting", "Hello");
+    env.add_function("inspect", |state: &State, expr: String| {
+        state.eval_expr(&expr)
+    });
+    let tmpl = env
+        .template_from_str(
+            "{% macro shout(x) %}{{ x|upper }}!{% endmacro %}\
+             {% set sep = '-' %}\
+             {% for name in names %}\
+             [{{ inspect('greeting ~ sep ~ shout(name) ~ sep ~ loop.index') }}]\
+             {{ inspect('\"no output\"') }}\
+             {% endfor %}",
+        )
+        .unwrap();
+    let rv = tmpl
+        .render(minijinja::context! { names => ["a", "b"] })
+        .unwrap();
+    assert_eq!(rv, "[Hello-A!-1]no output[Hello-B!-2]no output");
+
+    let err = env.empty_state().eval_expr("1 +").unwrap_err();
+    assert_eq!(err.kind(), minijinja::ErrorKind::SyntaxError);
+}
@@ -0,0 +1,39 @@
+#![cfg(feature = "stats")]
+use minijinja::{context, Environment};
+
+#[test]
+fn test_basic() {
+    let env = Environment::new();
+    let tmpl = env
+        .template_from_str("{% for x in seq %}{{ x|abs|int }}{{ range(x) }}{% endfor %}")
+        .unwrap();
+    let (rv, state) = tmpl
+        .render_and_return_state(context!(seq => vec![1, 2, 3]))
+        .unwrap();
+    assert_eq!(rv, "1[0]2[0, 1]3[0, 1, 2]");
+    let stats = state.stats();
+    assert_eq!(stats.filter_calls, 6);
+    assert_eq!(stats.function_calls, 3);
+    assert!(stats.instructions > stats.filter_calls + stats.function_calls);
+    assert!(stats.max_stack_size > 0);
+}
+
+#[cfg(feature = "macros")]
+#[test]
+fn test_macro_stats() {
+    let env = Environment::new();
+    let tmpl = env
+        .template_from_str(
+            "{% macro f(n) %}{% if n > 0 %}{{ f(n - 1) }}{% endif %}{% endmacro %}{{ f(5) }}",
+        )
+        .unwrap();
+    let (_, shallow) = tmpl.render_and_return_state(()).unwrap();
+    let stats = shallow.stats();
+    assert_eq!(stats.function_calls, 6);
+    assert!(stats.max_depth >= 6);
+
+    let tmpl = env.template_from_str("{{ 42 }}").unwrap();
+    let (_, state) = tmpl.render_and_return_state(()).unwrap();
+    assert!(state.stats().max_depth < stats.max_depth);
+    assert_eq!(state.stats().function_calls, 0);
+}
@@ -57,6 +57,16 @@ fn test_vm() {
             };
             match template.render(&actual_context) {
                 Ok(mut rendered) => {
+                    // incremental rendering must produce the exact same output
+                    let chunked = template
+                        .render_chunks(context! {
+                            one_shot_iterator => Value::make_one_shot_iterator(0..3),
+                            ..ctx.clone()
+                        })
+                        .unwrap()
+                        .collect::<Result<String, _>>()
+                        .unwrap();
+                    assert_eq!(chunked, rendered);
                     rendered.push('\n');
                     rendered
                 }
@@ -226,6 +236,16 @@ fn test_urlencode_with_struct() {
     );
 }
 
+#[test]
+fn test_nl2br_keeps_line_endings() {
+    let mut env = Environment::new();
+    env.add_template("comment.html", "{{ comment|nl2br }}")
+        .unwrap();
+    let tmpl = env.get_template("comment.html").unwrap();
+    let rv = tmpl.render(context!(comment => "a\r\n<b>\nc")).unwrap();
+    assert_eq!(rv, "a<br>\r\n&lt;b&gt;<br>\nc");
+}
+
 #[test]
 fn test_single() {
     let mut env = Environment::new();
@@ -560,6 +580,102 @@ fn test_render_to_write_state() {
     assert_eq!(state.call_macro("bar", &[]).ok().as_deref(), Some("x"));
 }
 
+#[test]
+fn test_render_to_write_failure_location() {
+    struct FailingWriter {
+        remaining: usize,
+    }
+
+    impl std::io::Write for FailingWriter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            if buf.len() > self.remaining {
+                return Err(std::io::Error::new(std::io::ErrorKind::Other, "disk full"));
+            }
+            self.remaining -= buf.len();
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let mut env = Environment::new();
+    env.add_template(
+        "t.txt",
+        "first\n{{ 'second' }}\n{% if true %}third{% endif %}",
+    )
+    .unwrap();
+    let tmpl = env.get_template("t.txt").unwrap();
+
+    // fails in raw template data
+    let err = tmpl
+        .render_to_write((), FailingWriter { remaining: 13 })
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::WriteFailure);
+    assert_eq!(err.name(), Some("t.txt"));
+    assert_eq!(err.line(), Some(3));
+    let source = std::error::Error::source(&err).unwrap();
+    assert_eq!(source.to_string(), "disk full");
+
+    // fails in an emitted expression
+    let err = tmpl
+        .render_to_write((), FailingWriter { remaining: 8 })
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::WriteFailure);
+    assert_eq!(err.name(), Some("t.txt"));
+    assert_eq!(err.line(), Some(2));
+}
+
+#[test]
+fn test_render_to_tee() {
+    use minijinja::Tee;
+
+    struct FailingWriter;
+
+    impl std::io::Write for FailingWriter {
+        fn write(&mut self, _buf: &[u8]) -> std::io::Result<usize> {
+            Err(std::io::Error::new(std::io::ErrorKind::Other, "log full"))
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let mut env = Environment::new();
+    env.add_template(
+        "t.txt",
+        "{% set secret %}hidden{% endset %}[{{ value }}]\
+         {% filter upper %}shout{% endfilter %}\
+         {% macro m() %}discarded{% endmacro %}{% set x = m() %}",
+    )
+    .unwrap();
+    let tmpl = env.get_template("t.txt").unwrap();
+
+    let mut out = Vec::new();
+    let mut log = Vec::new();
+    tmpl.render_to_write(context!(value => 42), Tee::new(&mut out, &mut log))
+        .unwrap();
+    assert_eq!(std::str::from_utf8(&out).unwrap(), "[42]SHOUT");
+    assert_eq!(out, log);
+
+    // failures of either writer fail the render
+    let err = tmpl
+        .render_to_write(context!(value => 42), Tee::new(Vec::new(), FailingWriter))
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::WriteFailure);
+    let err = tmpl
+        .render_to_write(context!(value => 42), Tee::new(FailingWriter, Vec::new()))
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::WriteFailure);
+
+    // fmt::Write targets work as well
+    let mut tee = Tee::new(String::new(), String::new());
+    write!(tee, "{}", 42).unwrap();
+    assert_eq!(tee.into_inner(), ("42".to_string(), "42".to_string()));
+}
+
 #[test]
 fn test_functions() {
     assert_snapshot!(
@@ -630,7 +746,66 @@ fn test_multiple_extended_includes_in_loop() {
     assert_eq!(rv, "012");
 }
 
-/// See https://github.com/mitsuhiko/minijinja/issues/551
+#[test]
+fn test_include_forgets_loaded_templates() {
+    let mut env = Environment::new();
+    env.add_template("layout.txt", "[{% block body %}{% endblock %}]")
+        .unwrap();
+    env.add_template(
+        "partial.txt",
+        "{% extends 'layout.txt' %}{% block body %}{{ item }}{% endblock %}",
+    )
+    .unwrap();
+    env.add_template(
+        "page.txt",
+        "{% for item in range(2) %}{% include 'partial.txt' %}{% endfor %}\
+         {% extends 'layout.txt' %}{% block body %}page{% endblock %}",
+    )
+    .unwrap();
+    env.add_template(
+        "nested.txt",
+        "{% extends 'layout.txt' %}{% block body %}{% include 'partial.txt' %}{% endblock %}",
+    )
+    .unwrap();
+    env.add_template("a.txt", "{% extends 'b.txt' %}").unwrap();
+    env.add_template("b.txt", "{% extends 'a.txt' %}").unwrap();
+    env.add_template(
+        "cycle.txt",
+        "{% include 'partial.txt' %}{% include 'a.txt' %}",
+    )
+    .unwrap();
+
+    // templates loaded by an include are forgotten once it finishes so the
+    // page can extend the same layout afterwards.
+    let rv = env
+        .get_template("page.txt")
+        .unwrap()
+        .render(context!(item => "x"))
+        .unwrap();
+    assert_eq!(rv, "[0][1][page]");
+
+    // templates loaded before an include are still known within it.
+    let err = env
+        .get_template("nested.txt")
+        .unwrap()
+        .render(())
+        .unwrap_err();
+    let source = std::error::Error::source(&err).unwrap();
+    assert!(source
+        .to_string()
+        .contains("\"layout.txt\" was referenced more than once"));
+
+    let err = env
+        .get_template("cycle.txt")
+        .unwrap()
+        .render(())
+        .unwrap_err();
+    let source = std::error::Error::source(&err).unwrap();
+    assert!(source
+        .to_string()
+        .contains("\"b.txt\" was referenced more than once"));
+}
+
 #[test]
 fn test_filter_caching() {
     let mut env = Environment::new();
@@ -658,3 +833,835 @@ fn test_test_caching() {
     let rv = env.get_template("child.txt").unwrap().render(()).unwrap();
     assert_eq!(rv, "false");
 }
+
+#[test]
+fn test_error_template_stack() {
+    fn frames(err: &Error) -> Vec<(&str, usize)> {
+        err.template_stack()
+            .iter()
+            .map(|x| (x.name(), x.line().unwrap()))
+            .collect()
+    }
+
+    let mut env = Environment::new();
+    env.add_template(
+        "macros.txt",
+        "{% macro fail() %}\n{{ 'a' + 1 }}\n{% endmacro %}",
+    )
+    .unwrap();
+    env.add_template(
+        "include.txt",
+        "{% from 'macros.txt' import fail %}\n\n{{ fail() }}",
+    )
+    .unwrap();
+    env.add_template("layout.txt", "{% block body %}{% endblock %}")
+        .unwrap();
+    env.add_template(
+        "index.txt",
+        "{% extends 'layout.txt' %}\n{% block body %}\n{% include 'include.txt' %}{% endblock %}",
+    )
+    .unwrap();
+
+    let err = env
+        .get_template("index.txt")
+        .unwrap()
+        .render(())
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::BadInclude);
+    assert_eq!(
+        frames(&err),
+        vec![
+            ("macros.txt", 2),
+            ("include.txt", 3),
+            ("index.txt", 3),
+            ("layout.txt", 1),
+        ]
+    );
+
+    let err = env.render_str("{{ 'a' + 1 }}", ()).unwrap_err();
+    assert_eq!(frames(&err), vec![("<string>", 1)]);
+}
+
+#[test]
+fn test_unknown_block_available_names() {
+    let env = Environment::new();
+    let tmpl = env
+        .template_from_str("{% block title %}{% endblock %}{% block body %}{% endblock %}")
+        .unwrap();
+    let err = tmpl
+        .eval_to_state(())
+        .unwrap()
+        .render_block("missing")
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::UnknownBlock);
+    assert_eq!(err.available_names().unwrap(), ["body", "title"]);
+}
+
+#[test]
+fn test_render_with_base_context() {
+    let mut env = Environment::new();
+    env.add_template("t.txt", "{{ title }}|{{ user }}|{{ items|join(',') }}")
+        .unwrap();
+    let base = Value::from_serialize(context! {
+        title => "Base",
+        user => "nobody",
+        items => vec![1, 2, 3],
+    });
+    let tmpl = env.get_template("t.txt").unwrap();
+
+    assert_eq!(
+        tmpl.render_with_base_context(&base, ()).unwrap(),
+        "Base|nobody|1,2,3"
+    );
+    assert_eq!(
+        tmpl.render_with_base_context(&base, context! { user => "peter" })
+            .unwrap(),
+        "Base|peter|1,2,3"
+    );
+
+    let handles = (0..4)
+        .map(|idx| {
+            let base = base.clone();
+            std::thread::spawn(move || {
+                let mut env = Environment::new();
+                env.add_template("t.txt", "{{ title }}|{{ user }}").unwrap();
+                env.get_template("t.txt")
+                    .unwrap()
+                    .render_with_base_context(&base, context! { title => idx })
+                    .unwrap()
+            })
+        })
+        .collect::<Vec<_>>();
+    for (idx, handle) in handles.into_iter().enumerate() {
+        assert_eq!(handle.join().unwrap(), format!("{idx}|nobody"));
+    }
+}
+
+#[test]
+fn test_render_with_recursion_limit() {
+    let mut env = Environment::new();
+    env.add_template("leaf.txt", "{{ i }}").unwrap();
+    env.add_template(
+        "tree.txt",
+        "{% macro walk(i) %}{% if i > 0 %}{{ walk(i - 1) }}{% else %}\
+         {% include 'leaf.txt' %}{% endif %}{% endmacro %}{{ walk(depth) }}",
+    )
+    .unwrap();
+    env.set_recursion_limit(50);
+    let tmpl = env.get_template("tree.txt").unwrap();
+
+    // raising the limit above the one of the environment
+    let ctx = context! { depth => 20 };
+    let err = tmpl.render(&ctx).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidOperation);
+    assert_eq!(tmpl.render_with_recursion_limit(&ctx, 200).unwrap(), "0");
+
+    // lowering the limit below the one of the environment
+    let ctx = context! { depth => 2 };
+    assert_eq!(tmpl.render(&ctx).unwrap(), "0");
+    let err = tmpl.render_with_recursion_limit(&ctx, 15).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidOperation);
+
+    // the limit of the environment is unchanged
+    assert_eq!(env.recursion_limit(), 50);
+}
+
+#[test]
+fn test_render_chunks() {
+    let mut env = Environment::new();
+    env.add_template(
+        "layout.html",
+        "<title>{% block title %}{% endblock %}</title>",
+    )
+    .unwrap();
+    env.add_template("include.html", "{{ x }}!").unwrap();
+    env.add_template(
+        "child.html",
+        "{% extends 'layout.html' %}{% block title %}Hello {{ x }}{% endblock %}",
+    )
+    .unwrap();
+    env.add_template(
+        "main.html",
+        "{% macro m() %}<{{ x }}>{% endmacro %}\
+         {% for item in seq %}{{ item }}{% if not loop.last %},{% endif %}{% endfor %}\
+         {% filter upper %}{{ x }}{% endfilter %}|{% include 'include.html' %}|{{ m() }}",
+    )
+    .unwrap();
+    let ctx = context! { x => "<x>", seq => [1, 2, 3] };
+
+    let tmpl = env.get_template("main.html").unwrap();
+    let chunks = tmpl
+        .render_chunks(&ctx)
+        .unwrap()
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    assert_eq!(
+        chunks,
+        [
+            "1",
+            ",",
+            "2",
+            ",",
+            "3",
+            "&amp;LT;X&amp;GT;",
+            "|",
+            "&lt;x&gt;!",
+            "|",
+            "<&lt;x&gt;>"
+        ]
+    );
+    assert_eq!(chunks.concat(), tmpl.render(&ctx).unwrap());
+
+    let tmpl = env.get_template("child.html").unwrap();
+    let rendered = tmpl
+        .render_chunks(&ctx)
+        .unwrap()
+        .collect::<Result<String, _>>();
+    assert_eq!(rendered.unwrap(), tmpl.render(&ctx).unwrap());
+
+    let tmpl = env.template_from_str("a{{ x }}b{{ 'a' + 1 }}c").unwrap();
+    let mut chunks = tmpl.render_chunks(&ctx).unwrap();
+    assert_eq!(chunks.next().unwrap().unwrap(), "a");
+    assert_eq!(chunks.next().unwrap().unwrap(), "<x>");
+    assert_eq!(chunks.next().unwrap().unwrap(), "b");
+    assert_eq!(
+        chunks.next().unwrap().unwrap_err().kind(),
+        ErrorKind::InvalidOperation
+    );
+    assert!(chunks.next().is_none());
+}
+
+#[test]
+fn test_render_blocks_parallel() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::{Duration, Instant};
+
+    let env = Box::leak(Box::new(Environment::new()));
+    let arrived = Arc::new(AtomicUsize::new(0));
+    let parallel = std::thread::available_parallelism().map_or(false, |x| x.get() > 1);
+    // only returns true if both blocks are being rendered at the same time
+    // unless blocks can't be rendered in parallel on this machine.
+    env.add_function("rendezvous", move || {
+        if !parallel {
+            return true;
+        }
+        arrived.fetch_add(1, Ordering::SeqCst);
+        let start = Instant::now();
+        while arrived.load(Ordering::SeqCst) < 2 {
+            if start.elapsed() > Duration::from_secs(5) {
+                return false;
+            }
+            std::thread::yield_now();
+        }
+        true
+    });
+    env.add_template(
+        "layout.html",
+        "<h1>{% block title %}{% endblock %}</h1>\
+         <div>{% block left %}{% endblock %}</div>\
+         <div>{% block right %}{% endblock %}</div>",
+    )
+    .unwrap();
+    env.add_template(
+        "dashboard.html",
+        "{% extends 'layout.html' %}{% set greeting = 'Hello' %}\
+         {% block title %}{{ greeting }} {{ user }}{% endblock %}\
+         {% block left %}{{ rendezvous() }}{% endblock %}\
+         {% block right %}{{ rendezvous() }}{% endblock %}",
+    )
+    .unwrap();
+
+    let tmpl = env.get_template("dashboard.html").unwrap();
+    let ctx = context!(user => "<John>");
+    let rv = tmpl
+        .render_blocks_parallel(&ctx, &["left", "right", "title"])
+        .unwrap();
+    assert_eq!(
+        rv,
+        "<h1>Hello &lt;John&gt;</h1><div>true</div><div>true</div>"
+    );
+    assert_eq!(rv, tmpl.render(&ctx).unwrap());
+
+    let err = tmpl
+        .render_blocks_parallel(&ctx, &["title", "missing"])
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::UnknownBlock);
+}
+
+#[test]
+fn test_render_blocks_parallel_include() {
+    let env = Box::leak(Box::new(Environment::new()));
+    env.add_template("card.html", "[{% block title %}card{% endblock %}]")
+        .unwrap();
+    env.add_template(
+        "page.html",
+        "{% block title %}page{% endblock %}{% include 'card.html' %}",
+    )
+    .unwrap();
+
+    let tmpl = env.get_template("page.html").unwrap();
+    let rv = tmpl.render_blocks_parallel((), &["title"]).unwrap();
+    assert_eq!(rv, "page[card]");
+    assert_eq!(rv, tmpl.render(()).unwrap());
+}
+
+#[test]
+fn test_whitespace_report() {
+    use minijinja::TrimReason;
+
+    let mut env = Environment::new();
+    env.set_trim_blocks(true);
+    env.set_lstrip_blocks(true);
+    let source = "<ul>\n  {% for x in seq %}\n  <li>{{- x -}} </li>\n  {%- endfor %}\n\
+                  {%- raw -%} raw {%- endraw %}\n";
+    let tmpl = env.template_from_str(source).unwrap();
+    let report = tmpl
+        .whitespace_report()
+        .into_iter()
+        .map(|trim| (trim.reason(), trim.line(), &source[trim.range()]))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        report,
+        vec![
+            (TrimReason::LstripBlocks, 2, "  "),
+            (TrimReason::TrimBlocks, 2, "\n"),
+            (TrimReason::TrimMarker, 3, " "),
+            (TrimReason::TrimMarker, 3, "\n  "),
+            (TrimReason::TrimBlocks, 4, "\n"),
+            (TrimReason::TrimMarker, 5, " "),
+            (TrimReason::TrimMarker, 5, " "),
+            (TrimReason::TrailingNewline, 5, "\n"),
+        ]
+    );
+
+    // rendering is not affected
+    assert_eq!(
+        tmpl.render(context! { seq => [1] }).unwrap(),
+        "<ul>\n  <li>1</li>raw"
+    );
+}
+
+#[test]
+fn test_template_const() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    let calls = Arc::new(AtomicUsize::new(0));
+    let mut env = Environment::new();
+    env.add_global("base", 40);
+    env.add_function("expensive", {
+        let calls = calls.clone();
+        move |n: i64| {
+            calls.fetch_add(1, Ordering::Relaxed);
+            n * 2
+        }
+    });
+    let tmpl = env
+        .template_from_str(
+            "{% const table = expensive(base) %}{% const total = table + 2 %}\
+             {% macro show() %}{{ total }}{% endmacro %}\
+             {{ table }}|{{ show() }}|{{ value }}",
+        )
+        .unwrap();
+
+    // the constants are evaluated once and then reused across renders
+    assert_eq!(tmpl.render(context! { value => 1 }).unwrap(), "80|82|1");
+    assert_eq!(tmpl.render(context! { value => 2 }).unwrap(), "80|82|2");
+    assert_eq!(calls.load(Ordering::Relaxed), 1);
+
+    // per-render variables cannot be referenced
+    let err = env
+        .template_from_str("{% const x = value + 1 %}{{ x }}")
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::SyntaxError);
+    assert_eq!(
+        err.detail(),
+        Some("const \"x\" references \"value\" which is neither a constant nor a global")
+    );
+    let err = env
+        .template_from_str("{% const x = undefined_thing %}")
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::SyntaxError);
+
+    // neither can template variables, even if they shadow a global
+    let err = env
+        .template_from_str("{% set base = 1 %}{% const x = base %}{{ base }}|{{ x }}")
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::SyntaxError);
+    assert_eq!(
+        err.detail(),
+        Some("const \"x\" references \"base\" which is a template variable")
+    );
+
+    // constants are only allowed on the top level and only once
+    let err = env
+        .template_from_str("{% if true %}{% const x = 1 %}{% endif %}")
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::SyntaxError);
+    let err = env
+        .template_from_str("{% const x = 1 %}{% const x = 2 %}")
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::SyntaxError);
+}
+
+#[test]
+fn test_render_with_capacity() {
+    let env = Environment::new();
+    let tmpl = env
+        .template_from_str("{% for x in seq %}{{ x }},{% endfor %}")
+        .unwrap();
+    let ctx = context! { seq => (0..100).collect::<Vec<_>>() };
+    let expected = tmpl.render(&ctx).unwrap();
+    let rv = tmpl.render_with_capacity(&ctx, 1024).unwrap();
+    assert_eq!(rv, expected);
+    assert!(rv.capacity() >= 1024);
+    assert_eq!(tmpl.render_with_capacity(&ctx, 0).unwrap(), expected);
+}
+
+#[test]
+fn test_string_concat_chain() {
+    let mut env = Environment::new();
+    env.set_auto_escape_callback(|_| minijinja::AutoEscape::Html);
+    let ctx = context! {
+        a => "a",
+        b => Value::from_safe_string("<b>".into()),
+        c => 42,
+        d => (),
+        e => vec![1, 2],
+    };
+    let chained = env
+        .render_str("{{ a ~ b ~ c ~ d ~ missing ~ e ~ (a ~ c) ~ 1.5 }}", &ctx)
+        .unwrap();
+    let binary = env
+        .render_str(
+            "{% set x = a ~ b %}{% set x = x ~ c %}{% set x = x ~ d %}\
+             {% set x = x ~ missing %}{% set x = x ~ e %}{% set x = x ~ (a ~ c) %}\
+             {{ x ~ 1.5 }}",
+            &ctx,
+        )
+        .unwrap();
+    assert_eq!(chained, binary);
+    assert_eq!(chained, "a&lt;b&gt;42none[1, 2]a421.5");
+}
+
+#[test]
+fn test_render_with_globals() {
+    let mut env = Environment::new();
+    env.add_global("site", "default");
+    env.add_template(
+        "macros.html",
+        "{% macro whoami() %}{{ user }}@{{ site }}{% endmacro %}",
+    )
+    .unwrap();
+    let tmpl = env
+        .template_from_str(
+            "{% macro greet() %}Hello {{ user }}!{% endmacro %}\
+             {% from 'macros.html' import whoami %}\
+             {{ greet() }}|{{ whoami() }}|{{ user }}|{{ site }}",
+        )
+        .unwrap();
+
+    let rv = tmpl
+        .render_with_globals(context!(), context!(user => "peter", site => "overlay"))
+        .unwrap();
+    assert_eq!(rv, "Hello peter!|peter@overlay|peter|overlay");
+
+    // the context shadows the per-render globals
+    let rv = tmpl
+        .render_with_globals(context!(site => "ctx"), context!(user => "peter"))
+        .unwrap();
+    assert_eq!(rv, "Hello peter!|peter@ctx|peter|ctx");
+
+    // the overlay does not leak into regular renders
+    assert_eq!(tmpl.render(()).unwrap(), "Hello !|@default||default");
+
+    let expr = env.compile_expression("user ~ '@' ~ site").unwrap();
+    assert_eq!(
+        expr.eval_with_globals(context!(), context!(user => "peter"))
+            .unwrap(),
+        Value::from("peter@default")
+    );
+}
+
+#[test]
+fn test_state_name_and_current_block() {
+    let mut env = Environment::new();
+    env.add_function("where", |state: &State| {
+        format!("{}:{}", state.name(), state.current_block().unwrap_or("-"))
+    });
+    env.add_template(
+        "layout.html",
+        "{{ where() }}|{% block body %}{{ where() }}{% endblock %}",
+    )
+    .unwrap();
+    env.add_template("include.html", "{{ where() }}").unwrap();
+    env.add_template(
+        "child.html",
+        "{% extends 'layout.html' %}\
+         {% block body %}{{ super() }}|{{ where() }}|{% include 'include.html' %}{% endblock %}",
+    )
+    .unwrap();
+
+    let rv = env.get_template("child.html").unwrap().render(()).unwrap();
+    // included templates report their own name but stay in the block
+    // they were included from
+    assert_eq!(
+        rv,
+        "layout.html:-|layout.html:body|child.html:body|include.html:body"
+    );
+}
+
+#[test]
+fn test_undefined_block_in_strict_env() {
+    let mut env = Environment::new();
+    env.set_undefined_behavior(minijinja::UndefinedBehavior::Strict);
+    env.add_template("include.txt", "[{{ missing }}]").unwrap();
+    env.add_template(
+        "main.txt",
+        "{% macro show(x) %}<{{ x.missing }}>{% endmacro %}\
+         {% undefined 'lenient' %}{{ show({}) }}{% include 'include.txt' %}{% endundefined %}\
+         {{ show({}) }}",
+    )
+    .unwrap();
+    let err = env
+        .get_template("main.txt")
+        .unwrap()
+        .render(())
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::UndefinedError);
+
+    env.add_template(
+        "main.txt",
+        "{% macro show(x) %}<{{ x.missing }}>{% endmacro %}\
+         {% undefined 'lenient' %}{{ show({}) }}{% include 'include.txt' %}{% endundefined %}",
+    )
+    .unwrap();
+    let rv = env.get_template("main.txt").unwrap().render(()).unwrap();
+    assert_eq!(rv, "<>[]");
+}
+
+#[test]
+#[cfg(feature = "loop_controls")]
+fn test_undefined_block_loop_controls() {
+    let mut env = Environment::new();
+    env.set_undefined_behavior(minijinja::UndefinedBehavior::Strict);
+
+    // break and continue leave the undefined block
+    for source in [
+        "{% for x in [1] %}{% undefined 'lenient' %}{% break %}{% endundefined %}{% endfor %}[{{ missing }}]",
+        "{% for x in [1, 2] %}{% if x == 2 %}[{{ missing }}]{% endif %}\
+         {% undefined 'lenient' %}{% continue %}{% endundefined %}{% endfor %}",
+    ] {
+        let err = env.render_str(source, ()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UndefinedError, "{source}");
+    }
+
+    // undefined blocks around the loop are kept
+    let rv = env
+        .render_str(
+            "{% undefined 'lenient' %}{% for x in [1, 2] %}\
+             {% undefined 'strict' %}{% continue %}{% endundefined %}\
+             {% endfor %}[{{ missing }}]{% endundefined %}",
+            (),
+        )
+        .unwrap();
+    assert_eq!(rv, "[]");
+}
+
+#[test]
+#[cfg(feature = "json")]
+fn test_json_auto_escape_output() {
+    let mut env = Environment::new();
+    env.add_template("data.json", "{{ value }}|{{ safe }}|{{ text }}")
+        .unwrap();
+    let value = Value::from_serialize(serde_json::json!({
+        "items": [
+            {"id": 1, "name": "a \"quoted\" name", "tags": ["x", "ü", "\u{2028}"]},
+            {"id": 2.5, "name": null, "nested": {"deep": [[], {}, true]}},
+        ],
+        "html": "</script>",
+    }));
+    let rv = env
+        .get_template("data.json")
+        .unwrap()
+        .render(context! {
+            value => value.clone(),
+            safe => Value::from_safe_string("[1, 2]".into()),
+            text => "line\nbreak",
+        })
+        .unwrap();
+    assert_eq!(
+        rv,
+        format!(
+            "{}|[1, 2]|{}",
+            serde_json::to_string(&value).unwrap(),
+            serde_json::to_string("line\nbreak").unwrap()
+        )
+    );
+
+    // values that fail to serialize leave incomplete JSON behind
+    env.set_keep_partial_output_on_error(true);
+    let long = "x".repeat(2000);
+    let value = Value::from_iter([
+        (Value::from("a"), Value::from(long.as_str())),
+        (Value::from(vec![1]), Value::from(1)),
+    ]);
+    let err = env
+        .get_template("data.json")
+        .unwrap()
+        .render(context! { value, safe => "", text => "" })
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::BadSerialization);
+    assert_eq!(err.detail(), Some("unable to format to JSON"));
+    let partial = err.partial_output().unwrap();
+    assert!(partial.starts_with(r#"{"a":"xxx"#));
+    assert!(serde_json::from_str::<serde_json::Value>(partial).is_err());
+}
+
+#[test]
+fn test_render_many() {
+    let mut env = Environment::new();
+    env.add_template(
+        "row.txt",
+        "{% for cell in row %}{{ cell|upper }}{% if not loop.last %},{% endif %}{% endfor %}",
+    )
+    .unwrap();
+    let tmpl = env.get_template("row.txt").unwrap();
+    let rows = [
+        context!(row => vec!["a", "b"]),
+        context!(row => (0..100).map(|x| x.to_string()).collect::<Vec<_>>()),
+        context!(row => 42),
+        context!(row => vec!["c"]),
+    ];
+    let results = tmpl.render_many(rows.iter()).collect::<Vec<_>>();
+    assert_eq!(results.len(), rows.len());
+    for (rv, row) in results.into_iter().zip(rows.iter()) {
+        match (rv, tmpl.render(row)) {
+            (Ok(a), Ok(b)) => assert_eq!(a, b),
+            (Err(a), Err(b)) => assert_eq!(a.to_string(), b.to_string()),
+            (a, b) => panic!("mismatch: {a:?} vs {b:?}"),
+        }
+    }
+    assert_eq!(tmpl.render_many(Vec::<Value>::new()).count(), 0);
+}
+
+#[test]
+fn test_include_with_context() {
+    let mut env = Environment::new();
+    env.add_global("site", "example.com");
+    env.add_template("card.txt", "[{{ title }}|{{ body }}|{{ site }}]")
+        .unwrap();
+    env.add_template(
+        "page.txt",
+        "{% set body = 'outer' %}\
+         {% include 'card.txt' with {'title': title|upper} %}\
+         {% include 'card.txt' %}\
+         {% include 'missing.txt' ignore missing with {} %}",
+    )
+    .unwrap();
+
+    // the map is evaluated in the caller's scope, the include only sees it
+    // and the globals.
+    let rv = env
+        .get_template("page.txt")
+        .unwrap()
+        .render(context!(title => "hello"))
+        .unwrap();
+    assert_eq!(rv, "[HELLO||example.com][hello|outer|example.com]");
+
+    let err = env
+        .render_str("{% include 'card.txt' with 42 %}", ())
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidOperation);
+    assert_eq!(err.detail(), Some("include context must be a map"));
+}
+
+#[test]
+fn test_resolved_blocks() {
+    let mut env = Environment::new();
+    env.add_template(
+        "base.txt",
+        "{% block title %}{% endblock %}{% block nav %}{% endblock %}{% block body %}{% endblock %}",
+    )
+    .unwrap();
+    env.add_template(
+        "layout.txt",
+        "{% extends 'base.txt' %}{% block nav %}nav{% endblock %}{% block body %}\
+         {% block content %}{% endblock %}{% endblock %}",
+    )
+    .unwrap();
+    env.add_template(
+        "page.txt",
+        "{% extends 'layout.txt' %}{% block body %}{{ super() }}{% endblock %}\
+         {% block content %}page{% endblock %}",
+    )
+    .unwrap();
+    env.add_template(
+        "dynamic.txt",
+        "{% extends layout %}{% block title %}{% endblock %}",
+    )
+    .unwrap();
+    env.add_template("a.txt", "{% extends 'b.txt' %}{% block x %}{% endblock %}")
+        .unwrap();
+    env.add_template("b.txt", "{% extends 'a.txt' %}{% block y %}{% endblock %}")
+        .unwrap();
+
+    let blocks = env.get_template("page.txt").unwrap().resolved_blocks();
+    let blocks = blocks
+        .iter()
+        .map(|(k, v)| (k.as_str(), v.as_str()))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        blocks,
+        [
+            ("body", "page.txt"),
+            ("content", "page.txt"),
+            ("nav", "layout.txt"),
+            ("title", "base.txt"),
+        ]
+    );
+
+    let blocks = env.get_template("base.txt").unwrap().resolved_blocks();
+    assert!(blocks.values().all(|x| x == "base.txt"));
+
+    // dynamic parents cannot be resolved without rendering
+    let blocks = env.get_template("dynamic.txt").unwrap().resolved_blocks();
+    assert_eq!(blocks.len(), 1);
+
+    // cycles stop the resolution
+    let blocks = env.get_template("a.txt").unwrap().resolved_blocks();
+    assert_eq!(blocks["x"], "a.txt");
+    assert_eq!(blocks["y"], "b.txt");
+}
+
+#[test]
+fn test_counter() {
+    let mut env = Environment::new();
+    env.add_template("item.txt", "[{{ ids.next() }}]").unwrap();
+    env.add_template(
+        "list.txt",
+        "{% set ids = counter(1) %}{% set other = counter() %}\
+         {% for x in range(3) %}{{ ids.next() }}{% include 'item.txt' %}{% endfor %}|\
+         {{ other.next() }}{{ other.next() }}|{{ ids.next() }}",
+    )
+    .unwrap();
+    let rv = env.get_template("list.txt").unwrap().render(()).unwrap();
+    assert_eq!(rv, "1[2]3[4]5[6]|01|7");
+
+    // every render gets fresh counters
+    let rv = env.get_template("list.txt").unwrap().render(()).unwrap();
+    assert_eq!(rv, "1[2]3[4]5[6]|01|7");
+
+    let err = env.render_str("{{ counter().reset() }}", ()).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::UnknownMethod);
+}
+
+#[test]
+fn test_include_ignore_errors() {
+    use minijinja::{Warning, WarningKind};
+    use std::sync::{Arc, Mutex};
+
+    let warnings = Arc::new(Mutex::new(Vec::<Warning>::new()));
+    let mut env = Environment::new();
+    let sink = warnings.clone();
+    env.set_warning_sink(move |warning| sink.lock().unwrap().push(warning));
+    env.add_template(
+        "broken.txt",
+        "partial{% for x in [1, 2] %}{% set y %}{{ x }}{{ x + 'a' }}{% endset %}{% endfor %}",
+    )
+    .unwrap();
+    env.add_template("ok.txt", "[{{ value }}]").unwrap();
+    env.add_template(
+        "page.txt",
+        "{% for value in [1, 2] %}<{% include 'broken.txt' ignore errors %}\
+         {% include 'ok.txt' ignore errors %}{{ loop.index }}>{% endfor %}",
+    )
+    .unwrap();
+
+    let rv = env.get_template("page.txt").unwrap().render(()).unwrap();
+    assert_eq!(rv, "<[1]1><[2]2>");
+    {
+        let warnings = warnings.lock().unwrap();
+        assert_eq!(warnings.len(), 2);
+        assert_eq!(warnings[0].kind(), WarningKind::IncludeError);
+        assert_eq!(warnings[0].name(), Some("page.txt"));
+        assert!(warnings[0].detail().contains("\"broken.txt\""));
+    }
+
+    // without the flag the error fails the render
+    let err = env
+        .render_str("{% include 'broken.txt' %}", ())
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::BadInclude);
+
+    // missing templates are not errors of the included template
+    let err = env
+        .render_str("{% include 'missing.txt' ignore errors %}", ())
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::TemplateNotFound);
+    let rv = env
+        .render_str(
+            "{% include 'missing.txt' ignore missing ignore errors %}\
+             {% include 'broken.txt' ignore errors with {'value': 1} %}!",
+            (),
+        )
+        .unwrap();
+    assert_eq!(rv, "!");
+}
+
+#[test]
+fn test_template_metrics() {
+    let mut env = Environment::new();
+    env.add_template("helpers.txt", "{% macro x() %}{% endmacro %}")
+        .unwrap();
+    env.add_template(
+        "page.txt",
+        "{% extends 'layout.txt' %}\
+         {% import 'helpers.txt' as helpers %}\
+         {% from 'helpers.txt' import x %}\
+         {% macro row(items) %}{% for item in items %}{{ item }}{% endfor %}{% endmacro %}\
+         {% block body %}\
+           {% for a in x %}{% with y = a %}{% for b in y %}\
+             {% block inner %}{% for c in b %}{% endfor %}{% endblock %}\
+           {% endfor %}{% endwith %}{% endfor %}\
+           {% call row() %}{% endcall %}\
+           {% include 'header.txt' %}{% include 'footer.txt' ignore missing %}\
+         {% endblock %}",
+    )
+    .unwrap();
+    let tmpl = env.get_template("page.txt").unwrap();
+    let metrics = tmpl.metrics();
+    assert_eq!(metrics.blocks, 2);
+    assert_eq!(metrics.macros, 1);
+    assert_eq!(metrics.max_loop_depth, 2);
+    assert_eq!(metrics.includes, 2);
+    assert_eq!(metrics.extends, 1);
+
+    // the instructions of nested blocks are counted too
+    let without_inner = env
+        .template_from_str("{% for c in b %}{% endfor %}")
+        .unwrap()
+        .metrics();
+    assert!(metrics.instructions > without_inner.instructions);
+
+    let empty = env.template_from_str("").unwrap().metrics();
+    assert_eq!(empty, minijinja::TemplateMetrics::default());
+}
+
+#[test]
+fn test_namespace_missing_nested_reads() {
+    use minijinja::UndefinedBehavior;
+
+    let mut env = Environment::new();
+    let tmpl = "{% set ns = namespace() %}[{{ ns.a.b.c }}]";
+    assert_eq!(env.render_str(tmpl, ()).unwrap(), "[]");
+
+    // only direct lookups on the missing attribute are undefined
+    let err = env
+        .render_str("{% set ns = namespace() %}{% set x = ns.a %}{{ x.b }}", ())
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::UndefinedError);
+
+    env.set_undefined_behavior(UndefinedBehavior::Strict);
+    let err = env.render_str(tmpl, ()).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::UndefinedError);
+}
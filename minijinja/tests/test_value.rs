@@ -541,6 +541,49 @@ fn test_make_iterable() {
     "###);
 }
 
+#[test]
+#[cfg(feature = "builtins")]
+fn test_make_iterable_reiterable() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    let calls = Arc::new(AtomicUsize::new(0));
+    let value = Value::make_iterable({
+        let calls = calls.clone();
+        move || {
+            calls.fetch_add(1, Ordering::Relaxed);
+            0..3
+        }
+    });
+    let rv = render!(
+        "{% for x in iter %}{{ x }}{% endfor %}|{% for x in iter %}{{ x }}/{{ loop.length }}{% endfor %}|{{ iter|length }}",
+        iter => value
+    );
+    assert_snapshot!(rv, @"012|0/31/32/3|3");
+    assert_eq!(calls.load(Ordering::Relaxed), 3);
+}
+
+#[test]
+fn test_from_set() {
+    let set = Value::from_set(vec![
+        Value::from(3),
+        Value::from(1),
+        Value::from(true),
+        Value::from(3.0),
+        Value::from(vec![1, 2]),
+        Value::from(vec![1, 2]),
+        Value::from(()),
+    ]);
+    assert_eq!(set.len(), Some(4));
+    assert_eq!(set.to_string(), "[3, 1, [1, 2], none]");
+    let rv = render!(
+        "{{ 3 in s }}|{{ 1.0 in s }}|{{ true in s }}|{{ [1, 2] in s }}|{{ none in s }}|{{ 2 in s }}|{{ '3' in s }}",
+        s => set
+    );
+    assert_snapshot!(rv, @"true|true|true|true|true|false|false");
+    let rv = render!("{% for x in s %}{{ loop.index }}:{{ x }} {% endfor %}|{{ s[2] }}", s => set);
+    assert_snapshot!(rv, @"1:3 2:1 3:[1, 2] 4:none |[1, 2]");
+}
+
 #[test]
 fn test_complex_key() {
     let value = Value::from_iter([
@@ -1193,7 +1236,21 @@ fn test_bytes() {
     let bytes = vec![1u8, 2, 3, 4];
     let byte_value = Value::from_bytes(bytes);
     assert_eq!(byte_value.kind(), ValueKind::Bytes);
-    assert!(byte_value.try_iter().is_err());
+    assert_eq!(byte_value.len(), Some(4));
+    assert_eq!(byte_value.get_item_by_index(1).unwrap(), Value::from(2));
+    assert_eq!(
+        byte_value.get_item(&Value::from(-1)).unwrap(),
+        Value::from(4)
+    );
+    assert_eq!(
+        byte_value.try_iter().unwrap().collect::<Vec<_>>(),
+        vec![
+            Value::from(1),
+            Value::from(2),
+            Value::from(3),
+            Value::from(4)
+        ]
+    );
     assert_eq!(format!("{:?}", byte_value), "b'\\x01\\x02\\x03\\x04'");
 
     let bytes = vec![1u8, 2, 3, 4];
@@ -1207,3 +1264,225 @@ fn test_bytes() {
         "b'\\'foo\"'"
     );
 }
+
+#[test]
+#[cfg(feature = "deserialization")]
+fn test_bytes_serde_roundtrip() {
+    use serde::Deserialize;
+
+    let byte_value = Value::from_bytes(vec![0, 159, 146, 150]);
+    let rv = Value::from_serialize(&byte_value);
+    assert_eq!(rv.kind(), ValueKind::Bytes);
+    assert_eq!(rv, byte_value);
+
+    let rv = Value::deserialize(byte_value.clone()).unwrap();
+    assert_eq!(rv.kind(), ValueKind::Bytes);
+    assert_eq!(rv, byte_value);
+
+    let rv = Value::deserialize(
+        serde::de::value::BytesDeserializer::<serde::de::value::Error>::new(&[1, 2, 3]),
+    )
+    .unwrap();
+    assert_eq!(rv, Value::from_bytes(vec![1, 2, 3]));
+}
+
+#[test]
+#[cfg(feature = "builtins")]
+fn test_bytes_in_templates() {
+    let env = Environment::new();
+    let ctx = context! { data => Value::from_bytes(b"hello\xff".to_vec()) };
+    let rv = env
+        .render_str(
+            "{{ data|length }}|{{ data[0] }}|{{ data|list }}|{{ data|hex }}|{{ data|b64encode }}",
+            &ctx,
+        )
+        .unwrap();
+    assert_eq!(
+        rv,
+        "6|104|[104, 101, 108, 108, 111, 255]|68656c6c6fff|aGVsbG//"
+    );
+
+    let err = env.render_str("{{ data }}", &ctx).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidOperation);
+    assert_eq!(
+        err.detail(),
+        Some("cannot print bytes, use an encoding filter such as b64encode or hex")
+    );
+}
+
+#[test]
+#[cfg(feature = "builtins")]
+fn test_object_custom_cmp() {
+    use std::cmp::Ordering;
+
+    #[derive(Debug)]
+    struct Version(u32, u32);
+
+    impl Object for Version {
+        fn repr(self: &Arc<Self>) -> ObjectRepr {
+            ObjectRepr::Plain
+        }
+
+        fn custom_cmp(self: &Arc<Self>, other: &DynObject) -> Option<Ordering> {
+            match other.downcast_ref::<Self>() {
+                Some(other) => Some((self.0, self.1).cmp(&(other.0, other.1))),
+                // versions sort after all other plain objects
+                None => Some(Ordering::Greater),
+            }
+        }
+    }
+
+    #[derive(Debug)]
+    struct Unversioned;
+
+    impl Object for Unversioned {
+        fn repr(self: &Arc<Self>) -> ObjectRepr {
+            ObjectRepr::Plain
+        }
+    }
+
+    let a = Value::from_object(Version(1, 2));
+    let b = Value::from_object(Version(1, 10));
+    assert!(a < b);
+    assert_eq!(a, Value::from_object(Version(1, 2)));
+    assert_ne!(a, b);
+
+    let rv = render!(
+        "{{ a < b }}|{{ a == b }}|{{ [b, a]|sort|first == a }}",
+        a,
+        b
+    );
+    assert_eq!(rv, "true|false|true");
+
+    // the hook is consulted no matter which side the object is on
+    let c = Value::from_object(Unversioned);
+    assert!(a > c);
+    assert!(c < a);
+    assert_ne!(c, a);
+    let rv = render!("{{ a > c }}|{{ c < a }}", a, c);
+    assert_eq!(rv, "true|true");
+}
+
+#[test]
+fn test_deep_eq_and_ptr_eq() {
+    #[derive(Debug)]
+    struct Thing;
+    impl Object for Thing {}
+
+    let seq = Value::from(vec![1, 2, 3]);
+    let other = Value::from(vec![1, 2, 3]);
+    assert!(seq.deep_eq(&other));
+    assert!(!seq.ptr_eq(&other));
+    assert!(seq.ptr_eq(&seq.clone()));
+
+    let obj = Arc::new(Thing);
+    let a = Value::from_dyn_object(obj.clone());
+    let b = Value::from_dyn_object(obj);
+    assert!(a.ptr_eq(&b));
+    assert!(a.ptr_eq(&a.clone()));
+    assert!(!a.ptr_eq(&Value::from_object(Thing)));
+
+    let s = Value::from("a string that is too long to be stored inline");
+    assert!(s.ptr_eq(&s.clone()));
+    assert!(!s.ptr_eq(&Value::from(s.as_str().unwrap())));
+    assert!(s.deep_eq(&Value::from(s.as_str().unwrap())));
+
+    assert!(Value::from(1).deep_eq(&Value::from(1.0)));
+    assert!(!Value::from(1).ptr_eq(&Value::from(1)));
+    assert!(Value::from(()).deep_eq(&Value::from(())));
+    assert!(Value::UNDEFINED.deep_eq(&Value::UNDEFINED));
+    assert!(!Value::UNDEFINED.deep_eq(&Value::from(())));
+    assert!(!Value::UNDEFINED.ptr_eq(&Value::UNDEFINED));
+}
+
+#[test]
+fn test_get_path() {
+    let value = context! {
+        user => context! {
+            name => "Peter",
+            address => context! { city => "Vienna" },
+            tags => vec!["admin", "staff"],
+        },
+        items => vec![
+            context! { name => "first" },
+            context! { name => "second" },
+        ],
+    };
+
+    assert_eq!(value.get_path("user.name"), Value::from("Peter"));
+    assert_eq!(value.get_path("user.address.city"), Value::from("Vienna"));
+    assert_eq!(value.get_path("user.tags.1"), Value::from("staff"));
+    assert_eq!(value.get_path("items.0.name"), Value::from("first"));
+    assert_eq!(value.get_path("items.1.name"), Value::from("second"));
+
+    assert!(value.get_path("missing").is_undefined());
+    assert!(value.get_path("missing.deeper.still").is_undefined());
+    assert!(value.get_path("user.address.zip").is_undefined());
+    assert!(value.get_path("items.2.name").is_undefined());
+    assert!(value.get_path("user.name.first").is_undefined());
+    assert!(Value::UNDEFINED.get_path("foo").is_undefined());
+    assert!(Value::from(()).get_path("foo.0").is_undefined());
+}
+
+#[test]
+#[cfg(feature = "builtins")]
+fn test_serialization_hook() {
+    use minijinja::value::with_serialization_hook;
+
+    #[derive(serde::Serialize)]
+    #[serde(tag = "type")]
+    enum Shape {
+        Circle { radius: i64 },
+        Square { side: i64 },
+    }
+
+    #[derive(Debug)]
+    struct Circle {
+        radius: i64,
+    }
+
+    impl Object for Circle {
+        fn get_value(self: &Arc<Self>, key: &Value) -> Option<Value> {
+            match key.as_str()? {
+                "diameter" => Some(Value::from(self.radius * 2)),
+                _ => None,
+            }
+        }
+
+        fn render(self: &Arc<Self>, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result
+        where
+            Self: Sized + 'static,
+        {
+            write!(f, "circle({})", self.radius)
+        }
+    }
+
+    let hook = |value: Value| {
+        if value
+            .get_attr("type")
+            .ok()
+            .as_ref()
+            .and_then(|x| x.as_str())
+            == Some("Circle")
+        {
+            let radius = i64::try_from(value.get_attr("radius").unwrap()).unwrap();
+            Value::from_object(Circle { radius })
+        } else {
+            value
+        }
+    };
+    let shapes = vec![Shape::Circle { radius: 2 }, Shape::Square { side: 3 }];
+
+    let rv = with_serialization_hook(hook, || {
+        render!(
+            "{% for s in shapes %}[{{ s.type|default(s) }}|{{ s.diameter }}|{{ s.side }}]{% endfor %}",
+            shapes => &shapes
+        )
+    });
+    assert_eq!(rv, "[circle(2)|4|][Square||3]");
+
+    // the hook is only active within the callback
+    let value = Value::from_serialize(&shapes[0]);
+    assert_eq!(value.get_attr("diameter").unwrap(), Value::UNDEFINED);
+    assert_eq!(value.get_attr("radius").unwrap(), Value::from(2));
+}